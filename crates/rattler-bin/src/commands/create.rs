@@ -10,12 +10,13 @@ use rattler::{
     install::{Transaction, TransactionOperation},
 };
 use rattler_conda_types::{
-    Channel, ChannelConfig, GenericVirtualPackage, MatchSpec, ParseStrictness, Platform,
-    PrefixRecord, RepoDataRecord, Version,
+    Channel, ChannelConfig, GenericVirtualPackage, MatchSpec, ParseStrictness, PinnedFile,
+    Platform, PrefixRecord, RepoDataRecord, Version,
 };
 use rattler_networking::{AuthenticationMiddleware, AuthenticationStorage};
 use rattler_repodata_gateway::{Gateway, RepoData};
 use rattler_solve::{
+    find_pin_conflicts,
     libsolv_c::{self},
     resolvo, SolverImpl, SolverTask,
 };
@@ -127,6 +128,14 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
     // Determine the packages that are currently installed in the environment.
     let installed_packages = PrefixRecord::collect_from_prefix(&target_prefix)?;
 
+    // Read the packages that are pinned in the environment.
+    let pinned = PinnedFile::from_prefix(&target_prefix).with_context(|| {
+        format!(
+            "failed to read {}",
+            PinnedFile::path(&target_prefix).display()
+        )
+    })?;
+
     // For each channel/subdirectory combination, download and cache the `repodata.json` that should
     // be available from the corresponding Url. The code below also displays a nice CLI progress-bar
     // to give users some more information about what is going on.
@@ -219,6 +228,19 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
         .map(|record| record.repodata_record.clone())
         .collect();
 
+    // Requests that can never be satisfied because of a pin are reported
+    // upfront, the solver would only report that the problem is unsolvable.
+    let conflicts = find_pin_conflicts(&specs, &pinned.specs, repo_data.iter().flatten());
+    if !conflicts.is_empty() {
+        anyhow::bail!(
+            "the request conflicts with the pins in {}:\n{}",
+            PinnedFile::path(&target_prefix).display(),
+            conflicts
+                .iter()
+                .format_with("\n", |c, f| f(&format_args!("  - {c}")))
+        );
+    }
+
     let solver_task = SolverTask {
         locked_packages,
         virtual_packages,
@@ -226,7 +248,8 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
         timeout: opt.timeout.map(Duration::from_millis),
         strategy: opt.strategy.map_or_else(Default::default, Into::into),
         ..SolverTask::from_iter(&repo_data)
-    };
+    }
+    .with_pins(pinned.specs);
 
     // Next, use a solver to solve this specific problem. This provides us with all the operations
     // we need to apply to our environment to bring it up to date.
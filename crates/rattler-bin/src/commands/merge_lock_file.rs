@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+/// Merges two lock-files that were derived from a common base. This command can be used as a git
+/// merge driver (`rattler merge-lock-file %O %A %B`), the result is written to `ours`.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The lock-file of the common ancestor
+    base: PathBuf,
+
+    /// Our version of the lock-file, this is overwritten with the merged lock-file
+    ours: PathBuf,
+
+    /// Their version of the lock-file
+    theirs: PathBuf,
+}
+
+pub fn merge_lock_file(opt: Opt) -> anyhow::Result<()> {
+    match rattler_lock::merge_driver(&opt.base, &opt.ours, &opt.theirs) {
+        Err(rattler_lock::MergeDriverError::Conflicts(err)) => {
+            for conflict in &err.conflicts {
+                eprintln!("{conflict}");
+            }
+            Err(err.into())
+        }
+        result => Ok(result?),
+    }
+}
@@ -1,2 +1,4 @@
 pub mod create;
+pub mod merge_lock_file;
+pub mod verify;
 pub mod virtual_packages;
@@ -0,0 +1,97 @@
+use std::{env, path::PathBuf, str::FromStr, sync::Arc};
+
+use rattler::{
+    default_cache_dir,
+    install::{repair_prefix, verify_prefix, AppleCodeSignBehavior, VerifyOptions},
+    package_cache::PackageCache,
+};
+use rattler_conda_types::{Platform, PrefixRecord};
+use rattler_networking::{AuthenticationMiddleware, AuthenticationStorage};
+
+/// Verifies that the files of all packages installed in a prefix are intact.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The prefix to verify
+    #[clap(long)]
+    target_prefix: Option<PathBuf>,
+
+    /// Relink damaged files from the package cache
+    #[clap(long)]
+    repair: bool,
+
+    /// Skip computing the hashes of all files, only check their existence and size
+    #[clap(long)]
+    skip_hashes: bool,
+
+    /// The platform of the prefix, defaults to the current platform
+    #[clap(long)]
+    platform: Option<String>,
+}
+
+pub async fn verify(opt: Opt) -> anyhow::Result<()> {
+    let target_prefix = opt
+        .target_prefix
+        .unwrap_or_else(|| env::current_dir().unwrap_or_default().join(".prefix"));
+    println!("Target prefix: {}", target_prefix.display());
+
+    let platform = if let Some(platform) = opt.platform {
+        Platform::from_str(&platform)?
+    } else {
+        Platform::current()
+    };
+
+    let records = PrefixRecord::collect_from_prefix(&target_prefix)?;
+    let options = VerifyOptions {
+        check_hashes: !opt.skip_hashes,
+        ..VerifyOptions::default()
+    };
+    let report = verify_prefix(&target_prefix, &records, &options)?;
+
+    if report.is_ok() {
+        println!(
+            "{} Verified {} packages, no issues found",
+            console::style(console::Emoji("✔", "")).green(),
+            records.len()
+        );
+        return Ok(());
+    }
+
+    for issue in &report.issues {
+        println!("{} {issue}", console::style("!").yellow());
+    }
+
+    if !opt.repair {
+        anyhow::bail!("found {} issues in the prefix", report.issues.len());
+    }
+
+    let cache_dir = default_cache_dir()?;
+    let download_client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+        .with_arc(Arc::new(AuthenticationMiddleware::new(
+            AuthenticationStorage::default(),
+        )))
+        .build();
+    let unrepaired = repair_prefix(
+        &target_prefix,
+        &records,
+        &report,
+        &PackageCache::new(cache_dir.join(rattler_cache::PACKAGE_CACHE_DIR)),
+        download_client,
+        platform,
+        AppleCodeSignBehavior::default(),
+    )
+    .await?;
+
+    println!(
+        "{} Repaired {} issues",
+        console::style(console::Emoji("✔", "")).green(),
+        report.issues.len() - unrepaired.len()
+    );
+    if !unrepaired.is_empty() {
+        for issue in &unrepaired {
+            println!("{} {issue}", console::style("✘").red());
+        }
+        anyhow::bail!("{} issues could not be repaired", unrepaired.len());
+    }
+
+    Ok(())
+}
@@ -38,7 +38,9 @@ struct Opt {
 #[derive(Debug, clap::Subcommand)]
 enum Command {
     Create(commands::create::Opt),
+    MergeLockFile(commands::merge_lock_file::Opt),
     VirtualPackages(commands::virtual_packages::Opt),
+    Verify(commands::verify::Opt),
 }
 
 /// Entry point of the `rattler` cli.
@@ -71,6 +73,8 @@ async fn main() -> anyhow::Result<()> {
     // Dispatch the selected comment
     match opt.command {
         Command::Create(opts) => commands::create::create(opts).await,
+        Command::MergeLockFile(opts) => commands::merge_lock_file::merge_lock_file(opts),
         Command::VirtualPackages(opts) => commands::virtual_packages::virtual_packages(opts),
+        Command::Verify(opts) => commands::verify::verify(opts).await,
     }
 }
@@ -1,12 +1,21 @@
 //! This module contains CLI common entrypoint for authentication.
 use clap::Parser;
-use rattler_networking::{Authentication, AuthenticationStorage};
+use rattler_networking::{
+    authentication_storage::{
+        backends::{file::FileStorage, keyring::KeyringAuthenticationStorage, netrc::NetRcStorage},
+        migration::{migrate_credentials, MigrationOptions},
+        StorageBackend,
+    },
+    Authentication, AuthenticationStorage,
+};
 use thiserror;
 
 /// Command line arguments that contain authentication data
 #[derive(Parser, Debug)]
 pub struct LoginArgs {
-    /// The host to authenticate with (e.g. repo.prefix.dev)
+    /// The host to authenticate with (e.g. repo.prefix.dev). A path can be
+    /// added to only use the credentials for URLs below it (e.g.
+    /// repo.prefix.dev/my-channel)
     host: String,
 
     /// The token to use (for authentication with prefix.dev)
@@ -32,12 +41,27 @@ struct LogoutArgs {
     host: String,
 }
 
+#[derive(Parser, Debug)]
+struct MigrateArgs {
+    /// Keep the credentials in the credentials file after they have been copied to the keyring
+    #[clap(long)]
+    keep_source: bool,
+
+    /// Overwrite credentials that are already stored in the keyring
+    #[clap(long)]
+    overwrite: bool,
+}
+
 #[derive(Parser, Debug)]
 enum Subcommand {
     /// Store authentication information for a given host
     Login(LoginArgs),
     /// Remove authentication information for a given host
     Logout(LogoutArgs),
+    /// List the hosts for which authentication information is stored
+    List,
+    /// Move credentials from the credentials file and `.netrc` into the keyring
+    Migrate(MigrateArgs),
 }
 
 /// Login to prefix.dev or anaconda.org servers to access private channels
@@ -75,15 +99,29 @@ pub enum AuthenticationCLIError {
     /// (keyring or file system)
     #[error("Failed to interact with the authentication storage system")]
     StorageError(#[source] anyhow::Error),
+
+    /// Some credentials could not be migrated to the keyring
+    #[error("Failed to migrate the credentials for {}", .0.join(", "))]
+    MigrationFailed(Vec<String>),
 }
 
 fn get_url(url: &str) -> Result<String, AuthenticationCLIError> {
-    // parse as url and extract host without scheme or port
-    let host = if url.contains("://") {
-        url::Url::parse(url)?.host_str().unwrap().to_string()
+    // parse as url and extract host (and path) without scheme or port
+    let (host, path) = if url.contains("://") {
+        let url = url::Url::parse(url)?;
+        (url.host_str().unwrap().to_string(), url.path().to_string())
     } else {
-        url.to_string()
+        match url.split_once('/') {
+            Some((host, path)) => (host.to_string(), path.to_string()),
+            None => (url.to_string(), String::new()),
+        }
     };
+    let path = path.trim_matches('/');
+
+    if !path.is_empty() {
+        // credentials scoped to a path are only used for this exact host
+        return Ok(format!("{host}/{path}"));
+    }
 
     let host = if host.matches('.').count() == 1 {
         // use wildcard for top-level domains
@@ -139,6 +177,68 @@ fn logout(args: LogoutArgs, storage: AuthenticationStorage) -> Result<(), Authen
     Ok(())
 }
 
+fn list(storage: AuthenticationStorage) -> Result<(), AuthenticationCLIError> {
+    let credentials = storage
+        .list()
+        .map_err(AuthenticationCLIError::StorageError)?;
+
+    if credentials.is_empty() {
+        println!("No credentials stored");
+        return Ok(());
+    }
+
+    let width = credentials
+        .iter()
+        .map(|credentials| credentials.host.len())
+        .max()
+        .unwrap_or_default();
+    for credentials in credentials {
+        println!("{:width$}  {}", credentials.host, credentials.kind);
+    }
+
+    Ok(())
+}
+
+fn migrate(args: MigrateArgs) -> Result<(), AuthenticationCLIError> {
+    let keyring = KeyringAuthenticationStorage::default();
+    let options = MigrationOptions {
+        remove_from_source: !args.keep_source,
+        overwrite: args.overwrite,
+    };
+
+    let netrc = NetRcStorage::from_env().unwrap_or_else(|(path, err)| {
+        eprintln!("Skipping {}: {}", path.display(), err);
+        NetRcStorage::default()
+    });
+    let sources: [(&str, &dyn StorageBackend); 2] = [
+        ("credentials file", &FileStorage::default()),
+        (".netrc", &netrc),
+    ];
+
+    let mut failed = Vec::new();
+    for (name, source) in sources {
+        let report = migrate_credentials(source, &keyring, &options)
+            .map_err(AuthenticationCLIError::StorageError)?;
+
+        for host in report.migrated {
+            println!("Moved credentials for {host} from the {name} to the keyring");
+        }
+        for host in report.skipped {
+            println!("Skipped {host}, the keyring already contains credentials for it");
+        }
+        for (host, err) in report.failed {
+            eprintln!("Failed to move credentials for {host}: {err}");
+            failed.push(host);
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(AuthenticationCLIError::MigrationFailed(failed))
+    }
+}
+
 /// CLI entrypoint for authentication
 pub async fn execute(args: Args) -> Result<(), AuthenticationCLIError> {
     let storage = AuthenticationStorage::default();
@@ -146,5 +246,7 @@ pub async fn execute(args: Args) -> Result<(), AuthenticationCLIError> {
     match args.subcommand {
         Subcommand::Login(args) => login(args, storage),
         Subcommand::Logout(args) => logout(args, storage),
+        Subcommand::List => list(storage),
+        Subcommand::Migrate(args) => migrate(args),
     }
 }
@@ -0,0 +1,308 @@
+//! Cloning of an existing prefix to a new location.
+//!
+//! A prefix cannot simply be copied because files contain the path of the
+//! prefix they were installed in. Instead [`clone_prefix`] installs all
+//! packages of the source prefix into the target prefix with an
+//! [`Installer`], which links the files from the package cache and replaces
+//! their placeholders with the new prefix. Packages that are missing from the
+//! cache are fetched again. Files that are not tracked by any package can
+//! optionally be copied as well, this mirrors `conda create --clone`.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::PrefixRecord;
+use simple_spawn_blocking::{tokio::run_blocking_task, Cancelled};
+
+use super::{InstallationResult, Installer, InstallerError};
+
+/// Options that determine how a prefix is cloned.
+#[derive(Debug, Clone, Default)]
+pub struct CloneOptions {
+    /// Whether to also copy files that are not tracked by any package, e.g.
+    /// configuration files that were created by the user. Occurrences of the
+    /// source prefix in text files are replaced with the target prefix.
+    pub copy_untracked_files: bool,
+}
+
+/// The result of cloning a prefix.
+#[derive(Debug)]
+pub struct CloneResult {
+    /// The result of installing the packages of the source prefix.
+    pub installation: InstallationResult,
+
+    /// The untracked files that were copied, relative to the prefix.
+    pub untracked_files: Vec<PathBuf>,
+}
+
+/// An error that can occur while cloning a prefix.
+#[derive(Debug, thiserror::Error)]
+pub enum CloneError {
+    /// The packages installed in the source prefix could not be determined.
+    #[error("failed to read the installed packages of '{0}'")]
+    FailedToReadSource(PathBuf, #[source] std::io::Error),
+
+    /// The target prefix already contains files.
+    #[error("the target prefix '{0}' is not empty")]
+    TargetNotEmpty(PathBuf),
+
+    /// The packages could not be installed in the target prefix.
+    #[error(transparent)]
+    InstallerError(#[from] InstallerError),
+
+    /// An untracked file could not be copied.
+    #[error("failed to copy '{0}'")]
+    FailedToCopy(PathBuf, #[source] std::io::Error),
+
+    /// The operation was cancelled.
+    #[error("the operation was cancelled")]
+    Cancelled,
+}
+
+impl From<Cancelled> for CloneError {
+    fn from(_: Cancelled) -> Self {
+        CloneError::Cancelled
+    }
+}
+
+/// Clones the prefix at `source_prefix` to `target_prefix`, which must not
+/// exist yet or be empty.
+///
+/// The `installer` is used to install the packages and can be used to
+/// configure the package cache, the download client and reporting. It should
+/// target the same platform as the source prefix.
+pub async fn clone_prefix(
+    source_prefix: &Path,
+    target_prefix: &Path,
+    installer: Installer,
+    options: &CloneOptions,
+) -> Result<CloneResult, CloneError> {
+    let records = PrefixRecord::collect_from_prefix(source_prefix)
+        .map_err(|e| CloneError::FailedToReadSource(source_prefix.to_path_buf(), e))?;
+
+    if fs_err::read_dir(target_prefix).map_or(false, |mut entries| entries.next().is_some()) {
+        return Err(CloneError::TargetNotEmpty(target_prefix.to_path_buf()));
+    }
+
+    let installation = installer
+        .with_installed_packages(Vec::new())
+        .install(
+            target_prefix,
+            records.iter().map(|record| record.repodata_record.clone()),
+        )
+        .await?;
+
+    let untracked_files = if options.copy_untracked_files {
+        let source_prefix = source_prefix.to_path_buf();
+        let target_prefix = target_prefix.to_path_buf();
+        run_blocking_task(move || copy_untracked_files(&source_prefix, &target_prefix, &records))
+            .await?
+    } else {
+        Vec::new()
+    };
+
+    Ok(CloneResult {
+        installation,
+        untracked_files,
+    })
+}
+
+/// Copies all files of `source_prefix` that are not tracked by any of the
+/// `records` to `target_prefix`. Returns the paths of the copied files.
+fn copy_untracked_files(
+    source_prefix: &Path,
+    target_prefix: &Path,
+    records: &[PrefixRecord],
+) -> Result<Vec<PathBuf>, CloneError> {
+    let tracked = records
+        .iter()
+        .flat_map(|record| record.paths_data.paths.iter())
+        .map(|entry| entry.relative_path.as_path())
+        .collect::<HashSet<_>>();
+
+    let source = source_prefix.to_string_lossy();
+    let target = target_prefix.to_string_lossy();
+    let mut copied = Vec::new();
+    for entry in walkdir::WalkDir::new(source_prefix)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            let name = entry.file_name();
+            !(entry.depth() == 1 && name == "conda-meta") && name != "__pycache__"
+        })
+    {
+        let entry = entry.map_err(|e| {
+            let path = e.path().unwrap_or(source_prefix).to_path_buf();
+            CloneError::FailedToCopy(path, e.into())
+        })?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative_path = entry
+            .path()
+            .strip_prefix(source_prefix)
+            .expect("walkdir returns paths inside the prefix");
+        if tracked.contains(relative_path) {
+            continue;
+        }
+
+        let destination = target_prefix.join(relative_path);
+        let to_error = |e| CloneError::FailedToCopy(entry.path().to_path_buf(), e);
+        if let Some(parent) = destination.parent() {
+            fs_err::create_dir_all(parent).map_err(to_error)?;
+        }
+        if entry.path_is_symlink() {
+            let link = fs_err::read_link(entry.path()).map_err(to_error)?;
+            #[cfg(unix)]
+            fs_err::os::unix::fs::symlink(link, &destination).map_err(to_error)?;
+            #[cfg(windows)]
+            fs_err::os::windows::fs::symlink_file(link, &destination).map_err(to_error)?;
+        } else {
+            let contents = fs_err::read(entry.path()).map_err(to_error)?;
+            match String::from_utf8(contents) {
+                Ok(text) if text.contains(source.as_ref()) => {
+                    fs_err::write(&destination, text.replace(source.as_ref(), &target))
+                        .map_err(to_error)?;
+                    let permissions = entry.metadata().map_err(|e| to_error(e.into()))?;
+                    fs_err::set_permissions(&destination, permissions.permissions())
+                        .map_err(to_error)?;
+                }
+                _ => {
+                    fs_err::copy(entry.path(), &destination).map_err(to_error)?;
+                }
+            }
+        }
+        copied.push(relative_path.to_path_buf());
+    }
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rattler_conda_types::PrefixRecord;
+
+    use super::{clone_prefix, CloneError, CloneOptions};
+    use crate::{
+        get_repodata_record, get_test_data_dir,
+        install::{verify_prefix, Installer, VerifyOptions},
+        package_cache::PackageCache,
+    };
+
+    #[tokio::test]
+    async fn test_clone_prefix_placeholder() {
+        let source_cache = tempfile::tempdir().unwrap();
+        let target_cache = tempfile::tempdir().unwrap();
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let source_prefix = source.path();
+        let target_prefix = target.path().join("clone");
+
+        let record = get_repodata_record(
+            get_test_data_dir()
+                .join("prefix-placeholder/prefix-placeholder-0.1.0-h4616a5c_0.tar.bz2"),
+        );
+        Installer::new()
+            .with_package_cache(PackageCache::new(source_cache.path()))
+            .install(source_prefix, vec![record])
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(source_prefix.join("etc/prefix.txt")).unwrap(),
+            format!("prefix={}\n", source_prefix.display())
+        );
+
+        // The package is not in the cache of the clone so it is fetched again.
+        assert!(std::fs::read_dir(target_cache.path())
+            .unwrap()
+            .next()
+            .is_none());
+        clone_prefix(
+            source_prefix,
+            &target_prefix,
+            Installer::new().with_package_cache(PackageCache::new(target_cache.path())),
+            &CloneOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert!(std::fs::read_dir(target_cache.path())
+            .unwrap()
+            .next()
+            .is_some());
+
+        // The placeholder is replaced with the new prefix, the source is untouched.
+        assert_eq!(
+            std::fs::read_to_string(target_prefix.join("etc/prefix.txt")).unwrap(),
+            format!("prefix={}\n", target_prefix.display())
+        );
+        assert_eq!(
+            std::fs::read_to_string(source_prefix.join("etc/prefix.txt")).unwrap(),
+            format!("prefix={}\n", source_prefix.display())
+        );
+
+        let records = PrefixRecord::collect_from_prefix(&target_prefix).unwrap();
+        assert_eq!(records.len(), 1);
+        let report = verify_prefix(&target_prefix, &records, &VerifyOptions::default()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+    }
+
+    #[tokio::test]
+    async fn test_clone_untracked_files() {
+        let cache = tempfile::tempdir().unwrap();
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let source_prefix = source.path();
+        let target_prefix = target.path().join("clone");
+
+        std::fs::create_dir_all(source_prefix.join("conda-meta")).unwrap();
+        std::fs::write(source_prefix.join("conda-meta/history"), "").unwrap();
+        std::fs::create_dir_all(source_prefix.join("etc")).unwrap();
+        std::fs::write(
+            source_prefix.join("etc/config.txt"),
+            format!("prefix={}", source_prefix.display()),
+        )
+        .unwrap();
+        std::fs::write(source_prefix.join("etc/binary"), [0xff, 0xfe, 0x00]).unwrap();
+
+        let installer = || Installer::new().with_package_cache(PackageCache::new(cache.path()));
+        let result = clone_prefix(
+            source_prefix,
+            &target_prefix,
+            installer(),
+            &CloneOptions {
+                copy_untracked_files: true,
+            },
+        )
+        .await
+        .unwrap();
+
+        let mut untracked = result.untracked_files.clone();
+        untracked.sort();
+        assert_eq!(
+            untracked,
+            vec![Path::new("etc/binary"), Path::new("etc/config.txt")]
+        );
+        assert_eq!(
+            std::fs::read_to_string(target_prefix.join("etc/config.txt")).unwrap(),
+            format!("prefix={}", target_prefix.display())
+        );
+        assert_eq!(
+            std::fs::read(target_prefix.join("etc/binary")).unwrap(),
+            [0xff, 0xfe, 0x00]
+        );
+
+        // Cloning into a prefix that already contains files fails.
+        let result = clone_prefix(
+            source_prefix,
+            &target_prefix,
+            installer(),
+            &CloneOptions::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(CloneError::TargetNotEmpty(_))));
+    }
+}
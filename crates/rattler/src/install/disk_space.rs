@@ -0,0 +1,498 @@
+//! Checks whether there is enough disk space available to execute a
+//! transaction.
+//!
+//! Running out of disk space halfway through an installation leaves the
+//! prefix in a broken state. The [`super::Installer`] therefore estimates the
+//! number of bytes a transaction requires in the package cache and in the
+//! prefix (see [`estimate_disk_space`]) and compares that with the free space
+//! of the volumes they are located on before any changes are made (see
+//! [`check_disk_space`]).
+//!
+//! The extracted size of packages that are not in the package cache yet is
+//! guessed from their download size. If only that guess exceeds the free
+//! space a warning is logged instead of failing the installation.
+
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{
+    package::{PathType, PathsJson},
+    prefix_record, PrefixRecord, RepoDataRecord,
+};
+use simple_spawn_blocking::{tokio::run_blocking_task, Cancelled};
+
+use super::paths_have_same_filesystem;
+use crate::package_cache::CacheKey;
+
+/// The ratio between the extracted size and the archive size that is assumed
+/// for packages that are not yet present in the package cache. This is only a
+/// rough guess, see [`DiskSpaceEstimate::cache_guessed`].
+const EXTRACTED_SIZE_RATIO: u64 = 4;
+
+/// An error that is returned if there is not enough disk space available.
+#[derive(Debug, thiserror::Error)]
+pub enum DiskSpaceError {
+    /// There is not enough free space on a volume.
+    #[error(
+        "not enough disk space available for '{}', {} are required but only {} are available",
+        .path.display(), Bytes(*required), Bytes(*available)
+    )]
+    InsufficientSpace {
+        /// The directory that is located on the volume.
+        path: PathBuf,
+
+        /// The number of bytes that are required.
+        required: u64,
+
+        /// The number of bytes that are available.
+        available: u64,
+    },
+
+    /// The operation was cancelled.
+    #[error("the operation was cancelled")]
+    Cancelled,
+}
+
+impl From<Cancelled> for DiskSpaceError {
+    fn from(_: Cancelled) -> Self {
+        DiskSpaceError::Cancelled
+    }
+}
+
+/// The estimated number of bytes required to install a set of packages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSpaceEstimate {
+    /// The number of bytes required in the package cache.
+    pub cache: u64,
+
+    /// The number of bytes required in the prefix.
+    pub prefix: u64,
+
+    /// The part of `cache` that is guessed from the download size of packages
+    /// that are not in the package cache yet.
+    pub cache_guessed: u64,
+
+    /// The part of `prefix` that is guessed from the download size of
+    /// packages that are not in the package cache yet.
+    pub prefix_guessed: u64,
+}
+
+/// Estimates the number of bytes required to install `records`.
+///
+/// Packages that are already present in the package cache are measured using
+/// the `size_in_bytes` of their `paths.json`. If the cache and the prefix are
+/// located on the same volume files are hard linked and only files that have
+/// to be copied (files that contain a prefix placeholder or are marked as
+/// `no_link`) take up space in the prefix. For packages that are not cached
+/// yet the download size is used to guess the extracted size.
+///
+/// The files of the `removed` packages that take up space in the prefix by
+/// the same rules are subtracted from the space required in the prefix.
+pub fn estimate_disk_space<'r>(
+    records: impl IntoIterator<Item = &'r RepoDataRecord>,
+    removed: impl IntoIterator<Item = &'r PrefixRecord>,
+    cache_dir: &Path,
+    hard_links: bool,
+) -> DiskSpaceEstimate {
+    let mut estimate = DiskSpaceEstimate::default();
+    for record in records {
+        let package_dir = cache_dir.join(CacheKey::from(&record.package_record).to_string());
+        let paths_json = PathsJson::from_package_directory_with_deprecated_fallback(&package_dir);
+        let package = estimate_package(
+            paths_json.ok().as_ref(),
+            record.package_record.size.unwrap_or(0),
+            hard_links,
+        );
+        estimate.cache += package.cache;
+        estimate.prefix += package.prefix;
+        estimate.cache_guessed += package.cache_guessed;
+        estimate.prefix_guessed += package.prefix_guessed;
+    }
+
+    let freed = removed
+        .into_iter()
+        .flat_map(|record| record.paths_data.paths.iter())
+        .filter(|entry| entry.path_type == prefix_record::PathType::HardLink)
+        .filter(|entry| !hard_links || entry.no_link || entry.prefix_placeholder.is_some())
+        .filter_map(|entry| entry.size_in_bytes)
+        .sum::<u64>();
+    estimate.prefix = estimate.prefix.saturating_sub(freed);
+    estimate.prefix_guessed = estimate.prefix_guessed.min(estimate.prefix);
+
+    estimate
+}
+
+/// Estimates the number of bytes required to install a single package.
+fn estimate_package(
+    paths_json: Option<&PathsJson>,
+    download_size: u64,
+    hard_links: bool,
+) -> DiskSpaceEstimate {
+    let Some(paths_json) = paths_json else {
+        // The archive is downloaded and extracted into the cache.
+        let extracted = download_size * EXTRACTED_SIZE_RATIO;
+        let prefix = if hard_links { 0 } else { extracted };
+        return DiskSpaceEstimate {
+            cache: download_size + extracted,
+            prefix,
+            cache_guessed: extracted,
+            prefix_guessed: prefix,
+        };
+    };
+
+    let prefix = paths_json
+        .paths
+        .iter()
+        .filter(|entry| entry.path_type == PathType::HardLink)
+        .filter(|entry| !hard_links || entry.no_link || entry.prefix_placeholder.is_some())
+        .filter_map(|entry| entry.size_in_bytes)
+        .sum();
+    DiskSpaceEstimate {
+        prefix,
+        ..DiskSpaceEstimate::default()
+    }
+}
+
+/// Checks that there is enough free space on the volumes of the package cache
+/// and the prefix to install `records` and remove `removed`.
+///
+/// Whether files are hard linked is determined like [`super::link_package`]
+/// does: if `allow_hard_links` is `None` files are hard linked if the package
+/// cache and the prefix are on the same volume.
+///
+/// If the free space of a volume cannot be determined that volume is not
+/// checked.
+pub async fn check_disk_space(
+    records: Vec<RepoDataRecord>,
+    removed: Vec<PrefixRecord>,
+    target_prefix: &Path,
+    cache_dir: &Path,
+    allow_hard_links: Option<bool>,
+) -> Result<DiskSpaceEstimate, DiskSpaceError> {
+    check_disk_space_with(
+        records,
+        removed,
+        target_prefix,
+        cache_dir,
+        allow_hard_links,
+        available_space,
+    )
+    .await
+}
+
+/// Same as [`check_disk_space`] but uses `available_space` to determine the
+/// free space of a volume.
+async fn check_disk_space_with(
+    records: Vec<RepoDataRecord>,
+    removed: Vec<PrefixRecord>,
+    target_prefix: &Path,
+    cache_dir: &Path,
+    allow_hard_links: Option<bool>,
+    available_space: fn(&Path) -> std::io::Result<u64>,
+) -> Result<DiskSpaceEstimate, DiskSpaceError> {
+    // The directories might not exist yet, use the closest existing parent.
+    let prefix_dir = existing_ancestor(target_prefix);
+    let cache_dir_on_disk = existing_ancestor(cache_dir);
+    let same_volume = paths_have_same_filesystem(&prefix_dir, &cache_dir_on_disk).await;
+    let hard_links = allow_hard_links.unwrap_or(same_volume);
+
+    let cache_dir = cache_dir.to_path_buf();
+    run_blocking_task(move || {
+        let estimate = estimate_disk_space(&records, &removed, &cache_dir, hard_links);
+        let check = |path: &Path, required: u64, guessed: u64| {
+            ensure_available(path, required, guessed, available_space(path))
+        };
+        if same_volume {
+            check(
+                &prefix_dir,
+                estimate.cache + estimate.prefix,
+                estimate.cache_guessed + estimate.prefix_guessed,
+            )?;
+        } else {
+            check(&cache_dir_on_disk, estimate.cache, estimate.cache_guessed)?;
+            check(&prefix_dir, estimate.prefix, estimate.prefix_guessed)?;
+        }
+        Ok(estimate)
+    })
+    .await
+}
+
+/// Returns an error if less than `required` bytes are `available` on the
+/// volume that contains `path`. If there would be enough space without the
+/// `guessed` part of `required` only a warning is logged.
+fn ensure_available(
+    path: &Path,
+    required: u64,
+    guessed: u64,
+    available: std::io::Result<u64>,
+) -> Result<(), DiskSpaceError> {
+    if required == 0 {
+        return Ok(());
+    }
+    match available {
+        Ok(available) if available < required - guessed => Err(DiskSpaceError::InsufficientSpace {
+            path: path.to_path_buf(),
+            required,
+            available,
+        }),
+        Ok(available) if available < required => {
+            tracing::warn!(
+                "there might not be enough disk space available for '{}', about {} are required but only {} are available",
+                path.display(),
+                Bytes(required),
+                Bytes(available)
+            );
+            Ok(())
+        }
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::warn!(
+                "failed to determine the available disk space for '{}': {e} (ignored)",
+                path.display()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Returns `path` or the closest of its parents that exists.
+fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+/// Returns the number of bytes available to the current user on the volume
+/// that contains `path`.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn available_space(path: &Path) -> std::io::Result<u64> {
+    use std::{ffi::CString, mem, os::unix::prelude::*};
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    unsafe {
+        let mut buf: libc::statvfs = mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut buf) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(buf.f_bavail as u64 * buf.f_frsize as u64)
+    }
+}
+
+/// Returns the number of bytes available to the current user on the volume
+/// that contains `path`.
+#[cfg(windows)]
+fn available_space(path: &Path) -> std::io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path = path
+        .as_os_str()
+        .encode_wide()
+        .chain(Some(0))
+        .collect::<Vec<u16>>();
+    let mut available = 0u64;
+    let result = unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if result == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(available)
+}
+
+/// Returns the number of bytes available to the current user on the volume
+/// that contains `path`.
+#[cfg(not(any(unix, windows)))]
+fn available_space(_path: &Path) -> std::io::Result<u64> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "determining the available disk space is not supported on this platform",
+    ))
+}
+
+/// Formats a number of bytes in a human readable way.
+struct Bytes(u64);
+
+impl Display for Bytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use rattler_conda_types::{
+        package::{PackageFile, PathsJson},
+        prefix_record::{PathType, PathsEntry},
+        PrefixRecord,
+    };
+
+    use super::{
+        available_space, check_disk_space_with, estimate_disk_space, estimate_package, Bytes,
+        DiskSpaceError, DiskSpaceEstimate,
+    };
+    use crate::{get_repodata_record, get_test_data_dir};
+
+    const PATHS_JSON: &str = r#"{
+        "paths": [
+            { "_path": "bin/tool", "path_type": "hardlink", "size_in_bytes": 100,
+              "prefix_placeholder": "/opt/placeholder", "file_mode": "text" },
+            { "_path": "lib/libtool.so", "path_type": "hardlink", "size_in_bytes": 1000 },
+            { "_path": "lib/libtool.so.1", "path_type": "softlink", "size_in_bytes": 1000 },
+            { "_path": "share/data", "path_type": "hardlink", "size_in_bytes": 10,
+              "no_link": true }
+        ],
+        "paths_version": 1
+    }"#;
+
+    #[test]
+    fn test_estimate_package() {
+        let paths_json = PathsJson::from_str(PATHS_JSON).unwrap();
+
+        // Only files that are copied take up space if hard links are possible.
+        assert_eq!(
+            estimate_package(Some(&paths_json), 500, true),
+            DiskSpaceEstimate {
+                prefix: 110,
+                ..DiskSpaceEstimate::default()
+            }
+        );
+        assert_eq!(
+            estimate_package(Some(&paths_json), 500, false),
+            DiskSpaceEstimate {
+                prefix: 1110,
+                ..DiskSpaceEstimate::default()
+            }
+        );
+
+        // Packages that are not cached are estimated from their download size.
+        assert_eq!(
+            estimate_package(None, 500, true),
+            DiskSpaceEstimate {
+                cache: 2500,
+                prefix: 0,
+                cache_guessed: 2000,
+                prefix_guessed: 0,
+            }
+        );
+        assert_eq!(
+            estimate_package(None, 500, false),
+            DiskSpaceEstimate {
+                cache: 2500,
+                prefix: 2000,
+                cache_guessed: 2000,
+                prefix_guessed: 2000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_estimate_removed_packages() {
+        let cache = tempfile::tempdir().unwrap();
+        let record = get_repodata_record(
+            get_test_data_dir().join("clobber/clobber-1-0.1.0-h4616a5c_0.tar.bz2"),
+        );
+        let size = record.package_record.size.unwrap();
+        let removed = PrefixRecord::from_repodata_record(
+            record.clone(),
+            None,
+            None,
+            vec![PathsEntry {
+                relative_path: PathBuf::from("clobber.txt"),
+                original_path: None,
+                path_type: PathType::HardLink,
+                no_link: false,
+                sha256: None,
+                sha256_in_prefix: None,
+                size_in_bytes: Some(100),
+                file_mode: None,
+                prefix_placeholder: None,
+            }],
+            None,
+            None,
+        );
+
+        let estimate = estimate_disk_space([&record], [&removed], cache.path(), false);
+        assert_eq!(estimate.prefix, 4 * size - 100);
+        assert_eq!(estimate.prefix_guessed, 4 * size - 100);
+
+        // Hard linked files do not free any space.
+        let estimate = estimate_disk_space([&record], [&removed], cache.path(), true);
+        assert_eq!(estimate.prefix, 0);
+    }
+
+    #[tokio::test]
+    async fn test_check_disk_space() {
+        let cache = tempfile::tempdir().unwrap();
+        let prefix = tempfile::tempdir().unwrap();
+        let record = get_repodata_record(
+            get_test_data_dir().join("clobber/clobber-1-0.1.0-h4616a5c_0.tar.bz2"),
+        );
+        let size = record.package_record.size.unwrap();
+        let check = |available_space: fn(&Path) -> std::io::Result<u64>| {
+            check_disk_space_with(
+                vec![record.clone()],
+                Vec::new(),
+                prefix.path(),
+                cache.path(),
+                Some(true),
+                available_space,
+            )
+        };
+
+        // The package has to be downloaded and extracted into the cache.
+        let estimate = check(|_| Ok(u64::MAX)).await.unwrap();
+        assert_eq!(estimate.cache, 5 * size);
+        assert_eq!(estimate.cache_guessed, 4 * size);
+
+        // Not even the download fits.
+        assert!(matches!(
+            check(|_| Ok(0)).await,
+            Err(DiskSpaceError::InsufficientSpace { available: 0, .. })
+        ));
+
+        // Only the guessed extracted size does not fit, which is not an error.
+        assert!(check(|_| Ok(size)).await.is_ok());
+
+        // Volumes whose free space is unknown are not checked.
+        assert!(check(|_| Err(std::io::ErrorKind::Unsupported.into()))
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_available_space() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(available_space(dir.path()).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(Bytes(512).to_string(), "512 B");
+        assert_eq!(Bytes(1536).to_string(), "1.5 KiB");
+        assert_eq!(Bytes(3 * 1024 * 1024 * 1024).to_string(), "3.0 GiB");
+    }
+}
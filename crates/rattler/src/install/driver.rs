@@ -14,7 +14,9 @@ use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
 
 use super::{
     clobber_registry::{ClobberError, ClobberRegistry, ClobberedPath},
-    link_script::{PrePostLinkError, PrePostLinkResult},
+    link_script::{LinkScriptPolicy, PrePostLinkError, PrePostLinkResult},
+    menuinst::{install_menu_items, remove_menu_items, MenuInstOptions},
+    pyc::{compile_pyc_files, PycCompilationError, PycCompilationResult},
     unlink::{recursively_remove_empty_directories, UnlinkError},
     Transaction,
 };
@@ -31,6 +33,9 @@ pub struct InstallDriver {
     io_concurrency_semaphore: Option<Arc<Semaphore>>,
     clobber_registry: Arc<Mutex<ClobberRegistry>>,
     execute_link_scripts: bool,
+    link_script_policy: LinkScriptPolicy,
+    menuinst: Option<MenuInstOptions>,
+    compile_pyc: bool,
 }
 
 impl Default for InstallDriver {
@@ -48,6 +53,9 @@ pub struct InstallDriverBuilder {
     io_concurrency_semaphore: Option<Arc<Semaphore>>,
     clobber_registry: Option<ClobberRegistry>,
     execute_link_scripts: bool,
+    link_script_policy: LinkScriptPolicy,
+    menuinst: Option<MenuInstOptions>,
+    compile_pyc: bool,
 }
 
 /// The result of the post-processing step.
@@ -59,6 +67,11 @@ pub struct PostProcessResult {
 
     /// The paths that were clobbered during the installation process.
     pub clobbered_paths: HashMap<PathBuf, ClobberedPath>,
+
+    /// The result of compiling the python files of noarch python packages.
+    /// This is only present if compiling bytecode is enabled and python is
+    /// part of the environment.
+    pub pyc_compilation: Option<Result<PycCompilationResult, PycCompilationError>>,
 }
 
 /// An error that might have occurred during post-processing
@@ -113,6 +126,35 @@ impl InstallDriverBuilder {
         }
     }
 
+    /// Sets the policy that determines which link scripts are executed. This
+    /// only has an effect if executing link scripts is enabled.
+    pub fn with_link_script_policy(self, link_script_policy: LinkScriptPolicy) -> Self {
+        Self {
+            link_script_policy,
+            ..self
+        }
+    }
+
+    /// Enables the creation of menu shortcuts for packages that ship
+    /// menuinst files. Shortcuts are created during post-processing and
+    /// removed again during pre-processing when a package is removed.
+    pub fn with_menuinst(self, options: MenuInstOptions) -> Self {
+        Self {
+            menuinst: Some(options),
+            ..self
+        }
+    }
+
+    /// Sets whether the python files of installed noarch python packages
+    /// are compiled to bytecode during post-processing. The generated files
+    /// are recorded in the prefix records of the packages.
+    pub fn with_compile_pyc(self, compile_pyc: bool) -> Self {
+        Self {
+            compile_pyc,
+            ..self
+        }
+    }
+
     pub fn finish(self) -> InstallDriver {
         InstallDriver {
             io_concurrency_semaphore: self.io_concurrency_semaphore,
@@ -122,6 +164,9 @@ impl InstallDriverBuilder {
                 .map(Arc::new)
                 .unwrap_or_default(),
             execute_link_scripts: self.execute_link_scripts,
+            link_script_policy: self.link_script_policy,
+            menuinst: self.menuinst,
+            compile_pyc: self.compile_pyc,
         }
     }
 }
@@ -148,6 +193,16 @@ impl InstallDriver {
         self.clobber_registry.lock().unwrap()
     }
 
+    /// Returns true if link scripts are executed.
+    pub fn executes_link_scripts(&self) -> bool {
+        self.execute_link_scripts
+    }
+
+    /// Returns the policy that determines which link scripts are executed.
+    pub fn link_script_policy(&self) -> &LinkScriptPolicy {
+        &self.link_script_policy
+    }
+
     /// Call this before any packages are installed to perform any pre
     /// processing that is required.
     pub fn pre_process<Old: Borrow<PrefixRecord>, New>(
@@ -155,10 +210,11 @@ impl InstallDriver {
         transaction: &Transaction<Old, New>,
         target_prefix: &Path,
     ) -> Result<Option<PrePostLinkResult>, PrePostLinkError> {
+        let mut result = None;
         if self.execute_link_scripts {
             match self.run_pre_unlink_scripts(transaction, target_prefix) {
                 Ok(res) => {
-                    return Ok(Some(res));
+                    result = Some(res);
                 }
                 Err(e) => {
                     tracing::error!("Error running pre-unlink scripts: {:?}", e);
@@ -166,7 +222,22 @@ impl InstallDriver {
             }
         }
 
-        Ok(None)
+        // Remove the menu shortcuts of the packages that are removed.
+        if let Some(options) = &self.menuinst {
+            for record in transaction.removed_packages().map(Borrow::borrow) {
+                if let Err(e) =
+                    remove_menu_items(target_prefix, record, transaction.platform, options)
+                {
+                    tracing::warn!(
+                        "Failed to remove menu shortcuts of {}: {}",
+                        record.repodata_record.package_record.name.as_normalized(),
+                        e
+                    );
+                }
+            }
+        }
+
+        Ok(result)
     }
 
     /// Runs a blocking task that will execute on a seperate thread. The task is
@@ -194,8 +265,13 @@ impl InstallDriver {
     /// processing that is required.
     ///
     /// This function will select a winner among multiple packages that might
-    /// write to a single package and will also execute any
-    /// `post-link.sh/bat` scripts
+    /// write to a single package, compiles python bytecode and creates menu
+    /// shortcuts if enabled and will also execute any `post-link.sh/bat`
+    /// scripts
+    ///
+    /// The [`super::Installer`] calls this after the transaction journal was
+    /// committed, so the changes made here (including the renames of
+    /// clobbered files) are not rolled back if something fails later on.
     pub fn post_process<Old: Borrow<PrefixRecord> + AsRef<New>, New: AsRef<PackageRecord>>(
         &self,
         transaction: &Transaction<Old, New>,
@@ -216,6 +292,52 @@ impl InstallDriver {
             .clobber_registry()
             .unclobber(&required_packages, target_prefix)?;
 
+        let installed = transaction
+            .installed_packages()
+            .map(|r| &r.as_ref().name)
+            .collect::<HashSet<_>>();
+
+        // Compile the python files of the noarch python packages that were
+        // installed.
+        let pyc_compilation = match &transaction.python_info {
+            Some(python_info) if self.compile_pyc => {
+                let mut records = required_packages
+                    .iter()
+                    .filter(|r| installed.contains(&r.repodata_record.package_record.name))
+                    .map(|&r| r.clone())
+                    .collect::<Vec<_>>();
+                let result = compile_pyc_files(target_prefix, &mut records, python_info);
+                if let Err(e) = &result {
+                    tracing::warn!("Failed to compile python bytecode: {}", e);
+                }
+                Some(result)
+            }
+            _ => None,
+        };
+
+        // Create the menu shortcuts of the packages that were installed.
+        if let Some(options) = &self.menuinst {
+            for record in required_packages
+                .iter()
+                .filter(|r| installed.contains(&r.repodata_record.package_record.name))
+            {
+                if let Err(e) = install_menu_items(
+                    target_prefix,
+                    record,
+                    transaction.platform,
+                    options,
+                    self.execute_link_scripts
+                        .then_some(&self.link_script_policy),
+                ) {
+                    tracing::warn!(
+                        "Failed to create menu shortcuts of {}: {}",
+                        record.repodata_record.package_record.name.as_normalized(),
+                        e
+                    );
+                }
+            }
+        }
+
         let post_link_result = if self.execute_link_scripts {
             Some(self.run_post_link_scripts(transaction, &required_packages, target_prefix))
         } else {
@@ -225,6 +347,7 @@ impl InstallDriver {
         Ok(PostProcessResult {
             post_link_result,
             clobbered_paths,
+            pyc_compilation,
         })
     }
 
@@ -0,0 +1,648 @@
+//! Reading and writing of the `conda-meta/history` file.
+//!
+//! Every time the packages in a prefix are modified conda appends a revision
+//! to `conda-meta/history`. A revision looks like this:
+//!
+//! ```text
+//! ==> 2024-05-21 14:03:11 <==
+//! # cmd: rattler create numpy
+//! # user agent: rattler/0.27.4
+//! -conda-forge/linux-64::numpy-1.26.3-py312h8753938_0
+//! +conda-forge/linux-64::numpy-1.26.4-py312heda63a1_0
+//! # update specs: ['numpy']
+//! ```
+//!
+//! The [`super::Installer`] appends such a revision for every transaction it
+//! executes. [`History`] parses the file back into revisions, which can be
+//! used to reconstruct the state of the prefix at every revision and to
+//! compute the [`Transaction`] required to go back to it.
+
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter},
+    io::{ErrorKind, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use chrono::NaiveDateTime;
+use indexmap::IndexSet;
+use rattler_conda_types::{MatchSpec, Platform, PrefixRecord, RepoDataRecord};
+
+use super::{Transaction, TransactionError};
+
+/// The format of the timestamp in the header of a revision.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// The channel prefix that conda strips from channel urls to get the name of
+/// a channel.
+const DEFAULT_CHANNEL_ALIAS: &str = "https://conda.anaconda.org/";
+
+/// An error that can occur when reading the history of a prefix or when
+/// restoring a revision.
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    /// Failed to read the history file.
+    #[error("failed to read the history file")]
+    IoError(#[from] std::io::Error),
+
+    /// The header of a revision could not be parsed.
+    #[error("invalid revision header on line {0}: '{1}'")]
+    InvalidHeader(usize, String),
+
+    /// A package line could not be parsed.
+    #[error("invalid package on line {0}: '{1}'")]
+    InvalidPackage(usize, String),
+
+    /// The requested revision does not exist.
+    #[error("revision {0} does not exist, the history contains {1} revisions")]
+    RevisionNotFound(usize, usize),
+
+    /// A package that is part of the requested revision is not installed and
+    /// is not available either.
+    #[error("the package '{0}' is required to restore the revision but it is not available")]
+    MissingPackage(String),
+
+    /// Failed to construct the transaction to restore a revision.
+    #[error(transparent)]
+    TransactionError(#[from] TransactionError),
+}
+
+/// A package as it is recorded in the history file, e.g.
+/// `conda-forge/linux-64::numpy-1.26.4-py312heda63a1_0`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct HistoryPackage {
+    /// The name of the channel the package came from, if known.
+    pub channel: Option<String>,
+
+    /// The subdirectory of the channel, if known.
+    pub subdir: Option<String>,
+
+    /// The name of the package.
+    pub name: String,
+
+    /// The version of the package.
+    pub version: String,
+
+    /// The build string of the package.
+    pub build: String,
+}
+
+impl HistoryPackage {
+    /// Constructs the history entry of a record.
+    pub fn from_record(record: &RepoDataRecord) -> Self {
+        let channel = record.channel.trim_end_matches('/');
+        let channel = channel
+            .strip_prefix(DEFAULT_CHANNEL_ALIAS.trim_end_matches('/'))
+            .map_or(channel, |name| name.trim_start_matches('/'));
+        Self {
+            channel: (!channel.is_empty()).then(|| channel.to_string()),
+            subdir: Some(record.package_record.subdir.clone()),
+            name: record.package_record.name.as_normalized().to_string(),
+            version: record.package_record.version.to_string(),
+            build: record.package_record.build.clone(),
+        }
+    }
+
+    /// Returns the `name-version-build` string of the package.
+    pub fn dist_name(&self) -> String {
+        format!("{}-{}-{}", self.name, self.version, self.build)
+    }
+
+    /// Returns true if this entry describes the given record. The channel and
+    /// subdir are only compared if they are known.
+    fn matches(&self, record: &RepoDataRecord, compare_channel: bool) -> bool {
+        let other = Self::from_record(record);
+        self.name == other.name
+            && self.version == other.version
+            && self.build == other.build
+            && (self.subdir.is_none() || self.subdir == other.subdir)
+            && (!compare_channel || self.channel.is_none() || self.channel == other.channel)
+    }
+}
+
+impl FromStr for HistoryPackage {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, subdir, dist) = match s.rsplit_once("::") {
+            Some((channel, dist)) => match channel.rsplit_once('/') {
+                Some((name, subdir)) if Platform::from_str(subdir).is_ok() => {
+                    (Some(name), Some(subdir), dist)
+                }
+                _ => (Some(channel), None, dist),
+            },
+            None => (None, None, s),
+        };
+
+        let mut parts = dist.rsplitn(3, '-');
+        let (Some(build), Some(version), Some(name)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(());
+        };
+        if name.is_empty() || version.is_empty() || build.is_empty() {
+            return Err(());
+        }
+
+        Ok(Self {
+            channel: channel.filter(|c| !c.is_empty()).map(ToString::to_string),
+            subdir: subdir.map(ToString::to_string),
+            name: name.to_string(),
+            version: version.to_string(),
+            build: build.to_string(),
+        })
+    }
+}
+
+impl Display for HistoryPackage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.channel, &self.subdir) {
+            (Some(channel), Some(subdir)) => write!(f, "{channel}/{subdir}::")?,
+            (Some(channel), None) => write!(f, "{channel}::")?,
+            (None, _) => {}
+        }
+        write!(f, "{}", self.dist_name())
+    }
+}
+
+/// A single revision of the history of a prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Revision {
+    /// The moment the revision was recorded, in local time.
+    pub timestamp: Option<NaiveDateTime>,
+
+    /// The command that created the revision.
+    pub command: Option<String>,
+
+    /// The tool and version that created the revision, e.g. `rattler/0.27.4`.
+    pub user_agent: Option<String>,
+
+    /// The specs that were requested to be installed or updated.
+    pub update_specs: Vec<String>,
+
+    /// The specs that were requested to be removed.
+    pub remove_specs: Vec<String>,
+
+    /// The packages that were added to the prefix.
+    pub added: Vec<HistoryPackage>,
+
+    /// The packages that were removed from the prefix.
+    pub removed: Vec<HistoryPackage>,
+
+    /// True if the revision lists the complete state of the prefix instead of
+    /// the changes with respect to the previous revision. Old versions of
+    /// conda wrote the first revision like this.
+    pub is_snapshot: bool,
+}
+
+impl Revision {
+    /// Constructs a revision from the changes made by a transaction.
+    /// Reinstalled packages are not recorded.
+    pub fn from_transaction(
+        transaction: &Transaction<PrefixRecord, RepoDataRecord>,
+        command: Option<String>,
+        requested_specs: &[MatchSpec],
+    ) -> Self {
+        let removed = transaction
+            .removed_packages()
+            .map(|record| HistoryPackage::from_record(&record.repodata_record))
+            .collect::<IndexSet<_>>();
+        let added = transaction
+            .installed_packages()
+            .map(HistoryPackage::from_record)
+            .collect::<IndexSet<_>>();
+
+        Self {
+            timestamp: Some(chrono::Local::now().naive_local()),
+            command,
+            user_agent: Some(format!("rattler/{}", env!("CARGO_PKG_VERSION"))),
+            update_specs: requested_specs.iter().map(ToString::to_string).collect(),
+            remove_specs: Vec::new(),
+            added: added.difference(&removed).cloned().collect(),
+            removed: removed.difference(&added).cloned().collect(),
+            is_snapshot: false,
+        }
+    }
+
+    /// Returns true if the revision does not change any packages.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Display for Revision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(timestamp) = &self.timestamp {
+            writeln!(f, "==> {} <==", timestamp.format(TIMESTAMP_FORMAT))?;
+        }
+        if let Some(command) = &self.command {
+            writeln!(f, "# cmd: {command}")?;
+        }
+        if let Some(user_agent) = &self.user_agent {
+            writeln!(f, "# user agent: {user_agent}")?;
+        }
+        for package in &self.removed {
+            writeln!(f, "-{package}")?;
+        }
+        for package in &self.added {
+            if self.is_snapshot {
+                writeln!(f, "{package}")?;
+            } else {
+                writeln!(f, "+{package}")?;
+            }
+        }
+        if !self.update_specs.is_empty() {
+            writeln!(
+                f,
+                "# update specs: {}",
+                format_spec_list(&self.update_specs)
+            )?;
+        }
+        if !self.remove_specs.is_empty() {
+            writeln!(
+                f,
+                "# remove specs: {}",
+                format_spec_list(&self.remove_specs)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The history of a prefix as recorded in `conda-meta/history`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct History {
+    /// The revisions in the order in which they were applied.
+    pub revisions: Vec<Revision>,
+}
+
+impl History {
+    /// Returns the path of the history file of a prefix.
+    pub fn path(prefix: &Path) -> std::path::PathBuf {
+        prefix.join("conda-meta").join("history")
+    }
+
+    /// Reads the history of a prefix. Returns an empty history if the prefix
+    /// has no history file.
+    pub fn from_prefix(prefix: &Path) -> Result<Self, HistoryError> {
+        match fs_err::read_to_string(Self::path(prefix)) {
+            Ok(contents) => contents.parse(),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(HistoryError::IoError(e)),
+        }
+    }
+
+    /// Appends a revision to the history file of a prefix.
+    pub fn append_to_prefix(prefix: &Path, revision: &Revision) -> std::io::Result<()> {
+        let path = Self::path(prefix);
+        if let Some(parent) = path.parent() {
+            fs_err::create_dir_all(parent)?;
+        }
+        let mut file = fs_err::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        write!(file, "{revision}")?;
+        file.flush()
+    }
+
+    /// Returns the packages that were installed in the prefix after the
+    /// revision with the given index was applied.
+    pub fn revision_state(&self, revision: usize) -> Result<Vec<HistoryPackage>, HistoryError> {
+        if revision >= self.revisions.len() {
+            return Err(HistoryError::RevisionNotFound(
+                revision,
+                self.revisions.len(),
+            ));
+        }
+
+        let mut state = IndexSet::new();
+        for revision in &self.revisions[..=revision] {
+            if revision.is_snapshot {
+                state.clear();
+            }
+            for package in &revision.removed {
+                state.shift_remove(package);
+            }
+            state.extend(revision.added.iter().cloned());
+        }
+
+        Ok(state.into_iter().collect())
+    }
+
+    /// Computes the transaction that brings the prefix from its `installed`
+    /// state back to the state after the revision with the given index.
+    ///
+    /// Packages that are still installed are reused, all other packages are
+    /// looked up in `available`, e.g. the records from a repodata query or a
+    /// lock file.
+    pub fn restore_transaction(
+        &self,
+        revision: usize,
+        installed: Vec<PrefixRecord>,
+        available: impl IntoIterator<Item = RepoDataRecord>,
+        platform: Platform,
+    ) -> Result<Transaction<PrefixRecord, RepoDataRecord>, HistoryError> {
+        let state = self.revision_state(revision)?;
+        let available = available.into_iter().collect::<Vec<_>>();
+        let candidates = installed
+            .iter()
+            .map(|record| &record.repodata_record)
+            .chain(available.iter())
+            .collect::<Vec<_>>();
+
+        let mut seen = HashSet::new();
+        let desired = state
+            .iter()
+            .map(|package| {
+                candidates
+                    .iter()
+                    .find(|record| package.matches(record, true))
+                    .or_else(|| {
+                        candidates
+                            .iter()
+                            .find(|record| package.matches(record, false))
+                    })
+                    .map(|&record| record.clone())
+                    .ok_or_else(|| HistoryError::MissingPackage(package.to_string()))
+            })
+            .filter(|record| {
+                record.as_ref().map_or(true, |record| {
+                    seen.insert(record.package_record.name.clone())
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Transaction::from_current_and_desired(
+            installed, desired, platform,
+        )?)
+    }
+}
+
+impl FromStr for History {
+    type Err = HistoryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut revisions = Vec::new();
+        let mut current: Option<Revision> = None;
+
+        for (idx, line) in s.lines().enumerate() {
+            let line_number = idx + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix("==>") {
+                let timestamp = header
+                    .strip_suffix("<==")
+                    .and_then(|ts| NaiveDateTime::parse_from_str(ts.trim(), TIMESTAMP_FORMAT).ok())
+                    .ok_or_else(|| HistoryError::InvalidHeader(line_number, line.to_string()))?;
+                revisions.extend(current.take());
+                current = Some(Revision {
+                    timestamp: Some(timestamp),
+                    ..Revision::default()
+                });
+                continue;
+            }
+
+            // Some files contain lines before the first header.
+            let revision = current.get_or_insert_with(Revision::default);
+            if let Some(comment) = line.strip_prefix('#') {
+                parse_comment(revision, comment.trim());
+            } else if let Some(package) = line.strip_prefix('+') {
+                revision.added.push(parse_package(line_number, package)?);
+            } else if let Some(package) = line.strip_prefix('-') {
+                revision.removed.push(parse_package(line_number, package)?);
+            } else {
+                revision.is_snapshot = true;
+                revision.added.push(parse_package(line_number, line)?);
+            }
+        }
+        revisions.extend(current);
+
+        Ok(Self { revisions })
+    }
+}
+
+fn parse_package(line_number: usize, package: &str) -> Result<HistoryPackage, HistoryError> {
+    package
+        .trim()
+        .parse()
+        .map_err(|_err| HistoryError::InvalidPackage(line_number, package.to_string()))
+}
+
+/// Parses the comment lines that conda understands. Other comments are
+/// ignored.
+fn parse_comment(revision: &mut Revision, comment: &str) {
+    let Some((key, value)) = comment.split_once(':') else {
+        return;
+    };
+    let value = value.trim();
+    match key.trim() {
+        "cmd" => revision.command = Some(value.to_string()),
+        "user agent" => revision.user_agent = Some(value.to_string()),
+        "conda version" => revision.user_agent = Some(format!("conda/{value}")),
+        "update specs" => revision.update_specs.extend(parse_spec_list(value)),
+        "remove specs" => revision.remove_specs.extend(parse_spec_list(value)),
+        _ => {}
+    }
+}
+
+/// Formats a list of specs the way python formats a list of strings.
+fn format_spec_list(specs: &[String]) -> String {
+    let specs = specs
+        .iter()
+        .map(|spec| format!("'{spec}'"))
+        .collect::<Vec<_>>();
+    format!("[{}]", specs.join(", "))
+}
+
+/// Parses a python formatted list of strings, e.g. `['numpy', "python >=3.8,<4"]`.
+fn parse_spec_list(value: &str) -> Vec<String> {
+    let mut specs = Vec::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\'' || c == '"' {
+            let spec = chars.by_ref().take_while(|&d| d != c).collect::<String>();
+            specs.push(spec);
+        }
+    }
+    specs
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use rattler_conda_types::{PackageName, PackageRecord, Platform, RepoDataRecord, Version};
+
+    use super::{History, HistoryError, HistoryPackage, Revision};
+    use crate::install::TransactionOperation;
+
+    const HISTORY: &str = r#"==> 2023-01-10 10:21:08 <==
+# cmd: /opt/conda/bin/conda create -n test python
+# conda version: 22.11.1
++conda-forge/linux-64::python-3.11.0-he550d4f_1
++conda-forge/noarch::tzdata-2022g-h191b570_0
+# update specs: ['python']
+==> 2023-01-11 09:00:00 <==
+# cmd: rattler install numpy
+# user agent: rattler/0.27.4
+-conda-forge/noarch::tzdata-2022g-h191b570_0
++conda-forge/noarch::tzdata-2023c-h71feb2d_0
++conda-forge/linux-64::numpy-1.24.1-py311h8e6699e_0
+# update specs: ['numpy >=1.24,<2', "tzdata"]
+"#;
+
+    fn record(name: &str, version: &str, build: &str, subdir: &str) -> RepoDataRecord {
+        let mut package_record = PackageRecord::new(
+            PackageName::new_unchecked(name),
+            Version::from_str(version).unwrap(),
+            build.to_string(),
+        );
+        package_record.subdir = subdir.to_string();
+        RepoDataRecord {
+            file_name: format!("{name}-{version}-{build}.conda"),
+            url: format!(
+                "https://conda.anaconda.org/conda-forge/{subdir}/{name}-{version}-{build}.conda"
+            )
+            .parse()
+            .unwrap(),
+            channel: String::from("https://conda.anaconda.org/conda-forge/"),
+            package_record,
+        }
+    }
+
+    #[test]
+    fn test_parse_history() {
+        let history = History::from_str(HISTORY).unwrap();
+        assert_eq!(history.revisions.len(), 2);
+
+        let first = &history.revisions[0];
+        assert_eq!(first.user_agent.as_deref(), Some("conda/22.11.1"));
+        assert_eq!(first.added.len(), 2);
+        assert_eq!(first.update_specs, vec!["python"]);
+
+        let second = &history.revisions[1];
+        assert_eq!(second.command.as_deref(), Some("rattler install numpy"));
+        assert_eq!(second.removed[0].version, "2022g");
+        assert_eq!(second.update_specs, vec!["numpy >=1.24,<2", "tzdata"]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let history = History::from_str(HISTORY).unwrap();
+        let written = history
+            .revisions
+            .iter()
+            .map(ToString::to_string)
+            .collect::<String>();
+        assert_eq!(History::from_str(&written).unwrap(), history);
+    }
+
+    #[test]
+    fn test_package_format() {
+        let package =
+            HistoryPackage::from_record(&record("numpy", "1.24.1", "py311h8e6699e_0", "linux-64"));
+        assert_eq!(
+            package.to_string(),
+            "conda-forge/linux-64::numpy-1.24.1-py311h8e6699e_0"
+        );
+        assert_eq!(package.to_string().parse::<HistoryPackage>(), Ok(package));
+
+        let package = "defaults::foo-bar-1.0-0".parse::<HistoryPackage>().unwrap();
+        assert_eq!(package.channel.as_deref(), Some("defaults"));
+        assert_eq!(package.subdir, None);
+        assert_eq!(package.name, "foo-bar");
+    }
+
+    #[test]
+    fn test_revision_state() {
+        let history = History::from_str(HISTORY).unwrap();
+        let names = |revision| {
+            history
+                .revision_state(revision)
+                .unwrap()
+                .iter()
+                .map(HistoryPackage::dist_name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(0),
+            vec!["python-3.11.0-he550d4f_1", "tzdata-2022g-h191b570_0"]
+        );
+        assert_eq!(
+            names(1),
+            vec![
+                "python-3.11.0-he550d4f_1",
+                "tzdata-2023c-h71feb2d_0",
+                "numpy-1.24.1-py311h8e6699e_0"
+            ]
+        );
+        assert!(matches!(
+            history.revision_state(2),
+            Err(HistoryError::RevisionNotFound(2, 2))
+        ));
+    }
+
+    #[test]
+    fn test_restore_transaction() {
+        let history = History::from_str(HISTORY).unwrap();
+        let installed = [
+            record("python", "3.11.0", "he550d4f_1", "linux-64"),
+            record("tzdata", "2023c", "h71feb2d_0", "noarch"),
+            record("numpy", "1.24.1", "py311h8e6699e_0", "linux-64"),
+        ]
+        .into_iter()
+        .map(|record| {
+            rattler_conda_types::PrefixRecord::from_repodata_record(
+                record,
+                None,
+                None,
+                Vec::new(),
+                None,
+                None,
+            )
+        })
+        .collect::<Vec<_>>();
+
+        // The old version of tzdata is not installed so it needs to be
+        // available.
+        assert!(matches!(
+            history.restore_transaction(0, installed.clone(), Vec::new(), Platform::Linux64),
+            Err(HistoryError::MissingPackage(_))
+        ));
+
+        let transaction = history
+            .restore_transaction(
+                0,
+                installed,
+                vec![record("tzdata", "2022g", "h191b570_0", "noarch")],
+                Platform::Linux64,
+            )
+            .unwrap();
+
+        let revision = Revision::from_transaction(&transaction, None, &[]);
+        assert_eq!(
+            revision
+                .removed
+                .iter()
+                .map(HistoryPackage::dist_name)
+                .collect::<Vec<_>>(),
+            vec!["numpy-1.24.1-py311h8e6699e_0", "tzdata-2023c-h71feb2d_0"]
+        );
+        assert_eq!(
+            revision
+                .added
+                .iter()
+                .map(HistoryPackage::dist_name)
+                .collect::<Vec<_>>(),
+            vec!["tzdata-2022g-h191b570_0"]
+        );
+        assert!(transaction
+            .operations
+            .iter()
+            .all(|op| !matches!(op, TransactionOperation::Install(_))));
+    }
+}
@@ -2,8 +2,11 @@ use simple_spawn_blocking::Cancelled;
 
 use crate::{
     install::{
-        clobber_registry::ClobberError, driver::PostProcessingError, link_script::PrePostLinkError,
-        unlink::UnlinkError, InstallError, TransactionError,
+        clobber_registry::ClobberError,
+        driver::PostProcessingError,
+        link_script::{LinkScriptError, PrePostLinkError},
+        unlink::UnlinkError,
+        DiskSpaceError, InstallError, JournalError, TransactionError,
     },
     package_cache::PackageCacheError,
 };
@@ -39,10 +42,22 @@ pub enum InstallerError {
     #[error("pre-processing failed")]
     PreProcessingFailed(#[source] PrePostLinkError),
 
+    /// Failed to run a pre-link script
+    #[error("failed to run the pre-link script of {0}")]
+    PreLinkScriptFailed(String, #[source] LinkScriptError),
+
     /// Failed to run a post-link script
     #[error("post-processing failed")]
     PostProcessingFailed(#[source] PrePostLinkError),
 
+    /// Failed to journal or roll back the transaction
+    #[error("failed to journal the transaction")]
+    JournalError(#[from] JournalError),
+
+    /// There is not enough disk space to execute the transaction
+    #[error("insufficient disk space")]
+    InsufficientDiskSpace(#[from] DiskSpaceError),
+
     /// A clobbering error occured
     #[error("failed to unclobber clobbered files")]
     ClobberError(#[from] ClobberError),
@@ -4,7 +4,7 @@ mod indicatif;
 mod reporter;
 use std::{
     collections::HashMap,
-    future::ready,
+    future::{ready, Future},
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -18,7 +18,7 @@ pub use indicatif::{
 };
 use rattler_conda_types::{
     prefix_record::{Link, LinkType},
-    Platform, PrefixRecord, RepoDataRecord,
+    MatchSpec, PackageRecord, Platform, PrefixRecord, RepoDataRecord,
 };
 use rattler_networking::retry_policies::default_retry_policy;
 pub use reporter::Reporter;
@@ -26,8 +26,12 @@ use reqwest::Client;
 use simple_spawn_blocking::tokio::run_blocking_task;
 use tokio::{sync::Semaphore, task::JoinError};
 
-use super::{unlink_package, AppleCodeSignBehavior, InstallDriver, InstallOptions, Transaction};
-use crate::install::link_script::LinkScriptError;
+use super::{
+    check_disk_space, link_package_journaled, unlink_package, AppleCodeSignBehavior, History,
+    InstallDriver, InstallOptions, MenuInstOptions, PycCompilationError, PycCompilationResult,
+    Revision, Transaction, TransactionJournal,
+};
+use crate::install::link_script::{LinkScriptError, LinkScriptPolicy, LinkScriptType};
 use crate::{
     default_cache_dir,
     install::{clobber_registry::ClobberedPath, link_script::PrePostLinkResult},
@@ -41,11 +45,17 @@ pub struct Installer {
     package_cache: Option<PackageCache>,
     downloader: Option<reqwest_middleware::ClientWithMiddleware>,
     execute_link_scripts: bool,
+    link_script_policy: LinkScriptPolicy,
+    menuinst: Option<MenuInstOptions>,
+    compile_pyc: bool,
+    skip_disk_space_check: bool,
     io_semaphore: Option<Arc<Semaphore>>,
     reporter: Option<Arc<dyn Reporter>>,
     target_platform: Option<Platform>,
     apple_code_sign_behavior: AppleCodeSignBehavior,
     alternative_target_prefix: Option<PathBuf>,
+    requested_specs: Vec<MatchSpec>,
+    history_command: Option<String>,
     // TODO: Determine upfront if these are possible.
     // allow_symbolic_links: Option<bool>,
     // allow_hard_links: Option<bool>,
@@ -57,7 +67,7 @@ pub struct InstallationResult {
     /// The transaction that was applied
     pub transaction: Transaction<PrefixRecord, RepoDataRecord>,
 
-    /// The result of running pre link scripts. `None` if no
+    /// The result of running pre-unlink and pre-link scripts. `None` if no
     /// pre-processing was performed, possibly because link scripts were
     /// disabled.
     pub pre_link_script_result: Option<PrePostLinkResult>,
@@ -69,6 +79,11 @@ pub struct InstallationResult {
 
     /// The paths that were clobbered during the installation process.
     pub clobbered_paths: HashMap<PathBuf, ClobberedPath>,
+
+    /// The result of compiling the python files of noarch python packages.
+    /// `None` if compiling bytecode was disabled or the environment does not
+    /// contain python.
+    pub pyc_compilation: Option<Result<PycCompilationResult, PycCompilationError>>,
 }
 
 impl Installer {
@@ -140,6 +155,89 @@ impl Installer {
         self
     }
 
+    /// Sets the policy that determines which link scripts are executed, e.g.
+    /// only the scripts of an allow-list of packages. This only has an effect
+    /// if link scripts are executed (see [`Self::with_execute_link_scripts`]).
+    #[must_use]
+    pub fn with_link_script_policy(self, policy: LinkScriptPolicy) -> Self {
+        Self {
+            link_script_policy: policy,
+            ..self
+        }
+    }
+
+    /// Sets the policy that determines which link scripts are executed.
+    ///
+    /// This function is similar to [`Self::with_link_script_policy`], but
+    /// modifies an existing instance.
+    pub fn set_link_script_policy(&mut self, policy: LinkScriptPolicy) -> &mut Self {
+        self.link_script_policy = policy;
+        self
+    }
+
+    /// Enables the creation of menu shortcuts for packages that ship
+    /// menuinst files (`Menu/*.json`). By default no shortcuts are created.
+    #[must_use]
+    pub fn with_menuinst(self, options: MenuInstOptions) -> Self {
+        Self {
+            menuinst: Some(options),
+            ..self
+        }
+    }
+
+    /// Enables the creation of menu shortcuts for packages that ship
+    /// menuinst files (`Menu/*.json`).
+    ///
+    /// This function is similar to [`Self::with_menuinst`], but modifies an
+    /// existing instance.
+    pub fn set_menuinst(&mut self, options: MenuInstOptions) -> &mut Self {
+        self.menuinst = Some(options);
+        self
+    }
+
+    /// Sets whether the python files of noarch python packages are compiled
+    /// to bytecode (`__pycache__/*.pyc`) after they have been installed. By
+    /// default no bytecode is generated.
+    #[must_use]
+    pub fn with_compile_pyc(self, compile_pyc: bool) -> Self {
+        Self {
+            compile_pyc,
+            ..self
+        }
+    }
+
+    /// Sets whether the python files of noarch python packages are compiled
+    /// to bytecode after they have been installed.
+    ///
+    /// This function is similar to [`Self::with_compile_pyc`], but modifies
+    /// an existing instance.
+    pub fn set_compile_pyc(&mut self, compile_pyc: bool) -> &mut Self {
+        self.compile_pyc = compile_pyc;
+        self
+    }
+
+    /// Sets whether to skip checking that there is enough disk space available
+    /// before the transaction is executed. By default the installation fails
+    /// early if the package cache or prefix volume does not have enough free
+    /// space.
+    #[must_use]
+    pub fn with_skip_disk_space_check(self, skip: bool) -> Self {
+        Self {
+            skip_disk_space_check: skip,
+            ..self
+        }
+    }
+
+    /// Sets whether to skip checking that there is enough disk space available
+    /// before the transaction is executed.
+    ///
+    /// This function is similar to [`Self::with_skip_disk_space_check`], but
+    /// modifies an existing instance.
+    pub fn set_skip_disk_space_check(&mut self, skip: bool) -> &mut Self {
+        self.skip_disk_space_check = skip;
+        self
+    }
+
     /// Sets the package cache to use.
     #[must_use]
     pub fn with_package_cache(self, package_cache: PackageCache) -> Self {
@@ -264,7 +362,59 @@ impl Installer {
         self
     }
 
+    /// Sets the specs that were requested by the user. These are recorded in
+    /// the `conda-meta/history` file of the prefix and in the `requested_spec`
+    /// of the [`PrefixRecord`] of the matching packages.
+    #[must_use]
+    pub fn with_requested_specs(self, specs: impl IntoIterator<Item = MatchSpec>) -> Self {
+        Self {
+            requested_specs: specs.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Sets the specs that were requested by the user. These are recorded in
+    /// the `conda-meta/history` file of the prefix and in the `requested_spec`
+    /// of the [`PrefixRecord`] of the matching packages.
+    ///
+    /// This function is similar to [`Self::with_requested_specs`], but
+    /// modifies an existing instance.
+    pub fn set_requested_specs(&mut self, specs: impl IntoIterator<Item = MatchSpec>) -> &mut Self {
+        self.requested_specs = specs.into_iter().collect();
+        self
+    }
+
+    /// Sets the command that is recorded in the `conda-meta/history` file of
+    /// the prefix. Defaults to the arguments of the current process.
+    #[must_use]
+    pub fn with_history_command(self, command: impl Into<String>) -> Self {
+        Self {
+            history_command: Some(command.into()),
+            ..self
+        }
+    }
+
+    /// Sets the command that is recorded in the `conda-meta/history` file of
+    /// the prefix. Defaults to the arguments of the current process.
+    ///
+    /// This function is similar to [`Self::with_history_command`], but
+    /// modifies an existing instance.
+    pub fn set_history_command(&mut self, command: impl Into<String>) -> &mut Self {
+        self.history_command = Some(command.into());
+        self
+    }
+
     /// Install the packages in the given prefix.
+    ///
+    /// After a successful installation a revision describing the changes is
+    /// appended to `conda-meta/history` (see [`History`]).
+    ///
+    /// The changes to the prefix are journaled (see [`TransactionJournal`]).
+    /// If unlinking or linking a package fails the prefix is restored to its
+    /// original state. A transaction that was interrupted before, e.g.
+    /// because the returned future was dropped or the process was killed, is
+    /// rolled back before the installation starts. Post-processing runs after
+    /// the transaction is committed and is not rolled back.
     pub async fn install(
         self,
         prefix: impl AsRef<Path>,
@@ -281,6 +431,13 @@ impl Installer {
             )
         });
 
+        // Roll back any transaction that was interrupted before.
+        let recover_prefix = prefix.as_ref().to_path_buf();
+        run_blocking_task(move || {
+            TransactionJournal::recover(&recover_prefix).map_err(InstallerError::JournalError)
+        })
+        .await?;
+
         // Create a future to determine the currently installed packages. We
         // can start this in parallel with the other operations and resolve it
         // when we need it.
@@ -297,13 +454,18 @@ impl Installer {
         };
 
         // Construct a driver.
-        let driver = InstallDriver::builder()
+        let mut driver = InstallDriver::builder()
             .execute_link_scripts(self.execute_link_scripts)
+            .with_link_script_policy(self.link_script_policy)
+            .with_compile_pyc(self.compile_pyc)
             .with_io_concurrency_semaphore(
                 self.io_semaphore.unwrap_or(Arc::new(Semaphore::new(100))),
             )
-            .with_prefix_records(&installed)
-            .finish();
+            .with_prefix_records(&installed);
+        if let Some(menuinst) = self.menuinst {
+            driver = driver.with_menuinst(menuinst);
+        }
+        let driver = driver.finish();
 
         // Construct a transaction from the current and desired situation.
         let target_platform = self.target_platform.unwrap_or_else(Platform::current);
@@ -320,6 +482,7 @@ impl Installer {
                 pre_link_script_result: None,
                 post_link_script_result: None,
                 clobbered_paths: HashMap::default(),
+                pyc_compilation: None,
             });
         }
 
@@ -332,6 +495,18 @@ impl Installer {
             ..InstallOptions::default()
         };
 
+        // Make sure there is enough disk space before touching the prefix.
+        if !self.skip_disk_space_check {
+            check_disk_space(
+                transaction.installed_packages().cloned().collect(),
+                transaction.removed_packages().cloned().collect(),
+                prefix.as_ref(),
+                &package_cache.path(),
+                base_install_options.allow_hard_links,
+            )
+            .await?;
+        }
+
         if let Some(reporter) = &self.reporter {
             reporter.on_transaction_start(&transaction);
         }
@@ -341,6 +516,66 @@ impl Installer {
             .pre_process(&transaction, prefix.as_ref())
             .map_err(InstallerError::PreProcessingFailed)?;
 
+        // The pre-link scripts are executed from the extracted packages, one
+        // at a time and in topological order, before any package is linked.
+        // Whether a package has a pre-link script is only known once it has
+        // been extracted, so all packages are fetched first. The packages with
+        // a pre-link script are linked from the directory the script ran in,
+        // all others take the regular fetch path below.
+        let mut prefetched_packages = HashMap::new();
+        let mut pre_link_outputs = Vec::new();
+        if driver.executes_link_scripts() {
+            let records = transaction
+                .operations
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, operation)| Some((idx, operation.record_to_install()?)))
+                .collect::<Vec<_>>();
+            let cache_paths = futures::future::try_join_all(records.iter().map(|(idx, record)| {
+                fetch_package(
+                    *idx,
+                    (*record).clone(),
+                    downloader.clone(),
+                    package_cache.clone(),
+                    self.reporter.clone(),
+                )
+            }))
+            .await?;
+
+            let platform = base_install_options
+                .platform
+                .unwrap_or_else(Platform::current);
+            let mut package_dirs = HashMap::new();
+            let mut records_with_script = Vec::new();
+            for ((idx, record), (cache_path, _)) in records.into_iter().zip(cache_paths) {
+                let script = cache_path
+                    .join(LinkScriptType::PreLink.get_path(&record.package_record, &platform));
+                if script.is_file() {
+                    package_dirs.insert(&record.package_record.name, cache_path.clone());
+                    prefetched_packages.insert(idx, cache_path);
+                    records_with_script.push(record);
+                }
+            }
+
+            for record in PackageRecord::sort_topologically(records_with_script) {
+                let package_dir = &package_dirs[&record.package_record.name];
+                let output = driver
+                    .run_pre_link_script(
+                        &record.package_record,
+                        package_dir,
+                        prefix.as_ref(),
+                        &platform,
+                    )
+                    .map_err(|e| {
+                        InstallerError::PreLinkScriptFailed(record.file_name.clone(), e)
+                    })?;
+                pre_link_outputs.extend(output);
+            }
+        }
+
+        // Start journaling the changes to the prefix.
+        let journal = TransactionJournal::begin(prefix.as_ref())?;
+
         // Execute the operations in the transaction.
         let mut pending_futures = FuturesUnordered::new();
         for (idx, operation) in transaction.operations.iter().enumerate() {
@@ -350,44 +585,34 @@ impl Installer {
             let base_install_options = &base_install_options;
             let driver = &driver;
             let prefix = &prefix;
+            let journal = &journal;
+            let requested_specs = &self.requested_specs;
+            let prefetched_packages = &prefetched_packages;
             let operation_future = async move {
                 if let Some(reporter) = &reporter {
                     reporter.on_transaction_operation_start(idx);
                 }
 
                 // Start populating the cache with the package if it's not already there.
-                let package_to_install = if let Some(record) = operation.record_to_install() {
-                    let record = record.clone();
-                    let downloader = downloader.clone();
-                    let reporter = reporter.clone();
-                    let package_cache = package_cache.clone();
-                    tokio::spawn(async move {
-                        let populate_cache_report = reporter.clone().map(|r| {
-                            let cache_index = r.on_populate_cache_start(idx, &record);
-                            (r, cache_index)
-                        });
-                        let cache_path = populate_cache(
-                            &record,
-                            downloader,
-                            &package_cache,
-                            populate_cache_report.clone(),
-                        )
-                        .await?;
-                        if let Some((reporter, index)) = populate_cache_report {
-                            reporter.on_populate_cache_complete(index);
+                let package_to_install = match operation.record_to_install() {
+                    Some(record) => match prefetched_packages.get(&idx) {
+                        Some(cache_path) => {
+                            ready(Ok(Some((cache_path.clone(), record.clone())))).left_future()
                         }
-                        Ok((cache_path, record))
-                    })
-                    .map_err(JoinError::try_into_panic)
-                    .map(|res| match res {
-                        Ok(Ok(result)) => Ok(Some(result)),
-                        Ok(Err(e)) => Err(e),
-                        Err(Ok(payload)) => std::panic::resume_unwind(payload),
-                        Err(Err(_err)) => Err(InstallerError::Cancelled),
-                    })
-                    .left_future()
-                } else {
-                    ready(Ok(None)).right_future()
+                        // Packages that were prefetched have already been
+                        // reported.
+                        None => fetch_package(
+                            idx,
+                            record.clone(),
+                            downloader.clone(),
+                            package_cache.clone(),
+                            reporter.clone().filter(|_| !driver.executes_link_scripts()),
+                        )
+                        .map_ok(Some)
+                        .right_future(),
+                    }
+                    .left_future(),
+                    None => ready(Ok(None)).right_future(),
                 };
 
                 // Uninstall the package if it was removed.
@@ -396,6 +621,7 @@ impl Installer {
                         .as_deref()
                         .map(move |r| (r, r.on_unlink_start(idx, record)));
                     driver.clobber_registry().unregister_paths(record);
+                    journal.backup_package(record).await?;
                     unlink_package(prefix.as_ref(), record).await.map_err(|e| {
                         InstallerError::UnlinkError(record.repodata_record.file_name.clone(), e)
                     })?;
@@ -409,12 +635,26 @@ impl Installer {
                     let reporter = reporter
                         .as_deref()
                         .map(|r| (r, r.on_link_start(idx, &record)));
+
+                    // Keep the spec of a package that is updated but was not
+                    // requested this time.
+                    let requested_spec = requested_specs
+                        .iter()
+                        .find(|spec| spec.name.as_ref() == Some(&record.package_record.name))
+                        .map(ToString::to_string)
+                        .or_else(|| {
+                            operation
+                                .record_to_remove()
+                                .and_then(|record| record.requested_spec.clone())
+                        });
                     link_package(
                         &record,
+                        requested_spec,
                         prefix.as_ref(),
                         &cached_path,
                         base_install_options.clone(),
                         driver,
+                        journal,
                     )
                     .await?;
                     if let Some((reporter, index)) = reporter {
@@ -432,15 +672,69 @@ impl Installer {
             pending_futures.push(operation_future);
         }
 
-        // Wait for all transaction operations to finish
-        while let Some(result) = pending_futures.next().await {
-            result?;
+        // Wait for all transaction operations to finish. Even if an operation
+        // failed the others are awaited, otherwise files that are still being
+        // linked might be created after the transaction has been rolled back.
+        let mut result = Ok(());
+        while let Some(operation_result) = pending_futures.next().await {
+            if let Err(err) = operation_result {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
         }
         drop(pending_futures);
 
+        // Undo all changes if any of the operations failed, otherwise the
+        // transaction is complete.
+        if let Err(err) = result {
+            if let Err(rollback_err) = journal.rollback() {
+                tracing::error!("failed to roll back the transaction: {rollback_err}");
+            }
+            return Err(err);
+        }
+        journal.commit()?;
+
+        // Add the results of the pre-link scripts to the results of the
+        // pre-processing step.
+        let pre_process_result = if pre_link_outputs.is_empty() {
+            pre_process_result
+        } else {
+            let mut pre_process_result = pre_process_result.unwrap_or_default();
+            for output in pre_link_outputs {
+                pre_process_result.push(output);
+            }
+            Some(pre_process_result)
+        };
+
         // Post process the transaction
         let post_process_result = driver.post_process(&transaction, prefix.as_ref())?;
 
+        // Record the changes in the history of the prefix.
+        let revision = Revision::from_transaction(
+            &transaction,
+            Some(
+                self.history_command
+                    .unwrap_or_else(|| std::env::args().collect::<Vec<_>>().join(" ")),
+            ),
+            &self.requested_specs,
+        );
+        if !revision.is_empty() {
+            let history_prefix = prefix.as_ref().to_path_buf();
+            // The transaction has already been committed, failing to record it
+            // should not make the installation fail.
+            let result = driver
+                .run_blocking_io_task(move || {
+                    History::append_to_prefix(&history_prefix, &revision).map_err(|e| {
+                        InstallerError::IoError("failed to write conda-meta/history".to_string(), e)
+                    })
+                })
+                .await;
+            if let Err(err) = result {
+                tracing::warn!("failed to record the transaction in the history: {err}");
+            }
+        }
+
         if let Some(reporter) = &self.reporter {
             reporter.on_transaction_complete();
         }
@@ -450,22 +744,31 @@ impl Installer {
             pre_link_script_result: pre_process_result,
             post_link_script_result: post_process_result.post_link_result,
             clobbered_paths: post_process_result.clobbered_paths,
+            pyc_compilation: post_process_result.pyc_compilation,
         })
     }
 }
 
 async fn link_package(
     record: &RepoDataRecord,
+    requested_spec: Option<String>,
     target_prefix: &Path,
     cached_package_dir: &Path,
     install_options: InstallOptions,
     driver: &InstallDriver,
+    journal: &TransactionJournal,
 ) -> Result<(), InstallerError> {
-    // Link the contents of the package into the prefix.
-    let paths =
-        crate::install::link_package(cached_package_dir, target_prefix, driver, install_options)
-            .await
-            .map_err(|e| InstallerError::LinkError(record.file_name.clone(), e))?;
+    // Link the contents of the package into the prefix. The paths are
+    // journaled before they are created.
+    let paths = link_package_journaled(
+        cached_package_dir,
+        target_prefix,
+        driver,
+        install_options,
+        Some(journal),
+    )
+    .await
+    .map_err(|e| InstallerError::LinkError(record.file_name.clone(), e))?;
 
     // Construct a PrefixRecord for the package
     let prefix_record = PrefixRecord {
@@ -477,8 +780,7 @@ async fn link_package(
             .map(|entry| entry.relative_path.clone())
             .collect(),
         paths_data: paths.into(),
-        // TODO: Retrieve the requested spec for this package from the request
-        requested_spec: None,
+        requested_spec,
 
         link: Some(Link {
             source: cached_package_dir.to_path_buf(),
@@ -487,6 +789,10 @@ async fn link_package(
         }),
     };
 
+    // Record the metadata file before it is written so it is removed again if
+    // the transaction is rolled back.
+    journal.record_linked(vec![Path::new("conda-meta").join(prefix_record.file_name())])?;
+
     let target_prefix = target_prefix.to_path_buf();
     driver
         .run_blocking_io_task(move || {
@@ -509,7 +815,45 @@ async fn link_package(
                 .write_to_path(conda_meta_path.join(&pkg_meta_path), true)
                 .map_err(|e| InstallerError::IoError(format!("failed to write {pkg_meta_path}"), e))
         })
-        .await
+        .await?;
+
+    Ok(())
+}
+
+/// Fetches the package of the operation at `idx` into the cache on a separate
+/// task. The task is started immediately, the returned future resolves to the
+/// path of the package in the cache.
+fn fetch_package(
+    idx: usize,
+    record: RepoDataRecord,
+    downloader: reqwest_middleware::ClientWithMiddleware,
+    package_cache: PackageCache,
+    reporter: Option<Arc<dyn Reporter>>,
+) -> impl Future<Output = Result<(PathBuf, RepoDataRecord), InstallerError>> {
+    let task = tokio::spawn(async move {
+        let populate_cache_report = reporter.map(|r| {
+            let cache_index = r.on_populate_cache_start(idx, &record);
+            (r, cache_index)
+        });
+        let cache_path = populate_cache(
+            &record,
+            downloader,
+            &package_cache,
+            populate_cache_report.clone(),
+        )
+        .await?;
+        if let Some((reporter, index)) = populate_cache_report {
+            reporter.on_populate_cache_complete(index);
+        }
+        Ok((cache_path, record))
+    });
+
+    task.map_err(JoinError::try_into_panic)
+        .map(|res| match res {
+            Ok(result) => result,
+            Err(Ok(payload)) => std::panic::resume_unwind(payload),
+            Err(Err(_err)) => Err(InstallerError::Cancelled),
+        })
 }
 
 /// Given a repodata record, fetch the package into the cache if its not already
@@ -563,3 +907,82 @@ async fn populate_cache(
         .await
         .map_err(|e| InstallerError::FailedToFetch(record.file_name.clone(), e))
 }
+
+#[cfg(test)]
+mod test {
+    use rattler_conda_types::ParseStrictness;
+
+    use super::*;
+    use crate::{get_repodata_record, get_test_data_dir};
+
+    #[tokio::test]
+    async fn test_requested_spec() {
+        let cache = tempfile::tempdir().unwrap();
+        let prefix = tempfile::tempdir().unwrap();
+
+        // The history can not be written, this should not fail the installation.
+        std::fs::create_dir_all(prefix.path().join("conda-meta/history")).unwrap();
+
+        let records = [
+            "clobber/clobber-1-0.1.0-h4616a5c_0.tar.bz2",
+            "clobber/clobber-2-0.1.0-h4616a5c_0.tar.bz2",
+        ]
+        .map(|path| get_repodata_record(get_test_data_dir().join(path)));
+        Installer::new()
+            .with_package_cache(PackageCache::new(cache.path()))
+            .with_requested_specs([
+                MatchSpec::from_str("clobber-1 >=0.1", ParseStrictness::Strict).unwrap(),
+            ])
+            .install(prefix.path(), records)
+            .await
+            .unwrap();
+
+        let requested_spec = |file_name: &str| {
+            PrefixRecord::from_path(prefix.path().join("conda-meta").join(file_name))
+                .unwrap()
+                .requested_spec
+        };
+        assert_eq!(
+            requested_spec("clobber-1-0.1.0-h4616a5c_0.json").as_deref(),
+            Some("clobber-1 >=0.1")
+        );
+        assert_eq!(requested_spec("clobber-2-0.1.0-h4616a5c_0.json"), None);
+    }
+
+    #[tokio::test]
+    async fn test_failed_link_restores_prefix() {
+        let cache = tempfile::tempdir().unwrap();
+        let prefix = tempfile::tempdir().unwrap();
+
+        // A directory where the package wants to create a file makes linking fail.
+        std::fs::create_dir_all(prefix.path().join("clobber.txt")).unwrap();
+        std::fs::write(prefix.path().join("clobber.txt/keep.txt"), "keep").unwrap();
+
+        let record = get_repodata_record(
+            get_test_data_dir().join("clobber/clobber-1-0.1.0-h4616a5c_0.tar.bz2"),
+        );
+        let result = Installer::new()
+            .with_package_cache(PackageCache::new(cache.path()))
+            .install(prefix.path(), vec![record])
+            .await;
+        assert!(
+            matches!(result, Err(InstallerError::LinkError(..))),
+            "expected a link error, got {result:?}"
+        );
+
+        // The files that were linked are removed again, the existing files are untouched.
+        assert!(!prefix.path().join("another-clobber.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(prefix.path().join("clobber.txt/keep.txt")).unwrap(),
+            "keep"
+        );
+        assert!(!prefix
+            .path()
+            .join("conda-meta/clobber-1-0.1.0-h4616a5c_0.json")
+            .exists());
+        assert!(!prefix
+            .path()
+            .join("conda-meta/.rattler-transaction")
+            .exists());
+    }
+}
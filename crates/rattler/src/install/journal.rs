@@ -0,0 +1,513 @@
+//! Journaling of transactions so they can be rolled back.
+//!
+//! While a [`super::Transaction`] is executed by the [`super::Installer`] the
+//! prefix is in an intermediate state. To be able to return to the original
+//! state when something fails, a [`TransactionJournal`] is kept in
+//! `conda-meta/.rattler-transaction`:
+//!
+//! * Before a package is unlinked its files are moved to a backup directory
+//!   and a copy of its `conda-meta` record is made.
+//! * Before a package is linked the paths it is going to create are recorded
+//!   and files that already exist at those paths are moved to the backup
+//!   directory.
+//!
+//! If the transaction fails the journal is used to remove the linked files
+//! and to restore the backups. If the transaction is cancelled or the process
+//! is killed while it is in progress, the journal is left behind and the
+//! transaction is rolled back by [`TransactionJournal::recover`] the next time
+//! the prefix is modified.
+//!
+//! Post-processing (e.g. resolving clobbered paths or running post-link
+//! scripts) happens after the transaction is committed and is not covered by
+//! the journal.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use rattler_conda_types::PrefixRecord;
+use serde::{Deserialize, Serialize};
+
+/// The name of the directory in `conda-meta` that contains the journal.
+const JOURNAL_DIR: &str = ".rattler-transaction";
+
+/// The name of the journal file in the journal directory.
+const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// The name of the directory that contains the backups.
+const BACKUP_DIR: &str = "backup";
+
+/// An error that can occur while journaling or rolling back a transaction.
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    /// Another transaction is in progress for the prefix.
+    #[error("another transaction is in progress in '{0}'")]
+    TransactionInProgress(PathBuf),
+
+    /// Failed to write to the journal.
+    #[error("failed to write the transaction journal")]
+    FailedToWriteJournal(#[source] std::io::Error),
+
+    /// Failed to read the journal.
+    #[error("failed to read the transaction journal")]
+    FailedToReadJournal(#[source] std::io::Error),
+
+    /// Failed to make a backup of a file.
+    #[error("failed to make a backup of '{0}'")]
+    FailedToBackup(PathBuf, #[source] std::io::Error),
+
+    /// Failed to restore the state of the prefix.
+    #[error("failed to restore '{0}'")]
+    FailedToRestore(PathBuf, #[source] std::io::Error),
+}
+
+/// A single entry in the journal file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum JournalEntry {
+    /// Files that are about to be moved to the backup directory.
+    Backup { files: Vec<BackupFile> },
+
+    /// Paths, relative to the prefix, that were created by linking a package.
+    Linked { paths: Vec<PathBuf> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BackupFile {
+    /// The path of the file relative to the prefix.
+    path: PathBuf,
+
+    /// The name of the file in the backup directory.
+    backup: String,
+}
+
+/// A journal of the changes made to a prefix by a transaction.
+///
+/// The journal must be finished with either [`TransactionJournal::commit`] or
+/// [`TransactionJournal::rollback`]. If the journal is dropped before that,
+/// for instance because the installation was cancelled, the journal is left
+/// in place. Blocking tasks that link files might still be running at that
+/// point, so the transaction is rolled back by [`TransactionJournal::recover`]
+/// instead.
+#[derive(Debug)]
+pub struct TransactionJournal {
+    prefix: PathBuf,
+    directory: PathBuf,
+    file: Mutex<File>,
+    next_backup: AtomicUsize,
+    finished: AtomicBool,
+}
+
+impl TransactionJournal {
+    /// Starts a new transaction in the given prefix.
+    ///
+    /// Fails if the journal of another transaction is still present. Call
+    /// [`Self::recover`] first to roll back an interrupted transaction.
+    pub fn begin(prefix: &Path) -> Result<Self, JournalError> {
+        let directory = journal_dir(prefix);
+        if directory.exists() {
+            return Err(JournalError::TransactionInProgress(prefix.to_path_buf()));
+        }
+
+        fs_err::create_dir_all(directory.join(BACKUP_DIR))
+            .map_err(JournalError::FailedToWriteJournal)?;
+        let file = File::create(directory.join(JOURNAL_FILE))
+            .map_err(JournalError::FailedToWriteJournal)?;
+
+        Ok(Self {
+            prefix: prefix.to_path_buf(),
+            directory,
+            file: Mutex::new(file),
+            next_backup: AtomicUsize::new(0),
+            finished: AtomicBool::new(false),
+        })
+    }
+
+    /// Rolls back a transaction that was interrupted, e.g. because the
+    /// process was killed. Returns `true` if there was a transaction to roll
+    /// back.
+    pub fn recover(prefix: &Path) -> Result<bool, JournalError> {
+        let directory = journal_dir(prefix);
+        if !directory.exists() {
+            return Ok(false);
+        }
+
+        tracing::warn!(
+            "rolling back an interrupted transaction in {}",
+            prefix.display()
+        );
+        let entries = read_entries(&directory.join(JOURNAL_FILE))?;
+        restore(prefix, &directory, &entries)?;
+        Ok(true)
+    }
+
+    /// Moves all the files of a package that is about to be unlinked to the
+    /// backup directory and makes a copy of its `conda-meta` record. Files
+    /// that no longer exist are skipped.
+    pub async fn backup_package(&self, record: &PrefixRecord) -> Result<(), JournalError> {
+        let conda_meta_path = Path::new("conda-meta").join(record.file_name());
+        let files = record
+            .paths_data
+            .paths
+            .iter()
+            .map(|entry| entry.relative_path.clone())
+            .chain(std::iter::once(conda_meta_path.clone()))
+            .map(|path| BackupFile {
+                path,
+                backup: self.next_backup.fetch_add(1, Ordering::Relaxed).to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        // Write the intent to the journal before touching any files so the
+        // backups can be found if the process is killed halfway.
+        self.append(&JournalEntry::Backup {
+            files: files.clone(),
+        })?;
+
+        for file in files {
+            let source = self.prefix.join(&file.path);
+            let destination = self.directory.join(BACKUP_DIR).join(&file.backup);
+            match tokio::fs::symlink_metadata(&source).await {
+                Ok(metadata) if metadata.is_dir() => continue,
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(JournalError::FailedToBackup(file.path, e)),
+            }
+
+            // The record itself is removed by the unlink step, so only copy it.
+            let result = if file.path == conda_meta_path {
+                tokio::fs::copy(&source, &destination).await.map(|_| ())
+            } else {
+                move_file(&source, &destination).await
+            };
+            result.map_err(|e| JournalError::FailedToBackup(file.path, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the files that exist at the given paths, relative to the prefix,
+    /// to the backup directory. Call this before linking a package so files
+    /// that are overwritten, e.g. files not owned by any package, are
+    /// restored when the transaction is rolled back.
+    pub async fn backup_existing(&self, paths: &[PathBuf]) -> Result<(), JournalError> {
+        let mut files = Vec::new();
+        for path in paths {
+            match tokio::fs::symlink_metadata(self.prefix.join(path)).await {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => files.push(BackupFile {
+                    path: path.clone(),
+                    backup: self.next_backup.fetch_add(1, Ordering::Relaxed).to_string(),
+                }),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(JournalError::FailedToBackup(path.clone(), e)),
+            }
+        }
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        self.append(&JournalEntry::Backup {
+            files: files.clone(),
+        })?;
+
+        for file in files {
+            let source = self.prefix.join(&file.path);
+            let destination = self.directory.join(BACKUP_DIR).join(&file.backup);
+            move_file(&source, &destination)
+                .await
+                .map_err(|e| JournalError::FailedToBackup(file.path, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Records the paths that were created by linking a package. The paths
+    /// are relative to the prefix.
+    pub fn record_linked(&self, paths: Vec<PathBuf>) -> Result<(), JournalError> {
+        self.append(&JournalEntry::Linked { paths })
+    }
+
+    /// Marks the transaction as successful and removes the journal and all
+    /// backups.
+    pub fn commit(self) -> Result<(), JournalError> {
+        self.finished.store(true, Ordering::SeqCst);
+        fs_err::remove_dir_all(&self.directory).map_err(JournalError::FailedToWriteJournal)
+    }
+
+    /// Undoes all changes recorded in the journal and removes the journal.
+    ///
+    /// All tasks that modify the prefix must have finished before calling
+    /// this.
+    pub fn rollback(self) -> Result<(), JournalError> {
+        self.finished.store(true, Ordering::SeqCst);
+        let entries = read_entries(&self.directory.join(JOURNAL_FILE))?;
+        restore(&self.prefix, &self.directory, &entries)
+    }
+
+    fn append(&self, entry: &JournalEntry) -> Result<(), JournalError> {
+        let line = serde_json::to_string(entry)
+            .map_err(|e| JournalError::FailedToWriteJournal(e.into()))?;
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{line}")
+            .and_then(|_| file.sync_data())
+            .map_err(JournalError::FailedToWriteJournal)
+    }
+}
+
+impl Drop for TransactionJournal {
+    fn drop(&mut self) {
+        if !self.finished.load(Ordering::SeqCst) {
+            tracing::warn!(
+                "the transaction in {} was interrupted, it will be rolled back the next time the prefix is modified",
+                self.prefix.display()
+            );
+        }
+    }
+}
+
+fn journal_dir(prefix: &Path) -> PathBuf {
+    prefix.join("conda-meta").join(JOURNAL_DIR)
+}
+
+/// Reads all entries from the journal. An incomplete last line, which is the
+/// result of the process being killed while writing, is ignored.
+fn read_entries(path: &Path) -> Result<Vec<JournalEntry>, JournalError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(JournalError::FailedToReadJournal(e)),
+    };
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(JournalError::FailedToReadJournal)?;
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => {
+                tracing::warn!("ignoring invalid transaction journal entry: {e}");
+                break;
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Removes everything that was linked and restores all backups, then removes
+/// the journal directory.
+fn restore(prefix: &Path, directory: &Path, entries: &[JournalEntry]) -> Result<(), JournalError> {
+    // First remove the files of all linked packages.
+    for entry in entries.iter().rev() {
+        let JournalEntry::Linked { paths } = entry else {
+            continue;
+        };
+        for path in paths.iter().rev() {
+            // Paths are journaled before they are created, so they might not
+            // exist or might be a directory that was there before.
+            let full_path = prefix.join(path);
+            if full_path.symlink_metadata().map_or(false, |m| m.is_dir()) {
+                continue;
+            }
+            match std::fs::remove_file(&full_path) {
+                Ok(_) => remove_empty_parents(prefix, &full_path),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(JournalError::FailedToRestore(path.clone(), e)),
+            }
+        }
+    }
+
+    // Then move the backups back into place.
+    for entry in entries.iter().rev() {
+        let JournalEntry::Backup { files } = entry else {
+            continue;
+        };
+        for file in files.iter().rev() {
+            let backup = directory.join(BACKUP_DIR).join(&file.backup);
+            if !backup.exists() {
+                continue;
+            }
+            let destination = prefix.join(&file.path);
+            if let Some(parent) = destination.parent() {
+                fs_err::create_dir_all(parent)
+                    .map_err(|e| JournalError::FailedToRestore(file.path.clone(), e))?;
+            }
+            if std::fs::rename(&backup, &destination).is_err() {
+                std::fs::copy(&backup, &destination)
+                    .map_err(|e| JournalError::FailedToRestore(file.path.clone(), e))?;
+            }
+        }
+    }
+
+    fs_err::remove_dir_all(directory).map_err(JournalError::FailedToWriteJournal)
+}
+
+/// Removes the empty parent directories of `path` up to `prefix`.
+fn remove_empty_parents(prefix: &Path, path: &Path) {
+    let mut current = path.parent();
+    while let Some(directory) = current {
+        if directory == prefix || !directory.starts_with(prefix) {
+            break;
+        }
+        if std::fs::remove_dir(directory).is_err() {
+            break;
+        }
+        current = directory.parent();
+    }
+}
+
+/// Moves a file, falling back to copying if the file cannot be renamed (e.g.
+/// because the destination is on another filesystem).
+async fn move_file(source: &Path, destination: &Path) -> std::io::Result<()> {
+    if tokio::fs::rename(source, destination).await.is_ok() {
+        return Ok(());
+    }
+    tokio::fs::copy(source, destination).await?;
+    tokio::fs::remove_file(source).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use rattler_conda_types::{
+        prefix_record::{PathType, PathsEntry},
+        PackageName, PackageRecord, PrefixRecord, RepoDataRecord, Version,
+    };
+
+    use super::TransactionJournal;
+
+    fn prefix_record(paths: &[&str]) -> PrefixRecord {
+        let package_record = PackageRecord::new(
+            PackageName::new_unchecked("foo"),
+            "1.0".parse::<Version>().unwrap(),
+            String::from("0"),
+        );
+        PrefixRecord::from_repodata_record(
+            RepoDataRecord {
+                package_record,
+                file_name: String::from("foo-1.0-0.conda"),
+                url: "https://conda.anaconda.org/conda-forge/noarch/foo-1.0-0.conda"
+                    .parse()
+                    .unwrap(),
+                channel: String::from("https://conda.anaconda.org/conda-forge/"),
+            },
+            None,
+            None,
+            paths
+                .iter()
+                .map(|path| PathsEntry {
+                    relative_path: PathBuf::from(path),
+                    original_path: None,
+                    path_type: PathType::HardLink,
+                    no_link: false,
+                    sha256: None,
+                    sha256_in_prefix: None,
+                    size_in_bytes: None,
+                    file_mode: None,
+                    prefix_placeholder: None,
+                })
+                .collect(),
+            None,
+            None,
+        )
+    }
+
+    fn write(prefix: &Path, path: &str, content: &str) {
+        let path = prefix.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    /// Simulates a transaction that replaces `foo` by a package that
+    /// installs `bin/bar`.
+    async fn replace_package(prefix: &Path) -> TransactionJournal {
+        let record = prefix_record(&["bin/foo", "lib/libfoo.so"]);
+        write(prefix, "bin/foo", "foo");
+        write(prefix, "lib/libfoo.so", "libfoo");
+        write(prefix, &format!("conda-meta/{}", record.file_name()), "{}");
+
+        let journal = TransactionJournal::begin(prefix).unwrap();
+        journal.backup_package(&record).await.unwrap();
+        crate::install::unlink_package(prefix, &record)
+            .await
+            .unwrap();
+
+        let linked = vec![PathBuf::from("bin/bar")];
+        journal.backup_existing(&linked).await.unwrap();
+        journal.record_linked(linked).unwrap();
+        write(prefix, "bin/bar", "bar");
+        journal
+    }
+
+    fn assert_restored(prefix: &Path) {
+        assert_eq!(
+            std::fs::read_to_string(prefix.join("bin/foo")).unwrap(),
+            "foo"
+        );
+        assert_eq!(
+            std::fs::read_to_string(prefix.join("lib/libfoo.so")).unwrap(),
+            "libfoo"
+        );
+        assert!(prefix.join("conda-meta/foo-1.0-0.json").is_file());
+        assert!(!prefix.join("bin/bar").exists());
+        assert!(!prefix.join("conda-meta/.rattler-transaction").exists());
+    }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        let prefix = tempfile::tempdir().unwrap();
+        let journal = replace_package(prefix.path()).await;
+        assert!(!prefix.path().join("bin/foo").exists());
+
+        journal.rollback().unwrap();
+        assert_restored(prefix.path());
+    }
+
+    #[tokio::test]
+    async fn test_recover_interrupted_transaction() {
+        let prefix = tempfile::tempdir().unwrap();
+        let journal = replace_package(prefix.path()).await;
+
+        // Dropping the journal, e.g. because the installation was cancelled,
+        // leaves it behind just like when the process is killed.
+        drop(journal);
+        assert!(TransactionJournal::begin(prefix.path()).is_err());
+
+        assert!(TransactionJournal::recover(prefix.path()).unwrap());
+        assert_restored(prefix.path());
+        assert!(!TransactionJournal::recover(prefix.path()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_rollback_restores_overwritten_files() {
+        let prefix = tempfile::tempdir().unwrap();
+        write(prefix.path(), "bin/bar", "not owned by any package");
+
+        let journal = replace_package(prefix.path()).await;
+        journal.rollback().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(prefix.path().join("bin/bar")).unwrap(),
+            "not owned by any package"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit() {
+        let prefix = tempfile::tempdir().unwrap();
+        let journal = replace_package(prefix.path()).await;
+        journal.commit().unwrap();
+
+        assert!(!prefix.path().join("bin/foo").exists());
+        assert!(prefix.path().join("bin/bar").is_file());
+        assert!(!prefix
+            .path()
+            .join("conda-meta/.rattler-transaction")
+            .exists());
+    }
+}
@@ -1,8 +1,9 @@
-//! Functions for running link scripts (pre-unlink and post-link) for a package
+//! Functions for running link scripts (pre-link, post-link and pre-unlink)
+//! for a package
 use std::{
     borrow::Borrow,
     collections::{HashMap, HashSet},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use rattler_conda_types::{PackageName, PackageRecord, Platform, PrefixRecord};
@@ -17,10 +18,20 @@ pub enum LinkScriptError {
     /// An error occurred while reading the message file
     #[error("{0}")]
     IoError(String, #[source] std::io::Error),
+
+    /// A script that must succeed did not run successfully.
+    #[error("the {0} script of {1} did not run successfully ({2:?})")]
+    ScriptFailed(String, String, LinkScriptStatus),
 }
 
 /// The type of link script to run
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum LinkScriptType {
+    /// The pre-link script (run before the package is linked)
+    /// This is not linked into the environment but executed from the
+    /// extracted package as `bin/.{name}-pre-link.sh` or
+    /// `Scripts/.{name}-pre-link.bat`
+    PreLink,
     /// The pre-unlink script (run before the package is unlinked)
     /// This is stored in the environment as `bin/.{name}-pre-unlink.sh` or
     /// `Scripts/.{name}-pre-unlink.bat`
@@ -37,6 +48,9 @@ impl LinkScriptType {
         let name = &package_record.name.as_normalized();
         if platform.is_windows() {
             match self {
+                LinkScriptType::PreLink => {
+                    format!("Scripts/.{name}-pre-link.bat")
+                }
                 LinkScriptType::PreUnlink => {
                     format!("Scripts/.{name}-pre-unlink.bat")
                 }
@@ -46,6 +60,9 @@ impl LinkScriptType {
             }
         } else {
             match self {
+                LinkScriptType::PreLink => {
+                    format!("bin/.{name}-pre-link.sh")
+                }
                 LinkScriptType::PreUnlink => {
                     format!("bin/.{name}-pre-unlink.sh")
                 }
@@ -60,20 +77,135 @@ impl LinkScriptType {
 impl ToString for LinkScriptType {
     fn to_string(&self) -> String {
         match self {
+            LinkScriptType::PreLink => "pre-link".to_string(),
             LinkScriptType::PreUnlink => "pre-unlink".to_string(),
             LinkScriptType::PostLink => "post-link".to_string(),
         }
     }
 }
 
-/// Records the results of running pre/post link scripts
+/// Determines which link scripts are executed.
+///
+/// Link scripts can run arbitrary code during the installation. The policy can
+/// be used to only run the scripts of trusted packages or to only report which
+/// scripts would run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LinkScriptPolicy {
+    /// Run the link scripts of all packages.
+    #[default]
+    All,
+
+    /// Only run the link scripts of the listed packages. The scripts of other
+    /// packages are reported as [`LinkScriptStatus::Skipped`].
+    AllowList(HashSet<PackageName>),
+
+    /// Do not run any link script but report the scripts that would run as
+    /// [`LinkScriptStatus::DryRun`].
+    DryRun,
+}
+
+impl LinkScriptPolicy {
+    /// Returns the status of a script of the given package that is not
+    /// executed because of this policy, or `None` if the script may run.
+    pub(crate) fn skip_status(&self, package: &PackageName) -> Option<LinkScriptStatus> {
+        match self {
+            LinkScriptPolicy::All => None,
+            LinkScriptPolicy::AllowList(allowed) if allowed.contains(package) => None,
+            LinkScriptPolicy::AllowList(_) => Some(LinkScriptStatus::Skipped),
+            LinkScriptPolicy::DryRun => Some(LinkScriptStatus::DryRun),
+        }
+    }
+}
+
+/// The outcome of a single link script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkScriptStatus {
+    /// The script ran successfully.
+    Succeeded,
+
+    /// The script exited with a non-zero exit code. The code is `None` if
+    /// the script was terminated by a signal.
+    Failed(Option<i32>),
+
+    /// The script could not be started.
+    Error(String),
+
+    /// The script was not executed because the package is not on the
+    /// allow-list of the [`LinkScriptPolicy`].
+    Skipped,
+
+    /// The script was not executed because the [`LinkScriptPolicy`] is a
+    /// dry-run.
+    DryRun,
+}
+
+/// The output of a single link script of a package.
 #[derive(Debug, Clone)]
+pub struct LinkScriptOutput {
+    /// The package the script belongs to.
+    pub package: PackageName,
+
+    /// The type of the script.
+    pub script_type: LinkScriptType,
+
+    /// The location of the script.
+    pub script: PathBuf,
+
+    /// The outcome of running the script.
+    pub status: LinkScriptStatus,
+
+    /// The captured standard output of the script.
+    pub stdout: String,
+
+    /// The captured standard error of the script.
+    pub stderr: String,
+
+    /// The contents of the `.messages.txt` file written by the script, if
+    /// any.
+    pub message: Option<String>,
+}
+
+/// Records the results of running pre/post link scripts
+#[derive(Debug, Clone, Default)]
 pub struct PrePostLinkResult {
     /// Messages from the link scripts
     pub messages: HashMap<PackageName, String>,
 
     /// Packages that failed to run the link scripts
     pub failed_packages: Vec<PackageName>,
+
+    /// The output of every link script that was found, including the scripts
+    /// that were not executed because of the [`LinkScriptPolicy`].
+    pub outputs: Vec<LinkScriptOutput>,
+}
+
+impl PrePostLinkResult {
+    /// Adds the output of a single link script.
+    pub fn push(&mut self, output: LinkScriptOutput) {
+        if matches!(
+            output.status,
+            LinkScriptStatus::Failed(_) | LinkScriptStatus::Error(_)
+        ) {
+            self.failed_packages.push(output.package.clone());
+        }
+        if !matches!(
+            output.status,
+            LinkScriptStatus::Skipped | LinkScriptStatus::DryRun
+        ) {
+            self.messages.insert(
+                output.package.clone(),
+                output.message.clone().unwrap_or_default(),
+            );
+        }
+        self.outputs.push(output);
+    }
+
+    /// Merges the results of another run into this one.
+    pub fn extend(&mut self, other: PrePostLinkResult) {
+        self.messages.extend(other.messages);
+        self.failed_packages.extend(other.failed_packages);
+        self.outputs.extend(other.outputs);
+    }
 }
 
 /// An error that can occur during pre-, post-link script execution.
@@ -90,93 +222,167 @@ pub fn run_link_scripts<'a>(
     prefix_records: impl Iterator<Item = &'a PrefixRecord>,
     target_prefix: &Path,
     platform: &Platform,
+    policy: &LinkScriptPolicy,
 ) -> Result<PrePostLinkResult, LinkScriptError> {
-    let mut env = HashMap::new();
-    env.insert(
-        "PREFIX".to_string(),
-        target_prefix.to_string_lossy().to_string(),
-    );
-
     // prefix records are topologically sorted, so we can be sure that all
     // dependencies are installed before the package itself.
-    let mut failed_packages = Vec::new();
-    let mut messages = HashMap::<PackageName, String>::new();
+    let mut result = PrePostLinkResult::default();
     for record in prefix_records {
         let prec = &record.repodata_record.package_record;
-        let link_file = target_prefix.join(&link_script_type.get_path(prec, platform));
+        let link_file = target_prefix.join(link_script_type.get_path(prec, platform));
+        if let Some(output) = run_link_script(
+            link_script_type,
+            prec,
+            &link_file,
+            target_prefix,
+            platform,
+            policy,
+        )? {
+            result.push(output);
+        }
+    }
 
-        if link_file.exists() {
-            env.insert(
-                "PKG_NAME".to_string(),
-                prec.name.as_normalized().to_string(),
-            );
-            env.insert("PKG_VERSION".to_string(), prec.version.to_string());
-            env.insert("PKG_BUILDNUM".to_string(), prec.build_number.to_string());
+    Ok(result)
+}
 
-            let shell = if platform.is_windows() {
-                ShellEnum::CmdExe(CmdExe)
-            } else {
-                ShellEnum::Bash(Bash)
-            };
+/// Run the pre-link script of a package. Unlike the other link scripts the
+/// pre-link script is executed from the extracted package in `package_dir`
+/// because it runs before the files of the package are linked into the
+/// prefix.
+///
+/// Returns `None` if the package does not contain a pre-link script.
+pub fn run_pre_link_script(
+    package_record: &PackageRecord,
+    package_dir: &Path,
+    target_prefix: &Path,
+    platform: &Platform,
+    policy: &LinkScriptPolicy,
+) -> Result<Option<LinkScriptOutput>, LinkScriptError> {
+    let link_file = package_dir.join(LinkScriptType::PreLink.get_path(package_record, platform));
+    run_link_script(
+        LinkScriptType::PreLink,
+        package_record,
+        &link_file,
+        target_prefix,
+        platform,
+        policy,
+    )
+}
 
-            tracing::info!(
-                "Running {} script for {}",
-                link_script_type.to_string(),
-                prec.name.as_normalized()
-            );
+/// Runs a single link script if it exists and the policy allows it.
+fn run_link_script(
+    link_script_type: LinkScriptType,
+    prec: &PackageRecord,
+    link_file: &Path,
+    target_prefix: &Path,
+    platform: &Platform,
+    policy: &LinkScriptPolicy,
+) -> Result<Option<LinkScriptOutput>, LinkScriptError> {
+    if !link_file.exists() {
+        return Ok(None);
+    }
 
-            match rattler_shell::run_in_environment(target_prefix, &link_file, shell, &env) {
-                Ok(o) if o.status.success() => {}
-                Ok(o) => {
-                    failed_packages.push(prec.name.clone());
-                    tracing::warn!("Error running post-link script. Status: {:?}", o.status);
-                    tracing::warn!("  stdout: {}", String::from_utf8_lossy(&o.stdout));
-                    tracing::warn!("  stderr: {}", String::from_utf8_lossy(&o.stderr));
-                }
-                Err(e) => {
-                    failed_packages.push(prec.name.clone());
-                    tracing::error!("Error running post-link script: {:?}", e);
-                }
-            }
+    let mut output = LinkScriptOutput {
+        package: prec.name.clone(),
+        script_type: link_script_type,
+        script: link_file.to_path_buf(),
+        status: LinkScriptStatus::Succeeded,
+        stdout: String::new(),
+        stderr: String::new(),
+        message: None,
+    };
+
+    if let Some(status) = policy.skip_status(&prec.name) {
+        tracing::info!(
+            "Not running {} script for {} ({:?})",
+            link_script_type.to_string(),
+            prec.name.as_normalized(),
+            status
+        );
+        output.status = status;
+        return Ok(Some(output));
+    }
+
+    let mut env = HashMap::new();
+    env.insert(
+        "PREFIX".to_string(),
+        target_prefix.to_string_lossy().to_string(),
+    );
+    env.insert(
+        "PKG_NAME".to_string(),
+        prec.name.as_normalized().to_string(),
+    );
+    env.insert("PKG_VERSION".to_string(), prec.version.to_string());
+    env.insert("PKG_BUILDNUM".to_string(), prec.build_number.to_string());
+
+    let shell = if platform.is_windows() {
+        ShellEnum::CmdExe(CmdExe)
+    } else {
+        ShellEnum::Bash(Bash)
+    };
 
-            let message_file = target_prefix.join(".messages.txt");
-            if message_file.exists() {
-                let message = std::fs::read_to_string(&message_file).map_err(|err| {
-                    LinkScriptError::IoError(
-                        format!(
-                            "error reading message file from {0}",
-                            message_file.display()
-                        ),
-                        err,
-                    )
-                })?;
-                tracing::info!(
-                    "Message from {} for {}: {}",
+    tracing::info!(
+        "Running {} script for {}",
+        link_script_type.to_string(),
+        prec.name.as_normalized()
+    );
+
+    match rattler_shell::run_in_environment(target_prefix, link_file, shell, &env) {
+        Ok(o) => {
+            output.stdout = String::from_utf8_lossy(&o.stdout).into_owned();
+            output.stderr = String::from_utf8_lossy(&o.stderr).into_owned();
+            if !o.status.success() {
+                output.status = LinkScriptStatus::Failed(o.status.code());
+                tracing::warn!(
+                    "Error running {} script. Status: {:?}",
                     link_script_type.to_string(),
-                    prec.name.as_normalized(),
-                    message
+                    o.status
                 );
-                messages.insert(prec.name.clone(), message);
-                // Remove the message file
-                std::fs::remove_file(&message_file).map_err(|err| {
-                    LinkScriptError::IoError(
-                        format!(
-                            "error removing message file from {0}",
-                            message_file.display()
-                        ),
-                        err,
-                    )
-                })?;
-            } else {
-                messages.insert(prec.name.clone(), "".to_string());
+                tracing::warn!("  stdout: {}", output.stdout);
+                tracing::warn!("  stderr: {}", output.stderr);
             }
         }
+        Err(e) => {
+            tracing::error!(
+                "Error running {} script: {:?}",
+                link_script_type.to_string(),
+                e
+            );
+            output.status = LinkScriptStatus::Error(e.to_string());
+        }
+    }
+
+    let message_file = target_prefix.join(".messages.txt");
+    if message_file.exists() {
+        let message = std::fs::read_to_string(&message_file).map_err(|err| {
+            LinkScriptError::IoError(
+                format!(
+                    "error reading message file from {0}",
+                    message_file.display()
+                ),
+                err,
+            )
+        })?;
+        tracing::info!(
+            "Message from {} for {}: {}",
+            link_script_type.to_string(),
+            prec.name.as_normalized(),
+            message
+        );
+        output.message = Some(message);
+        // Remove the message file
+        std::fs::remove_file(&message_file).map_err(|err| {
+            LinkScriptError::IoError(
+                format!(
+                    "error removing message file from {0}",
+                    message_file.display()
+                ),
+                err,
+            )
+        })?;
     }
 
-    Ok(PrePostLinkResult {
-        messages,
-        failed_packages,
-    })
+    Ok(Some(output))
 }
 
 impl InstallDriver {
@@ -207,11 +413,48 @@ impl InstallDriver {
             filter_iter,
             target_prefix,
             &transaction.platform,
+            self.link_script_policy(),
         )
     }
 
-    /// Run any post-link scripts that are part of the packages that are being
-    /// installed.
+    /// Run the pre-link script of a package that is about to be linked from
+    /// `package_dir`. Returns `None` if link scripts are disabled or if the
+    /// package does not have a pre-link script.
+    ///
+    /// Unlike the other link scripts a pre-link script that fails is an error,
+    /// the package must not be linked in that case.
+    pub fn run_pre_link_script(
+        &self,
+        package_record: &PackageRecord,
+        package_dir: &Path,
+        target_prefix: &Path,
+        platform: &Platform,
+    ) -> Result<Option<LinkScriptOutput>, LinkScriptError> {
+        if !self.executes_link_scripts() {
+            return Ok(None);
+        }
+        let output = run_pre_link_script(
+            package_record,
+            package_dir,
+            target_prefix,
+            platform,
+            self.link_script_policy(),
+        )?;
+        match output {
+            Some(LinkScriptOutput {
+                status: status @ (LinkScriptStatus::Failed(_) | LinkScriptStatus::Error(_)),
+                ..
+            }) => Err(LinkScriptError::ScriptFailed(
+                LinkScriptType::PreLink.to_string(),
+                package_record.name.as_normalized().to_string(),
+                status,
+            )),
+            output => Ok(output),
+        }
+    }
+
+    /// Run any pre-unlink scripts that are part of the packages that are being
+    /// removed.
     pub fn run_pre_unlink_scripts<Old, New>(
         &self,
         transaction: &Transaction<Old, New>,
@@ -225,6 +468,7 @@ impl InstallDriver {
             transaction.removed_packages().map(Borrow::borrow),
             target_prefix,
             &transaction.platform,
+            self.link_script_policy(),
         )
     }
 }
@@ -302,4 +546,128 @@ mod tests {
         // check that the pre-unlink script was run
         assert!(!target_prefix.path().join("i-was-post-linked").exists());
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_link_script_policy() {
+        use std::collections::HashSet;
+
+        use rattler_conda_types::{PackageName, PackageRecord};
+
+        use crate::install::link_script::{
+            run_link_scripts, run_pre_link_script, LinkScriptError, LinkScriptPolicy,
+            LinkScriptStatus, LinkScriptType,
+        };
+
+        let target_prefix = tempfile::tempdir().unwrap();
+        let package_dir = tempfile::tempdir().unwrap();
+        let record = PrefixRecord::from_repodata_record(
+            get_repodata_record(
+                get_test_data_dir().join("link-scripts/link-scripts-0.1.0-h4616a5c_0.conda"),
+            ),
+            None,
+            None,
+            Vec::new(),
+            None,
+            None,
+        );
+        let package_record: &PackageRecord = record.as_ref();
+        let script = |dir: &std::path::Path, script_type: LinkScriptType| {
+            let path = dir.join(script_type.get_path(package_record, &Platform::Linux64));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(
+                &path,
+                "echo hello\necho oops >&2\necho message > \"$PREFIX/.messages.txt\"\n",
+            )
+            .unwrap();
+        };
+        script(target_prefix.path(), LinkScriptType::PostLink);
+        script(package_dir.path(), LinkScriptType::PreLink);
+
+        // A dry-run only reports the script.
+        let result = run_link_scripts(
+            LinkScriptType::PostLink,
+            std::iter::once(&record),
+            target_prefix.path(),
+            &Platform::Linux64,
+            &LinkScriptPolicy::DryRun,
+        )
+        .unwrap();
+        assert_eq!(result.outputs.len(), 1);
+        assert_eq!(result.outputs[0].status, LinkScriptStatus::DryRun);
+        assert!(result.messages.is_empty());
+
+        // Packages that are not on the allow-list are skipped.
+        let result = run_link_scripts(
+            LinkScriptType::PostLink,
+            std::iter::once(&record),
+            target_prefix.path(),
+            &Platform::Linux64,
+            &LinkScriptPolicy::AllowList(HashSet::from([PackageName::new_unchecked("other")])),
+        )
+        .unwrap();
+        assert_eq!(result.outputs[0].status, LinkScriptStatus::Skipped);
+
+        // The output and the message of the script are captured.
+        let result = run_link_scripts(
+            LinkScriptType::PostLink,
+            std::iter::once(&record),
+            target_prefix.path(),
+            &Platform::Linux64,
+            &LinkScriptPolicy::AllowList(HashSet::from([package_record.name.clone()])),
+        )
+        .unwrap();
+        let output = &result.outputs[0];
+        assert_eq!(output.status, LinkScriptStatus::Succeeded);
+        assert_eq!(output.stdout.trim(), "hello");
+        assert_eq!(output.stderr.trim(), "oops");
+        assert_eq!(output.message.as_deref(), Some("message\n"));
+        assert!(!target_prefix.path().join(".messages.txt").exists());
+
+        // Pre-link scripts are executed from the package directory.
+        let output = run_pre_link_script(
+            package_record,
+            package_dir.path(),
+            target_prefix.path(),
+            &Platform::Linux64,
+            &LinkScriptPolicy::All,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(output.script_type, LinkScriptType::PreLink);
+        assert_eq!(output.status, LinkScriptStatus::Succeeded);
+
+        // A failing pre-link script is an error, unless it is not executed.
+        let pre_link = package_dir
+            .path()
+            .join(LinkScriptType::PreLink.get_path(package_record, &Platform::Linux64));
+        std::fs::write(pre_link, "exit 1\n").unwrap();
+        let run_pre_link = |policy: LinkScriptPolicy| {
+            InstallDriver::builder()
+                .execute_link_scripts(true)
+                .with_link_script_policy(policy)
+                .finish()
+                .run_pre_link_script(
+                    package_record,
+                    package_dir.path(),
+                    target_prefix.path(),
+                    &Platform::Linux64,
+                )
+        };
+        assert!(matches!(
+            run_pre_link(LinkScriptPolicy::All),
+            Err(LinkScriptError::ScriptFailed(
+                _,
+                _,
+                LinkScriptStatus::Failed(Some(1))
+            ))
+        ));
+        assert_eq!(
+            run_pre_link(LinkScriptPolicy::DryRun)
+                .unwrap()
+                .unwrap()
+                .status,
+            LinkScriptStatus::DryRun
+        );
+    }
 }
@@ -0,0 +1,474 @@
+//! The Linux menuinst backend which creates XDG desktop entries.
+
+use std::{
+    fmt::Write as _,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use rattler_conda_types::menuinst::{Linux, MenuItemCommand};
+
+use super::{slugify, MenuInstError, MenuInstOptions, Placeholders};
+
+/// A single menu item that is installed as a `.desktop` file.
+pub struct LinuxMenu<'a> {
+    name: String,
+    file_stem: String,
+    command: MenuItemCommand,
+    linux: &'a Linux,
+    placeholders: &'a Placeholders,
+    options: &'a MenuInstOptions,
+}
+
+impl<'a> LinuxMenu<'a> {
+    /// Constructs a new menu item from the merged command and the linux
+    /// specific properties of a menuinst item.
+    pub fn new(
+        menu_name: &str,
+        command: MenuItemCommand,
+        linux: &'a Linux,
+        placeholders: &'a Placeholders,
+        options: &'a MenuInstOptions,
+    ) -> Self {
+        let name = placeholders.substitute(command.name.resolve(placeholders.is_base()));
+        let file_stem = format!(
+            "{}_{}",
+            slugify(&placeholders.substitute(menu_name)),
+            slugify(&name)
+        );
+        Self {
+            name,
+            file_stem,
+            command,
+            linux,
+            placeholders,
+            options,
+        }
+    }
+
+    /// Returns the location of the `.desktop` file.
+    pub fn desktop_file_path(&self) -> PathBuf {
+        self.options
+            .data_dir
+            .join("applications")
+            .join(format!("{}.desktop", self.file_stem))
+    }
+
+    /// Returns the location of the file that registers the mime types of
+    /// the item.
+    pub fn mime_file_path(&self) -> PathBuf {
+        self.options
+            .data_dir
+            .join("mime/packages")
+            .join(format!("{}.xml", self.file_stem))
+    }
+
+    /// Creates the `.desktop` file and registers the mime types. Returns the
+    /// files that were created.
+    ///
+    /// The `precreate` command of the item can run arbitrary code, just like a
+    /// link script. It is only executed if `run_precreate` is true.
+    pub fn install(&self, run_precreate: bool) -> Result<Vec<PathBuf>, MenuInstError> {
+        if let Some(precreate) = self.command.precreate.as_ref().filter(|_| run_precreate) {
+            let precreate = self.placeholders.substitute(precreate);
+            match Command::new("bash").arg("-c").arg(&precreate).status() {
+                Ok(status) if status.success() => {}
+                Ok(status) => tracing::warn!("precreate command '{precreate}' failed: {status}"),
+                Err(e) => tracing::warn!("failed to run precreate command '{precreate}': {e}"),
+            }
+        }
+
+        let mut created = Vec::new();
+
+        let desktop_file = self.desktop_file_path();
+        write_file(&desktop_file, &self.desktop_entry())?;
+        created.push(desktop_file);
+
+        if let Some(mime_info) = self.mime_info() {
+            let mime_file = self.mime_file_path();
+            write_file(&mime_file, &mime_info)?;
+            created.push(mime_file);
+        }
+
+        self.update_databases(created.len() > 1);
+        Ok(created)
+    }
+
+    /// Removes the files created by [`Self::install`]. Returns the files that
+    /// were removed.
+    pub fn remove(&self) -> Result<Vec<PathBuf>, MenuInstError> {
+        let mut removed = Vec::new();
+        for path in [self.desktop_file_path(), self.mime_file_path()] {
+            match fs_err::remove_file(&path) {
+                Ok(()) => removed.push(path),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(MenuInstError::IoError(
+                        format!("failed to remove '{}'", path.display()),
+                        e,
+                    ))
+                }
+            }
+        }
+
+        if !removed.is_empty() {
+            self.update_databases(removed.len() > 1);
+        }
+        Ok(removed)
+    }
+
+    /// Returns the contents of the `.desktop` file.
+    ///
+    /// The `Icon` key refers to the icon in the prefix by its absolute path,
+    /// the icon is not installed into the XDG icon theme directories.
+    pub fn desktop_entry(&self) -> String {
+        let mut entry = String::from("[Desktop Entry]\nType=Application\nEncoding=UTF-8\n");
+        let mut add = |key: &str, value: &str| {
+            writeln!(entry, "{key}={}", escape_value(value)).unwrap();
+        };
+        let list = |values: &[String]| {
+            values
+                .iter()
+                .map(|value| format!("{};", value.replace(';', "\\;")))
+                .collect::<String>()
+        };
+        let boolean = |value: bool| if value { "true" } else { "false" };
+
+        add("Name", &self.name);
+        add("Exec", &self.exec());
+        add(
+            "Terminal",
+            boolean(self.command.terminal.unwrap_or_default()),
+        );
+        if let Some(icon) = &self.command.icon {
+            add("Icon", &self.placeholders.substitute(icon));
+        }
+        if !self.command.description.is_empty() {
+            add(
+                "Comment",
+                &self.placeholders.substitute(&self.command.description),
+            );
+        }
+        if let Some(working_dir) = &self.command.working_dir {
+            add("Path", &self.placeholders.substitute(working_dir));
+        }
+
+        let linux = self.linux;
+        if let Some(generic_name) = &linux.generic_name {
+            add("GenericName", generic_name);
+        }
+        if let Some(try_exec) = &linux.try_exec {
+            add("TryExec", &self.placeholders.substitute(try_exec));
+        }
+        if let Some(startup_wm_class) = &linux.startup_wm_class {
+            add("StartupWMClass", startup_wm_class);
+        }
+        for (key, values) in [
+            ("Categories", &linux.categories),
+            ("Implements", &linux.implements),
+            ("Keywords", &linux.keywords),
+            ("MimeType", &linux.mime_type),
+            ("NotShowIn", &linux.not_show_in),
+            ("OnlyShowIn", &linux.only_show_in),
+        ] {
+            if let Some(values) = values {
+                add(key, &list(values));
+            }
+        }
+        for (key, value) in [
+            ("DBusActivatable", linux.dbus_activatable),
+            ("Hidden", linux.hidden),
+            ("NoDisplay", linux.no_display),
+            ("PrefersNonDefaultGPU", linux.prefers_non_default_gpu),
+            ("SingleMainWindow", linux.single_main_window),
+            ("StartupNotify", linux.startup_notify),
+        ] {
+            if let Some(value) = value {
+                add(key, boolean(value));
+            }
+        }
+
+        entry
+    }
+
+    /// Returns the value of the `Exec` key.
+    ///
+    /// If the environment should be activated (or a precommand is specified)
+    /// the command is wrapped in a `bash -c` invocation that first runs the
+    /// precommand and prepends the `bin` directory of the prefix to `PATH`.
+    /// Field codes like `%F` are passed as arguments to the script so the
+    /// desktop environment can expand them.
+    fn exec(&self) -> String {
+        let (field_codes, arguments): (Vec<_>, Vec<_>) = self
+            .command
+            .command
+            .iter()
+            .map(|arg| self.placeholders.substitute(arg))
+            .partition(|arg| is_field_code(arg));
+
+        let activate = self.command.activate.unwrap_or(true);
+        if !activate && self.command.precommand.is_none() {
+            return arguments
+                .iter()
+                .map(|arg| quote_exec_arg(arg))
+                .chain(field_codes)
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+
+        let mut script = Vec::new();
+        if let Some(precommand) = &self.command.precommand {
+            script.push(self.placeholders.substitute(precommand));
+        }
+        if activate {
+            script.push(format!(
+                "export CONDA_PREFIX={prefix} PATH={bin}:\"$PATH\"",
+                prefix = shell_quote(&self.placeholders.substitute("{{ PREFIX }}")),
+                bin = shell_quote(&self.placeholders.substitute("{{ BIN_DIR }}")),
+            ));
+        }
+        let command = arguments
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        script.push(format!("exec {command} \"$@\""));
+
+        let script = script.join(" && ");
+        ["bash", "-c", script.as_str(), "bash"]
+            .into_iter()
+            .map(quote_exec_arg)
+            .chain(field_codes)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Returns the shared-mime-info XML that registers the glob patterns of
+    /// the mime types, if any.
+    fn mime_info(&self) -> Option<String> {
+        let glob_patterns = self.linux.glob_patterns.as_ref()?;
+        if glob_patterns.is_empty() {
+            return None;
+        }
+
+        let mut patterns = glob_patterns.iter().collect::<Vec<_>>();
+        patterns.sort();
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(
+            "<mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n",
+        );
+        for (mime_type, pattern) in patterns {
+            writeln!(
+                xml,
+                "  <mime-type type=\"{}\">\n    <glob pattern=\"{}\"/>\n  </mime-type>",
+                escape_xml(mime_type),
+                escape_xml(pattern)
+            )
+            .unwrap();
+        }
+        xml.push_str("</mime-info>\n");
+        Some(xml)
+    }
+
+    /// Refreshes the desktop database and, if mime types were changed, the
+    /// mime database. Failures are ignored because the tools are not
+    /// installed on every system.
+    fn update_databases(&self, update_mime: bool) {
+        if !self.options.update_databases {
+            return;
+        }
+
+        let mut commands = vec![(
+            "update-desktop-database",
+            self.options.data_dir.join("applications"),
+        )];
+        if update_mime {
+            commands.push(("update-mime-database", self.options.data_dir.join("mime")));
+        }
+        for (command, dir) in commands {
+            if let Err(e) = Command::new(command).arg(&dir).output() {
+                tracing::debug!("failed to run {command}: {e}");
+            }
+        }
+    }
+}
+
+/// Writes a file, creating its parent directories if needed.
+fn write_file(path: &Path, contents: &str) -> Result<(), MenuInstError> {
+    let to_error = |e| MenuInstError::IoError(format!("failed to write '{}'", path.display()), e);
+    if let Some(parent) = path.parent() {
+        fs_err::create_dir_all(parent).map_err(to_error)?;
+    }
+    fs_err::write(path, contents).map_err(to_error)
+}
+
+/// Returns true if the argument is a field code of the desktop entry
+/// specification, e.g. `%f` or `%U`.
+fn is_field_code(arg: &str) -> bool {
+    arg.len() == 2 && arg.starts_with('%')
+}
+
+/// Escapes the characters of a value that have a special meaning in a
+/// `.desktop` file.
+fn escape_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+/// Quotes an argument of the `Exec` key if it contains reserved characters.
+fn quote_exec_arg(arg: &str) -> String {
+    const RESERVED: &[char] = &[
+        ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(',
+        ')', '`',
+    ];
+    if !arg.is_empty() && !arg.contains(RESERVED) {
+        return arg.replace('%', "%%");
+    }
+
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '`' | '$' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '%' => quoted.push_str("%%"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quotes an argument for a POSIX shell.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Escapes a value for use in an XML attribute.
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rattler_conda_types::{menuinst::MenuInstSchema, Platform};
+
+    use super::{quote_exec_arg, LinuxMenu};
+    use crate::{
+        get_test_data_dir,
+        install::menuinst::{MenuInstOptions, Placeholders},
+    };
+
+    #[test]
+    fn test_quote_exec_arg() {
+        assert_eq!(quote_exec_arg("/usr/bin/app"), "/usr/bin/app");
+        assert_eq!(quote_exec_arg("with space"), "\"with space\"");
+        assert_eq!(quote_exec_arg("$HOME"), "\"\\$HOME\"");
+        assert_eq!(quote_exec_arg("100%"), "100%%");
+    }
+
+    #[test]
+    fn test_install_and_remove() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let options = MenuInstOptions {
+            data_dir: data_dir.path().to_path_buf(),
+            update_databases: false,
+        };
+        let schema =
+            MenuInstSchema::from_path(&get_test_data_dir().join("menuinst/example.json")).unwrap();
+        let item = &schema.menu_items[0];
+        let linux = item.platforms.linux.as_ref().unwrap();
+        let placeholders = Placeholders::new(Path::new("/opt/conda/envs/test"), Platform::Linux64);
+        let menu = LinuxMenu::new(
+            &schema.menu_name,
+            item.command.merge(&linux.base),
+            &linux.specific,
+            &placeholders,
+            &options,
+        );
+
+        let created = menu.install(false).unwrap();
+        assert_eq!(
+            created,
+            vec![
+                data_dir
+                    .path()
+                    .join("applications/example-menu_example.desktop"),
+                data_dir
+                    .path()
+                    .join("mime/packages/example-menu_example.xml"),
+            ]
+        );
+
+        let desktop_entry = std::fs::read_to_string(&created[0]).unwrap();
+        assert!(desktop_entry.starts_with("[Desktop Entry]\n"));
+        assert!(desktop_entry.contains("\nName=Example\n"));
+        assert!(desktop_entry.contains("\nTerminal=true\n"));
+        assert!(desktop_entry.contains("\nIcon=/opt/conda/envs/test/Menu/example.png\n"));
+        assert!(desktop_entry.contains("\nCategories=Science;Utility;\n"));
+        assert!(desktop_entry.contains("\nMimeType=application/x-example;\n"));
+        let exec = desktop_entry
+            .lines()
+            .find_map(|line| line.strip_prefix("Exec="))
+            .unwrap();
+        assert!(exec.starts_with("bash -c "));
+        assert!(exec.contains("/opt/conda/envs/test/bin/example"));
+        assert!(exec.ends_with(" bash %F"));
+
+        let mime_info = std::fs::read_to_string(&created[1]).unwrap();
+        assert!(mime_info.contains("<glob pattern=\"*.example\"/>"));
+
+        assert_eq!(menu.remove().unwrap(), created);
+        assert!(!created[0].exists());
+        assert!(menu.remove().unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_activate_and_precreate() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let options = MenuInstOptions {
+            data_dir: data_dir.path().to_path_buf(),
+            update_databases: false,
+        };
+        let schema =
+            MenuInstSchema::from_path(&get_test_data_dir().join("menuinst/example.json")).unwrap();
+        let item = &schema.menu_items[0];
+        let linux = item.platforms.linux.as_ref().unwrap();
+        let placeholders = Placeholders::new(Path::new("/opt/conda/envs/test"), Platform::Linux64);
+
+        let precreated = data_dir.path().join("precreated");
+        let mut command = item.command.merge(&linux.base);
+        command.activate = None;
+        command.precreate = Some(format!("touch '{}'", precreated.display()));
+        let menu = LinuxMenu::new(
+            &schema.menu_name,
+            command,
+            &linux.specific,
+            &placeholders,
+            &options,
+        );
+
+        // The environment is activated by default.
+        assert!(menu
+            .desktop_entry()
+            .contains("export CONDA_PREFIX='/opt/conda/envs/test'"));
+
+        menu.install(false).unwrap();
+        assert!(!precreated.exists());
+        menu.install(true).unwrap();
+        assert!(precreated.exists());
+    }
+}
@@ -0,0 +1,284 @@
+//! Creation and removal of menu shortcuts for packages that ship menuinst
+//! files (`Menu/*.json`).
+//!
+//! The [`super::InstallDriver`] creates the shortcuts of installed packages
+//! during post-processing and removes the shortcuts of removed packages
+//! during pre-processing if it is configured with [`MenuInstOptions`].
+//! Currently only Linux (XDG desktop entries) is supported. Icons are
+//! referenced from the prefix and are not copied into the XDG icon theme
+//! directories.
+
+mod linux;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{menuinst::MenuInstSchema, Platform, PrefixRecord};
+
+use super::link_script::LinkScriptPolicy;
+
+pub use linux::LinuxMenu;
+
+/// An error that can occur while creating or removing menu shortcuts.
+#[derive(Debug, thiserror::Error)]
+pub enum MenuInstError {
+    /// A menuinst file could not be parsed.
+    #[error("failed to parse menuinst file '{0}'")]
+    InvalidMenuFile(PathBuf, #[source] std::io::Error),
+
+    /// The data directory of the user could not be determined.
+    #[error("failed to determine the data directory of the user")]
+    UnknownDataDir,
+
+    /// A generic IO error occurred.
+    #[error("{0}")]
+    IoError(String, #[source] std::io::Error),
+}
+
+/// Options that determine where menu shortcuts are created.
+#[derive(Debug, Clone)]
+pub struct MenuInstOptions {
+    /// The directory in which the shortcuts are created. On Linux this is the
+    /// XDG data directory (`$XDG_DATA_HOME` or `~/.local/share`).
+    pub data_dir: PathBuf,
+
+    /// Whether to refresh the desktop and mime databases after shortcuts have
+    /// been added or removed.
+    pub update_databases: bool,
+}
+
+impl MenuInstOptions {
+    /// Creates the options for the data directory of the current user.
+    ///
+    /// Returns an error if the data directory cannot be determined (e.g.
+    /// because `$HOME` is not set) instead of creating the shortcuts relative
+    /// to the current directory.
+    pub fn from_env() -> Result<Self, MenuInstError> {
+        Ok(Self {
+            data_dir: dirs::data_dir().ok_or(MenuInstError::UnknownDataDir)?,
+            update_databases: true,
+        })
+    }
+}
+
+/// Returns the paths of the menuinst files installed by a package.
+fn menu_files(record: &PrefixRecord) -> impl Iterator<Item = &Path> + '_ {
+    record
+        .paths_data
+        .paths
+        .iter()
+        .map(|entry| entry.relative_path.as_path())
+        .filter(|path| {
+            path.parent() == Some(Path::new("Menu"))
+                && path.extension().map_or(false, |ext| ext == "json")
+        })
+}
+
+/// Creates the shortcuts of a package that is installed in `prefix`. Returns
+/// the files that were created.
+///
+/// The `precreate` commands of the menu items are treated like link scripts:
+/// they only run if `link_script_policy` is given (i.e. link scripts are
+/// executed) and allows the scripts of the package.
+pub fn install_menu_items(
+    prefix: &Path,
+    record: &PrefixRecord,
+    platform: Platform,
+    options: &MenuInstOptions,
+    link_script_policy: Option<&LinkScriptPolicy>,
+) -> Result<Vec<PathBuf>, MenuInstError> {
+    let run_precreate = link_script_policy.map_or(false, |policy| {
+        policy
+            .skip_status(&record.repodata_record.package_record.name)
+            .is_none()
+    });
+    process_menu_items(
+        prefix,
+        record,
+        platform,
+        options,
+        MenuAction::Install { run_precreate },
+    )
+}
+
+/// Removes the shortcuts of a package that is installed in `prefix`. Returns
+/// the files that were removed.
+pub fn remove_menu_items(
+    prefix: &Path,
+    record: &PrefixRecord,
+    platform: Platform,
+    options: &MenuInstOptions,
+) -> Result<Vec<PathBuf>, MenuInstError> {
+    process_menu_items(prefix, record, platform, options, MenuAction::Remove)
+}
+
+#[derive(Copy, Clone)]
+enum MenuAction {
+    Install { run_precreate: bool },
+    Remove,
+}
+
+fn process_menu_items(
+    prefix: &Path,
+    record: &PrefixRecord,
+    platform: Platform,
+    options: &MenuInstOptions,
+    action: MenuAction,
+) -> Result<Vec<PathBuf>, MenuInstError> {
+    let mut paths = Vec::new();
+    for menu_file in menu_files(record) {
+        if !platform.is_linux() {
+            tracing::debug!(
+                "skipping menu shortcuts of {}, menuinst is not supported on {platform}",
+                menu_file.display()
+            );
+            continue;
+        }
+
+        let path = prefix.join(menu_file);
+        let schema = MenuInstSchema::from_path(&path)
+            .map_err(|e| MenuInstError::InvalidMenuFile(path.clone(), e))?;
+        let placeholders = Placeholders::new(prefix, platform);
+        for item in &schema.menu_items {
+            let Some(linux) = &item.platforms.linux else {
+                continue;
+            };
+            let menu = LinuxMenu::new(
+                &schema.menu_name,
+                item.command.merge(&linux.base),
+                &linux.specific,
+                &placeholders,
+                options,
+            );
+            paths.extend(match action {
+                MenuAction::Install { run_precreate } => menu.install(run_precreate)?,
+                MenuAction::Remove => menu.remove()?,
+            });
+        }
+    }
+    Ok(paths)
+}
+
+/// The values of the `{{ PLACEHOLDER }}`s that can be used in menuinst files.
+#[derive(Debug, Clone)]
+pub struct Placeholders {
+    values: HashMap<&'static str, String>,
+    is_base: bool,
+}
+
+impl Placeholders {
+    /// Determines the placeholder values for a prefix.
+    ///
+    /// Environments are assumed to follow the conda layout in which named
+    /// environments are located in the `envs` directory of the base prefix.
+    pub fn new(prefix: &Path, platform: Platform) -> Self {
+        let base_prefix = prefix
+            .parent()
+            .filter(|parent| parent.file_name().map_or(false, |name| name == "envs"))
+            .and_then(Path::parent)
+            .unwrap_or(prefix);
+        let is_base = base_prefix == prefix;
+        let (bin_dir, python, icon_ext) = if platform.is_windows() {
+            (prefix.join("Library/bin"), prefix.join("python.exe"), "ico")
+        } else {
+            (prefix.join("bin"), prefix.join("bin/python"), "png")
+        };
+        let file_name = |path: &Path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+
+        let mut values = HashMap::new();
+        values.insert("PREFIX", prefix.display().to_string());
+        values.insert("BASE_PREFIX", base_prefix.display().to_string());
+        values.insert("DISTRIBUTION_NAME", file_name(base_prefix));
+        values.insert("ENV_NAME", file_name(prefix));
+        values.insert("MENU_DIR", prefix.join("Menu").display().to_string());
+        values.insert("BIN_DIR", bin_dir.display().to_string());
+        values.insert("PYTHON", python.display().to_string());
+        values.insert("ICON_EXT", icon_ext.to_string());
+        values.insert(
+            "HOME",
+            dirs::home_dir()
+                .map(|home| home.display().to_string())
+                .unwrap_or_default(),
+        );
+        Self { values, is_base }
+    }
+
+    /// Returns true if the prefix is the base environment.
+    pub fn is_base(&self) -> bool {
+        self.is_base
+    }
+
+    /// Replaces all known placeholders in `text`.
+    pub fn substitute(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else {
+                break;
+            };
+            let key = rest[start + 2..start + end].trim();
+            result.push_str(&rest[..start]);
+            match self.values.get(key) {
+                Some(value) => result.push_str(value),
+                None => result.push_str(&rest[start..start + end + 2]),
+            }
+            rest = &rest[start + end + 2..];
+        }
+        result.push_str(rest);
+        result
+    }
+}
+
+/// Turns a name into something that can safely be used as a file name.
+fn slugify(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rattler_conda_types::Platform;
+
+    use super::{slugify, Placeholders};
+
+    #[test]
+    fn test_placeholders() {
+        let placeholders =
+            Placeholders::new(Path::new("/opt/conda/envs/my-env"), Platform::Linux64);
+        assert!(!placeholders.is_base());
+        assert_eq!(
+            placeholders.substitute("{{ PREFIX }}/bin/app {{BASE_PREFIX}} {{ UNKNOWN }}"),
+            "/opt/conda/envs/my-env/bin/app /opt/conda {{ UNKNOWN }}"
+        );
+        assert_eq!(
+            placeholders.substitute("{{ MENU_DIR }}/icon.{{ ICON_EXT }}"),
+            "/opt/conda/envs/my-env/Menu/icon.png"
+        );
+        assert_eq!(placeholders.substitute("{{ ENV_NAME }}"), "my-env");
+
+        let placeholders = Placeholders::new(Path::new("/opt/conda"), Platform::Linux64);
+        assert!(placeholders.is_base());
+        assert_eq!(placeholders.substitute("{{ DISTRIBUTION_NAME }}"), "conda");
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Example Menu"), "example-menu");
+        assert_eq!(slugify("Spyder 5 (my-env)"), "spyder-5-my-env");
+    }
+}
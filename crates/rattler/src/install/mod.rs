@@ -17,13 +17,21 @@
 //! is used to verify that the file was not tampered with.
 pub mod apple_codesign;
 mod clobber_registry;
+mod clone;
+mod disk_space;
 mod driver;
 mod entry_point;
+mod history;
+mod journal;
 pub mod link;
 pub mod link_script;
+pub mod menuinst;
+mod pyc;
 mod python;
+mod relocate;
 mod transaction;
 pub mod unlink;
+mod verify;
 
 mod installer;
 #[cfg(test)]
@@ -40,27 +48,39 @@ use std::{
 };
 
 pub use apple_codesign::AppleCodeSignBehavior;
+pub use clone::{clone_prefix, CloneError, CloneOptions, CloneResult};
+pub use disk_space::{check_disk_space, estimate_disk_space, DiskSpaceError, DiskSpaceEstimate};
 pub use driver::InstallDriver;
 use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
+pub use history::{History, HistoryError, HistoryPackage, Revision};
 #[cfg(feature = "indicatif")]
 pub use installer::{
     DefaultProgressFormatter, IndicatifReporter, IndicatifReporterBuilder, Placement,
     ProgressFormatter,
 };
-pub use installer::{Installer, InstallerError, Reporter};
+pub use installer::{InstallationResult, Installer, InstallerError, Reporter};
 use itertools::Itertools;
+pub use journal::{JournalError, TransactionJournal};
 pub use link::{link_file, LinkFileError, LinkMethod};
+pub use link_script::{LinkScriptOutput, LinkScriptPolicy, LinkScriptStatus, LinkScriptType};
+pub use menuinst::MenuInstOptions;
+pub use pyc::{PycCompilationError, PycCompilationFailure, PycCompilationResult};
 pub use python::PythonInfo;
 use rattler_conda_types::{
     package::{IndexJson, LinkJson, NoArchLinks, PackageFile, PathsJson},
     prefix_record::PathsEntry,
     Platform,
 };
+pub use relocate::{relocate_prefix, RelocateError, RelocateOptions};
 use simple_spawn_blocking::Cancelled;
 use tokio::task::JoinError;
 use tracing::instrument;
 pub use transaction::{Transaction, TransactionError, TransactionOperation};
 pub use unlink::unlink_package;
+pub use verify::{
+    repair_prefix, verify_prefix, IssueKind, PathIssue, VerificationReport, VerifyError,
+    VerifyOptions,
+};
 
 use crate::install::entry_point::{
     create_unix_python_entry_point, create_windows_python_entry_point,
@@ -115,6 +135,10 @@ pub enum InstallError {
     /// Post-processing involves removing clobbered paths.
     #[error("failed to post process the environment (unclobbering)")]
     PostProcessFailed(#[source] std::io::Error),
+
+    /// The paths that are about to be linked could not be journaled.
+    #[error("failed to journal the paths of the package")]
+    FailedToJournal(#[source] JournalError),
 }
 
 impl From<Cancelled> for InstallError {
@@ -254,6 +278,20 @@ pub async fn link_package(
     target_dir: &Path,
     driver: &InstallDriver,
     options: InstallOptions,
+) -> Result<Vec<PathsEntry>, InstallError> {
+    link_package_journaled(package_dir, target_dir, driver, options, None).await
+}
+
+/// Same as [`link_package`] but records all the paths that will be created in
+/// the `journal` before any of them are touched, and backs up the files that
+/// already exist at those paths. This ensures that the prefix can be restored
+/// if linking fails halfway or is cancelled.
+pub(crate) async fn link_package_journaled(
+    package_dir: &Path,
+    target_dir: &Path,
+    driver: &InstallDriver,
+    options: InstallOptions,
+    journal: Option<&TransactionJournal>,
 ) -> Result<Vec<PathsEntry>, InstallError> {
     // Determine the target prefix for linking
     let target_prefix = options
@@ -322,6 +360,49 @@ pub async fn link_package(
         }
     }
 
+    if let Some(journal) = journal {
+        let entry_point_paths = link_json
+            .as_ref()
+            .zip(options.python_info.as_ref())
+            .map(|(link_json, python_info)| match &link_json.noarch {
+                NoArchLinks::Python(entry_points) => entry_points
+                    .entry_points
+                    .iter()
+                    .flat_map(|entry_point| {
+                        if platform.is_windows() {
+                            vec![
+                                python_info
+                                    .bin_dir
+                                    .join(format!("{}-script.py", &entry_point.command)),
+                                python_info
+                                    .bin_dir
+                                    .join(format!("{}.exe", &entry_point.command)),
+                            ]
+                        } else {
+                            vec![python_info.bin_dir.join(&entry_point.command)]
+                        }
+                    })
+                    .collect(),
+                NoArchLinks::Generic => Vec::new(),
+            })
+            .unwrap_or_default();
+        let linked_paths = final_paths
+            .iter()
+            .map(|(_, computed_path)| computed_path.clone())
+            .chain(entry_point_paths)
+            .collect::<Vec<_>>();
+
+        // Move files that are in the way out first, otherwise rolling back
+        // would remove them without being able to restore them.
+        journal
+            .backup_existing(&linked_paths)
+            .await
+            .map_err(InstallError::FailedToJournal)?;
+        journal
+            .record_linked(linked_paths)
+            .map_err(InstallError::FailedToJournal)?;
+    }
+
     // Figure out all the directories that we are going to need
     let mut directories_to_construct = HashSet::new();
     for (_, computed_path) in final_paths.iter() {
@@ -503,7 +584,16 @@ pub async fn link_package(
     let mut paths = Vec::with_capacity(number_of_paths_entries);
     let mut out_of_order_queue = BinaryHeap::<OrderWrapper<PathsEntry>>::with_capacity(100);
     while let Some(link_result) = pending_futures.next().await {
-        for (index, data) in link_result? {
+        let link_result = match link_result {
+            Ok(link_result) => link_result,
+            Err(e) => {
+                // Wait for the files that are still being linked, otherwise they
+                // might be created after the error has been handled.
+                while pending_futures.next().await.is_some() {}
+                return Err(e);
+            }
+        };
+        for (index, data) in link_result {
             if index == paths.len() {
                 // If this is the next element expected in the sorted list, add it immediately.
                 // This basically means the future finished in order.
@@ -0,0 +1,526 @@
+//! Compilation of the python files of noarch python packages to bytecode.
+//!
+//! The files of noarch python packages are installed as plain `.py` files.
+//! Python writes the compiled bytecode to `__pycache__` on first import, but
+//! that is slow and often not possible in read-only environments. The
+//! [`super::InstallDriver`] can therefore compile the files right after they
+//! have been installed (see [`compile_pyc_files`]). The generated files are
+//! recorded in the [`PrefixRecord`] of the package as [`PathType::PycFile`]
+//! so they are removed again when the package is unlinked.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rattler_conda_types::{
+    prefix_record::{PathType, PathsEntry},
+    PackageName, PrefixRecord,
+};
+
+use super::PythonInfo;
+
+/// The number of files passed to a single python process.
+const BATCH_SIZE: usize = 500;
+
+/// A file that could not be compiled.
+#[derive(Debug, Clone)]
+pub struct PycCompilationFailure {
+    /// The package the file belongs to.
+    pub package: PackageName,
+
+    /// The path of the source file relative to the prefix.
+    pub path: PathBuf,
+
+    /// The error reported by python for the file.
+    pub message: String,
+}
+
+/// The result of compiling the python files of a set of packages.
+#[derive(Debug, Clone, Default)]
+pub struct PycCompilationResult {
+    /// The number of files that were compiled.
+    pub compiled: usize,
+
+    /// The files that could not be compiled.
+    pub failed: Vec<PycCompilationFailure>,
+}
+
+/// An error that prevents compiling any files.
+#[derive(Debug, thiserror::Error)]
+pub enum PycCompilationError {
+    /// The python interpreter of the environment could not be executed.
+    #[error("failed to run '{0}'")]
+    FailedToRunPython(PathBuf, #[source] std::io::Error),
+
+    /// The python interpreter did not report its bytecode cache tag.
+    #[error("failed to determine the bytecode cache tag of '{0}'")]
+    UnknownCacheTag(PathBuf),
+
+    /// The updated prefix record could not be written.
+    #[error("failed to write '{0}'")]
+    FailedToWriteRecord(PathBuf, #[source] std::io::Error),
+}
+
+/// A python file that should be compiled.
+struct SourceFile {
+    record: usize,
+    path: PathBuf,
+    pyc_path: PathBuf,
+}
+
+/// Compiles the `.py` files of the noarch python packages in `records` with
+/// the python interpreter of the environment. Files are compiled in batches,
+/// at most one python process per available CPU runs at a time.
+///
+/// The generated `.pyc` files are added to the records which are written
+/// back to `conda-meta`.
+pub fn compile_pyc_files(
+    target_prefix: &Path,
+    records: &mut [PrefixRecord],
+    python_info: &PythonInfo,
+) -> Result<PycCompilationResult, PycCompilationError> {
+    let python = target_prefix.join(python_info.path());
+    let site_packages = &python_info.site_packages_path;
+
+    // Collect all files that need to be compiled.
+    let mut sources = Vec::new();
+    for (idx, record) in records.iter().enumerate() {
+        if !record.repodata_record.package_record.noarch.is_python() {
+            continue;
+        }
+        for entry in &record.paths_data.paths {
+            let path = &entry.relative_path;
+            if matches!(entry.path_type, PathType::HardLink | PathType::SoftLink)
+                && path.starts_with(site_packages)
+                && path.extension().map_or(false, |ext| ext == "py")
+            {
+                sources.push((idx, path.clone()));
+            }
+        }
+    }
+    if sources.is_empty() {
+        return Ok(PycCompilationResult::default());
+    }
+
+    // Skip the files that have already been compiled.
+    let cache_tag = cache_tag(&python)?;
+    let existing = records
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, record)| {
+            record
+                .paths_data
+                .paths
+                .iter()
+                .map(move |entry| (idx, entry.relative_path.as_path()))
+        })
+        .collect::<HashSet<_>>();
+    let sources = sources
+        .into_iter()
+        .filter_map(|(record, path)| {
+            let pyc_path = pyc_path(&path, &cache_tag)?;
+            Some(SourceFile {
+                record,
+                path,
+                pyc_path,
+            })
+        })
+        .filter(|source| !existing.contains(&(source.record, source.pyc_path.as_path())))
+        .collect::<Vec<_>>();
+
+    // Compile the files in batches, the workers pick up the next batch until
+    // all of them are done.
+    let python = python.as_path();
+    let batches = sources.chunks(BATCH_SIZE).collect::<Vec<_>>();
+    let workers = std::thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(batches.len());
+    let next_batch = AtomicUsize::new(0);
+    let mut outcomes = std::thread::scope(|scope| {
+        let (batches, next_batch) = (&batches, &next_batch);
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(move || {
+                    let mut outcomes = Vec::new();
+                    loop {
+                        let idx = next_batch.fetch_add(1, Ordering::Relaxed);
+                        let Some(batch) = batches.get(idx) else {
+                            break Ok::<_, PycCompilationError>(outcomes);
+                        };
+                        outcomes.push((idx, compile_batch(python, target_prefix, batch)?));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut outcomes = Vec::new();
+        for handle in handles {
+            outcomes.extend(handle.join().unwrap_or_else(std::panic::resume_unwind)?);
+        }
+        Ok::<_, PycCompilationError>(outcomes)
+    })?;
+    outcomes.sort_by_key(|(idx, _)| *idx);
+
+    // Record the files that were created.
+    let mut result = PycCompilationResult::default();
+    let mut updated = HashSet::new();
+    for (batch, errors) in batches
+        .iter()
+        .zip(outcomes.into_iter().map(|(_, errors)| errors))
+    {
+        for (source, error) in batch.iter().zip(errors) {
+            let record = &mut records[source.record];
+            if let Some(message) = error {
+                result.failed.push(PycCompilationFailure {
+                    package: record.repodata_record.package_record.name.clone(),
+                    path: source.path.clone(),
+                    message,
+                });
+            } else {
+                record.paths_data.paths.push(PathsEntry {
+                    relative_path: source.pyc_path.clone(),
+                    original_path: None,
+                    path_type: PathType::PycFile,
+                    no_link: false,
+                    sha256: None,
+                    sha256_in_prefix: None,
+                    size_in_bytes: None,
+                    file_mode: None,
+                    prefix_placeholder: None,
+                });
+                record.files.push(source.pyc_path.clone());
+                updated.insert(source.record);
+                result.compiled += 1;
+            }
+        }
+    }
+
+    for idx in updated {
+        let record = &records[idx];
+        let path = target_prefix.join("conda-meta").join(record.file_name());
+        record
+            .write_to_path(&path, true)
+            .map_err(|e| PycCompilationError::FailedToWriteRecord(path, e))?;
+    }
+
+    for failure in &result.failed {
+        tracing::warn!(
+            "failed to compile {} of {}",
+            failure.path.display(),
+            failure.package.as_normalized()
+        );
+    }
+
+    Ok(result)
+}
+
+/// Runs python to compile a batch of files. Returns for every file of the
+/// batch the error message if it failed to compile.
+///
+/// Existing bytecode files are removed first, a stale file left behind by a
+/// failed compilation would otherwise be mistaken for a successful one.
+fn compile_batch(
+    python: &Path,
+    target_prefix: &Path,
+    batch: &[SourceFile],
+) -> Result<Vec<Option<String>>, PycCompilationError> {
+    let mut errors = batch
+        .iter()
+        .map(
+            |source| match std::fs::remove_file(target_prefix.join(&source.pyc_path)) {
+                Ok(()) => None,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => Some(format!(
+                    "failed to remove the existing '{}': {e}",
+                    source.pyc_path.display()
+                )),
+            },
+        )
+        .collect::<Vec<_>>();
+    let to_compile = batch
+        .iter()
+        .zip(&errors)
+        .filter(|(_, error)| error.is_none())
+        .map(|(source, _)| source)
+        .collect::<Vec<_>>();
+    if to_compile.is_empty() {
+        return Ok(errors);
+    }
+
+    let to_error = |e| PycCompilationError::FailedToRunPython(python.to_path_buf(), e);
+    let mut child = Command::new(python)
+        .args(["-Wi", "-m", "compileall", "-q", "-l", "-i", "-"])
+        .current_dir(target_prefix)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(to_error)?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    for source in &to_compile {
+        writeln!(stdin, "{}", source.path.display()).map_err(to_error)?;
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().map_err(to_error)?;
+    let output = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let messages = compile_errors(&output);
+    for (source, error) in batch.iter().zip(&mut errors) {
+        if error.is_some() || target_prefix.join(&source.pyc_path).is_file() {
+            continue;
+        }
+        // Fall back to the complete output if python did not report an error
+        // for the file, e.g. because the interpreter crashed.
+        let message = messages
+            .get(source.path.to_string_lossy().as_ref())
+            .map_or(output.as_str(), String::as_str);
+        *error = Some(message.trim().to_string());
+    }
+
+    Ok(errors)
+}
+
+/// Splits the output of `compileall` into the error messages of the
+/// individual files. Every error starts with a line of the form
+/// `*** Error compiling 'path'...`.
+fn compile_errors(output: &str) -> HashMap<String, String> {
+    let mut errors = HashMap::new();
+    let mut current: Option<(String, String)> = None;
+    for line in output.lines() {
+        if let Some(path) = line
+            .strip_prefix("*** Error compiling ")
+            .and_then(|rest| rest.strip_suffix("..."))
+        {
+            errors.extend(current.take());
+            let path = path
+                .strip_prefix(['\'', '"'])
+                .and_then(|path| path.strip_suffix(['\'', '"']))
+                .unwrap_or(path);
+            current = Some((path.to_string(), String::new()));
+        } else if let Some((_, message)) = &mut current {
+            message.push_str(line);
+            message.push('\n');
+        }
+    }
+    errors.extend(current);
+    errors
+}
+
+/// Asks the python interpreter for the tag it uses for bytecode files, e.g.
+/// `cpython-311`.
+fn cache_tag(python: &Path) -> Result<String, PycCompilationError> {
+    let output = Command::new(python)
+        .args(["-c", "import sys; print(sys.implementation.cache_tag)"])
+        .output()
+        .map_err(|e| PycCompilationError::FailedToRunPython(python.to_path_buf(), e))?;
+    let tag = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || tag.is_empty() || tag == "None" {
+        return Err(PycCompilationError::UnknownCacheTag(python.to_path_buf()));
+    }
+    Ok(tag)
+}
+
+/// Returns the location of the bytecode file of a python source file, e.g.
+/// `foo/__pycache__/bar.cpython-311.pyc` for `foo/bar.py`.
+fn pyc_path(path: &Path, cache_tag: &str) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_str()?;
+    Some(
+        path.parent()?
+            .join("__pycache__")
+            .join(format!("{stem}.{cache_tag}.pyc")),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{compile_errors, pyc_path};
+
+    /// A fake python interpreter that "compiles" every file it is given,
+    /// except for files named `broken*.py`. Every invocation to compile files is
+    /// logged to `batches.log`.
+    #[cfg(unix)]
+    const STUB_PYTHON: &str = r#"#!/bin/sh
+if [ "$1" = "-c" ]; then
+    echo stub-311
+    exit 0
+fi
+echo batch >> batches.log
+while read -r file; do
+    case "$file" in
+        */broken*.py)
+            echo "*** Error compiling '$file'..."
+            echo "SyntaxError: invalid syntax ($file)"
+            ;;
+        *)
+            mkdir -p "$(dirname "$file")/__pycache__"
+            touch "$(dirname "$file")/__pycache__/$(basename "$file" .py).stub-311.pyc"
+            ;;
+    esac
+done
+"#;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_compile_pyc_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use rattler_conda_types::{
+            prefix_record::{PathType, PathsEntry},
+            NoArchType, PackageName, PackageRecord, Platform, PrefixRecord, RepoDataRecord,
+            Version,
+        };
+
+        use super::{compile_pyc_files, BATCH_SIZE};
+        use crate::install::PythonInfo;
+
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        let python_info =
+            PythonInfo::from_version(&"3.11".parse::<Version>().unwrap(), Platform::Linux64)
+                .unwrap();
+        let python = prefix.join(python_info.path());
+        std::fs::create_dir_all(python.parent().unwrap()).unwrap();
+        std::fs::write(&python, STUB_PYTHON).unwrap();
+        std::fs::set_permissions(&python, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::create_dir_all(prefix.join("conda-meta")).unwrap();
+
+        // Enough files to require two batches, plus two that fail to compile.
+        let site_packages = python_info.site_packages_path.join("foo");
+        std::fs::create_dir_all(prefix.join(&site_packages)).unwrap();
+        let paths = (0..BATCH_SIZE)
+            .map(|idx| format!("mod{idx}.py"))
+            .chain(["broken.py", "broken2.py", "data.txt"].map(String::from))
+            .map(|name| {
+                let path = site_packages.join(name);
+                std::fs::write(prefix.join(&path), "").unwrap();
+                PathsEntry {
+                    relative_path: path,
+                    original_path: None,
+                    path_type: PathType::HardLink,
+                    no_link: false,
+                    sha256: None,
+                    sha256_in_prefix: None,
+                    size_in_bytes: None,
+                    file_mode: None,
+                    prefix_placeholder: None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut package_record = PackageRecord::new(
+            PackageName::new_unchecked("foo"),
+            "1.0".parse::<Version>().unwrap(),
+            String::from("pyh_0"),
+        );
+        package_record.noarch = NoArchType::python();
+        let record = PrefixRecord::from_repodata_record(
+            RepoDataRecord {
+                package_record,
+                file_name: String::from("foo-1.0-pyh_0.conda"),
+                url: "https://conda.anaconda.org/conda-forge/noarch/foo-1.0-pyh_0.conda"
+                    .parse()
+                    .unwrap(),
+                channel: String::from("https://conda.anaconda.org/conda-forge/"),
+            },
+            None,
+            None,
+            paths,
+            None,
+            None,
+        );
+        let mut records = vec![record];
+
+        // A stale bytecode file of a file that fails to compile is not counted.
+        let stale = site_packages.join("__pycache__/broken.stub-311.pyc");
+        std::fs::create_dir_all(prefix.join(stale.parent().unwrap())).unwrap();
+        std::fs::write(prefix.join(&stale), "").unwrap();
+
+        let result = compile_pyc_files(prefix, &mut records, &python_info).unwrap();
+        assert_eq!(result.compiled, BATCH_SIZE);
+        assert_eq!(result.failed.len(), 2);
+        assert!(!prefix.join(&stale).exists());
+
+        // Every failure carries the error of its own file.
+        assert_eq!(result.failed[0].path, site_packages.join("broken.py"));
+        assert_eq!(
+            result.failed[0].message,
+            format!(
+                "SyntaxError: invalid syntax ({})",
+                site_packages.join("broken.py").display()
+            )
+        );
+        assert_eq!(result.failed[1].path, site_packages.join("broken2.py"));
+        assert!(!result.failed[1].message.contains("broken.py"));
+        assert_eq!(
+            std::fs::read_to_string(prefix.join("batches.log"))
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+
+        // The compiled files are added to the record that is written to disk.
+        let written =
+            PrefixRecord::from_path(prefix.join("conda-meta/foo-1.0-pyh_0.json")).unwrap();
+        let pyc_files = written
+            .paths_data
+            .paths
+            .iter()
+            .filter(|entry| entry.path_type == PathType::PycFile)
+            .map(|entry| entry.relative_path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(pyc_files.len(), BATCH_SIZE);
+        assert!(pyc_files.contains(&site_packages.join("__pycache__/mod0.stub-311.pyc")));
+        assert_eq!(written.paths_data.paths, records[0].paths_data.paths);
+
+        // Files that are already compiled are skipped.
+        let result = compile_pyc_files(prefix, &mut records, &python_info).unwrap();
+        assert_eq!(result.compiled, 0);
+        assert_eq!(result.failed.len(), 2);
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let output = "\
+*** Error compiling 'foo/a.py'...
+  File \"foo/a.py\", line 1
+SyntaxError: invalid syntax
+*** Error compiling \"foo/it's.py\"...
+SyntaxError: unterminated string literal
+";
+        let errors = compile_errors(output);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors["foo/a.py"],
+            "  File \"foo/a.py\", line 1\nSyntaxError: invalid syntax\n"
+        );
+        assert_eq!(
+            errors["foo/it's.py"],
+            "SyntaxError: unterminated string literal\n"
+        );
+    }
+
+    #[test]
+    fn test_pyc_path() {
+        assert_eq!(
+            pyc_path(
+                Path::new("lib/python3.11/site-packages/foo/bar.py"),
+                "cpython-311"
+            ),
+            Some(PathBuf::from(
+                "lib/python3.11/site-packages/foo/__pycache__/bar.cpython-311.pyc"
+            ))
+        );
+    }
+}
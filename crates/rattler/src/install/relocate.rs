@@ -0,0 +1,604 @@
+//! Relocation of an installed environment to a new path.
+//!
+//! Files that contained a prefix placeholder when they were installed now
+//! contain the path of the prefix instead. [`relocate_prefix`] moves the
+//! environment and replaces the old path with the new one in all of these
+//! files, using the `prefix_placeholder` and `file_mode` that are stored in
+//! the [`PrefixRecord`]s of the environment. Python entry points and absolute
+//! symlinks into the environment are updated as well.
+//!
+//! The environment is moved with a rename, so the target must be on the same
+//! filesystem as the source. All files are rewritten to temporary files first;
+//! if that fails the environment is moved back and left untouched.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{
+    package::FileMode,
+    prefix_record::{PathType, PathsEntry},
+    Platform, PrefixRecord,
+};
+use rattler_digest::HashingWriter;
+
+use super::{
+    apple_codesign::{codesign, AppleCodeSignBehavior},
+    link::copy_and_replace_placeholders,
+};
+
+/// Options that determine how a prefix is relocated.
+#[derive(Debug, Clone, Default)]
+pub struct RelocateOptions {
+    /// The platform of the environment. Defaults to the current platform.
+    pub platform: Option<Platform>,
+
+    /// What to do with binaries that have to be re-signed on macOS ARM64.
+    pub apple_codesign_behavior: AppleCodeSignBehavior,
+}
+
+/// An error that can occur while relocating a prefix.
+#[derive(Debug, thiserror::Error)]
+pub enum RelocateError {
+    /// The packages installed in the prefix could not be determined.
+    #[error("failed to read the installed packages of '{0}'")]
+    FailedToReadRecords(PathBuf, #[source] std::io::Error),
+
+    /// The target path already exists.
+    #[error("the target prefix '{0}' already exists")]
+    TargetExists(PathBuf),
+
+    /// Binary files can only be relocated to a path that is not longer than
+    /// the current path because the length of the embedded strings cannot
+    /// change.
+    #[error("cannot relocate '{path}' because the new prefix is longer than the old prefix")]
+    PrefixTooLong {
+        /// A binary file that contains the prefix.
+        path: PathBuf,
+    },
+
+    /// The environment could not be moved.
+    #[error("failed to move '{0}' to '{1}'")]
+    FailedToMove(PathBuf, PathBuf, #[source] std::io::Error),
+
+    /// The target is on a different filesystem than the source. An
+    /// environment can only be relocated within the same filesystem.
+    #[error("cannot relocate '{0}' to '{1}' because it is on a different filesystem")]
+    CrossDevice(PathBuf, PathBuf),
+
+    /// A file could not be rewritten.
+    #[error("failed to update '{0}'")]
+    FailedToRewrite(PathBuf, #[source] std::io::Error),
+
+    /// A binary could not be re-signed.
+    #[error("failed to sign '{0}'")]
+    FailedToSign(PathBuf, #[source] super::LinkFileError),
+
+    /// The updated prefix record could not be written.
+    #[error("failed to write '{0}'")]
+    FailedToWriteRecord(PathBuf, #[source] std::io::Error),
+}
+
+/// Moves the environment at `source_prefix` to `target_prefix` and replaces
+/// all occurrences of the old path in the installed files. Returns the paths
+/// of the files that were rewritten, relative to the prefix.
+///
+/// `source_prefix` must be the path the environment was installed to. The
+/// target must not exist yet, must be on the same filesystem as the source
+/// and, if the environment contains binary files with an embedded prefix,
+/// must not be longer than the source. Menu shortcuts and untracked files are
+/// not updated.
+///
+/// If a file cannot be rewritten the environment is moved back to
+/// `source_prefix` unchanged. Only an error while replacing the files with
+/// their rewritten versions, which are renames within the same directory, can
+/// leave the environment partially relocated.
+pub fn relocate_prefix(
+    source_prefix: &Path,
+    target_prefix: &Path,
+    options: &RelocateOptions,
+) -> Result<Vec<PathBuf>, RelocateError> {
+    let platform = options.platform.unwrap_or_else(Platform::current);
+    let mut records = PrefixRecord::collect_from_prefix(source_prefix)
+        .map_err(|e| RelocateError::FailedToReadRecords(source_prefix.to_path_buf(), e))?;
+
+    if target_prefix.exists() {
+        return Err(RelocateError::TargetExists(target_prefix.to_path_buf()));
+    }
+
+    let old_prefix = prefix_string(source_prefix, platform);
+    let new_prefix = prefix_string(target_prefix, platform);
+
+    // Make sure all binary files can be patched before anything is moved.
+    if new_prefix.len() > old_prefix.len() && !platform.is_windows() {
+        let binary = records
+            .iter()
+            .flat_map(|record| record.paths_data.paths.iter())
+            .find(|entry| {
+                entry.prefix_placeholder.is_some() && entry.file_mode == Some(FileMode::Binary)
+            });
+        if let Some(entry) = binary {
+            return Err(RelocateError::PrefixTooLong {
+                path: entry.relative_path.clone(),
+            });
+        }
+    }
+
+    let move_error = |e| {
+        RelocateError::FailedToMove(source_prefix.to_path_buf(), target_prefix.to_path_buf(), e)
+    };
+    if let Some(parent) = target_prefix.parent() {
+        fs_err::create_dir_all(parent).map_err(move_error)?;
+    }
+    std::fs::rename(source_prefix, target_prefix).map_err(|e| {
+        if is_cross_device(&e) {
+            RelocateError::CrossDevice(source_prefix.to_path_buf(), target_prefix.to_path_buf())
+        } else {
+            move_error(e)
+        }
+    })?;
+
+    // Rewrite all files to temporary files first. If that fails nothing has
+    // been modified yet and the environment can be moved back.
+    let rewrites = match prepare_rewrites(
+        source_prefix,
+        target_prefix,
+        &records,
+        &old_prefix,
+        &new_prefix,
+        platform,
+        options.apple_codesign_behavior,
+    ) {
+        Ok(rewrites) => rewrites,
+        Err(e) => {
+            if let Err(move_back_error) = fs_err::rename(target_prefix, source_prefix) {
+                tracing::error!(
+                    "failed to move '{}' back to '{}': {move_back_error}",
+                    target_prefix.display(),
+                    source_prefix.display()
+                );
+            }
+            return Err(e);
+        }
+    };
+
+    let mut rewritten = Vec::with_capacity(rewrites.len());
+    let mut changed_records = Vec::new();
+    for rewrite in rewrites {
+        let entry = &mut records[rewrite.record].paths_data.paths[rewrite.entry];
+        let path = target_prefix.join(&entry.relative_path);
+        match rewrite.kind {
+            RewriteKind::File {
+                temp_file,
+                sha256_in_prefix,
+                size_in_bytes,
+            } => {
+                temp_file
+                    .persist(&path)
+                    .map_err(|e| RelocateError::FailedToRewrite(path.clone(), e.error))?;
+                entry.sha256_in_prefix = Some(sha256_in_prefix);
+                entry.size_in_bytes = Some(size_in_bytes);
+            }
+            RewriteKind::Symlink(new_link) => {
+                let to_error = |e| RelocateError::FailedToRewrite(path.clone(), e);
+                fs_err::remove_file(&path).map_err(to_error)?;
+                #[cfg(unix)]
+                fs_err::os::unix::fs::symlink(new_link, &path).map_err(to_error)?;
+                #[cfg(windows)]
+                fs_err::os::windows::fs::symlink_file(new_link, &path).map_err(to_error)?;
+            }
+        }
+        rewritten.push(entry.relative_path.clone());
+        if !changed_records.contains(&rewrite.record) {
+            changed_records.push(rewrite.record);
+        }
+    }
+
+    for idx in changed_records {
+        let record = &records[idx];
+        let path = target_prefix.join("conda-meta").join(record.file_name());
+        record
+            .write_to_path(&path, true)
+            .map_err(|e| RelocateError::FailedToWriteRecord(path, e))?;
+    }
+
+    Ok(rewritten)
+}
+
+/// A file of the environment that has been prepared for relocation.
+struct Rewrite {
+    record: usize,
+    entry: usize,
+    kind: RewriteKind,
+}
+
+enum RewriteKind {
+    /// The rewritten contents of a file, not yet moved over the original.
+    File {
+        temp_file: tempfile::NamedTempFile,
+        sha256_in_prefix: rattler_digest::Sha256Hash,
+        size_in_bytes: u64,
+    },
+
+    /// The new destination of a symlink.
+    Symlink(PathBuf),
+}
+
+/// Determines the changes to all files that refer to the old prefix without
+/// modifying any file of the environment.
+fn prepare_rewrites(
+    source_prefix: &Path,
+    target_prefix: &Path,
+    records: &[PrefixRecord],
+    old_prefix: &str,
+    new_prefix: &str,
+    platform: Platform,
+    apple_codesign_behavior: AppleCodeSignBehavior,
+) -> Result<Vec<Rewrite>, RelocateError> {
+    let mut rewrites = Vec::new();
+    for (record_idx, record) in records.iter().enumerate() {
+        for (entry_idx, entry) in record.paths_data.paths.iter().enumerate() {
+            let file_mode = match entry.path_type {
+                PathType::HardLink if entry.prefix_placeholder.is_some() => {
+                    entry.file_mode.unwrap_or(FileMode::Text)
+                }
+                PathType::UnixPythonEntryPoint | PathType::WindowsPythonEntryPointScript => {
+                    FileMode::Text
+                }
+                PathType::SoftLink => {
+                    if let Some(new_link) = relocate_symlink(source_prefix, target_prefix, entry)? {
+                        rewrites.push(Rewrite {
+                            record: record_idx,
+                            entry: entry_idx,
+                            kind: RewriteKind::Symlink(new_link),
+                        });
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some(kind) = relocate_file(
+                target_prefix,
+                entry,
+                old_prefix,
+                new_prefix,
+                platform,
+                file_mode,
+                apple_codesign_behavior,
+            )? {
+                rewrites.push(Rewrite {
+                    record: record_idx,
+                    entry: entry_idx,
+                    kind,
+                });
+            }
+        }
+    }
+    Ok(rewrites)
+}
+
+/// Returns true if an error of a rename indicates that the source and the
+/// destination are on different filesystems.
+fn is_cross_device(error: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        error.raw_os_error() == Some(libc::EXDEV)
+    }
+    #[cfg(windows)]
+    {
+        error.raw_os_error() == Some(windows_sys::Win32::Foundation::ERROR_NOT_SAME_DEVICE as i32)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = error;
+        false
+    }
+}
+
+/// Returns the prefix as it is written into the files of the environment.
+fn prefix_string(prefix: &Path, platform: Platform) -> String {
+    // Placeholders are replaced with forward slashes on Windows, see
+    // [`super::link_file`].
+    let prefix = prefix.to_string_lossy();
+    if platform.is_windows() {
+        prefix.replace('\\', "/")
+    } else {
+        prefix.into_owned()
+    }
+}
+
+/// Writes the contents of a single file with the old prefix replaced to a
+/// temporary file next to it. Returns `None` if the file does not contain the
+/// old prefix.
+fn relocate_file(
+    target_prefix: &Path,
+    entry: &PathsEntry,
+    old_prefix: &str,
+    new_prefix: &str,
+    platform: Platform,
+    file_mode: FileMode,
+    apple_codesign_behavior: AppleCodeSignBehavior,
+) -> Result<Option<RewriteKind>, RelocateError> {
+    let path = target_prefix.join(&entry.relative_path);
+    let to_error = |e| RelocateError::FailedToRewrite(path.clone(), e);
+
+    let contents = fs_err::read(&path).map_err(to_error)?;
+    if memchr::memmem::find(&contents, old_prefix.as_bytes()).is_none() {
+        return Ok(None);
+    }
+
+    // Write the result to a new file so files that happen to be hard linked
+    // to the package cache are never modified.
+    let parent = path.parent().unwrap_or(target_prefix);
+    let temp_file = tempfile::NamedTempFile::new_in(parent).map_err(to_error)?;
+    let mut writer = HashingWriter::<_, rattler_digest::Sha256>::new(temp_file);
+    copy_and_replace_placeholders(
+        &contents,
+        &mut writer,
+        old_prefix,
+        new_prefix,
+        &platform,
+        file_mode,
+    )
+    .map_err(to_error)?;
+    let (mut temp_file, sha256) = writer.finalize();
+    temp_file.flush().map_err(to_error)?;
+
+    let permissions = fs_err::metadata(&path).map_err(to_error)?.permissions();
+    fs_err::set_permissions(temp_file.path(), permissions).map_err(to_error)?;
+
+    let mut sha256_in_prefix = sha256;
+    if platform == Platform::OsxArm64
+        && file_mode == FileMode::Binary
+        && apple_codesign_behavior != AppleCodeSignBehavior::DoNothing
+    {
+        match codesign(temp_file.path()) {
+            Ok(()) => {
+                sha256_in_prefix =
+                    rattler_digest::compute_file_digest::<rattler_digest::Sha256>(temp_file.path())
+                        .map_err(to_error)?;
+            }
+            Err(e) if apple_codesign_behavior == AppleCodeSignBehavior::Fail => {
+                return Err(RelocateError::FailedToSign(path.clone(), e));
+            }
+            Err(_) => {}
+        }
+    }
+
+    let size_in_bytes = fs_err::metadata(temp_file.path()).map_err(to_error)?.len();
+    Ok(Some(RewriteKind::File {
+        temp_file,
+        sha256_in_prefix,
+        size_in_bytes,
+    }))
+}
+
+/// Returns the new destination of a symlink that points to an absolute path
+/// inside the old prefix, or `None` if the link does not have to change.
+fn relocate_symlink(
+    source_prefix: &Path,
+    target_prefix: &Path,
+    entry: &PathsEntry,
+) -> Result<Option<PathBuf>, RelocateError> {
+    let path = target_prefix.join(&entry.relative_path);
+    let link = match fs_err::read_link(&path) {
+        Ok(link) => link,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(RelocateError::FailedToRewrite(path, e)),
+    };
+    Ok(link
+        .strip_prefix(source_prefix)
+        .ok()
+        .map(|relative| target_prefix.join(relative)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rattler_conda_types::{
+        package::FileMode,
+        prefix_record::{PathType, PathsEntry},
+        PackageName, PackageRecord, Platform, PrefixRecord, RepoDataRecord, Version,
+    };
+
+    use super::{relocate_prefix, RelocateError, RelocateOptions};
+
+    fn entry(
+        prefix: &Path,
+        path: &str,
+        content: &[u8],
+        path_type: PathType,
+        file_mode: Option<FileMode>,
+    ) -> PathsEntry {
+        let full_path = prefix.join(path);
+        std::fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+        std::fs::write(&full_path, content).unwrap();
+        PathsEntry {
+            relative_path: path.into(),
+            original_path: None,
+            path_type,
+            no_link: false,
+            sha256: None,
+            sha256_in_prefix: None,
+            size_in_bytes: Some(content.len() as u64),
+            file_mode,
+            prefix_placeholder: file_mode.map(|_| String::from("/opt/placeholder")),
+        }
+    }
+
+    fn write_record(prefix: &Path, paths: Vec<PathsEntry>) {
+        let record = PrefixRecord::from_repodata_record(
+            RepoDataRecord {
+                package_record: PackageRecord::new(
+                    PackageName::new_unchecked("example"),
+                    "1.0".parse::<Version>().unwrap(),
+                    String::from("0"),
+                ),
+                file_name: String::from("example-1.0-0.conda"),
+                url: "https://conda.anaconda.org/conda-forge/linux-64/example-1.0-0.conda"
+                    .parse()
+                    .unwrap(),
+                channel: String::from("https://conda.anaconda.org/conda-forge/"),
+            },
+            None,
+            None,
+            paths,
+            None,
+            None,
+        );
+        std::fs::create_dir_all(prefix.join("conda-meta")).unwrap();
+        record
+            .write_to_path(prefix.join("conda-meta").join(record.file_name()), true)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_relocate_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("envs/a-rather-long-environment-name");
+        let target = dir.path().join("envs/short");
+        let old = source.to_string_lossy().into_owned();
+        let new = target.to_string_lossy().into_owned();
+
+        let mut binary = format!("\0{old}/lib\0rest").into_bytes();
+        binary.extend_from_slice(&[0xff, 0xfe]);
+        let paths = vec![
+            entry(
+                &source,
+                "etc/config.sh",
+                format!("export PREFIX={old}\n").as_bytes(),
+                PathType::HardLink,
+                Some(FileMode::Text),
+            ),
+            entry(
+                &source,
+                "lib/libexample.so",
+                &binary,
+                PathType::HardLink,
+                Some(FileMode::Binary),
+            ),
+            entry(
+                &source,
+                "bin/example",
+                format!("#!{old}/bin/python\nimport example\n").as_bytes(),
+                PathType::UnixPythonEntryPoint,
+                None,
+            ),
+            entry(
+                &source,
+                "share/data",
+                b"untouched",
+                PathType::HardLink,
+                None,
+            ),
+        ];
+        write_record(&source, paths);
+
+        let options = RelocateOptions {
+            platform: Some(Platform::Linux64),
+            ..RelocateOptions::default()
+        };
+        let mut rewritten = relocate_prefix(&source, &target, &options).unwrap();
+        rewritten.sort();
+        assert_eq!(
+            rewritten,
+            vec![
+                Path::new("bin/example"),
+                Path::new("etc/config.sh"),
+                Path::new("lib/libexample.so")
+            ]
+        );
+        assert!(!source.exists());
+
+        assert_eq!(
+            std::fs::read_to_string(target.join("etc/config.sh")).unwrap(),
+            format!("export PREFIX={new}\n")
+        );
+        assert_eq!(
+            std::fs::read_to_string(target.join("bin/example")).unwrap(),
+            format!("#!{new}/bin/python\nimport example\n")
+        );
+
+        // Binary files keep their length, the string is padded with nul bytes.
+        let patched = std::fs::read(target.join("lib/libexample.so")).unwrap();
+        assert_eq!(patched.len(), binary.len());
+        assert!(patched.starts_with(format!("\0{new}/lib\0").as_bytes()));
+        assert!(patched.ends_with(b"\0rest\xff\xfe"));
+
+        // The records are updated with the new hashes.
+        let records = PrefixRecord::collect_from_prefix(&target).unwrap();
+        let config = records[0]
+            .paths_data
+            .paths
+            .iter()
+            .find(|entry| entry.relative_path == Path::new("etc/config.sh"))
+            .unwrap();
+        assert_eq!(
+            config.sha256_in_prefix,
+            Some(
+                rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(format!(
+                    "export PREFIX={new}\n"
+                ))
+            )
+        );
+
+        // Binary files cannot be relocated to a longer prefix.
+        let longer = dir.path().join("envs/an-even-longer-environment-name");
+        assert!(matches!(
+            relocate_prefix(&target, &longer, &options),
+            Err(RelocateError::PrefixTooLong { .. })
+        ));
+        assert!(target.exists());
+    }
+
+    #[test]
+    fn test_relocate_prefix_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("envs/source");
+        let target = dir.path().join("envs/target");
+        let old = source.to_string_lossy().into_owned();
+
+        let config = format!("export PREFIX={old}\n");
+        let mut broken = entry(&source, "etc/broken", b"", PathType::HardLink, None);
+        broken.prefix_placeholder = Some(String::from("/opt/placeholder"));
+        let paths = vec![
+            entry(
+                &source,
+                "etc/config.sh",
+                config.as_bytes(),
+                PathType::HardLink,
+                Some(FileMode::Text),
+            ),
+            broken,
+        ];
+        write_record(&source, paths);
+
+        // A directory where a file is expected cannot be rewritten.
+        std::fs::remove_file(source.join("etc/broken")).unwrap();
+        std::fs::create_dir(source.join("etc/broken")).unwrap();
+
+        let options = RelocateOptions {
+            platform: Some(Platform::Linux64),
+            ..RelocateOptions::default()
+        };
+        assert!(matches!(
+            relocate_prefix(&source, &target, &options),
+            Err(RelocateError::FailedToRewrite(..))
+        ));
+
+        // The environment is moved back without any changes.
+        assert!(!target.exists());
+        assert_eq!(
+            std::fs::read_to_string(source.join("etc/config.sh")).unwrap(),
+            config
+        );
+        let mut files = std::fs::read_dir(source.join("etc"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, ["broken", "config.sh"]);
+    }
+}
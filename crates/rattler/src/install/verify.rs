@@ -0,0 +1,869 @@
+//! Verification and repair of the files in a prefix.
+//!
+//! [`verify_prefix`] checks every [`PathsEntry`] of the [`PrefixRecord`]s in
+//! `conda-meta` against the files on disk and reports files that are missing,
+//! modified, not correctly prefix-replaced or broken. It also reports files
+//! that are not owned by any package and paths that are clobbered by multiple
+//! packages.
+//!
+//! Most issues can be fixed with [`repair_prefix`] which relinks the affected
+//! files from the package cache.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{
+    package::{self, IndexJson, PackageFile, PathsJson},
+    prefix_record::{PathType, PathsEntry},
+    PackageName, Platform, PrefixRecord,
+};
+use rattler_digest::Sha256;
+
+use super::{compute_paths, link_file, AppleCodeSignBehavior, LinkFileError, PythonInfo};
+use crate::package_cache::{PackageCache, PackageCacheError};
+
+/// The kind of problem found for a path in the prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The file does not exist.
+    Missing,
+
+    /// The size of the file differs from the recorded size.
+    SizeMismatch {
+        /// The size recorded in the prefix record.
+        expected: u64,
+        /// The size of the file on disk.
+        actual: u64,
+    },
+
+    /// The contents of the file differ from the recorded `sha256_in_prefix`.
+    HashMismatch,
+
+    /// The file is executable in the package but not in the prefix, or the
+    /// other way around.
+    ModeMismatch,
+
+    /// The file still contains the prefix placeholder, the placeholder was
+    /// not replaced when the file was linked.
+    UnreplacedPlaceholder(String),
+
+    /// The file is a symbolic link that points to a path that does not exist.
+    BrokenSymlink,
+
+    /// The path is installed by multiple packages.
+    Clobbered(Vec<PackageName>),
+
+    /// The file is not owned by any package.
+    Unowned,
+}
+
+impl IssueKind {
+    /// Returns true if the issue can be fixed by relinking the file from the
+    /// package.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, IssueKind::Clobbered(_) | IssueKind::Unowned)
+    }
+}
+
+impl Display for IssueKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IssueKind::Missing => write!(f, "missing"),
+            IssueKind::SizeMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "size mismatch (expected {expected} bytes, found {actual})"
+                )
+            }
+            IssueKind::HashMismatch => write!(f, "sha256 mismatch"),
+            IssueKind::ModeMismatch => write!(f, "file mode mismatch"),
+            IssueKind::UnreplacedPlaceholder(placeholder) => {
+                write!(f, "prefix placeholder '{placeholder}' was not replaced")
+            }
+            IssueKind::BrokenSymlink => write!(f, "broken symbolic link"),
+            IssueKind::Clobbered(packages) => {
+                write!(f, "clobbered by ")?;
+                for (idx, package) in packages.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", package.as_normalized())?;
+                }
+                Ok(())
+            }
+            IssueKind::Unowned => write!(f, "not owned by any package"),
+        }
+    }
+}
+
+/// A problem with a single path in the prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathIssue {
+    /// The path relative to the prefix.
+    pub path: PathBuf,
+
+    /// The package that owns the path, `None` for unowned files.
+    pub package: Option<PackageName>,
+
+    /// What is wrong with the path.
+    pub kind: IssueKind,
+}
+
+impl Display for PathIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.kind)?;
+        if let Some(package) = &self.package {
+            write!(f, " [{}]", package.as_normalized())?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of [`verify_prefix`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerificationReport {
+    /// All issues that were found.
+    pub issues: Vec<PathIssue>,
+}
+
+impl VerificationReport {
+    /// Returns true if no issues were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Returns the issues that can be fixed by [`repair_prefix`].
+    pub fn repairable(&self) -> impl Iterator<Item = &PathIssue> + '_ {
+        self.issues
+            .iter()
+            .filter(|issue| issue.kind.is_repairable())
+    }
+}
+
+/// Options that control which checks [`verify_prefix`] performs.
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// Whether to compute the hash of every file and compare it to the
+    /// recorded `sha256_in_prefix`. This is the most expensive check.
+    pub check_hashes: bool,
+
+    /// Whether to look for files that are not owned by any package.
+    pub detect_unowned: bool,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            check_hashes: true,
+            detect_unowned: true,
+        }
+    }
+}
+
+/// An error that can occur while verifying or repairing a prefix.
+#[derive(Debug, thiserror::Error)]
+pub enum VerifyError {
+    /// Failed to read the contents of the prefix.
+    #[error("failed to read '{0}'")]
+    IoError(PathBuf, #[source] std::io::Error),
+
+    /// Failed to fetch a package into the cache.
+    #[error("failed to fetch {0}")]
+    FailedToFetch(String, #[source] PackageCacheError),
+
+    /// Failed to read the metadata of a package.
+    #[error("failed to read the metadata of {0}")]
+    FailedToReadPackage(String, #[source] std::io::Error),
+
+    /// Failed to relink a file.
+    #[error("failed to relink '{0}'")]
+    FailedToLink(PathBuf, #[source] LinkFileError),
+
+    /// Failed to write the updated prefix record.
+    #[error("failed to write '{0}'")]
+    FailedToWriteRecord(PathBuf, #[source] std::io::Error),
+}
+
+/// Verifies the files of all `records` installed in `prefix`.
+///
+/// This is a blocking operation that reads every file in the prefix.
+pub fn verify_prefix(
+    prefix: &Path,
+    records: &[PrefixRecord],
+    options: &VerifyOptions,
+) -> Result<VerificationReport, VerifyError> {
+    let target_prefix = prefix.to_string_lossy();
+    let mut issues = Vec::new();
+    let mut owners: HashMap<&Path, Vec<&PackageName>> = HashMap::new();
+    let mut owned: HashSet<&Path> = HashSet::new();
+
+    for record in records {
+        let name = &record.repodata_record.package_record.name;
+        for entry in &record.paths_data.paths {
+            owned.insert(&entry.relative_path);
+            owners
+                .entry(
+                    entry
+                        .original_path
+                        .as_deref()
+                        .unwrap_or(&entry.relative_path),
+                )
+                .or_default()
+                .push(name);
+
+            let package_path = entry.original_path.as_ref().unwrap_or(&entry.relative_path);
+            let source = record
+                .extracted_package_dir
+                .as_deref()
+                .map(|dir| dir.join(package_path));
+            if let Some(kind) =
+                verify_entry(prefix, &target_prefix, entry, source.as_deref(), options)?
+            {
+                issues.push(PathIssue {
+                    path: entry.relative_path.clone(),
+                    package: Some(name.clone()),
+                    kind,
+                });
+            }
+        }
+    }
+
+    for (path, packages) in owners {
+        if packages.len() > 1 {
+            issues.push(PathIssue {
+                path: path.to_path_buf(),
+                package: None,
+                kind: IssueKind::Clobbered(packages.into_iter().cloned().collect()),
+            });
+        }
+    }
+
+    if options.detect_unowned {
+        for entry in walkdir::WalkDir::new(prefix)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| {
+                let name = entry.file_name();
+                !(entry.depth() == 1 && name == "conda-meta") && name != "__pycache__"
+            })
+        {
+            let entry = entry.map_err(|e| {
+                let path = e.path().unwrap_or(prefix).to_path_buf();
+                VerifyError::IoError(path, e.into())
+            })?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let relative_path = entry
+                .path()
+                .strip_prefix(prefix)
+                .expect("walkdir returns paths inside the prefix");
+            if !owned.contains(relative_path) {
+                issues.push(PathIssue {
+                    path: relative_path.to_path_buf(),
+                    package: None,
+                    kind: IssueKind::Unowned,
+                });
+            }
+        }
+    }
+
+    issues.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(VerificationReport { issues })
+}
+
+/// Verifies a single entry, returns the first problem that was found.
+fn verify_entry(
+    prefix: &Path,
+    target_prefix: &str,
+    entry: &PathsEntry,
+    source: Option<&Path>,
+    options: &VerifyOptions,
+) -> Result<Option<IssueKind>, VerifyError> {
+    let path = prefix.join(&entry.relative_path);
+    let metadata = match fs_err::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Some(IssueKind::Missing)),
+        Err(e) => return Err(VerifyError::IoError(path, e)),
+    };
+
+    if entry.path_type == PathType::Directory {
+        return Ok((!metadata.is_dir()).then_some(IssueKind::Missing));
+    }
+
+    if metadata.is_symlink() {
+        return Ok(path.metadata().is_err().then_some(IssueKind::BrokenSymlink));
+    }
+
+    if let Some(expected) = entry.size_in_bytes {
+        if expected != metadata.len() {
+            return Ok(Some(IssueKind::SizeMismatch {
+                expected,
+                actual: metadata.len(),
+            }));
+        }
+    }
+
+    if let Some(placeholder) = &entry.prefix_placeholder {
+        if placeholder != target_prefix {
+            let contents =
+                fs_err::read(&path).map_err(|e| VerifyError::IoError(path.clone(), e))?;
+            if memchr::memmem::find(&contents, placeholder.as_bytes()).is_some() {
+                return Ok(Some(IssueKind::UnreplacedPlaceholder(placeholder.clone())));
+            }
+        }
+    }
+
+    if options.check_hashes {
+        if let Some(expected) = entry.sha256_in_prefix {
+            let actual = rattler_digest::compute_file_digest::<Sha256>(&path)
+                .map_err(|e| VerifyError::IoError(path.clone(), e))?;
+            if actual != expected {
+                return Ok(Some(IssueKind::HashMismatch));
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(source) = source {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(source_metadata) = source.metadata() {
+            let is_executable = |mode: u32| mode & 0o111 != 0;
+            if is_executable(source_metadata.permissions().mode())
+                != is_executable(metadata.permissions().mode())
+            {
+                return Ok(Some(IssueKind::ModeMismatch));
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = source;
+
+    Ok(None)
+}
+
+/// Repairs the repairable issues of a [`VerificationReport`] by relinking the
+/// affected files from the package cache. Packages that are not in the cache
+/// are fetched with `client`.
+///
+/// Relinked binaries are signed according to `apple_codesign_behavior`, just
+/// like when the package is installed. Use [`AppleCodeSignBehavior::Fail`]
+/// (the default) unless there is a reason not to.
+///
+/// If a file in the cache does not match its hash from `paths.json` the
+/// package is fetched again before anything is relinked.
+///
+/// The prefix records of the repaired packages are updated in `conda-meta`.
+/// Returns the issues that could not be repaired.
+pub async fn repair_prefix(
+    prefix: &Path,
+    records: &[PrefixRecord],
+    report: &VerificationReport,
+    package_cache: &PackageCache,
+    client: reqwest_middleware::ClientWithMiddleware,
+    platform: Platform,
+    apple_codesign_behavior: AppleCodeSignBehavior,
+) -> Result<Vec<PathIssue>, VerifyError> {
+    let target_prefix = prefix.to_string_lossy().into_owned();
+    let python_info = records
+        .iter()
+        .map(|record| &record.repodata_record.package_record)
+        .find(|record| record.name.as_normalized() == "python")
+        .and_then(|record| PythonInfo::from_version(&record.version, platform).ok());
+
+    let mut unrepaired = Vec::new();
+    let mut issues_per_package: HashMap<&PackageName, Vec<&PathIssue>> = HashMap::new();
+    for issue in &report.issues {
+        match &issue.package {
+            Some(package) if issue.kind.is_repairable() => {
+                issues_per_package.entry(package).or_default().push(issue);
+            }
+            _ => unrepaired.push(issue.clone()),
+        }
+    }
+
+    for (name, issues) in issues_per_package {
+        let Some(record) = records
+            .iter()
+            .find(|record| &record.repodata_record.package_record.name == name)
+        else {
+            unrepaired.extend(issues.into_iter().cloned());
+            continue;
+        };
+        let file_name = &record.repodata_record.file_name;
+
+        // Use the extracted package if it is still around, otherwise get it
+        // from the cache.
+        let mut package_dir = match record
+            .extracted_package_dir
+            .as_ref()
+            .filter(|dir| dir.join("info").is_dir())
+        {
+            Some(dir) => dir.clone(),
+            None => package_cache
+                .get_or_fetch_from_url(
+                    &record.repodata_record.package_record,
+                    record.repodata_record.url.clone(),
+                    client.clone(),
+                    None,
+                )
+                .await
+                .map_err(|e| VerifyError::FailedToFetch(file_name.clone(), e))?,
+        };
+        let Some(mut package_entries) =
+            read_package_entries(&package_dir, file_name, python_info.as_ref())?
+        else {
+            unrepaired.extend(issues.into_iter().cloned());
+            continue;
+        };
+
+        // Files in the prefix are usually hard links to the files in the
+        // cache, a file that was modified in place means the cached copy is
+        // corrupt as well. Relinking from it would not fix anything so throw
+        // the package away and fetch it again.
+        if !sources_are_valid(&package_dir, &package_entries, record, &issues)? {
+            tracing::warn!("the cached copy of {file_name} is corrupt, fetching it again");
+            fs_err::remove_dir_all(&package_dir)
+                .map_err(|e| VerifyError::IoError(package_dir.clone(), e))?;
+            package_dir = package_cache
+                .get_or_fetch_from_url(
+                    &record.repodata_record.package_record,
+                    record.repodata_record.url.clone(),
+                    client.clone(),
+                    None,
+                )
+                .await
+                .map_err(|e| VerifyError::FailedToFetch(file_name.clone(), e))?;
+            let Some(refetched_entries) =
+                read_package_entries(&package_dir, file_name, python_info.as_ref())?
+            else {
+                unrepaired.extend(issues.into_iter().cloned());
+                continue;
+            };
+            package_entries = refetched_entries;
+            if !sources_are_valid(&package_dir, &package_entries, record, &issues)? {
+                unrepaired.extend(issues.into_iter().cloned());
+                continue;
+            }
+        }
+
+        let mut record = record.clone();
+        for issue in issues {
+            let Some(prefix_entry) = record
+                .paths_data
+                .paths
+                .iter_mut()
+                .find(|entry| entry.relative_path == issue.path)
+            else {
+                unrepaired.push(issue.clone());
+                continue;
+            };
+            let Some(package_entry) = package_entries.get(
+                prefix_entry
+                    .original_path
+                    .as_ref()
+                    .unwrap_or(&prefix_entry.relative_path),
+            ) else {
+                // Files that were generated during the installation (e.g.
+                // entry points) cannot be relinked.
+                unrepaired.push(issue.clone());
+                continue;
+            };
+
+            // Remove the broken file first, otherwise linking fails.
+            let destination = prefix.join(&issue.path);
+            match fs_err::remove_file(&destination) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(VerifyError::IoError(destination, e)),
+            }
+            if let Some(parent) = destination.parent() {
+                fs_err::create_dir_all(parent)
+                    .map_err(|e| VerifyError::IoError(parent.to_path_buf(), e))?;
+            }
+
+            let linked = link_file(
+                package_entry,
+                issue.path.clone(),
+                &package_dir,
+                prefix,
+                &target_prefix,
+                !package_entry.no_link,
+                !package_entry.no_link,
+                !package_entry.no_link,
+                platform,
+                apple_codesign_behavior,
+            )
+            .map_err(|e| VerifyError::FailedToLink(issue.path.clone(), e))?;
+
+            // The recorded hash is what the file is supposed to look like,
+            // only fill it in when it was never recorded.
+            match prefix_entry.sha256_in_prefix {
+                Some(expected) if expected != linked.sha256 => {
+                    unrepaired.push(issue.clone());
+                }
+                Some(_) => {}
+                None => {
+                    prefix_entry.sha256_in_prefix = Some(linked.sha256);
+                    prefix_entry.size_in_bytes = Some(linked.file_size);
+                }
+            }
+        }
+
+        let record_path = prefix.join("conda-meta").join(record.file_name());
+        record
+            .write_to_path(&record_path, true)
+            .map_err(|e| VerifyError::FailedToWriteRecord(record_path, e))?;
+    }
+
+    unrepaired.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(unrepaired)
+}
+
+/// Reads the paths of the package extracted in `package_dir` keyed by the
+/// path they are installed to. Returns `None` for noarch python packages if
+/// there is no python in the prefix.
+fn read_package_entries(
+    package_dir: &Path,
+    file_name: &str,
+    python_info: Option<&PythonInfo>,
+) -> Result<Option<HashMap<PathBuf, package::PathsEntry>>, VerifyError> {
+    let paths_json = PathsJson::from_package_directory_with_deprecated_fallback(package_dir)
+        .map_err(|e| VerifyError::FailedToReadPackage(file_name.to_string(), e))?;
+    let index_json = IndexJson::from_package_directory(package_dir)
+        .map_err(|e| VerifyError::FailedToReadPackage(file_name.to_string(), e))?;
+    if index_json.noarch.is_python() && python_info.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(
+        compute_paths(&index_json, &paths_json, python_info)
+            .into_iter()
+            .map(|(entry, path)| (path, entry))
+            .collect(),
+    ))
+}
+
+/// Returns false if any of the files in `package_dir` that are used to
+/// repair `issues` does not match the hash from `paths.json`.
+fn sources_are_valid(
+    package_dir: &Path,
+    package_entries: &HashMap<PathBuf, package::PathsEntry>,
+    record: &PrefixRecord,
+    issues: &[&PathIssue],
+) -> Result<bool, VerifyError> {
+    for issue in issues {
+        let original_path = record
+            .paths_data
+            .paths
+            .iter()
+            .find(|entry| entry.relative_path == issue.path)
+            .map(|entry| entry.original_path.as_ref().unwrap_or(&entry.relative_path));
+        let Some(package_entry) = original_path.and_then(|path| package_entries.get(path)) else {
+            continue;
+        };
+        let (package::PathType::HardLink, Some(expected)) =
+            (package_entry.path_type, package_entry.sha256)
+        else {
+            continue;
+        };
+        let source = package_dir.join(&package_entry.relative_path);
+        match rattler_digest::compute_file_digest::<Sha256>(&source) {
+            Ok(actual) if actual == expected => {}
+            Ok(_) => return Ok(false),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(VerifyError::IoError(source, e)),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, path::Path};
+
+    use rattler_conda_types::{
+        prefix_record::{PathType, PathsEntry},
+        PackageName, PackageRecord, Platform, PrefixRecord, RepoDataRecord, Version,
+    };
+
+    use super::{repair_prefix, verify_prefix, IssueKind, VerifyOptions};
+    use crate::{
+        get_repodata_record, get_test_data_dir,
+        install::{AppleCodeSignBehavior, Installer},
+        package_cache::PackageCache,
+    };
+
+    fn prefix_record(name: &str, paths: Vec<PathsEntry>) -> PrefixRecord {
+        PrefixRecord::from_repodata_record(
+            RepoDataRecord {
+                package_record: PackageRecord::new(
+                    PackageName::new_unchecked(name),
+                    "1.0".parse::<Version>().unwrap(),
+                    String::from("0"),
+                ),
+                file_name: format!("{name}-1.0-0.conda"),
+                url: format!("https://conda.anaconda.org/conda-forge/noarch/{name}-1.0-0.conda")
+                    .parse()
+                    .unwrap(),
+                channel: String::from("https://conda.anaconda.org/conda-forge/"),
+            },
+            None,
+            None,
+            paths,
+            None,
+            None,
+        )
+    }
+
+    fn entry(prefix: &Path, path: &str, content: &str) -> PathsEntry {
+        let full_path = prefix.join(path);
+        std::fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+        std::fs::write(&full_path, content).unwrap();
+        PathsEntry {
+            relative_path: path.into(),
+            original_path: None,
+            path_type: PathType::HardLink,
+            no_link: false,
+            sha256: None,
+            sha256_in_prefix: Some(
+                rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(content),
+            ),
+            size_in_bytes: Some(content.len() as u64),
+            file_mode: None,
+            prefix_placeholder: None,
+        }
+    }
+
+    fn issue_kind<'a>(report: &'a super::VerificationReport, path: &str) -> Option<&'a IssueKind> {
+        report
+            .issues
+            .iter()
+            .find(|issue| issue.path == Path::new(path))
+            .map(|issue| &issue.kind)
+    }
+
+    #[test]
+    fn test_verify_prefix() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+
+        let mut placeholder = entry(prefix, "etc/config", "prefix=/opt/placeholder");
+        placeholder.prefix_placeholder = Some(String::from("/opt/placeholder"));
+        let foo = prefix_record(
+            "foo",
+            vec![
+                entry(prefix, "bin/foo", "foo"),
+                entry(prefix, "lib/missing", "missing"),
+                entry(prefix, "lib/modified", "original"),
+                entry(prefix, "lib/resized", "original"),
+                entry(prefix, "share/shared", "shared"),
+                placeholder,
+            ],
+        );
+        let bar = prefix_record("bar", vec![entry(prefix, "share/shared", "shared")]);
+
+        std::fs::remove_file(prefix.join("lib/missing")).unwrap();
+        std::fs::write(prefix.join("lib/modified"), "modifieD").unwrap();
+        std::fs::write(prefix.join("lib/resized"), "resized!!").unwrap();
+        std::fs::write(prefix.join("bin/unowned"), "").unwrap();
+        std::fs::create_dir_all(prefix.join("conda-meta")).unwrap();
+        std::fs::write(prefix.join("conda-meta/history"), "").unwrap();
+
+        let records = [foo, bar];
+        let report = verify_prefix(prefix, &records, &VerifyOptions::default()).unwrap();
+
+        assert_eq!(issue_kind(&report, "bin/foo"), None);
+        assert_eq!(
+            issue_kind(&report, "lib/missing"),
+            Some(&IssueKind::Missing)
+        );
+        assert_eq!(
+            issue_kind(&report, "lib/modified"),
+            Some(&IssueKind::HashMismatch)
+        );
+        assert_eq!(
+            issue_kind(&report, "lib/resized"),
+            Some(&IssueKind::SizeMismatch {
+                expected: 8,
+                actual: 9
+            })
+        );
+        assert_eq!(
+            issue_kind(&report, "etc/config"),
+            Some(&IssueKind::UnreplacedPlaceholder(String::from(
+                "/opt/placeholder"
+            )))
+        );
+        assert!(matches!(
+            issue_kind(&report, "share/shared"),
+            Some(IssueKind::Clobbered(packages)) if packages.len() == 2
+        ));
+        assert_eq!(
+            issue_kind(&report, "bin/unowned"),
+            Some(&IssueKind::Unowned)
+        );
+        assert_eq!(issue_kind(&report, "conda-meta/history"), None);
+
+        // Without hashing the modified file is not detected.
+        let report = verify_prefix(
+            prefix,
+            &records,
+            &VerifyOptions {
+                check_hashes: false,
+                detect_unowned: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(issue_kind(&report, "lib/modified"), None);
+        assert_eq!(issue_kind(&report, "bin/unowned"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_broken_symlink() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        std::fs::create_dir_all(prefix.join("lib")).unwrap();
+        std::os::unix::fs::symlink("libfoo.so.1", prefix.join("lib/libfoo.so")).unwrap();
+
+        let record = prefix_record(
+            "foo",
+            vec![PathsEntry {
+                relative_path: "lib/libfoo.so".into(),
+                original_path: None,
+                path_type: PathType::SoftLink,
+                no_link: false,
+                sha256: None,
+                sha256_in_prefix: None,
+                size_in_bytes: None,
+                file_mode: None,
+                prefix_placeholder: None,
+            }],
+        );
+        let report = verify_prefix(prefix, &[record], &VerifyOptions::default()).unwrap();
+        assert_eq!(
+            issue_kind(&report, "lib/libfoo.so"),
+            Some(&IssueKind::BrokenSymlink)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repair_prefix() {
+        let cache = tempfile::tempdir().unwrap();
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        let package_cache = PackageCache::new(cache.path());
+
+        let record = get_repodata_record(
+            get_test_data_dir().join("clobber/clobber-1-0.1.0-h4616a5c_0.tar.bz2"),
+        );
+        Installer::new()
+            .with_package_cache(package_cache.clone())
+            .install(prefix, vec![record])
+            .await
+            .unwrap();
+
+        // Replace the files instead of modifying them, they might be hard
+        // links to the files in the cache.
+        let damaged = prefix.join("clobber.txt");
+        std::fs::remove_file(&damaged).unwrap();
+        std::fs::write(&damaged, "damaged").unwrap();
+        let executable = prefix.join("another-clobber.txt");
+        let contents = std::fs::read(&executable).unwrap();
+        std::fs::remove_file(&executable).unwrap();
+        std::fs::write(&executable, contents).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let records = PrefixRecord::collect_from_prefix(prefix).unwrap();
+        let report = verify_prefix(prefix, &records, &VerifyOptions::default()).unwrap();
+        assert!(matches!(
+            issue_kind(&report, "clobber.txt"),
+            Some(IssueKind::SizeMismatch { .. } | IssueKind::HashMismatch)
+        ));
+        #[cfg(unix)]
+        assert_eq!(
+            issue_kind(&report, "another-clobber.txt"),
+            Some(&IssueKind::ModeMismatch)
+        );
+
+        let unrepaired = repair_prefix(
+            prefix,
+            &records,
+            &report,
+            &package_cache,
+            reqwest_middleware::ClientWithMiddleware::from(reqwest::Client::new()),
+            Platform::current(),
+            AppleCodeSignBehavior::default(),
+        )
+        .await
+        .unwrap();
+        assert!(unrepaired.is_empty(), "{unrepaired:?}");
+
+        let records = PrefixRecord::collect_from_prefix(prefix).unwrap();
+        let report = verify_prefix(prefix, &records, &VerifyOptions::default()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+    }
+
+    #[tokio::test]
+    async fn test_repair_prefix_modified_hard_link() {
+        let cache = tempfile::tempdir().unwrap();
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+
+        let record = get_repodata_record(
+            get_test_data_dir().join("clobber/clobber-1-0.1.0-h4616a5c_0.tar.bz2"),
+        );
+        Installer::new()
+            .with_package_cache(PackageCache::new(cache.path()))
+            .install(prefix, vec![record])
+            .await
+            .unwrap();
+
+        // Modify the file in place, this also modifies the file in the cache.
+        let damaged = prefix.join("clobber.txt");
+        let original = std::fs::read_to_string(&damaged).unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&damaged)
+            .unwrap()
+            .write_all(b"damaged")
+            .unwrap();
+
+        let records = PrefixRecord::collect_from_prefix(prefix).unwrap();
+        let cached = records[0]
+            .extracted_package_dir
+            .as_ref()
+            .unwrap()
+            .join("clobber.txt");
+        assert_ne!(std::fs::read_to_string(&cached).unwrap(), original);
+
+        let report = verify_prefix(prefix, &records, &VerifyOptions::default()).unwrap();
+        assert!(issue_kind(&report, "clobber.txt").is_some());
+
+        let unrepaired = repair_prefix(
+            prefix,
+            &records,
+            &report,
+            &PackageCache::new(cache.path()),
+            reqwest_middleware::ClientWithMiddleware::from(reqwest::Client::new()),
+            Platform::current(),
+            AppleCodeSignBehavior::default(),
+        )
+        .await
+        .unwrap();
+        assert!(unrepaired.is_empty(), "{unrepaired:?}");
+        assert_eq!(std::fs::read_to_string(&damaged).unwrap(), original);
+
+        let records = PrefixRecord::collect_from_prefix(prefix).unwrap();
+        let report = verify_prefix(prefix, &records, &VerifyOptions::default()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+    }
+}
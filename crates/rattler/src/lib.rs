@@ -18,6 +18,7 @@ use std::path::PathBuf;
 #[cfg(feature = "cli-tools")]
 pub mod cli;
 pub mod install;
+pub mod license;
 pub use rattler_cache::{package_cache, validation};
 
 /// A helper function that returns a [`Channel`] instance that points to an
@@ -0,0 +1,305 @@
+//! License compliance reporting for installed environments.
+//!
+//! A [`LicenseReport`] is created from the [`PrefixRecord`]s of an environment. It groups all
+//! packages by their SPDX license expression and flags packages whose license is missing or is
+//! not a valid SPDX expression. The report can be evaluated against a [`LicensePolicy`] and the
+//! license texts shipped in the `info/licenses` directory of every package can be collected into
+//! a single directory.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{PackageName, PrefixRecord};
+use spdx::{Expression, LicenseItem, LicenseReq};
+
+/// The license information of a single package.
+#[derive(Debug, Clone)]
+pub struct PackageLicense {
+    /// The name of the package
+    pub name: PackageName,
+
+    /// The version of the package
+    pub version: String,
+
+    /// The license string as stored in the package metadata.
+    pub license: Option<String>,
+
+    /// The license family as stored in the package metadata.
+    pub license_family: Option<String>,
+
+    /// The parsed SPDX expression if `license` is a valid SPDX expression.
+    pub expression: Option<Expression>,
+
+    /// The license files in the `info/licenses` directory of the extracted package. The paths
+    /// are relative to that directory.
+    pub license_files: Vec<PathBuf>,
+
+    /// The directory that contains the license files, if known.
+    pub license_dir: Option<PathBuf>,
+}
+
+/// A report of the licenses of all packages in an environment.
+#[derive(Debug, Clone, Default)]
+pub struct LicenseReport {
+    /// The license information of every package, ordered by name.
+    pub packages: Vec<PackageLicense>,
+}
+
+/// A policy that defines which licenses are acceptable.
+///
+/// Licenses are identified by their SPDX identifier (e.g. `MIT` or `GPL-3.0-only`). An SPDX
+/// expression is acceptable if it can be satisfied by a combination of licenses that are
+/// allowed: `MIT OR GPL-3.0-only` is acceptable if only `MIT` is allowed, `MIT AND GPL-3.0-only`
+/// is not.
+#[derive(Debug, Clone, Default)]
+pub struct LicensePolicy {
+    /// The licenses that are allowed. If this is empty all licenses that are not explicitly
+    /// denied are allowed.
+    pub allow: Vec<String>,
+
+    /// The licenses that are never allowed.
+    pub deny: Vec<String>,
+
+    /// Whether packages without a license or with a license that is not a valid SPDX expression
+    /// are accepted.
+    pub allow_unknown: bool,
+}
+
+/// A package that does not comply with a [`LicensePolicy`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum LicenseViolation {
+    /// The license of the package is not accepted by the policy.
+    #[error("the license '{license}' of '{}' is not allowed", .name.as_normalized())]
+    NotAllowed {
+        /// The name of the package
+        name: PackageName,
+        /// The license of the package
+        license: String,
+    },
+
+    /// The package has no license or its license is not a valid SPDX expression.
+    #[error("'{}' has an unknown license", .name.as_normalized())]
+    UnknownLicense {
+        /// The name of the package
+        name: PackageName,
+        /// The license of the package, if any
+        license: Option<String>,
+    },
+}
+
+impl LicenseReport {
+    /// Creates a report from the records of an installed environment.
+    ///
+    /// License files are only found for records that have an
+    /// [`PrefixRecord::extracted_package_dir`] that still exists.
+    pub fn from_prefix_records(records: &[PrefixRecord]) -> Self {
+        let mut packages = records
+            .iter()
+            .map(|record| {
+                let package_record = &record.repodata_record.package_record;
+                let license_dir = record
+                    .extracted_package_dir
+                    .as_ref()
+                    .map(|dir| dir.join("info/licenses"))
+                    .filter(|dir| dir.is_dir());
+                let license_files = license_dir
+                    .as_deref()
+                    .map(collect_license_files)
+                    .unwrap_or_default();
+
+                PackageLicense {
+                    name: package_record.name.clone(),
+                    version: package_record.version.to_string(),
+                    license: package_record.license.clone(),
+                    license_family: package_record.license_family.clone(),
+                    expression: package_record
+                        .license
+                        .as_deref()
+                        .and_then(|license| Expression::parse(license).ok()),
+                    license_files,
+                    license_dir,
+                }
+            })
+            .collect::<Vec<_>>();
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self { packages }
+    }
+
+    /// Returns the packages grouped by their SPDX expression. Packages without a valid SPDX
+    /// expression are not included, see [`Self::unknown`].
+    pub fn by_expression(&self) -> BTreeMap<String, Vec<&PackageLicense>> {
+        let mut result: BTreeMap<String, Vec<&PackageLicense>> = BTreeMap::new();
+        for package in &self.packages {
+            if let Some(expression) = &package.expression {
+                result
+                    .entry(expression.as_ref().to_string())
+                    .or_default()
+                    .push(package);
+            }
+        }
+        result
+    }
+
+    /// Returns the packages that have no license or a license that is not a valid SPDX
+    /// expression.
+    pub fn unknown(&self) -> impl Iterator<Item = &PackageLicense> + '_ {
+        self.packages.iter().filter(|p| p.expression.is_none())
+    }
+
+    /// Evaluates the licenses of all packages against the given policy and returns every
+    /// violation.
+    pub fn evaluate(&self, policy: &LicensePolicy) -> Vec<LicenseViolation> {
+        self.packages
+            .iter()
+            .filter_map(|package| match &package.expression {
+                Some(expression) => (!expression.evaluate(|req| policy.allows(req))).then(|| {
+                    LicenseViolation::NotAllowed {
+                        name: package.name.clone(),
+                        license: expression.as_ref().to_string(),
+                    }
+                }),
+                None => (!policy.allow_unknown).then(|| LicenseViolation::UnknownLicense {
+                    name: package.name.clone(),
+                    license: package.license.clone(),
+                }),
+            })
+            .collect()
+    }
+
+    /// Copies the license files of all packages into `target_dir`. The files of every package
+    /// are stored in a subdirectory named `<name>-<version>`.
+    pub fn collect_license_texts(&self, target_dir: &Path) -> std::io::Result<()> {
+        for package in &self.packages {
+            let Some(license_dir) = &package.license_dir else {
+                continue;
+            };
+
+            let package_dir = target_dir.join(format!(
+                "{}-{}",
+                package.name.as_normalized(),
+                package.version
+            ));
+            for file in &package.license_files {
+                let destination = package_dir.join(file);
+                if let Some(parent) = destination.parent() {
+                    fs_err::create_dir_all(parent)?;
+                }
+                fs_err::copy(license_dir.join(file), destination)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LicensePolicy {
+    /// Returns true if the given license requirement is accepted by this policy.
+    fn allows(&self, req: &LicenseReq) -> bool {
+        let id = match &req.license {
+            LicenseItem::Spdx { id, .. } => id.name.to_string(),
+            LicenseItem::Other { lic_ref, .. } => format!("LicenseRef-{lic_ref}"),
+        };
+
+        if self
+            .deny
+            .iter()
+            .any(|denied| denied.eq_ignore_ascii_case(&id))
+        {
+            return false;
+        }
+
+        self.allow.is_empty()
+            || self
+                .allow
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(&id))
+    }
+}
+
+/// Returns the paths of all files in the given directory relative to that directory.
+fn collect_license_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.path().strip_prefix(dir).ok().map(Path::to_path_buf))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod test {
+    use rattler_conda_types::{PackageName, PackageRecord, PrefixRecord, RepoDataRecord};
+
+    use super::{LicensePolicy, LicenseReport, LicenseViolation};
+
+    fn prefix_record(name: &str, license: Option<&str>) -> PrefixRecord {
+        let mut package_record = PackageRecord::new(
+            PackageName::new_unchecked(name),
+            "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+            String::from("0"),
+        );
+        package_record.license = license.map(ToString::to_string);
+        PrefixRecord::from_repodata_record(
+            RepoDataRecord {
+                package_record,
+                file_name: format!("{name}-1.0-0.conda"),
+                url: format!("https://conda.anaconda.org/conda-forge/noarch/{name}-1.0-0.conda")
+                    .parse()
+                    .unwrap(),
+                channel: String::from("https://conda.anaconda.org/conda-forge/"),
+            },
+            None,
+            None,
+            Vec::new(),
+            None,
+            None,
+        )
+    }
+
+    fn report() -> LicenseReport {
+        LicenseReport::from_prefix_records(&[
+            prefix_record("a", Some("MIT")),
+            prefix_record("b", Some("MIT OR GPL-3.0-only")),
+            prefix_record("c", Some("GPL-3.0-only")),
+            prefix_record("d", Some("BSD")),
+            prefix_record("e", None),
+        ])
+    }
+
+    #[test]
+    fn test_group_by_expression() {
+        let report = report();
+        let groups = report.by_expression();
+        assert_eq!(
+            groups.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["GPL-3.0-only", "MIT", "MIT OR GPL-3.0-only"]
+        );
+        assert_eq!(
+            report
+                .unknown()
+                .map(|p| p.name.as_normalized())
+                .collect::<Vec<_>>(),
+            ["d", "e"]
+        );
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = LicensePolicy {
+            deny: vec![String::from("GPL-3.0-only")],
+            allow_unknown: true,
+            ..LicensePolicy::default()
+        };
+        assert_eq!(
+            report().evaluate(&policy),
+            [LicenseViolation::NotAllowed {
+                name: PackageName::new_unchecked("c"),
+                license: String::from("GPL-3.0-only"),
+            }]
+        );
+    }
+}
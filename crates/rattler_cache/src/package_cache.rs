@@ -17,6 +17,7 @@ use rattler_conda_types::{package::ArchiveIdentifier, PackageRecord};
 use rattler_digest::Sha256Hash;
 use rattler_networking::retry_policies::{DoNotRetryPolicy, RetryDecision, RetryPolicy};
 use rattler_package_streaming::{DownloadReporter, ExtractError};
+use rattler_redaction::{Redact, Redacted, RedactedError};
 use reqwest::StatusCode;
 use tokio::sync::broadcast;
 use tracing::Instrument;
@@ -128,6 +129,8 @@ struct Package {
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum PackageCacheError {
     /// An error occurred while fetching the package.
+    ///
+    /// Known secrets (e.g. tokens in URLs) are redacted from the error and its sources.
     #[error(transparent)]
     FetchError(#[from] Arc<dyn std::error::Error + Send + Sync + 'static>),
 }
@@ -143,6 +146,11 @@ impl PackageCache {
         }
     }
 
+    /// Returns the directory in which the packages are cached.
+    pub fn path(&self) -> PathBuf {
+        self.inner.lock().path.clone()
+    }
+
     /// Returns the directory that contains the specified package.
     ///
     /// If the package was previously successfully fetched and stored in the
@@ -260,7 +268,7 @@ impl PackageCache {
             let mut current_try = 0;
             loop {
                 current_try += 1;
-                tracing::debug!("downloading {} to {}", &url, destination.display());
+                tracing::debug!("downloading {} to {}", url.clone().redact(), destination.display());
 
                 let result = rattler_package_streaming::reqwest::tokio::extract(
                     client.clone(),
@@ -302,9 +310,9 @@ impl PackageCache {
                 // chance of success.
                 tracing::warn!(
                     "failed to download and extract {} to {}: {}. Retry #{}, Sleeping {:?} until the next attempt...",
-                    &url,
+                    url.clone().redact(),
                     destination.display(),
-                    err,
+                    Redacted(&err),
                     current_try,
                     duration
                 );
@@ -366,7 +374,7 @@ where
     // Otherwise, defer to populate method to fill our cache.
     fetch(path)
         .await
-        .map_err(|e| PackageCacheError::FetchError(Arc::new(e)))
+        .map_err(|e| PackageCacheError::FetchError(Arc::new(RedactedError::new(&e))))
 }
 
 struct PassthroughReporter {
@@ -630,6 +638,29 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn test_fetch_error_is_redacted() {
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path());
+
+        let result = cache
+            .get_or_fetch(
+                ArchiveIdentifier::try_from_filename("foo-1.0-0.conda").unwrap(),
+                |_destination| async move {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "failed to fetch https://conda.anaconda.org/t/secret-token/channel/noarch/foo-1.0-0.conda",
+                    ))
+                },
+                None,
+            )
+            .await;
+
+        let err = result.unwrap_err();
+        assert!(!err.to_string().contains("secret-token"));
+        assert!(!format!("{err:?}").contains("secret-token"));
+    }
+
     #[tokio::test]
     async fn test_flaky() {
         let tar_bz2 = "conda-forge/win-64/conda-22.9.0-py310h5588dad_2.tar.bz2";
@@ -40,7 +40,10 @@ pub struct EnvironmentYaml {
 /// `environment.yaml` file.
 #[derive(Debug, Clone, PartialEq)]
 pub enum MatchSpecOrSubSection {
+    /// A regular conda matchspec.
     MatchSpec(MatchSpec),
+
+    /// A named subsection with specs for another package manager (e.g. `pip`).
     SubSection(String, Vec<String>),
 }
 
@@ -8,8 +8,10 @@ mod channel;
 mod channel_data;
 mod explicit_environment_spec;
 mod match_spec;
+pub mod menuinst;
 mod no_arch_type;
 mod parse_mode;
+mod pinned_file;
 mod platform;
 mod repo_data;
 mod repo_data_record;
@@ -30,7 +32,7 @@ use std::path::{Path, PathBuf};
 pub use build_spec::{BuildNumber, BuildNumberSpec, ParseBuildNumberSpecError};
 pub use channel::{Channel, ChannelConfig, NamedChannelOrUrl, ParseChannelError};
 pub use channel_data::{ChannelData, ChannelDataPackage};
-pub use environment_yaml::EnvironmentYaml;
+pub use environment_yaml::{EnvironmentYaml, MatchSpecOrSubSection};
 pub use explicit_environment_spec::{
     ExplicitEnvironmentEntry, ExplicitEnvironmentSpec, PackageArchiveHash,
     ParseExplicitEnvironmentSpecError, ParsePackageArchiveHashError,
@@ -44,6 +46,7 @@ pub use match_spec::{
 pub use no_arch_type::{NoArchKind, NoArchType};
 pub use package_name::{InvalidPackageNameError, PackageName};
 pub use parse_mode::ParseStrictness;
+pub use pinned_file::{ParsePinnedFileError, PinnedFile};
 pub use platform::{Arch, ParseArchError, ParsePlatformError, Platform};
 pub use prefix_record::PrefixRecord;
 pub use repo_data::{
@@ -0,0 +1,373 @@
+//! Types for the menuinst v2 schema.
+//!
+//! Packages can ship `Menu/*.json` files that describe shortcuts (menu
+//! entries) that should be created when the package is installed. The format
+//! of these files is described by the
+//! [menuinst schema](https://conda.github.io/menuinst/reference/).
+
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+/// The contents of a `Menu/*.json` file.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MenuInstSchema {
+    /// The json schema the file adheres to.
+    #[serde(rename = "$schema", default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+
+    /// The name of the menu that contains the items.
+    pub menu_name: String,
+
+    /// The items (shortcuts) of the menu.
+    pub menu_items: Vec<MenuItem>,
+}
+
+impl MenuInstSchema {
+    /// Parses a menuinst file.
+    pub fn from_path(path: &Path) -> Result<Self, std::io::Error> {
+        let contents = std::fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(Into::into)
+    }
+}
+
+/// The name of a menu item. The name can differ depending on whether the
+/// package is installed in the base environment.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum MenuItemName {
+    /// The name is the same in every environment.
+    Simple(String),
+
+    /// The name depends on the environment.
+    Complex {
+        /// The name used in the base environment.
+        target_environment_is_base: String,
+
+        /// The name used in all other environments.
+        target_environment_is_not_base: String,
+    },
+}
+
+impl MenuItemName {
+    /// Returns the name to use in a base or non-base environment.
+    pub fn resolve(&self, is_base: bool) -> &str {
+        match self {
+            MenuItemName::Simple(name) => name,
+            MenuItemName::Complex {
+                target_environment_is_base,
+                target_environment_is_not_base,
+            } => {
+                if is_base {
+                    target_environment_is_base
+                } else {
+                    target_environment_is_not_base
+                }
+            }
+        }
+    }
+}
+
+/// A single menu item.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MenuItem {
+    /// The properties that are shared between all platforms.
+    #[serde(flatten)]
+    pub command: MenuItemCommand,
+
+    /// Platform specific properties. A shortcut is only created on the
+    /// platforms that are listed.
+    pub platforms: Platforms,
+}
+
+/// The properties of a menu item that are shared by all platforms.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MenuItemCommand {
+    /// The name of the shortcut.
+    pub name: MenuItemName,
+
+    /// A description of the shortcut.
+    pub description: String,
+
+    /// The command to execute, one argument per element.
+    pub command: Vec<String>,
+
+    /// The path to the icon of the shortcut.
+    pub icon: Option<String>,
+
+    /// A command to run before the environment is activated.
+    pub precommand: Option<String>,
+
+    /// A command to run before the shortcut is created.
+    pub precreate: Option<String>,
+
+    /// The working directory of the command.
+    pub working_dir: Option<String>,
+
+    /// Whether to activate the environment before running the command.
+    pub activate: Option<bool>,
+
+    /// Whether to run the command in a terminal.
+    pub terminal: Option<bool>,
+}
+
+impl MenuItemCommand {
+    /// Overrides the properties of this command with the properties that
+    /// are set in the platform specific section.
+    pub fn merge(&self, platform: &BasePlatformSpecific) -> Self {
+        Self {
+            name: platform.name.clone().unwrap_or_else(|| self.name.clone()),
+            description: platform
+                .description
+                .clone()
+                .unwrap_or_else(|| self.description.clone()),
+            command: platform
+                .command
+                .clone()
+                .unwrap_or_else(|| self.command.clone()),
+            icon: platform.icon.clone().or_else(|| self.icon.clone()),
+            precommand: platform
+                .precommand
+                .clone()
+                .or_else(|| self.precommand.clone()),
+            precreate: platform
+                .precreate
+                .clone()
+                .or_else(|| self.precreate.clone()),
+            working_dir: platform
+                .working_dir
+                .clone()
+                .or_else(|| self.working_dir.clone()),
+            activate: platform.activate.or(self.activate),
+            terminal: platform.terminal.or(self.terminal),
+        }
+    }
+}
+
+/// The properties of [`MenuItemCommand`] that can be overridden per
+/// platform.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BasePlatformSpecific {
+    /// Overrides [`MenuItemCommand::name`].
+    pub name: Option<MenuItemName>,
+
+    /// Overrides [`MenuItemCommand::description`].
+    pub description: Option<String>,
+
+    /// Overrides [`MenuItemCommand::command`].
+    pub command: Option<Vec<String>>,
+
+    /// Overrides [`MenuItemCommand::icon`].
+    pub icon: Option<String>,
+
+    /// Overrides [`MenuItemCommand::precommand`].
+    pub precommand: Option<String>,
+
+    /// Overrides [`MenuItemCommand::precreate`].
+    pub precreate: Option<String>,
+
+    /// Overrides [`MenuItemCommand::working_dir`].
+    pub working_dir: Option<String>,
+
+    /// Overrides [`MenuItemCommand::activate`].
+    pub activate: Option<bool>,
+
+    /// Overrides [`MenuItemCommand::terminal`].
+    pub terminal: Option<bool>,
+}
+
+/// The platform specific section of a menu item.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct PlatformSpecific<T> {
+    /// Overrides of the shared properties.
+    #[serde(flatten)]
+    pub base: BasePlatformSpecific,
+
+    /// The properties that only exist on this platform.
+    #[serde(flatten)]
+    pub specific: T,
+}
+
+/// The platforms a menu item is defined for.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Platforms {
+    /// The Linux specific properties.
+    pub linux: Option<PlatformSpecific<Linux>>,
+
+    /// The macOS specific properties.
+    pub osx: Option<PlatformSpecific<MacOS>>,
+
+    /// The Windows specific properties.
+    pub win: Option<PlatformSpecific<Windows>>,
+}
+
+/// Linux specific properties, these map to the keys of a `.desktop` file as
+/// described by the
+/// [Desktop Entry Specification](https://specifications.freedesktop.org/desktop-entry-spec/latest/).
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct Linux {
+    /// The categories in which the entry should be shown in a menu.
+    pub categories: Option<Vec<String>>,
+
+    /// Whether D-Bus activation is supported by the application.
+    #[serde(rename = "DBusActivatable")]
+    pub dbus_activatable: Option<bool>,
+
+    /// A generic name of the application, e.g. "Web Browser".
+    pub generic_name: Option<String>,
+
+    /// Whether the entry should be treated as deleted.
+    pub hidden: Option<bool>,
+
+    /// The interfaces that the application implements.
+    pub implements: Option<Vec<String>>,
+
+    /// Additional words that describe the entry.
+    pub keywords: Option<Vec<String>>,
+
+    /// The mime types supported by the application.
+    pub mime_type: Option<Vec<String>>,
+
+    /// Whether the entry should not be displayed in menus.
+    pub no_display: Option<bool>,
+
+    /// The desktop environments in which the entry should not be shown.
+    pub not_show_in: Option<Vec<String>>,
+
+    /// The desktop environments in which the entry should only be shown.
+    pub only_show_in: Option<Vec<String>>,
+
+    /// Whether the application prefers to run on a discrete GPU.
+    #[serde(rename = "PrefersNonDefaultGPU")]
+    pub prefers_non_default_gpu: Option<bool>,
+
+    /// Whether the application sends a startup notification.
+    pub startup_notify: Option<bool>,
+
+    /// The `WM_CLASS` of the application window.
+    #[serde(rename = "StartupWMClass")]
+    pub startup_wm_class: Option<String>,
+
+    /// A path to an executable that determines whether the program is
+    /// installed.
+    pub try_exec: Option<String>,
+
+    /// Maps the mime types in [`Self::mime_type`] to glob patterns of file
+    /// names, used to register new mime types.
+    #[serde(rename = "glob_patterns")]
+    pub glob_patterns: Option<HashMap<String, String>>,
+
+    /// Whether the application has a single main window.
+    pub single_main_window: Option<bool>,
+}
+
+/// macOS specific properties. These map to keys of the `Info.plist` file of
+/// the generated application bundle.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MacOS {
+    /// The display name of the bundle.
+    #[serde(rename = "CFBundleDisplayName")]
+    pub cf_bundle_display_name: Option<String>,
+
+    /// The identifier of the bundle.
+    #[serde(rename = "CFBundleIdentifier")]
+    pub cf_bundle_identifier: Option<String>,
+
+    /// The short name of the bundle.
+    #[serde(rename = "CFBundleName")]
+    pub cf_bundle_name: Option<String>,
+
+    /// The version of the bundle.
+    #[serde(rename = "CFBundleVersion")]
+    pub cf_bundle_version: Option<String>,
+
+    /// The category of the application in the App Store.
+    #[serde(rename = "LSApplicationCategoryType")]
+    pub ls_application_category_type: Option<String>,
+
+    /// The minimum version of macOS required to run the application.
+    #[serde(rename = "LSMinimumSystemVersion")]
+    pub ls_minimum_system_version: Option<String>,
+
+    /// Whether the bundle contains an executable that should be linked
+    /// instead of a launcher script.
+    pub link_in_bundle: Option<HashMap<String, String>>,
+
+    /// A script that is run when the application bundle is launched.
+    pub event_handler: Option<String>,
+}
+
+/// Windows specific properties.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Windows {
+    /// Whether to create a shortcut on the desktop.
+    pub desktop: Option<bool>,
+
+    /// Whether to create a shortcut in the quick launch bar.
+    pub quicklaunch: Option<bool>,
+
+    /// The `AppUserModelID` of the shortcut.
+    pub app_user_model_id: Option<String>,
+
+    /// The file extensions to associate with the shortcut.
+    pub file_extensions: Option<Vec<String>>,
+
+    /// The URL protocols to associate with the shortcut.
+    pub url_protocols: Option<Vec<String>>,
+
+    /// Whether to create a shortcut in the terminal profiles of Windows
+    /// Terminal.
+    pub terminal_profile: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MenuInstSchema, MenuItemName};
+
+    #[test]
+    fn test_parse_menuinst() {
+        let path = crate::get_test_data_dir().join("menuinst/example.json");
+        let schema = MenuInstSchema::from_path(&path).unwrap();
+        assert_eq!(schema.menu_name, "Example Menu");
+        assert_eq!(schema.menu_items.len(), 1);
+
+        let item = &schema.menu_items[0];
+        assert_eq!(item.command.name.resolve(true), "Example (base)");
+        assert_eq!(item.command.name.resolve(false), "Example");
+        assert_eq!(item.command.command, vec!["{{ PREFIX }}/bin/example", "%F"]);
+
+        let linux = item.platforms.linux.as_ref().unwrap();
+        assert_eq!(
+            linux.specific.categories.as_deref(),
+            Some(&[String::from("Science"), String::from("Utility")][..])
+        );
+        assert_eq!(
+            linux.specific.glob_patterns.as_ref().unwrap()["application/x-example"],
+            "*.example"
+        );
+
+        // The platform specific section overrides the shared properties.
+        let command = item.command.merge(&linux.base);
+        assert_eq!(command.terminal, Some(true));
+        assert_eq!(command.description, "An example application");
+
+        let osx = item.platforms.osx.as_ref().unwrap();
+        assert_eq!(
+            osx.base.name,
+            Some(MenuItemName::Simple(String::from("Example.app")))
+        );
+        assert_eq!(
+            osx.specific.cf_bundle_identifier.as_deref(),
+            Some("org.example.app")
+        );
+        assert!(item.platforms.win.is_none());
+    }
+}
@@ -0,0 +1,166 @@
+//! Defines the [`PinnedFile`] struct which represents the `conda-meta/pinned`
+//! file of a prefix.
+
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{MatchSpec, PackageName, ParseMatchSpecError, ParseStrictness};
+
+/// The contents of the `conda-meta/pinned` file of a prefix.
+///
+/// The file contains a match spec per line that restricts which packages can
+/// be installed in the prefix. Empty lines and lines that start with `#` are
+/// ignored.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PinnedFile {
+    /// The pinned specs.
+    pub specs: Vec<MatchSpec>,
+}
+
+/// An error that can occur when parsing a [`PinnedFile`].
+#[derive(Debug, thiserror::Error)]
+#[error("invalid pin '{spec}' on line {line}")]
+pub struct ParsePinnedFileError {
+    /// The line (1-based) that contains the invalid spec.
+    pub line: usize,
+
+    /// The invalid spec.
+    pub spec: String,
+
+    /// The reason why the spec is invalid.
+    #[source]
+    pub source: ParseMatchSpecError,
+}
+
+impl PinnedFile {
+    /// Returns the location of the pinned file in a prefix.
+    pub fn path(prefix: &Path) -> PathBuf {
+        prefix.join("conda-meta").join("pinned")
+    }
+
+    /// Reads the pinned file of a prefix. Returns an empty file if the prefix
+    /// does not contain a pinned file.
+    pub fn from_prefix(prefix: &Path) -> std::io::Result<Self> {
+        let contents = match std::fs::read_to_string(Self::path(prefix)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        contents
+            .parse()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the pinned file to a prefix.
+    pub fn to_prefix(&self, prefix: &Path) -> std::io::Result<()> {
+        let path = Self::path(prefix);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, self.to_string())
+    }
+
+    /// Returns the pins that apply to the package with the given name.
+    pub fn pins_for<'a>(&'a self, name: &'a PackageName) -> impl Iterator<Item = &'a MatchSpec> {
+        self.specs
+            .iter()
+            .filter(move |spec| spec.name.as_ref() == Some(name))
+    }
+
+    /// Adds a pin, replacing any existing pins of the same package.
+    pub fn pin(&mut self, spec: MatchSpec) {
+        if let Some(name) = &spec.name {
+            self.unpin(name);
+        }
+        self.specs.push(spec);
+    }
+
+    /// Removes all pins of the package with the given name. Returns true if
+    /// a pin was removed.
+    pub fn unpin(&mut self, name: &PackageName) -> bool {
+        let len = self.specs.len();
+        self.specs.retain(|spec| spec.name.as_ref() != Some(name));
+        len != self.specs.len()
+    }
+}
+
+impl FromStr for PinnedFile {
+    type Err = ParsePinnedFileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let specs = s
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| (idx, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(idx, line)| {
+                MatchSpec::from_str(line, ParseStrictness::Lenient).map_err(|source| {
+                    ParsePinnedFileError {
+                        line: idx + 1,
+                        spec: line.to_string(),
+                        source,
+                    }
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { specs })
+    }
+}
+
+impl Display for PinnedFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for spec in &self.specs {
+            writeln!(f, "{spec}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::PinnedFile;
+    use crate::{MatchSpec, PackageName, ParseStrictness};
+
+    #[test]
+    fn test_pinned_file() {
+        let mut pinned = PinnedFile::from_str(
+            "# pins carried over from conda\npython 3.11.*\n\nnumpy <2\n  openssl=3.1  \n",
+        )
+        .unwrap();
+        assert_eq!(pinned.specs.len(), 3);
+
+        let python = PackageName::new_unchecked("python");
+        assert_eq!(pinned.pins_for(&python).count(), 1);
+
+        pinned.pin(MatchSpec::from_str("python 3.12.*", ParseStrictness::Lenient).unwrap());
+        assert_eq!(
+            pinned
+                .pins_for(&python)
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![String::from("python 3.12.*")]
+        );
+        assert!(pinned.unpin(&PackageName::new_unchecked("numpy")));
+        assert!(!pinned.unpin(&PackageName::new_unchecked("numpy")));
+
+        let prefix = tempfile::tempdir().unwrap();
+        assert_eq!(
+            PinnedFile::from_prefix(prefix.path()).unwrap(),
+            PinnedFile::default()
+        );
+        pinned.to_prefix(prefix.path()).unwrap();
+        assert_eq!(PinnedFile::from_prefix(prefix.path()).unwrap(), pinned);
+    }
+
+    #[test]
+    fn test_invalid_pin() {
+        let err = PinnedFile::from_str("python 3.11.*\nnumpy[unknown=1.0]\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.spec, "numpy[unknown=1.0]");
+    }
+}
@@ -0,0 +1,55 @@
+use std::{path::Path, str::FromStr};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rattler_conda_types::Platform;
+use rattler_lock::{LazyLockFile, LockFile, DEFAULT_ENVIRONMENT_NAME};
+
+/// Constructs a large lock-file by copying the default environment of an
+/// existing lock-file into many environments.
+fn large_lock_file() -> String {
+    let lock_file = LockFile::from_path(
+        &Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock/v4/turtlesim-lock.yml"),
+    )
+    .unwrap();
+    let environment = lock_file.default_environment().unwrap();
+
+    let mut builder = LockFile::builder();
+    for idx in 0..40 {
+        let name = format!("env-{idx}");
+        builder.set_channels(&name, environment.channels().to_vec());
+        for (platform, packages) in environment.packages_by_platform() {
+            for package in packages {
+                builder.add_package(&name, platform, package);
+            }
+        }
+    }
+    builder.set_channels(DEFAULT_ENVIRONMENT_NAME, environment.channels().to_vec());
+
+    serde_yaml::to_string(&builder.finish()).unwrap()
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let source = large_lock_file();
+
+    let mut group = c.benchmark_group("parse-lock-file");
+    group.bench_function("eager", |b| {
+        b.iter(|| LockFile::from_str(black_box(&source)).unwrap());
+    });
+    group.bench_function("lazy-index", |b| {
+        b.iter(|| LazyLockFile::from_str(black_box(&source)).unwrap());
+    });
+    group.bench_function("lazy-single-environment", |b| {
+        b.iter(|| {
+            LazyLockFile::from_str(black_box(&source))
+                .unwrap()
+                .environment_for_platform("env-0", Platform::Linux64)
+                .unwrap()
+                .unwrap()
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
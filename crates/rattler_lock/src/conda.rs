@@ -134,7 +134,7 @@ fn file_name_from_url(url: &Url) -> Option<&str> {
 /// Channel from url, this is everything before the filename and the subdir
 /// So for example: <https://conda.anaconda.org/conda-forge/> is a channel name
 /// that we parse from something like: <https://conda.anaconda.org/conda-forge/osx-64/python-3.11.0-h4150a38_1_cpython.conda>
-fn channel_from_url(url: &Url) -> Option<Url> {
+pub(crate) fn channel_from_url(url: &Url) -> Option<Url> {
     let mut result = url.clone();
 
     // Strip the last two path segments. We assume the first one contains the file_name, and the
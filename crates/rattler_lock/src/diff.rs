@@ -0,0 +1,632 @@
+//! Semantic comparison of two lock-files.
+//!
+//! Use [`LockFile::diff`] to compute a [`LockFileDiff`] which describes for every environment and
+//! platform which packages were added, removed, up- or downgraded or otherwise changed. The
+//! result can be rendered as JSON or as a markdown table (e.g. to post in a pull request).
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use rattler_conda_types::Platform;
+use serde::Serialize;
+
+use crate::{Environment, LockFile, Package};
+
+/// The differences between two lock-files, see [`LockFile::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LockFileDiff {
+    /// The changed environments indexed by their name. Environments without changes are omitted.
+    pub environments: BTreeMap<String, EnvironmentDiff>,
+}
+
+/// The differences of a single environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EnvironmentDiff {
+    /// Set if the channels of the environment changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<ChannelsDiff>,
+
+    /// The changes per platform, ordered by platform name. Platforms without changes are
+    /// omitted.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub platforms: Vec<PlatformDiff>,
+}
+
+/// Describes a change to the channels of an environment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChannelsDiff {
+    /// The channels before the change, in order of priority.
+    pub before: Vec<String>,
+
+    /// The channels after the change, in order of priority.
+    pub after: Vec<String>,
+}
+
+/// The changed packages of an environment for a single platform.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlatformDiff {
+    /// The platform
+    pub platform: Platform,
+
+    /// The changed packages ordered by name.
+    pub packages: Vec<PackageDiff>,
+}
+
+/// The ecosystem a package belongs to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageKind {
+    /// A conda package
+    Conda,
+
+    /// A pypi package
+    Pypi,
+}
+
+/// The kind of change of a single package.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PackageChange {
+    /// The package was added.
+    Added,
+
+    /// The package was removed.
+    Removed,
+
+    /// The version of the package increased.
+    Upgraded,
+
+    /// The version of the package decreased.
+    Downgraded,
+
+    /// The version is the same but the build string changed.
+    BuildChanged,
+
+    /// The version and build are the same but the version is spelled differently (e.g. `1.0`
+    /// and `1.0.0`).
+    VersionSpellingChanged,
+
+    /// The version and build are the same but the package is now retrieved from a different
+    /// channel. For pypi packages this means the package is retrieved from a different location.
+    ChannelChanged,
+
+    /// Only the hashes of the package changed.
+    HashChanged,
+}
+
+impl PackageChange {
+    /// Returns a human readable description of the change.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PackageChange::Added => "added",
+            PackageChange::Removed => "removed",
+            PackageChange::Upgraded => "upgraded",
+            PackageChange::Downgraded => "downgraded",
+            PackageChange::BuildChanged => "build changed",
+            PackageChange::VersionSpellingChanged => "version spelling changed",
+            PackageChange::ChannelChanged => "channel changed",
+            PackageChange::HashChanged => "hash changed",
+        }
+    }
+}
+
+/// A single changed package.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageDiff {
+    /// The name of the package
+    pub name: String,
+
+    /// Whether this is a conda or a pypi package
+    pub kind: PackageKind,
+
+    /// What changed
+    pub change: PackageChange,
+
+    /// The package before the change, `None` if the package was added.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<LockedPackageSummary>,
+
+    /// The package after the change, `None` if the package was removed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<LockedPackageSummary>,
+}
+
+/// The information of a locked package that is relevant for a diff.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LockedPackageSummary {
+    /// The version of the package
+    pub version: String,
+
+    /// The build string of the package (conda packages only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+
+    /// The channel of a conda package or the location of a pypi package.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+
+    /// The hex encoded sha256 hash of the package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    /// The hex encoded md5 hash of the package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+}
+
+impl LockedPackageSummary {
+    /// Returns the version and build string (if any) separated by a space.
+    pub(crate) fn display_version(&self) -> String {
+        match &self.build {
+            Some(build) => format!("{} {}", self.version, build),
+            None => self.version.clone(),
+        }
+    }
+}
+
+/// A package together with the order of its version.
+struct LockedPackage {
+    summary: LockedPackageSummary,
+    version: ComparableVersion,
+}
+
+enum ComparableVersion {
+    Conda(rattler_conda_types::Version),
+    Pypi(pep440_rs::Version),
+}
+
+impl ComparableVersion {
+    fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (ComparableVersion::Conda(a), ComparableVersion::Conda(b)) => a.cmp(b),
+            (ComparableVersion::Pypi(a), ComparableVersion::Pypi(b)) => a.cmp(b),
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+impl From<Package> for LockedPackageSummary {
+    fn from(package: Package) -> Self {
+        LockedPackage::from(package).summary
+    }
+}
+
+impl From<Package> for LockedPackage {
+    fn from(package: Package) -> Self {
+        match package {
+            Package::Conda(package) => {
+                let record = package.package_record();
+                LockedPackage {
+                    summary: LockedPackageSummary {
+                        version: record.version.as_str().into_owned(),
+                        build: Some(record.build.clone()),
+                        channel: package.channel().map(|url| url.to_string()),
+                        sha256: record.sha256.map(|hash| format!("{hash:x}")),
+                        md5: record.md5.map(|hash| format!("{hash:x}")),
+                    },
+                    version: ComparableVersion::Conda(record.version.version().clone()),
+                }
+            }
+            Package::Pypi(package) => {
+                let data = package.data().package;
+                LockedPackage {
+                    summary: LockedPackageSummary {
+                        version: data.version.to_string(),
+                        build: None,
+                        channel: Some(data.url_or_path.to_string()),
+                        sha256: data
+                            .hash
+                            .as_ref()
+                            .and_then(|hash| hash.sha256())
+                            .map(|hash| format!("{hash:x}")),
+                        md5: data
+                            .hash
+                            .as_ref()
+                            .and_then(|hash| hash.md5())
+                            .map(|hash| format!("{hash:x}")),
+                    },
+                    version: ComparableVersion::Pypi(data.version.clone()),
+                }
+            }
+        }
+    }
+}
+
+impl LockFile {
+    /// Computes the semantic differences between this lock-file and `other`.
+    ///
+    /// `self` is considered the old state and `other` the new state. Environments or platforms
+    /// that only exist in one of the lock-files are reported as if all their packages were added
+    /// or removed.
+    pub fn diff(&self, other: &LockFile) -> LockFileDiff {
+        let names: BTreeSet<&str> = self
+            .environments()
+            .chain(other.environments())
+            .map(|(name, _)| name)
+            .collect();
+
+        let environments = names
+            .into_iter()
+            .filter_map(|name| {
+                let diff = diff_environment(self.environment(name), other.environment(name));
+                (!diff.is_empty()).then(|| (name.to_string(), diff))
+            })
+            .collect();
+
+        LockFileDiff { environments }
+    }
+}
+
+fn diff_environment(before: Option<Environment>, after: Option<Environment>) -> EnvironmentDiff {
+    let channel_urls = |env: &Option<Environment>| -> Vec<String> {
+        env.as_ref()
+            .map(|env| {
+                env.channels()
+                    .iter()
+                    .map(|channel| channel.url.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let before_channels = channel_urls(&before);
+    let after_channels = channel_urls(&after);
+    let channels = (before_channels != after_channels).then_some(ChannelsDiff {
+        before: before_channels,
+        after: after_channels,
+    });
+
+    let mut platform_set: Vec<Platform> = before
+        .iter()
+        .chain(after.iter())
+        .flat_map(|env| env.platforms().collect::<Vec<_>>())
+        .collect();
+    platform_set.sort_by_key(|platform| platform.as_str());
+    platform_set.dedup();
+
+    let platforms = platform_set
+        .into_iter()
+        .filter_map(|platform| {
+            let packages = diff_packages(
+                locked_packages(before.as_ref(), platform),
+                locked_packages(after.as_ref(), platform),
+            );
+            (!packages.is_empty()).then_some(PlatformDiff { platform, packages })
+        })
+        .collect();
+
+    EnvironmentDiff {
+        channels,
+        platforms,
+    }
+}
+
+fn locked_packages(
+    environment: Option<&Environment>,
+    platform: Platform,
+) -> BTreeMap<(String, PackageKind), LockedPackage> {
+    let Some(packages) = environment.and_then(|env| env.packages(platform)) else {
+        return BTreeMap::new();
+    };
+
+    packages
+        .map(|package| {
+            let kind = if package.is_conda() {
+                PackageKind::Conda
+            } else {
+                PackageKind::Pypi
+            };
+            (
+                (package.name().into_owned(), kind),
+                LockedPackage::from(package),
+            )
+        })
+        .collect()
+}
+
+fn diff_packages(
+    mut before: BTreeMap<(String, PackageKind), LockedPackage>,
+    after: BTreeMap<(String, PackageKind), LockedPackage>,
+) -> Vec<PackageDiff> {
+    let mut result = Vec::new();
+    for ((name, kind), new) in after {
+        let change = match before.remove(&(name.clone(), kind)) {
+            None => Some((PackageChange::Added, None)),
+            Some(old) => package_change(&old, &new).map(|change| (change, Some(old.summary))),
+        };
+        if let Some((change, before)) = change {
+            result.push(PackageDiff {
+                name,
+                kind,
+                change,
+                before,
+                after: Some(new.summary),
+            });
+        }
+    }
+
+    result.extend(before.into_iter().map(|((name, kind), old)| PackageDiff {
+        name,
+        kind,
+        change: PackageChange::Removed,
+        before: Some(old.summary),
+        after: None,
+    }));
+
+    result.sort_by(|a, b| (&a.name, a.kind).cmp(&(&b.name, b.kind)));
+    result
+}
+
+fn package_change(before: &LockedPackage, after: &LockedPackage) -> Option<PackageChange> {
+    let (old, new) = (&before.summary, &after.summary);
+    match before.version.compare(&after.version) {
+        Ordering::Less => Some(PackageChange::Upgraded),
+        Ordering::Greater => Some(PackageChange::Downgraded),
+        Ordering::Equal if old.build != new.build => Some(PackageChange::BuildChanged),
+        Ordering::Equal if old.version != new.version => {
+            Some(PackageChange::VersionSpellingChanged)
+        }
+        Ordering::Equal if old.channel != new.channel => Some(PackageChange::ChannelChanged),
+        Ordering::Equal if old.sha256 != new.sha256 || old.md5 != new.md5 => {
+            Some(PackageChange::HashChanged)
+        }
+        Ordering::Equal => None,
+    }
+}
+
+impl LockFileDiff {
+    /// Returns true if the lock-files are semantically equal.
+    pub fn is_empty(&self) -> bool {
+        self.environments.is_empty()
+    }
+
+    /// Renders the diff as pretty printed JSON. The output is stable: environments, platforms and
+    /// packages are always sorted by name.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a diff can always be serialized")
+    }
+
+    /// Renders the diff as markdown, with a table of changed packages per environment and
+    /// platform.
+    pub fn to_markdown(&self) -> String {
+        if self.is_empty() {
+            return String::from("No changes.\n");
+        }
+
+        let mut out = String::new();
+        for (name, environment) in &self.environments {
+            writeln!(out, "## Environment `{name}`\n").unwrap();
+
+            if let Some(channels) = &environment.channels {
+                writeln!(
+                    out,
+                    "Channels changed from {} to {}\n",
+                    format_channels(&channels.before),
+                    format_channels(&channels.after)
+                )
+                .unwrap();
+            }
+
+            for platform in &environment.platforms {
+                writeln!(out, "### {}\n", platform.platform).unwrap();
+                writeln!(out, "| Package | Kind | Before | After | Change |").unwrap();
+                writeln!(out, "|---|---|---|---|---|").unwrap();
+                for package in &platform.packages {
+                    let kind = match package.kind {
+                        PackageKind::Conda => "conda",
+                        PackageKind::Pypi => "pypi",
+                    };
+                    let before = package
+                        .before
+                        .as_ref()
+                        .map_or(String::new(), LockedPackageSummary::display_version);
+                    let after = package
+                        .after
+                        .as_ref()
+                        .map_or(String::new(), LockedPackageSummary::display_version);
+                    writeln!(
+                        out,
+                        "| {} | {kind} | {before} | {after} | {} |",
+                        package.name,
+                        package.change.as_str()
+                    )
+                    .unwrap();
+                }
+                out.push('\n');
+            }
+        }
+
+        out
+    }
+}
+
+impl EnvironmentDiff {
+    /// Returns true if nothing changed in the environment.
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_none() && self.platforms.is_empty()
+    }
+}
+
+fn format_channels(channels: &[String]) -> String {
+    if channels.is_empty() {
+        return String::from("_none_");
+    }
+    channels
+        .iter()
+        .map(|channel| format!("`{channel}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use rattler_conda_types::{PackageName, PackageRecord, Platform, Version};
+    use url::Url;
+
+    use super::PackageChange;
+    use crate::{
+        CondaPackageData, LockFile, PackageHashes, PypiPackageData, PypiPackageEnvironmentData,
+        UrlOrPath, DEFAULT_ENVIRONMENT_NAME,
+    };
+
+    fn conda_package(name: &str, version: &str, build: &str, channel: &str) -> CondaPackageData {
+        let mut record = PackageRecord::new(
+            PackageName::new_unchecked(name),
+            Version::from_str(version).unwrap(),
+            build.to_string(),
+        );
+        record.subdir = "linux-64".to_string();
+        CondaPackageData {
+            package_record: record,
+            url: Url::parse(&format!(
+                "https://conda.anaconda.org/{channel}/linux-64/{name}-{version}-{build}.conda"
+            ))
+            .unwrap(),
+            file_name: None,
+            channel: None,
+        }
+    }
+
+    fn pypi_package(name: &str, version: &str, sha256: &str) -> PypiPackageData {
+        PypiPackageData {
+            name: name.parse().unwrap(),
+            version: version.parse().unwrap(),
+            url_or_path: UrlOrPath::Url(
+                Url::parse(&format!(
+                    "https://files.pythonhosted.org/{name}-{version}-py3-none-any.whl"
+                ))
+                .unwrap(),
+            ),
+            hash: PackageHashes::from_hashes(
+                None,
+                Some(
+                    rattler_digest::parse_digest_from_hex::<rattler_digest::Sha256>(sha256)
+                        .unwrap(),
+                ),
+            ),
+            requires_dist: vec![],
+            requires_python: None,
+            editable: false,
+        }
+    }
+
+    fn lock_file(
+        channels: &[&str],
+        conda: Vec<CondaPackageData>,
+        pypi: Vec<PypiPackageData>,
+    ) -> LockFile {
+        let mut builder = LockFile::builder();
+        builder.set_channels(DEFAULT_ENVIRONMENT_NAME, channels.iter().copied());
+        for package in conda {
+            builder.add_conda_package(DEFAULT_ENVIRONMENT_NAME, Platform::Linux64, package);
+        }
+        for package in pypi {
+            builder.add_pypi_package(
+                DEFAULT_ENVIRONMENT_NAME,
+                Platform::Linux64,
+                package,
+                PypiPackageEnvironmentData::default(),
+            );
+        }
+        builder.finish()
+    }
+
+    const HASH_A: &str = "8485a64911c7011c0270b8266ab2bffa1da41c59ac4f0a48000c31d4f4a966dd";
+    const HASH_B: &str = "0ba5cb5ea1e6fe8e5d8ef3d0d7bd6a3a1e6a57ad0a3e2a1c3e1b7d6c9a8f4e2d";
+
+    fn old_lock_file() -> LockFile {
+        lock_file(
+            &["https://conda.anaconda.org/conda-forge/"],
+            vec![
+                conda_package("python", "3.11.0", "h1_0", "conda-forge"),
+                conda_package("numpy", "1.26.4", "py311_0", "conda-forge"),
+                conda_package("openssl", "3.2.0", "h0_0", "conda-forge"),
+                conda_package("zlib", "1.3", "h0_0", "conda-forge"),
+                conda_package("libzlib", "1.3", "h0_0", "conda-forge"),
+            ],
+            vec![
+                pypi_package("requests", "2.31.0", HASH_A),
+                pypi_package("idna", "3.6", HASH_A),
+            ],
+        )
+    }
+
+    fn new_lock_file() -> LockFile {
+        lock_file(
+            &[
+                "https://conda.anaconda.org/conda-forge/",
+                "https://conda.anaconda.org/bioconda/",
+            ],
+            vec![
+                conda_package("python", "3.12.0", "h1_0", "conda-forge"),
+                conda_package("numpy", "1.26.0", "py312_0", "conda-forge"),
+                conda_package("openssl", "3.2.0", "h0_1", "conda-forge"),
+                conda_package("libzlib", "1.3", "h0_0", "bioconda"),
+                conda_package("samtools", "1.19", "h0_0", "bioconda"),
+            ],
+            vec![
+                pypi_package("requests", "2.31.0", HASH_B),
+                pypi_package("idna", "3.6", HASH_A),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let lock_file = old_lock_file();
+        let diff = lock_file.diff(&old_lock_file());
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_markdown(), "No changes.\n");
+    }
+
+    #[test]
+    fn test_diff_json() {
+        let diff = old_lock_file().diff(&new_lock_file());
+        insta::assert_snapshot!(diff.to_json());
+    }
+
+    #[test]
+    fn test_diff_markdown() {
+        let diff = old_lock_file().diff(&new_lock_file());
+        insta::assert_snapshot!(diff.to_markdown());
+    }
+
+    #[test]
+    fn test_diff_version_spelling() {
+        let channels = ["https://conda.anaconda.org/conda-forge/"];
+        let old = lock_file(
+            &channels,
+            vec![
+                conda_package("zlib", "1.3", "h0_0", "conda-forge"),
+                conda_package("bzip2", "1.0", "h0_0", "conda-forge"),
+            ],
+            vec![],
+        );
+        let new = lock_file(
+            &channels,
+            vec![
+                conda_package("zlib", "1.3.0", "h0_0", "conda-forge"),
+                conda_package("bzip2", "1.0.0", "h0_1", "conda-forge"),
+            ],
+            vec![],
+        );
+
+        let diff = old.diff(&new);
+        let changes = diff.environments[DEFAULT_ENVIRONMENT_NAME].platforms[0]
+            .packages
+            .iter()
+            .map(|package| (package.name.as_str(), package.change))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            [
+                ("bzip2", PackageChange::BuildChanged),
+                ("zlib", PackageChange::VersionSpellingChanged),
+            ]
+        );
+    }
+}
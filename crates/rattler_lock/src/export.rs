@@ -0,0 +1,294 @@
+//! Conversion of a locked environment to other environment formats.
+//!
+//! Not every tool is able to read lock-files directly. The functions in this module convert a
+//! single platform of an [`Environment`] into an [`ExplicitEnvironmentSpec`] (which can be
+//! installed with `conda create --file explicit.txt`) or into an [`EnvironmentYaml`] with pinned
+//! specs.
+
+use std::str::FromStr;
+
+use itertools::Itertools;
+use rattler_conda_types::{
+    version_spec::EqualityOperator, EnvironmentYaml, ExplicitEnvironmentEntry,
+    ExplicitEnvironmentSpec, MatchSpec, MatchSpecOrSubSection, NamedChannelOrUrl, PackageRecord,
+    Platform, StringMatcher, VersionSpec,
+};
+
+use crate::{CondaPackage, Environment, Package, PypiIndexes, PypiPackage, UrlOrPath};
+
+/// An error that can occur when converting an environment to another format.
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    /// A channel of the environment is not a valid channel name or url.
+    #[error("invalid channel '{0}'")]
+    InvalidChannel(String, #[source] url::ParseError),
+}
+
+impl Environment {
+    /// Converts the conda packages of this environment for the given platform into an
+    /// [`ExplicitEnvironmentSpec`]. Returns `None` if the platform is not part of this
+    /// environment.
+    ///
+    /// The packages are sorted topologically so they can be installed in order. The url of every
+    /// package carries a hash fragment so the downloaded archive can be verified. The md5 hash is
+    /// used if it is available because it is understood by all versions of conda, otherwise the
+    /// sha256 hash is used.
+    ///
+    /// Pypi packages cannot be represented in an explicit environment and are ignored.
+    pub fn to_explicit_environment_spec(
+        &self,
+        platform: Platform,
+    ) -> Option<ExplicitEnvironmentSpec> {
+        let conda_packages = self
+            .packages(platform)?
+            .filter_map(Package::into_conda)
+            .collect();
+
+        let packages = PackageRecord::sort_topologically(conda_packages)
+            .into_iter()
+            .map(|package| ExplicitEnvironmentEntry {
+                url: explicit_url(&package),
+            })
+            .collect();
+
+        Some(ExplicitEnvironmentSpec {
+            platform: Some(platform),
+            packages,
+        })
+    }
+
+    /// Converts this environment for the given platform into an [`EnvironmentYaml`]. Returns
+    /// `None` if the platform is not part of this environment.
+    ///
+    /// Every conda package is added as a spec that pins the exact version and build string. Pypi
+    /// packages are added to the `pip` subsection. Pypi packages that were not downloaded from one
+    /// of the indexes of the environment are pinned to their url.
+    pub fn to_environment_yaml(
+        &self,
+        platform: Platform,
+    ) -> Result<Option<EnvironmentYaml>, ExportError> {
+        let Some(packages) = self.packages(platform) else {
+            return Ok(None);
+        };
+        let (conda_packages, pypi_packages): (Vec<_>, Vec<_>) =
+            packages.partition_map(|package| match package {
+                Package::Conda(package) => itertools::Either::Left(package),
+                Package::Pypi(package) => itertools::Either::Right(package),
+            });
+
+        let mut dependencies = conda_packages
+            .iter()
+            .map(|package| MatchSpecOrSubSection::MatchSpec(pinned_match_spec(package)))
+            .collect_vec();
+        if !pypi_packages.is_empty() {
+            dependencies.push(MatchSpecOrSubSection::SubSection(
+                String::from("pip"),
+                pypi_packages
+                    .iter()
+                    .map(|package| pip_requirement(package, self.pypi_indexes()))
+                    .collect(),
+            ));
+        }
+
+        let channels = self
+            .channels()
+            .iter()
+            .map(|channel| {
+                NamedChannelOrUrl::from_str(&channel.url)
+                    .map_err(|e| ExportError::InvalidChannel(channel.url.clone(), e))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(EnvironmentYaml {
+            channels,
+            dependencies,
+            ..EnvironmentYaml::default()
+        }))
+    }
+}
+
+/// Returns the url of the package with a hash fragment attached.
+fn explicit_url(package: &CondaPackage) -> url::Url {
+    let record = package.package_record();
+    let mut url = package.url().clone();
+    if let Some(md5) = &record.md5 {
+        url.set_fragment(Some(&format!("{md5:x}")));
+    } else if let Some(sha256) = &record.sha256 {
+        url.set_fragment(Some(&format!("sha256:{sha256:x}")));
+    }
+    url
+}
+
+/// Returns a [`MatchSpec`] that only matches the exact version and build of the package.
+fn pinned_match_spec(package: &CondaPackage) -> MatchSpec {
+    let record = package.package_record();
+    MatchSpec {
+        name: Some(record.name.clone()),
+        version: Some(VersionSpec::Exact(
+            EqualityOperator::Equals,
+            record.version.version().clone(),
+        )),
+        build: Some(StringMatcher::Exact(record.build.clone())),
+        ..MatchSpec::default()
+    }
+}
+
+/// Returns a requirement string for the pip subsection of an `environment.yml` file.
+fn pip_requirement(package: &PypiPackage, indexes: Option<&PypiIndexes>) -> String {
+    let data = package.data();
+    let extras = if data.environment.extras.is_empty() {
+        String::new()
+    } else {
+        format!("[{}]", data.environment.extras.iter().join(","))
+    };
+
+    match &data.package.url_or_path {
+        UrlOrPath::Url(url) if is_index_url(url, indexes) => {
+            format!("{}{extras}=={}", data.package.name, data.package.version)
+        }
+        UrlOrPath::Url(url) => format!("{}{extras} @ {url}", data.package.name),
+        UrlOrPath::Path(path) if data.package.editable => format!("-e {}", path.display()),
+        UrlOrPath::Path(path) => path.display().to_string(),
+    }
+}
+
+/// Returns true if the url points to a distribution that pip can find on one of the indexes (or
+/// flat indexes) of the environment. Lock files without indexes were solved against PyPI.
+fn is_index_url(url: &url::Url, indexes: Option<&PypiIndexes>) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    let index_hosts = match indexes {
+        Some(indexes) => indexes
+            .indexes
+            .iter()
+            .chain(indexes.find_links.iter().filter_map(|link| link.as_url()))
+            .filter_map(|index| index.host_str())
+            .collect_vec(),
+        None => vec!["pypi.org"],
+    };
+    url.host_str().map_or(false, |host| {
+        // PyPI serves its files from a separate host.
+        index_hosts.contains(&host)
+            || (host == "files.pythonhosted.org" && index_hosts.contains(&"pypi.org"))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rattler_conda_types::{EnvironmentYaml, Platform};
+
+    use super::ExportError;
+    use crate::{LockFile, DEFAULT_ENVIRONMENT_NAME};
+
+    fn lock_file(name: &str) -> LockFile {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock")
+            .join(name);
+        LockFile::from_path(&path).unwrap()
+    }
+
+    #[test]
+    fn test_explicit_environment_spec() {
+        let lock_file = lock_file("v4/python-lock.yml");
+        let environment = lock_file.environment(DEFAULT_ENVIRONMENT_NAME).unwrap();
+
+        let spec = environment
+            .to_explicit_environment_spec(Platform::Linux64)
+            .unwrap();
+        assert_eq!(spec.platform, Some(Platform::Linux64));
+        assert_eq!(
+            spec.packages.len(),
+            environment.packages(Platform::Linux64).unwrap().len()
+        );
+        assert!(spec
+            .packages
+            .iter()
+            .all(|entry| entry.package_archive_hash().unwrap().is_some()));
+
+        assert!(environment
+            .to_explicit_environment_spec(Platform::EmscriptenWasm32)
+            .is_none());
+    }
+
+    #[test]
+    fn test_environment_yaml() {
+        let lock_file = lock_file("v4/pypi-matplotlib-lock.yml");
+        let environment = lock_file.environment(DEFAULT_ENVIRONMENT_NAME).unwrap();
+
+        let environment_yaml = environment
+            .to_environment_yaml(Platform::Linux64)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            environment_yaml
+                .channels
+                .iter()
+                .map(|c| c.as_str())
+                .collect::<Vec<_>>(),
+            ["conda-forge"]
+        );
+        assert!(environment_yaml
+            .match_specs()
+            .any(|spec| spec.to_string() == "python ==3.9.10 hc74c709_2_cpython"));
+        assert!(environment_yaml
+            .pip_specs()
+            .unwrap()
+            .iter()
+            .any(|spec| spec == "packaging==21.3"));
+
+        // The output should be a valid `environment.yml` file again.
+        let reparsed = EnvironmentYaml::from_yaml_str(&environment_yaml.to_yaml_string()).unwrap();
+        assert_eq!(
+            reparsed.match_specs().count(),
+            environment_yaml.match_specs().count()
+        );
+
+        assert!(environment
+            .to_environment_yaml(Platform::EmscriptenWasm32)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_environment_yaml_foreign_urls() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock/v4/pypi-matplotlib-lock.yml");
+        let source = std::fs::read_to_string(path).unwrap();
+
+        // Packages that are not hosted on the index are pinned to their url.
+        let lock_file: LockFile = source
+            .replace(
+                "https://files.pythonhosted.org/packages/5c/f9/",
+                "https://example.com/packages/5c/f9/",
+            )
+            .parse()
+            .unwrap();
+        let environment_yaml = lock_file
+            .environment(DEFAULT_ENVIRONMENT_NAME)
+            .unwrap()
+            .to_environment_yaml(Platform::Linux64)
+            .unwrap()
+            .unwrap();
+        let pip_specs = environment_yaml.pip_specs().unwrap();
+        assert!(pip_specs
+            .iter()
+            .any(|spec| spec.starts_with("cycler @ https://example.com/packages/5c/f9/")));
+        assert!(pip_specs.contains(&String::from("packaging==21.3")));
+
+        // Channels that cannot be represented are reported.
+        let lock_file: LockFile = source
+            .replace("url: conda-forge", "url: \"https://[invalid\"")
+            .parse()
+            .unwrap();
+        let err = lock_file
+            .environment(DEFAULT_ENVIRONMENT_NAME)
+            .unwrap()
+            .to_environment_yaml(Platform::Linux64)
+            .unwrap_err();
+        let ExportError::InvalidChannel(channel, _) = err;
+        assert_eq!(channel, "https://[invalid");
+    }
+}
@@ -86,30 +86,47 @@ use std::{
 };
 
 use fxhash::FxHashMap;
+use indexmap::IndexMap;
 use pep508_rs::{ExtraName, Requirement};
-use rattler_conda_types::{MatchSpec, PackageRecord, Platform, RepoDataRecord};
+use rattler_conda_types::{MatchSpec, PackageName, PackageRecord, Platform, RepoDataRecord};
 use url::Url;
 
 mod builder;
 mod channel;
 mod conda;
+mod diff;
+mod export;
 mod file_format_version;
 mod hash;
+mod merge;
 mod parse;
 mod pypi;
 mod pypi_indexes;
+mod satisfiability;
+mod sbom;
 mod url_or_path;
 mod utils;
 
 pub use builder::LockFileBuilder;
 pub use channel::Channel;
 pub use conda::{CondaPackageData, ConversionError};
+pub use diff::{
+    ChannelsDiff, EnvironmentDiff, LockFileDiff, LockedPackageSummary, PackageChange, PackageDiff,
+    PackageKind, PlatformDiff,
+};
+pub use export::ExportError;
 pub use file_format_version::FileFormatVersion;
 pub use hash::PackageHashes;
-pub use parse::ParseCondaLockError;
+pub use merge::{merge_driver, MergeConflict, MergeDriverError, MergeError};
+pub use parse::{
+    LazyLockFile, LockFileDiagnostic, ParseCondaLockError, Severity, SourceLocation,
+    ValidationReport,
+};
 pub use pypi::{PypiPackageData, PypiPackageEnvironmentData, PypiSourceTreeHashable};
 pub use pypi_indexes::{FindLinksUrlOrPath, PypiIndexes};
 pub use rattler_conda_types::Matches;
+pub use satisfiability::{UnsatisfiableError, UnsatisfiedRequirement};
+pub use sbom::{Sbom, SbomPackage};
 pub use url_or_path::UrlOrPath;
 
 /// The name of the default environment in a [`LockFile`]. This is the
@@ -146,7 +163,7 @@ struct LockFileInner {
 /// enum and might contain additional data that is specific to the environment.
 /// For instance different environments might select the same Pypi package but
 /// with different extras.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum EnvironmentPackageData {
     Conda(usize),
     Pypi(usize, usize),
@@ -235,6 +252,62 @@ impl LockFile {
     pub fn version(&self) -> FileFormatVersion {
         self.inner.version
     }
+
+    /// Returns a copy of this lock-file in which the conda packages of
+    /// `environment` for `platform` are replaced by `conda_packages`.
+    ///
+    /// Packages that are present both before and after the replacement keep
+    /// their position, packages that are no longer present are dropped and new
+    /// packages are appended. All other platforms, environments and the pypi
+    /// packages are left untouched. If the environment does not exist yet it
+    /// is created.
+    pub fn with_conda_packages(
+        &self,
+        environment: &str,
+        platform: Platform,
+        conda_packages: impl IntoIterator<Item = CondaPackageData>,
+    ) -> LockFile {
+        let mut replacements: IndexMap<PackageName, CondaPackageData> = conda_packages
+            .into_iter()
+            .map(|package| (package.package_record.name.clone(), package))
+            .collect();
+
+        let mut environments = self.inner.environment_lookup.iter().collect::<Vec<_>>();
+        environments.sort_by_key(|(_, index)| **index);
+
+        let mut builder = LockFileBuilder::new();
+        for (name, index) in environments {
+            let data = &self.inner.environments[*index];
+            builder.set_channels(name.clone(), data.channels.clone());
+            if let Some(indexes) = &data.indexes {
+                builder.set_pypi_indexes(name.clone(), indexes.clone());
+            }
+
+            for (&package_platform, packages) in &data.packages {
+                let is_target = name == environment && package_platform == platform;
+                for &package in packages {
+                    let package = Package::from_env_package(package, self.inner.clone());
+                    match package {
+                        Package::Conda(conda) if is_target => {
+                            let package_name = &conda.package_record().name;
+                            if let Some(replacement) = replacements.shift_remove(package_name) {
+                                builder.add_conda_package(environment, platform, replacement);
+                            }
+                        }
+                        package => {
+                            builder.add_package(name.clone(), package_platform, package);
+                        }
+                    }
+                }
+            }
+        }
+
+        for package in replacements.into_values() {
+            builder.add_conda_package(environment, platform, package);
+        }
+
+        builder.finish()
+    }
 }
 
 /// Information about a specific environment in the lock-file.
@@ -644,12 +717,12 @@ pub struct PypiPackageDataRef<'p> {
 
 #[cfg(test)]
 mod test {
-    use std::path::Path;
+    use std::{path::Path, str::FromStr};
 
     use rattler_conda_types::Platform;
     use rstest::*;
 
-    use super::{LockFile, DEFAULT_ENVIRONMENT_NAME};
+    use super::{LockFile, ParseCondaLockError, DEFAULT_ENVIRONMENT_NAME};
 
     #[rstest]
     #[case("v0/numpy-conda-lock.yml")]
@@ -705,4 +778,82 @@ mod test {
             .map(|p| p.url_or_path().into_owned())
             .collect::<Vec<_>>());
     }
+
+    #[test]
+    fn test_conda_lock_categories() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock")
+            .join("v0/categories-conda-lock.yml");
+        let conda_lock = LockFile::from_path(&path).unwrap();
+
+        let package_names = |env: &str| {
+            conda_lock
+                .environment(env)
+                .unwrap()
+                .packages(Platform::Linux64)
+                .unwrap()
+                .map(|p| p.name().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(conda_lock.environments().len(), 2);
+        assert_eq!(
+            package_names(DEFAULT_ENVIRONMENT_NAME),
+            ["_libgcc_mutex", "ca-certificates"]
+        );
+        assert_eq!(
+            package_names("dev"),
+            ["_libgcc_mutex", "ca-certificates", "tzdata"]
+        );
+    }
+
+    #[test]
+    fn test_conda_lock_categories_list() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock")
+            .join("v0/categories-list-conda-lock.yml");
+        let conda_lock = LockFile::from_path(&path).unwrap();
+
+        let package_names = |env: &str| {
+            conda_lock
+                .environment(env)
+                .unwrap()
+                .packages(Platform::Linux64)
+                .unwrap()
+                .map(|p| p.name().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(conda_lock.environments().len(), 3);
+        assert_eq!(
+            package_names(DEFAULT_ENVIRONMENT_NAME),
+            ["_libgcc_mutex", "ca-certificates"]
+        );
+        // Optional packages without a category end up in `dev`.
+        assert_eq!(
+            package_names("dev"),
+            ["_libgcc_mutex", "ca-certificates", "tzdata", "libzlib"]
+        );
+        assert_eq!(
+            package_names("docs"),
+            ["_libgcc_mutex", "ca-certificates", "tzdata"]
+        );
+    }
+
+    #[test]
+    fn test_conda_lock_default_category() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock")
+            .join("v0/categories-conda-lock.yml");
+        let source = std::fs::read_to_string(path)
+            .unwrap()
+            .replace("category: dev", "category: default");
+
+        // The category would replace the default environment.
+        let err = LockFile::from_str(&source).unwrap_err();
+        assert!(
+            matches!(err, ParseCondaLockError::ReservedCategoryName(ref name) if name == "default"),
+            "{err}"
+        );
+    }
 }
@@ -0,0 +1,404 @@
+//! Three-way merging of lock-files.
+//!
+//! Use [`LockFile::merge`] to combine the changes that two branches made to a common base
+//! lock-file. Changes are merged at the granularity of a single package per environment and
+//! platform, so two branches that touch different packages can always be merged automatically.
+//! [`merge_driver`] wraps this in an interface that can be used as a git merge driver.
+
+use std::{fmt, path::Path};
+
+use indexmap::IndexMap;
+use rattler_conda_types::Platform;
+
+use crate::{
+    Environment, LockFile, LockFileBuilder, LockedPackageSummary, Package, PackageKind,
+    ParseCondaLockError,
+};
+
+/// A change that was made on both sides of a merge and that could not be resolved automatically.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// Both sides changed the channels of an environment.
+    Channels {
+        /// The name of the environment
+        environment: String,
+    },
+
+    /// Both sides changed the pypi indexes of an environment.
+    PypiIndexes {
+        /// The name of the environment
+        environment: String,
+    },
+
+    /// Both sides changed the same package.
+    Package {
+        /// The name of the environment
+        environment: String,
+
+        /// The platform for which the package is locked
+        platform: Platform,
+
+        /// The name of the package
+        name: String,
+
+        /// Whether this is a conda or a pypi package
+        kind: PackageKind,
+
+        /// The package in the base lock-file, `None` if it did not exist.
+        base: Option<LockedPackageSummary>,
+
+        /// The package on our side, `None` if it was removed.
+        ours: Option<LockedPackageSummary>,
+
+        /// The package on their side, `None` if it was removed.
+        theirs: Option<LockedPackageSummary>,
+    },
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn describe(package: &Option<LockedPackageSummary>) -> String {
+            package
+                .as_ref()
+                .map_or_else(|| String::from("<removed>"), |p| p.display_version())
+        }
+
+        match self {
+            MergeConflict::Channels { environment } => {
+                write!(f, "the channels of environment '{environment}' changed on both sides")
+            }
+            MergeConflict::PypiIndexes { environment } => write!(
+                f,
+                "the pypi indexes of environment '{environment}' changed on both sides"
+            ),
+            MergeConflict::Package {
+                environment,
+                platform,
+                name,
+                ours,
+                theirs,
+                ..
+            } => write!(
+                f,
+                "'{name}' in environment '{environment}' for '{platform}' changed on both sides (ours: {}, theirs: {})",
+                describe(ours),
+                describe(theirs)
+            ),
+        }
+    }
+}
+
+/// The error returned by [`LockFile::merge`] if the lock-files could not be merged
+/// automatically.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("failed to merge the lock-files because of {} conflict(s)", .conflicts.len())]
+pub struct MergeError {
+    /// All the conflicts that were encountered.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// An error that can occur when running [`merge_driver`].
+#[derive(Debug, thiserror::Error)]
+pub enum MergeDriverError {
+    /// One of the lock-files could not be read.
+    #[error(transparent)]
+    ParseError(#[from] ParseCondaLockError),
+
+    /// The merged lock-file could not be written.
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    /// The lock-files contain conflicting changes.
+    #[error(transparent)]
+    Conflicts(#[from] MergeError),
+}
+
+/// Identifies a package within a single environment and platform.
+type PackageKey = (PackageKind, String);
+
+impl LockFile {
+    /// Performs a three-way merge of two lock-files that were both derived from `base`.
+    ///
+    /// For every environment and platform each package is merged individually: if only one side
+    /// changed (added, removed or modified) a package that change is taken, if both sides made the
+    /// same change it is taken once. If both sides changed the same package differently a
+    /// [`MergeConflict`] is reported. The channels and pypi indexes of every environment are merged
+    /// in the same way.
+    ///
+    /// The order of `ours` is preserved, additions from `theirs` are appended.
+    pub fn merge(base: &LockFile, ours: &LockFile, theirs: &LockFile) -> Result<Self, MergeError> {
+        let mut names = environment_names(ours);
+        for name in environment_names(theirs) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        let mut builder = LockFileBuilder::new();
+        let mut conflicts = Vec::new();
+        for name in names {
+            let base = base.environment(name);
+            let ours = ours.environment(name);
+            let theirs = theirs.environment(name);
+
+            match three_way(
+                base.as_ref().map(Environment::channels),
+                ours.as_ref().map(Environment::channels),
+                theirs.as_ref().map(Environment::channels),
+                |a, b| a == b,
+            ) {
+                Some(Some(channels)) => {
+                    builder.set_channels(name, channels.iter().cloned());
+                }
+                Some(None) => {}
+                None => conflicts.push(MergeConflict::Channels {
+                    environment: name.to_string(),
+                }),
+            }
+
+            match three_way(
+                base.as_ref().and_then(Environment::pypi_indexes),
+                ours.as_ref().and_then(Environment::pypi_indexes),
+                theirs.as_ref().and_then(Environment::pypi_indexes),
+                |a, b| a == b,
+            ) {
+                Some(Some(indexes)) => {
+                    builder.set_pypi_indexes(name, indexes.clone());
+                }
+                Some(None) => {}
+                None => conflicts.push(MergeConflict::PypiIndexes {
+                    environment: name.to_string(),
+                }),
+            }
+
+            let base = packages_by_platform(base.as_ref());
+            let ours = packages_by_platform(ours.as_ref());
+            let theirs = packages_by_platform(theirs.as_ref());
+
+            let mut platforms = ours.keys().copied().collect::<Vec<_>>();
+            platforms.extend(theirs.keys().filter(|p| !ours.contains_key(*p)));
+
+            for platform in platforms {
+                let base = base.get(&platform);
+                let ours = ours.get(&platform);
+                let theirs = theirs.get(&platform);
+
+                let mut keys = ours
+                    .into_iter()
+                    .flat_map(IndexMap::keys)
+                    .collect::<Vec<_>>();
+                keys.extend(
+                    theirs
+                        .into_iter()
+                        .flat_map(IndexMap::keys)
+                        .filter(|key| !ours.is_some_and(|ours| ours.contains_key(*key))),
+                );
+
+                for key in keys {
+                    let base = base.and_then(|packages| packages.get(key));
+                    let ours = ours.and_then(|packages| packages.get(key));
+                    let theirs = theirs.and_then(|packages| packages.get(key));
+
+                    match three_way(base, ours, theirs, is_same_package) {
+                        Some(Some(package)) => {
+                            builder.add_package(name, platform, package.clone());
+                        }
+                        Some(None) => {}
+                        None => conflicts.push(MergeConflict::Package {
+                            environment: name.to_string(),
+                            platform,
+                            name: key.1.clone(),
+                            kind: key.0,
+                            base: base.cloned().map(Into::into),
+                            ours: ours.cloned().map(Into::into),
+                            theirs: theirs.cloned().map(Into::into),
+                        }),
+                    }
+                }
+            }
+        }
+
+        if conflicts.is_empty() {
+            Ok(builder.finish())
+        } else {
+            Err(MergeError { conflicts })
+        }
+    }
+}
+
+/// Merges the lock-files at the given paths and writes the result to `ours`.
+///
+/// The arguments match the arguments that git passes to a merge driver. To use this function as a
+/// merge driver for lock-files, a binary that calls it with `%O %A %B` can be registered in the
+/// git configuration and assigned to lock-files in `.gitattributes`:
+///
+/// ```text
+/// [merge "conda-lock"]
+///     name = conda lock-file merge driver
+///     driver = rattler merge-lock-file %O %A %B
+/// ```
+///
+/// If the lock-files contain conflicting changes `ours` is left untouched and the conflicts are
+/// returned.
+pub fn merge_driver(base: &Path, ours: &Path, theirs: &Path) -> Result<(), MergeDriverError> {
+    let merged = LockFile::merge(
+        &LockFile::from_path(base)?,
+        &LockFile::from_path(ours)?,
+        &LockFile::from_path(theirs)?,
+    )?;
+    merged.to_path(ours)?;
+    Ok(())
+}
+
+/// Returns the names of the environments in the order in which they are stored.
+fn environment_names(lock_file: &LockFile) -> Vec<&str> {
+    let mut environments = lock_file
+        .inner
+        .environment_lookup
+        .iter()
+        .collect::<Vec<_>>();
+    environments.sort_by_key(|(_, index)| **index);
+    environments
+        .into_iter()
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+/// Returns the packages of an environment per platform indexed by their kind and name.
+fn packages_by_platform(
+    environment: Option<&Environment>,
+) -> IndexMap<Platform, IndexMap<PackageKey, Package>> {
+    environment
+        .into_iter()
+        .flat_map(Environment::packages_by_platform)
+        .map(|(platform, packages)| {
+            let packages = packages
+                .map(|package| {
+                    let kind = match package {
+                        Package::Conda(_) => PackageKind::Conda,
+                        Package::Pypi(_) => PackageKind::Pypi,
+                    };
+                    ((kind, package.name().into_owned()), package)
+                })
+                .collect();
+            (platform, packages)
+        })
+        .collect()
+}
+
+/// Returns true if two packages refer to exactly the same locked data.
+fn is_same_package(a: &Package, b: &Package) -> bool {
+    match (a, b) {
+        (Package::Conda(a), Package::Conda(b)) => a.package_data() == b.package_data(),
+        (Package::Pypi(a), Package::Pypi(b)) => {
+            a.package_data() == b.package_data() && a.extras() == b.extras()
+        }
+        _ => false,
+    }
+}
+
+/// Merges a single value. Returns the merged value (where `None` means the value is absent) or
+/// `None` if both sides changed the value in a different way.
+fn three_way<'a, T: ?Sized>(
+    base: Option<&'a T>,
+    ours: Option<&'a T>,
+    theirs: Option<&'a T>,
+    eq: impl Fn(&T, &T) -> bool,
+) -> Option<Option<&'a T>> {
+    let same = |a: Option<&T>, b: Option<&T>| match (a, b) {
+        (Some(a), Some(b)) => eq(a, b),
+        (None, None) => true,
+        _ => false,
+    };
+
+    if same(ours, theirs) || same(base, theirs) {
+        Some(ours)
+    } else if same(base, ours) {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rattler_conda_types::{PackageName, PackageRecord, Platform, Version};
+    use url::Url;
+
+    use super::MergeConflict;
+    use crate::{CondaPackageData, LockFile, DEFAULT_ENVIRONMENT_NAME};
+
+    fn package(name: &str, version: &str) -> CondaPackageData {
+        let file_name = format!("{name}-{version}-0.conda");
+        CondaPackageData {
+            package_record: PackageRecord {
+                subdir: String::from("linux-64"),
+                ..PackageRecord::new(
+                    PackageName::new_unchecked(name),
+                    version.parse::<Version>().unwrap(),
+                    String::from("0"),
+                )
+            },
+            url: Url::parse(&format!(
+                "https://conda.anaconda.org/conda-forge/linux-64/{file_name}"
+            ))
+            .unwrap(),
+            file_name: None,
+            channel: None,
+        }
+    }
+
+    fn lock_file(packages: &[(&str, &str)]) -> LockFile {
+        let mut builder = LockFile::builder();
+        builder.set_channels(DEFAULT_ENVIRONMENT_NAME, ["conda-forge"]);
+        for (name, version) in packages {
+            builder.add_conda_package(
+                DEFAULT_ENVIRONMENT_NAME,
+                Platform::Linux64,
+                package(name, version),
+            );
+        }
+        builder.finish()
+    }
+
+    fn versions(lock_file: &LockFile) -> Vec<(String, String)> {
+        lock_file
+            .default_environment()
+            .unwrap()
+            .packages(Platform::Linux64)
+            .unwrap()
+            .map(|p| (p.name().into_owned(), p.version().into_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_non_overlapping() {
+        let base = lock_file(&[("a", "1"), ("b", "1"), ("c", "1")]);
+        let ours = lock_file(&[("a", "2"), ("b", "1"), ("c", "1")]);
+        let theirs = lock_file(&[("a", "1"), ("c", "1"), ("d", "1")]);
+
+        let merged = LockFile::merge(&base, &ours, &theirs).unwrap();
+        assert_eq!(
+            versions(&merged),
+            [
+                (String::from("a"), String::from("2")),
+                (String::from("c"), String::from("1")),
+                (String::from("d"), String::from("1")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_conflict() {
+        let base = lock_file(&[("a", "1")]);
+        let ours = lock_file(&[("a", "2")]);
+        let theirs = lock_file(&[("a", "3")]);
+
+        let err = LockFile::merge(&base, &ours, &theirs).unwrap_err();
+        assert_eq!(err.conflicts.len(), 1);
+        assert!(matches!(
+            &err.conflicts[0],
+            MergeConflict::Package { name, .. } if name == "a"
+        ));
+    }
+}
@@ -15,30 +15,30 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 use url::Url;
 
-#[derive(Deserialize)]
-struct DeserializableLockFile<'d> {
+#[derive(Deserialize, PartialEq)]
+pub(super) struct DeserializableLockFile<'d> {
     environments: BTreeMap<String, DeserializableEnvironment>,
     packages: Vec<DeserializablePackageData<'d>>,
 }
 
-#[derive(Deserialize)]
-struct DeserializableEnvironment {
+#[derive(Deserialize, PartialEq)]
+pub(super) struct DeserializableEnvironment {
     channels: Vec<Channel>,
     #[serde(flatten)]
     indexes: Option<PypiIndexes>,
     packages: BTreeMap<Platform, Vec<DeserializablePackageSelector>>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, PartialEq)]
 #[serde(tag = "kind", rename_all = "snake_case")]
-enum DeserializablePackageData<'d> {
+pub(super) enum DeserializablePackageData<'d> {
     Conda(Box<RawCondaPackageData<'d>>),
     Pypi(Box<PypiPackageData>),
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, PartialEq)]
 #[serde(untagged, rename_all = "snake_case")]
-enum DeserializablePackageSelector {
+pub(super) enum DeserializablePackageSelector {
     Conda {
         conda: Url,
     },
@@ -50,7 +50,7 @@ enum DeserializablePackageSelector {
 }
 
 #[derive(Hash, Deserialize, Eq, PartialEq)]
-struct DeserializablePypiPackageEnvironmentData {
+pub(super) struct DeserializablePypiPackageEnvironmentData {
     #[serde(default)]
     extras: BTreeSet<ExtraName>,
 }
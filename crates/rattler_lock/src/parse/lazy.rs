@@ -0,0 +1,396 @@
+//! Lazy parsing of lock files.
+//!
+//! Parsing a [`LockFile`] deserializes every environment and every package in
+//! the file. For lock files with many environments and platforms this is
+//! wasteful when only a single environment is needed. A [`LazyLockFile`] only
+//! indexes where each environment and package is located in the source and
+//! deserializes the data of an environment when it is requested.
+
+use std::{io::Read, ops::Range, path::Path, str::FromStr};
+
+use fxhash::FxHashMap;
+use indexmap::{IndexMap, IndexSet};
+use rattler_conda_types::Platform;
+use serde_yaml::{Mapping, Value};
+
+use super::{deserialize::parse_from_document, ParseCondaLockError};
+use crate::{file_format_version::FileFormatVersion, Environment, LockFile};
+
+/// A lock-file that is only parsed on demand.
+///
+/// Use [`LazyLockFile::environment`] or
+/// [`LazyLockFile::environment_for_platform`] to materialize the packages of a
+/// single environment. Only the parts of the source that are required for the
+/// requested environment are deserialized.
+///
+/// Lock files with a version of [`FileFormatVersion::V3`] or lower, or that
+/// are not formatted the way rattler writes them, are parsed completely when
+/// the [`LazyLockFile`] is created.
+pub struct LazyLockFile {
+    source: String,
+    kind: LazyKind,
+}
+
+enum LazyKind {
+    Indexed(SourceIndex),
+    Parsed(LockFile),
+}
+
+/// The locations of the environments and packages in the source of a lock
+/// file.
+struct SourceIndex {
+    version: FileFormatVersion,
+
+    /// The byte ranges of the environments in the `environments` section.
+    environments: IndexMap<String, Range<usize>>,
+
+    /// The byte ranges of the packages in the `packages` section indexed by
+    /// their url or path.
+    packages: FxHashMap<String, Range<usize>>,
+}
+
+impl FromStr for LazyLockFile {
+    type Err = ParseCondaLockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s.to_string())
+    }
+}
+
+impl LazyLockFile {
+    /// Indexes the given lock-file source.
+    pub fn new(source: String) -> Result<Self, ParseCondaLockError> {
+        let kind = match SourceIndex::new(&source) {
+            Some(index) if index.version > FileFormatVersion::V3 => LazyKind::Indexed(index),
+            _ => LazyKind::Parsed(LockFile::from_str(&source)?),
+        };
+        Ok(Self { source, kind })
+    }
+
+    /// Indexes a lock-file from a reader.
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, ParseCondaLockError> {
+        let mut source = String::new();
+        reader.read_to_string(&mut source)?;
+        Self::new(source)
+    }
+
+    /// Indexes a lock-file from a file.
+    pub fn from_path(path: &Path) -> Result<Self, ParseCondaLockError> {
+        Self::new(std::fs::read_to_string(path)?)
+    }
+
+    /// Returns the version of the lock-file.
+    pub fn version(&self) -> FileFormatVersion {
+        match &self.kind {
+            LazyKind::Indexed(index) => index.version,
+            LazyKind::Parsed(lock_file) => lock_file.version(),
+        }
+    }
+
+    /// Returns the names of all environments in the lock-file.
+    pub fn environment_names(&self) -> Vec<&str> {
+        match &self.kind {
+            LazyKind::Indexed(index) => index.environments.keys().map(String::as_str).collect(),
+            LazyKind::Parsed(lock_file) => lock_file.environments().map(|(name, _)| name).collect(),
+        }
+    }
+
+    /// Parses the environment with the given name including the packages of
+    /// all its platforms.
+    pub fn environment(&self, name: &str) -> Result<Option<Environment>, ParseCondaLockError> {
+        self.materialize(name, None)
+    }
+
+    /// Parses the environment with the given name but only the packages for
+    /// `platform`. The returned environment does not contain any other
+    /// platforms.
+    pub fn environment_for_platform(
+        &self,
+        name: &str,
+        platform: Platform,
+    ) -> Result<Option<Environment>, ParseCondaLockError> {
+        self.materialize(name, Some(platform))
+    }
+
+    /// Parses the complete lock-file.
+    pub fn into_lock_file(self) -> Result<LockFile, ParseCondaLockError> {
+        match self.kind {
+            LazyKind::Indexed(_) => LockFile::from_str(&self.source),
+            LazyKind::Parsed(lock_file) => Ok(lock_file),
+        }
+    }
+
+    fn materialize(
+        &self,
+        name: &str,
+        platform: Option<Platform>,
+    ) -> Result<Option<Environment>, ParseCondaLockError> {
+        let index = match &self.kind {
+            LazyKind::Indexed(index) => index,
+            LazyKind::Parsed(lock_file) => {
+                return Ok(lock_file
+                    .environment(name)
+                    .map(|environment| match platform {
+                        Some(platform) => only_platform(&environment, name, platform),
+                        None => environment,
+                    }))
+            }
+        };
+        let Some(range) = index.environments.get(name) else {
+            return Ok(None);
+        };
+
+        // Parse the environment itself.
+        let mut environments: Mapping = serde_yaml::from_str::<Mapping>(&format!(
+            "environments:\n{}",
+            &self.source[range.clone()]
+        ))?
+        .remove("environments")
+        .and_then(|environments| match environments {
+            Value::Mapping(environments) => Some(environments),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+        // Drop the packages of all other platforms and determine which packages
+        // are referenced.
+        let mut references = IndexSet::new();
+        for environment in environments.values_mut() {
+            let Some(Value::Mapping(platforms)) = environment.get_mut("packages") else {
+                continue;
+            };
+            if let Some(platform) = platform {
+                platforms.retain(|key, _| key.as_str() == Some(platform.as_str()));
+            }
+            for selector in platforms.values().filter_map(Value::as_sequence).flatten() {
+                if let Some(url) = selector
+                    .get("conda")
+                    .or_else(|| selector.get("pypi"))
+                    .and_then(Value::as_str)
+                {
+                    references.insert(url.to_string());
+                }
+            }
+        }
+
+        // Only parse the packages that are referenced. If a reference is not
+        // spelled exactly like the url of a package all packages are parsed,
+        // references to packages that really do not exist are reported when
+        // the document is parsed.
+        let ranges: Vec<&Range<usize>> =
+            if references.iter().all(|r| index.packages.contains_key(r)) {
+                references.iter().map(|r| &index.packages[r]).collect()
+            } else {
+                index.packages.values().collect()
+            };
+        let mut packages = String::new();
+        for range in ranges {
+            packages.push_str(&self.source[range.clone()]);
+            if !packages.ends_with('\n') {
+                packages.push('\n');
+            }
+        }
+        let packages = if packages.is_empty() {
+            Value::Sequence(Vec::new())
+        } else {
+            serde_yaml::from_str::<Mapping>(&format!("packages:\n{packages}"))?
+                .remove("packages")
+                .unwrap_or_default()
+        };
+
+        let mut document = Mapping::new();
+        document.insert(Value::from("environments"), Value::Mapping(environments));
+        document.insert(Value::from("packages"), packages);
+        let lock_file = parse_from_document(Value::Mapping(document), index.version)?;
+        Ok(lock_file.environment(name))
+    }
+}
+
+/// Returns a copy of `environment` that only contains the packages of
+/// `platform`.
+fn only_platform(environment: &Environment, name: &str, platform: Platform) -> Environment {
+    let mut builder = LockFile::builder();
+    builder.set_channels(name, environment.channels().to_vec());
+    if let Some(indexes) = environment.pypi_indexes() {
+        builder.set_pypi_indexes(name, indexes.clone());
+    }
+    for package in environment.packages(platform).into_iter().flatten() {
+        builder.add_package(name, platform, package);
+    }
+    builder
+        .finish()
+        .environment(name)
+        .expect("the environment was just added")
+}
+
+impl SourceIndex {
+    /// Scans the source for the locations of environments and packages.
+    /// Returns `None` if the source is not formatted as expected, in which
+    /// case the file should be parsed completely.
+    fn new(source: &str) -> Option<Self> {
+        #[derive(PartialEq)]
+        enum Section {
+            Environments,
+            Packages,
+            Other,
+        }
+
+        let mut version = None;
+        let mut environments = IndexMap::new();
+        let mut packages = FxHashMap::default();
+
+        let mut section = Section::Other;
+        let mut environment_indent = None;
+        let mut package_indent = None;
+        let mut current_environment: Option<(String, usize)> = None;
+        let mut current_package: Option<(Option<String>, usize)> = None;
+
+        let mut offset = 0;
+        for line in source.split_inclusive('\n') {
+            let start = offset;
+            offset += line.len();
+
+            let content = line.trim_end();
+            let trimmed = content.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let indent = content.len() - trimmed.len();
+
+            if indent == 0 && !trimmed.starts_with('-') {
+                // A new top-level key, finish whatever we are currently
+                // indexing.
+                if let Some((name, env_start)) = current_environment.take() {
+                    environments.insert(name, env_start..start);
+                }
+                if let Some((key, package_start)) = current_package.take() {
+                    packages.insert(key?, package_start..start);
+                }
+
+                let (key, value) = trimmed.split_once(':')?;
+                let value = value.trim();
+                section = match key {
+                    "version" => {
+                        version =
+                            Some(FileFormatVersion::try_from(value.parse::<u64>().ok()?).ok()?);
+                        Section::Other
+                    }
+                    "environments" | "packages" if !value.is_empty() => return None,
+                    "environments" => Section::Environments,
+                    "packages" => Section::Packages,
+                    _ => Section::Other,
+                };
+                continue;
+            }
+
+            match section {
+                Section::Environments => {
+                    if indent == *environment_indent.get_or_insert(indent) {
+                        if let Some((name, env_start)) = current_environment.take() {
+                            environments.insert(name, env_start..start);
+                        }
+                        let (key, _) = trimmed.split_once(':')?;
+                        let name = serde_yaml::from_str::<String>(key).ok()?;
+                        current_environment = Some((name, start));
+                    }
+                }
+                Section::Packages => {
+                    let package_indent = *package_indent.get_or_insert(indent);
+                    if indent == package_indent {
+                        let rest = trimmed.strip_prefix("- ")?;
+                        if let Some((key, package_start)) = current_package.take() {
+                            packages.insert(key?, package_start..start);
+                        }
+                        current_package = Some((url_or_path(rest), start));
+                    } else if indent == package_indent + 2 {
+                        if let Some((key, _)) = &mut current_package {
+                            if key.is_none() {
+                                *key = url_or_path(trimmed);
+                            }
+                        }
+                    }
+                }
+                Section::Other => {}
+            }
+        }
+
+        if let Some((name, env_start)) = current_environment.take() {
+            environments.insert(name, env_start..source.len());
+        }
+        if let Some((key, package_start)) = current_package.take() {
+            packages.insert(key?, package_start..source.len());
+        }
+
+        Some(Self {
+            version: version?,
+            environments,
+            packages,
+        })
+    }
+}
+
+/// Returns the value of a `url` or `path` field of a package.
+fn url_or_path(field: &str) -> Option<String> {
+    let value = field
+        .strip_prefix("url:")
+        .or_else(|| field.strip_prefix("path:"))?;
+    serde_yaml::from_str(value.trim()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{path::Path, str::FromStr};
+
+    use rattler_conda_types::Platform;
+    use rstest::rstest;
+
+    use super::LazyLockFile;
+    use crate::{Environment, LockFile, UrlOrPath};
+
+    fn urls(environment: &Environment, platform: Platform) -> Vec<UrlOrPath> {
+        environment
+            .packages(platform)
+            .unwrap()
+            .map(|p| p.url_or_path().into_owned())
+            .collect()
+    }
+
+    #[rstest]
+    #[case::v5("v5/flat-index-lock.yml")]
+    #[case::v4("v4/pypi-matplotlib-lock.yml")]
+    #[case::v4_path("v4/path-based-lock.yml")]
+    #[case::v3("v3/robostack-turtlesim-conda-lock.yml")]
+    fn test_lazy_matches_eager(#[case] path: &str) {
+        let source = std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../../test-data/conda-lock")
+                .join(path),
+        )
+        .unwrap();
+        let lock_file = LockFile::from_str(&source).unwrap();
+        let lazy = LazyLockFile::from_str(&source).unwrap();
+        assert_eq!(lazy.version(), lock_file.version());
+
+        for (name, environment) in lock_file.environments() {
+            assert!(lazy.environment_names().contains(&name));
+
+            let lazy_environment = lazy.environment(name).unwrap().unwrap();
+            assert_eq!(lazy_environment.channels(), environment.channels());
+            for platform in environment.platforms() {
+                assert_eq!(
+                    urls(&lazy_environment, platform),
+                    urls(&environment, platform)
+                );
+
+                let single = lazy
+                    .environment_for_platform(name, platform)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(single.platforms().collect::<Vec<_>>(), [platform]);
+                assert_eq!(urls(&single, platform), urls(&environment, platform));
+            }
+        }
+
+        assert!(lazy.environment("does-not-exist").unwrap().is_none());
+    }
+}
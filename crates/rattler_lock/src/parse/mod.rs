@@ -1,14 +1,18 @@
 mod deserialize;
+mod lazy;
 mod serialize;
 mod v3;
+mod validate;
 
 use super::{LockFile, UrlOrPath};
 use crate::file_format_version::FileFormatVersion;
+pub use lazy::LazyLockFile;
 use rattler_conda_types::Platform;
 use serde::de::Error;
 use serde_yaml::Value;
 use std::str::FromStr;
 use v3::parse_v3_or_lower;
+pub use validate::{LockFileDiagnostic, Severity, SourceLocation, ValidationReport};
 
 #[allow(missing_docs)]
 #[derive(Debug, thiserror::Error)]
@@ -30,38 +34,65 @@ pub enum ParseCondaLockError {
 
     #[error(transparent)]
     InvalidPypiPackageName(#[from] pep508_rs::InvalidNameError),
+
+    /// A conda-lock category has the name of the default environment.
+    #[error("the conda-lock category '{0}' conflicts with the name of the default environment")]
+    ReservedCategoryName(String),
+
+    /// The lock file could not be parsed. The report contains all problems
+    /// with their location in the source, see [`LockFile::validate`].
+    #[error("the lock file is invalid\n{0}")]
+    InvalidLockFile(Box<ValidationReport>),
 }
 
 impl FromStr for LockFile {
     type Err = ParseCondaLockError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // First parse the document to a `serde_yaml::Value`.
-        let document: Value = serde_yaml::from_str(s).map_err(ParseCondaLockError::ParseError)?;
+        parse_str(s).map_err(|err| match err {
+            // The errors of `serde_yaml` lack the location of the problem
+            // because the document is deserialized from a `Value`. Validate
+            // the source to find out where it went wrong.
+            ParseCondaLockError::ParseError(_) => {
+                let report = LockFile::validate(s);
+                if report.is_valid() {
+                    err
+                } else {
+                    ParseCondaLockError::InvalidLockFile(Box::new(report))
+                }
+            }
+            err => err,
+        })
+    }
+}
 
-        // Read the version number from the document
-        let version: FileFormatVersion = document
-            .get("version")
-            .ok_or_else(|| {
+/// Parses a lock file without trying to find the location of errors.
+fn parse_str(s: &str) -> Result<LockFile, ParseCondaLockError> {
+    // First parse the document to a `serde_yaml::Value`.
+    let document: Value = serde_yaml::from_str(s).map_err(ParseCondaLockError::ParseError)?;
+
+    // Read the version number from the document
+    let version: FileFormatVersion = document
+        .get("version")
+        .ok_or_else(|| {
+            ParseCondaLockError::ParseError(serde_yaml::Error::custom(
+                "missing `version` field in lock file",
+            ))
+        })
+        .and_then(|v| {
+            let v = v.as_u64().ok_or_else(|| {
                 ParseCondaLockError::ParseError(serde_yaml::Error::custom(
-                    "missing `version` field in lock file",
+                    "`version` field in lock file is not an integer",
                 ))
-            })
-            .and_then(|v| {
-                let v = v.as_u64().ok_or_else(|| {
-                    ParseCondaLockError::ParseError(serde_yaml::Error::custom(
-                        "`version` field in lock file is not an integer",
-                    ))
-                })?;
-
-                FileFormatVersion::try_from(v)
             })?;
 
-        if version <= FileFormatVersion::V3 {
-            parse_v3_or_lower(document, version)
-        } else {
-            deserialize::parse_from_document(document, version)
-        }
+            FileFormatVersion::try_from(v)
+        })?;
+
+    if version <= FileFormatVersion::V3 {
+        parse_v3_or_lower(document, version)
+    } else {
+        deserialize::parse_from_document(document, version)
     }
 }
 
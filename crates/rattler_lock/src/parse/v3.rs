@@ -1,4 +1,14 @@
 //! A module that enables parsing of lock files version 3 or lower.
+//!
+//! Version 1 of this format is also the format used by
+//! [`conda-lock`](https://github.com/conda/conda-lock) (`conda-lock.yml`).
+//! Conda-lock groups packages in categories (e.g. `main` or `dev`). Packages in
+//! the `main` category end up in the default environment, every other category
+//! is turned into an environment with the same name that contains the `main`
+//! packages as well as the packages of that category. Older versions of
+//! conda-lock write a single `category` per package, newer versions a list of
+//! `categories`. Packages that are marked as `optional` without a category are
+//! part of the `dev` category.
 
 use super::ParseCondaLockError;
 use crate::file_format_version::FileFormatVersion;
@@ -8,7 +18,7 @@ use crate::{
     DEFAULT_ENVIRONMENT_NAME,
 };
 use fxhash::FxHashMap;
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
 use pep440_rs::VersionSpecifiers;
 use pep508_rs::{ExtraName, Requirement};
 use rattler_conda_types::{
@@ -36,13 +46,51 @@ struct LockMetaV3 {
     pub platforms: Vec<Platform>,
 }
 
+/// The name of the category that conda-lock uses for required packages.
+const MAIN_CATEGORY: &str = "main";
+
+/// The name of the category that conda-lock uses for optional packages.
+const DEV_CATEGORY: &str = "dev";
+
 #[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
-struct LockedPackageV3 {
+pub(super) struct LockedPackageV3 {
     pub platform: Platform,
+    /// The conda-lock category this package belongs to. Packages without a
+    /// category are considered part of the `main` category.
+    #[serde(default)]
+    pub category: Option<String>,
+    /// The conda-lock categories this package belongs to. This is used by
+    /// newer versions of conda-lock instead of `category`.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Whether the package is not part of the `main` category.
+    #[serde(default)]
+    pub optional: Option<bool>,
     #[serde(flatten)]
     pub kind: LockedPackageKindV3,
 }
 
+impl LockedPackageV3 {
+    /// Returns the names of the conda-lock categories this package belongs to.
+    fn categories(&self) -> IndexSet<String> {
+        let mut categories = self.categories.iter().cloned().collect::<IndexSet<_>>();
+        categories.extend(self.category.clone());
+
+        if categories.is_empty() {
+            let category = if self.optional == Some(true) {
+                DEV_CATEGORY
+            } else {
+                MAIN_CATEGORY
+            };
+            categories.insert(category.to_string());
+        } else if self.optional == Some(false) {
+            // Packages that are not optional are always required.
+            categories.insert(MAIN_CATEGORY.to_string());
+        }
+        categories
+    }
+}
+
 #[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
 #[serde(tag = "manager", rename_all = "snake_case")]
 enum LockedPackageKindV3 {
@@ -118,6 +166,19 @@ pub struct CondaLockedPackageV3 {
     pub purls: BTreeSet<PackageUrl>,
 }
 
+/// The deduplicated packages of a category for each platform.
+type PackagesPerPlatform = FxHashMap<Platform, IndexSet<EnvironmentPackageData>>;
+
+/// Converts the packages of a category to the packages of an environment.
+fn to_environment_packages(
+    packages: PackagesPerPlatform,
+) -> FxHashMap<Platform, Vec<EnvironmentPackageData>> {
+    packages
+        .into_iter()
+        .map(|(platform, packages)| (platform, packages.into_iter().collect()))
+        .collect()
+}
+
 /// A function that enables parsing of lock files version 3 or lower.
 pub fn parse_v3_or_lower(
     document: serde_yaml::Value,
@@ -131,9 +192,11 @@ pub fn parse_v3_or_lower(
     let mut conda_packages = IndexSet::with_capacity(lock_file.package.len());
     let mut pypi_packages = IndexSet::with_capacity(lock_file.package.len());
     let mut pypi_runtime_configs = IndexSet::with_capacity(lock_file.package.len());
-    let mut per_platform: FxHashMap<Platform, Vec<EnvironmentPackageData>> = FxHashMap::default();
+    let mut per_category: IndexMap<String, PackagesPerPlatform> = IndexMap::default();
+    per_category.insert(MAIN_CATEGORY.to_string(), FxHashMap::default());
     for package in lock_file.package {
-        let LockedPackageV3 { platform, kind } = package;
+        let categories = package.categories();
+        let LockedPackageV3 { platform, kind, .. } = package;
 
         let pkg: EnvironmentPackageData = match kind {
             LockedPackageKindV3::Conda(value) => {
@@ -201,15 +264,50 @@ pub fn parse_v3_or_lower(
             }
         };
 
-        per_platform.entry(package.platform).or_default().push(pkg);
+        for category in categories {
+            // The main category is the default environment, another category with
+            // that name would replace it.
+            if category == DEFAULT_ENVIRONMENT_NAME {
+                return Err(ParseCondaLockError::ReservedCategoryName(category));
+            }
+            per_category
+                .entry(category)
+                .or_default()
+                .entry(platform)
+                .or_default()
+                .insert(pkg);
+        }
     }
 
-    // Construct the default environment
-    let default_environment = EnvironmentData {
-        channels: lock_file.metadata.channels,
+    // The main category makes up the default environment. Every other category
+    // becomes an environment that extends the main packages.
+    let main_packages = per_category
+        .shift_remove(MAIN_CATEGORY)
+        .expect("the main category is always present");
+    let mut environment_lookup = FxHashMap::default();
+    let mut environments = Vec::with_capacity(per_category.len() + 1);
+    environment_lookup.insert(DEFAULT_ENVIRONMENT_NAME.to_string(), environments.len());
+    environments.push(EnvironmentData {
+        channels: lock_file.metadata.channels.clone(),
         indexes: None,
-        packages: per_platform,
-    };
+        packages: to_environment_packages(main_packages.clone()),
+    });
+    for (category, category_packages) in per_category {
+        let mut packages = main_packages.clone();
+        for (platform, category_packages) in category_packages {
+            packages
+                .entry(platform)
+                .or_default()
+                .extend(category_packages);
+        }
+
+        environment_lookup.insert(category, environments.len());
+        environments.push(EnvironmentData {
+            channels: lock_file.metadata.channels.clone(),
+            indexes: None,
+            packages: to_environment_packages(packages),
+        });
+    }
 
     Ok(LockFile {
         inner: Arc::new(LockFileInner {
@@ -221,10 +319,8 @@ pub fn parse_v3_or_lower(
                 .map(Into::into)
                 .collect(),
 
-            environment_lookup: [(DEFAULT_ENVIRONMENT_NAME.to_string(), 0)]
-                .into_iter()
-                .collect(),
-            environments: vec![default_environment],
+            environment_lookup,
+            environments,
         }),
     })
 }
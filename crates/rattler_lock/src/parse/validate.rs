@@ -0,0 +1,1273 @@
+//! Validation of hand-edited lock files.
+//!
+//! [`LockFile::from_str`] stops at the first problem it encounters and the
+//! errors produced by `serde_yaml` often lack the context needed to find the
+//! offending entry. [`LockFile::validate`] instead walks the whole document and
+//! reports every problem it finds together with the line and column, and the
+//! environment, platform and package it relates to. The same report is
+//! returned by [`LockFile::from_str`] as
+//! [`ParseCondaLockError::InvalidLockFile`] if the document cannot be
+//! deserialized.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use pep508_rs::ExtraName;
+use rattler_conda_types::Platform;
+use serde::{
+    de::{
+        DeserializeOwned, DeserializeSeed, Error as _, IgnoredAny, MapAccess, SeqAccess, Visitor,
+    },
+    forward_to_deserialize_any, Deserializer,
+};
+use serde_yaml::{Mapping, Value};
+use url::Url;
+
+use super::{
+    deserialize::{
+        DeserializableEnvironment, DeserializableLockFile, DeserializablePackageData,
+        DeserializablePackageSelector,
+    },
+    v3::LockedPackageV3,
+};
+use crate::{
+    file_format_version::FileFormatVersion, Channel, LockFile, ParseCondaLockError, PypiIndexes,
+    UrlOrPath,
+};
+
+/// The severity of a [`LockFileDiagnostic`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Severity {
+    /// The lock file is invalid or will be interpreted differently than
+    /// intended.
+    Error,
+
+    /// The lock file can be read but contains data that is ignored.
+    Warning,
+}
+
+/// A position in the source of a lock file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct SourceLocation {
+    /// The one-based line number.
+    pub line: usize,
+
+    /// The one-based column.
+    pub column: usize,
+
+    /// The number of characters that are covered by the location.
+    pub length: usize,
+}
+
+/// A single problem found by [`LockFile::validate`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LockFileDiagnostic {
+    /// The severity of the problem.
+    pub severity: Severity,
+
+    /// A description of the problem.
+    pub message: String,
+
+    /// The location in the source, if it could be determined.
+    pub location: Option<SourceLocation>,
+
+    /// The environment the problem relates to.
+    pub environment: Option<String>,
+
+    /// The platform the problem relates to.
+    pub platform: Option<Platform>,
+
+    /// The name of the package the problem relates to.
+    pub package: Option<String>,
+
+    /// An optional hint on how to fix the problem.
+    pub help: Option<String>,
+}
+
+/// The result of [`LockFile::validate`].
+///
+/// The [`Display`] implementation renders all diagnostics together with the
+/// offending lines of the source.
+#[derive(Debug, Clone)]
+pub struct ValidationReport {
+    /// All problems that were found, in the order they appear in the document.
+    pub diagnostics: Vec<LockFileDiagnostic>,
+
+    source: String,
+}
+
+impl LockFile {
+    /// Validates the source of a lock file and reports all problems it
+    /// contains.
+    ///
+    /// Apart from everything that would prevent [`LockFile::from_str`] from
+    /// succeeding this also reports fields that are unknown to the
+    /// [`FileFormatVersion`] of the document, packages that are referenced by
+    /// an environment but not defined and duplicate entries.
+    pub fn validate(source: &str) -> ValidationReport {
+        let mut diagnostics = Validator::new(source).validate();
+        diagnostics.sort_by_key(|d| d.location.map(|l| (l.line, l.column)));
+        ValidationReport {
+            diagnostics,
+            source: source.to_string(),
+        }
+    }
+}
+
+impl ValidationReport {
+    /// Returns true if no errors were found. Warnings are ignored.
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    /// Returns all diagnostics with [`Severity::Error`].
+    pub fn errors(&self) -> impl Iterator<Item = &LockFileDiagnostic> + '_ {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+    }
+
+    /// Returns all diagnostics with [`Severity::Warning`].
+    pub fn warnings(&self) -> impl Iterator<Item = &LockFileDiagnostic> + '_ {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Warning)
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (idx, diagnostic) in self.diagnostics.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            diagnostic.render(&self.source, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl LockFileDiagnostic {
+    /// Describes the environment, platform and package this diagnostic
+    /// relates to.
+    fn context(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(package) = &self.package {
+            parts.push(format!("package `{package}`"));
+        }
+        if let Some(environment) = &self.environment {
+            parts.push(format!("environment `{environment}`"));
+        }
+        if let Some(platform) = &self.platform {
+            parts.push(format!("platform `{platform}`"));
+        }
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+
+    /// Renders the diagnostic together with the line of `source` it points to.
+    fn render(&self, source: &str, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let marker = match self.severity {
+            Severity::Error => "×",
+            Severity::Warning => "⚠",
+        };
+        writeln!(f, "  {marker} {}", self.message)?;
+
+        let line = self.location.and_then(|location| {
+            let line = source.lines().nth(location.line.checked_sub(1)?)?;
+            Some((location, line))
+        });
+        match line {
+            Some((location, line)) => {
+                let gutter = location.line.to_string().len();
+                let pad = " ".repeat(gutter);
+                let indent = " ".repeat(location.column.saturating_sub(1));
+                let length = location.length.max(1);
+                let middle = length / 2;
+                writeln!(f, "   {pad}╭─[{}:{}]", location.line, location.column)?;
+                writeln!(f, "   {} │ {line}", location.line)?;
+                writeln!(
+                    f,
+                    "   {pad} · {indent}{}┬{}",
+                    "─".repeat(middle),
+                    "─".repeat(length - middle - 1)
+                )?;
+                writeln!(
+                    f,
+                    "   {pad} · {indent}{}╰── {}",
+                    " ".repeat(middle),
+                    self.context().as_deref().unwrap_or("here")
+                )?;
+                writeln!(f, "   {pad}╰────")?;
+            }
+            None => {
+                if let Some(context) = self.context() {
+                    writeln!(f, "    ╰── {context}")?;
+                }
+            }
+        }
+
+        if let Some(help) = &self.help {
+            writeln!(f, "  help: {help}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for LockFileDiagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}:{}: ", location.line, location.column)?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(context) = self.context() {
+            write!(f, " ({context})")?;
+        }
+        Ok(())
+    }
+}
+
+/// A segment of the path from the root of the document to a value.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// The message of the error that is used to stop deserializing once the
+/// value that is looked for is reached.
+const FOUND: &str = "__rattler_lock_value_found__";
+
+/// Returns the location of the value at `path`, or of its closest parent
+/// whose location is known.
+///
+/// `serde_yaml` does not keep the location of values, but it does attach the
+/// location of the current event to errors. The document is deserialized up
+/// to the value at `path` which then raises an error to get its location.
+fn locate(source: &str, path: &[Segment]) -> Option<SourceLocation> {
+    (0..=path.len()).rev().find_map(|len| {
+        let path = &path[..len];
+        let err = Locate { path }
+            .deserialize(serde_yaml::Deserializer::from_str(source))
+            .err()?;
+        if !err.to_string().contains(FOUND) {
+            return None;
+        }
+        let location = err.location()?;
+
+        // Underline a key or the rest of the line.
+        let length = match path.last() {
+            Some(Segment::Key(key)) => key.chars().count(),
+            _ => source
+                .lines()
+                .nth(location.line() - 1)?
+                .chars()
+                .skip(location.column() - 1)
+                .collect::<String>()
+                .trim_end()
+                .chars()
+                .count(),
+        };
+        Some(SourceLocation {
+            line: location.line(),
+            column: location.column(),
+            length,
+        })
+    })
+}
+
+/// Walks a document along `path` and fails with [`FOUND`] once the value it
+/// points to is reached. If the path ends in a key the location of the key
+/// is reported instead of the location of its value.
+struct Locate<'p> {
+    path: &'p [Segment],
+}
+
+impl Locate<'_> {
+    fn found<E: serde::de::Error>(&self) -> Result<(), E> {
+        if self.path.is_empty() {
+            Err(E::custom(FOUND))
+        } else {
+            Err(E::custom("value not found"))
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Locate<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Locate<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_bool<E: serde::de::Error>(self, _: bool) -> Result<(), E> {
+        self.found()
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, _: i64) -> Result<(), E> {
+        self.found()
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, _: u64) -> Result<(), E> {
+        self.found()
+    }
+
+    fn visit_f64<E: serde::de::Error>(self, _: f64) -> Result<(), E> {
+        self.found()
+    }
+
+    fn visit_str<E: serde::de::Error>(self, _: &str) -> Result<(), E> {
+        self.found()
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<(), E> {
+        self.found()
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<(), E> {
+        self.found()
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let Some((Segment::Index(index), rest)) = self.path.split_first() else {
+            return self.found();
+        };
+        for _ in 0..*index {
+            if seq.next_element::<IgnoredAny>()?.is_none() {
+                return Err(A::Error::custom("value not found"));
+            }
+        }
+        seq.next_element_seed(Locate { path: rest })?;
+        Err(A::Error::custom("value not found"))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let Some((Segment::Key(key), rest)) = self.path.split_first() else {
+            return self.found();
+        };
+        while let Some(is_key) = map.next_key_seed(LocateKey {
+            key,
+            is_last: rest.is_empty(),
+        })? {
+            if is_key {
+                map.next_value_seed(Locate { path: rest })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Err(A::Error::custom("value not found"))
+    }
+}
+
+/// Deserializes a mapping key and returns whether it is the key that is
+/// looked for. Fails with [`FOUND`] if it is the last segment of the path.
+struct LocateKey<'k> {
+    key: &'k str,
+    is_last: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for LocateKey<'_> {
+    type Value = bool;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<bool, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for LocateKey<'_> {
+    type Value = bool;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("a mapping key")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<bool, E> {
+        match (value == self.key, self.is_last) {
+            (true, true) => Err(E::custom(FOUND)),
+            (is_key, _) => Ok(is_key),
+        }
+    }
+
+    fn visit_bool<E: serde::de::Error>(self, _: bool) -> Result<bool, E> {
+        Ok(false)
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, _: i64) -> Result<bool, E> {
+        Ok(false)
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, _: u64) -> Result<bool, E> {
+        Ok(false)
+    }
+
+    fn visit_f64<E: serde::de::Error>(self, _: f64) -> Result<bool, E> {
+        Ok(false)
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<bool, E> {
+        Ok(false)
+    }
+}
+
+/// Returns the keys of `mapping` that are ignored when it is deserialized as
+/// `T`.
+///
+/// Instead of keeping a list of the known fields this asks the `Deserialize`
+/// implementation of `T`, which also covers aliases and flattened fields. A
+/// key is ignored if `T` accepts a value for it that no field accepts, and
+/// leaving the key out has no effect on the result.
+fn ignored_keys<T: DeserializeOwned + PartialEq>(mapping: &Mapping) -> Vec<String> {
+    let Ok(expected) = serde_yaml::from_value::<T>(Value::Mapping(mapping.clone())) else {
+        // The errors are reported elsewhere.
+        return Vec::new();
+    };
+
+    // A mapping with a key that is not valid for any of the types in the
+    // lock file. Unknown keys are still accepted when a struct has flattened
+    // fields, which is why leaving out the key is tested as well.
+    let mut probe = Mapping::new();
+    probe.insert(Value::from("\u{0}"), Value::Null);
+
+    mapping
+        .keys()
+        .filter_map(Value::as_str)
+        .filter(|key| {
+            let mut replaced = mapping.clone();
+            replaced.insert(Value::from(*key), Value::Mapping(probe.clone()));
+            if serde_yaml::from_value::<T>(Value::Mapping(replaced)).is_err() {
+                return false;
+            }
+            let mut removed = mapping.clone();
+            removed.remove(*key);
+            serde_yaml::from_value::<T>(Value::Mapping(removed))
+                .is_ok_and(|value| value == expected)
+        })
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Returns the names of the fields of `T` as reported by its `Deserialize`
+/// implementation. Structs with flattened fields do not report any fields.
+fn struct_fields<T: DeserializeOwned>() -> &'static [&'static str] {
+    struct Fields<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for Fields<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(Self::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(Self::Error::custom("not a struct"))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = <T as serde::Deserialize<'_>>::deserialize(Fields(&mut fields));
+    fields
+}
+
+/// The environment, platform and package a value relates to.
+#[derive(Default, Clone)]
+struct Context {
+    environment: Option<String>,
+    platform: Option<Platform>,
+    package: Option<String>,
+}
+
+struct Validator<'s> {
+    source: &'s str,
+    diagnostics: Vec<LockFileDiagnostic>,
+}
+
+impl<'s> Validator<'s> {
+    fn new(source: &'s str) -> Self {
+        Self {
+            source,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        path: &[Segment],
+        message: impl Into<String>,
+        context: &Context,
+        help: Option<String>,
+    ) {
+        self.diagnostics.push(LockFileDiagnostic {
+            severity,
+            message: message.into(),
+            location: locate(self.source, path),
+            environment: context.environment.clone(),
+            platform: context.platform,
+            package: context.package.clone(),
+            help,
+        });
+    }
+
+    fn error(&mut self, path: &[Segment], message: impl Into<String>, context: &Context) {
+        self.push(Severity::Error, path, message, context, None);
+    }
+
+    fn validate(mut self) -> Vec<LockFileDiagnostic> {
+        let document: Value = match serde_yaml::from_str(self.source) {
+            Ok(document) => document,
+            Err(err) => {
+                let location = err.location().map(|location| SourceLocation {
+                    line: location.line(),
+                    column: location.column(),
+                    length: 1,
+                });
+                self.diagnostics.push(LockFileDiagnostic {
+                    severity: Severity::Error,
+                    message: format!("invalid YAML: {err}"),
+                    location,
+                    environment: None,
+                    platform: None,
+                    package: None,
+                    help: None,
+                });
+                return self.diagnostics;
+            }
+        };
+
+        let Some(document) = document.as_mapping() else {
+            self.error(&[], "the lock file must be a mapping", &Context::default());
+            return self.diagnostics;
+        };
+
+        let Some(version) = self.validate_version(document) else {
+            return self.diagnostics;
+        };
+
+        if version <= FileFormatVersion::V3 {
+            self.validate_v3(document);
+        } else {
+            self.validate_v4(document, version);
+        }
+
+        // Catch anything the checks above did not find.
+        if self
+            .diagnostics
+            .iter()
+            .all(|d| d.severity != Severity::Error)
+        {
+            if let Err(err) = super::parse_str(self.source) {
+                self.error(&[], err.to_string(), &Context::default());
+            }
+        }
+
+        self.diagnostics
+    }
+
+    fn validate_version(&mut self, document: &Mapping) -> Option<FileFormatVersion> {
+        let path = [Segment::Key(String::from("version"))];
+        let context = Context::default();
+        let Some(version) = document.get("version") else {
+            self.error(&[], "missing `version` field", &context);
+            return None;
+        };
+        let Some(version) = version.as_u64() else {
+            self.error(&path, "`version` field is not an integer", &context);
+            return None;
+        };
+        match FileFormatVersion::try_from(version) {
+            Ok(version) => Some(version),
+            Err(ParseCondaLockError::IncompatibleVersion {
+                max_supported_version,
+                ..
+            }) => {
+                self.push(
+                    Severity::Error,
+                    &path,
+                    format!("unsupported lock file version {version}"),
+                    &context,
+                    Some(format!(
+                        "only versions up to and including {max_supported_version} are supported"
+                    )),
+                );
+                None
+            }
+            Err(err) => {
+                self.error(&path, err.to_string(), &context);
+                None
+            }
+        }
+    }
+
+    /// Reports all keys of `mapping` that are ignored when it is
+    /// deserialized as `T`, except for the keys in `skip`.
+    fn check_ignored_fields<T: DeserializeOwned + PartialEq>(
+        &mut self,
+        mapping: &Mapping,
+        path: &[Segment],
+        skip: &[&str],
+        context: &Context,
+    ) {
+        for key in ignored_keys::<T>(mapping) {
+            if skip.contains(&key.as_str()) {
+                continue;
+            }
+            let key_path = child(path, Segment::Key(key.clone()));
+            self.push(
+                Severity::Warning,
+                &key_path,
+                format!("unknown field `{key}` is ignored"),
+                context,
+                None,
+            );
+        }
+    }
+
+    fn validate_v4(&mut self, document: &Mapping, version: FileFormatVersion) {
+        let root = Context::default();
+        self.check_ignored_fields::<DeserializableLockFile<'static>>(
+            document,
+            &[],
+            &["version"],
+            &root,
+        );
+
+        // Index all packages so references from environments can be checked.
+        let mut conda_packages = HashMap::new();
+        let mut pypi_packages = HashMap::new();
+        let packages_path = [Segment::Key(String::from("packages"))];
+        match document.get("packages") {
+            None => self.error(&[], "missing `packages` field", &root),
+            Some(Value::Sequence(packages)) => {
+                for (idx, package) in packages.iter().enumerate() {
+                    let path = child(&packages_path, Segment::Index(idx));
+                    self.validate_package(package, &path, &mut conda_packages, &mut pypi_packages);
+                }
+            }
+            Some(_) => self.error(&packages_path, "`packages` must be a list", &root),
+        }
+
+        let environments_path = [Segment::Key(String::from("environments"))];
+        match document.get("environments") {
+            None => self.error(&[], "missing `environments` field", &root),
+            Some(Value::Mapping(environments)) => {
+                for (name, environment) in environments {
+                    let Some(name) = name.as_str() else {
+                        self.error(
+                            &environments_path,
+                            "environment names must be strings",
+                            &root,
+                        );
+                        continue;
+                    };
+                    let path = child(&environments_path, Segment::Key(name.to_string()));
+                    let context = Context {
+                        environment: Some(name.to_string()),
+                        ..Context::default()
+                    };
+                    self.validate_environment(
+                        environment,
+                        &path,
+                        version,
+                        &context,
+                        &conda_packages,
+                        &pypi_packages,
+                    );
+                }
+            }
+            Some(_) => self.error(
+                &environments_path,
+                "`environments` must be a mapping",
+                &root,
+            ),
+        }
+    }
+
+    fn validate_package(
+        &mut self,
+        package: &Value,
+        path: &[Segment],
+        conda_packages: &mut HashMap<Url, Vec<Segment>>,
+        pypi_packages: &mut HashMap<UrlOrPath, Vec<Segment>>,
+    ) {
+        let Some(mapping) = package.as_mapping() else {
+            self.error(path, "a package must be a mapping", &Context::default());
+            return;
+        };
+        let context = Context {
+            package: mapping
+                .get("name")
+                .and_then(Value::as_str)
+                .map(ToString::to_string),
+            ..Context::default()
+        };
+
+        let kind = mapping.get("kind").and_then(Value::as_str);
+        match kind {
+            Some("conda" | "pypi") => {}
+            Some(kind) => {
+                self.push(
+                    Severity::Error,
+                    &child(path, Segment::Key(String::from("kind"))),
+                    format!("unknown package kind `{kind}`"),
+                    &context,
+                    Some(String::from("expected `conda` or `pypi`")),
+                );
+                return;
+            }
+            None => {
+                self.push(
+                    Severity::Error,
+                    path,
+                    "missing `kind` field",
+                    &context,
+                    Some(String::from("expected `kind: conda` or `kind: pypi`")),
+                );
+                return;
+            }
+        }
+        self.check_ignored_fields::<DeserializablePackageData<'static>>(
+            mapping,
+            path,
+            &[],
+            &context,
+        );
+
+        if let Err(err) = serde_yaml::from_value::<DeserializablePackageData<'_>>(package.clone()) {
+            self.error(path, format!("invalid package: {err}"), &context);
+            return;
+        }
+
+        // Detect packages that are defined more than once.
+        let first = if kind == Some("conda") {
+            mapping
+                .get("url")
+                .and_then(|url| serde_yaml::from_value::<Url>(url.clone()).ok())
+                .and_then(|url| insert_first(conda_packages, url, path))
+        } else {
+            mapping
+                .get("url")
+                .or_else(|| mapping.get("path"))
+                .and_then(|url| serde_yaml::from_value::<UrlOrPath>(url.clone()).ok())
+                .and_then(|url| insert_first(pypi_packages, url, path))
+        };
+        if let Some(first) = first {
+            let help = locate(self.source, &first)
+                .map(|location| format!("the package is first defined on line {}", location.line));
+            self.push(Severity::Error, path, "duplicate package", &context, help);
+        }
+    }
+
+    fn validate_environment(
+        &mut self,
+        environment: &Value,
+        path: &[Segment],
+        version: FileFormatVersion,
+        context: &Context,
+        conda_packages: &HashMap<Url, Vec<Segment>>,
+        pypi_packages: &HashMap<UrlOrPath, Vec<Segment>>,
+    ) {
+        let Some(mapping) = environment.as_mapping() else {
+            self.error(path, "an environment must be a mapping", context);
+            return;
+        };
+
+        // The pypi indexes were introduced with version 5.
+        let index_fields = struct_fields::<PypiIndexes>();
+        if version < FileFormatVersion::V5 {
+            for key in index_fields
+                .iter()
+                .filter(|key| mapping.contains_key(**key))
+            {
+                self.push(
+                    Severity::Warning,
+                    &child(path, Segment::Key(key.to_string())),
+                    format!("`{key}` is not part of lock file version {version}"),
+                    context,
+                    Some(format!(
+                        "`{key}` was introduced in lock file version {}",
+                        FileFormatVersion::V5
+                    )),
+                );
+            }
+        }
+        self.check_ignored_fields::<DeserializableEnvironment>(
+            mapping,
+            path,
+            index_fields,
+            context,
+        );
+
+        self.validate_channels(mapping.get("channels"), path, context);
+
+        if version >= FileFormatVersion::V5 {
+            let mut indexes = Mapping::new();
+            for field in index_fields {
+                if let Some(value) = mapping.get(field) {
+                    indexes.insert(Value::from(*field), value.clone());
+                }
+            }
+            if !indexes.is_empty() {
+                if let Err(err) = serde_yaml::from_value::<PypiIndexes>(Value::Mapping(indexes)) {
+                    let path = child(path, Segment::Key(String::from("indexes")));
+                    self.error(&path, format!("invalid pypi indexes: {err}"), context);
+                }
+            }
+        }
+
+        let packages_path = child(path, Segment::Key(String::from("packages")));
+        let platforms = match mapping.get("packages") {
+            None => {
+                self.error(path, "missing `packages` field", context);
+                return;
+            }
+            Some(Value::Mapping(platforms)) => platforms,
+            Some(_) => {
+                self.error(&packages_path, "`packages` must be a mapping", context);
+                return;
+            }
+        };
+
+        for (platform, packages) in platforms {
+            let Some(platform_str) = platform.as_str() else {
+                self.error(&packages_path, "platform names must be strings", context);
+                continue;
+            };
+            let platform_path = child(&packages_path, Segment::Key(platform_str.to_string()));
+            let Ok(platform) = Platform::from_str(platform_str) else {
+                self.error(
+                    &platform_path,
+                    format!("unknown platform `{platform_str}`"),
+                    context,
+                );
+                continue;
+            };
+            let context = Context {
+                platform: Some(platform),
+                ..context.clone()
+            };
+
+            let Some(packages) = packages.as_sequence() else {
+                self.error(
+                    &platform_path,
+                    "the packages of a platform must be a list",
+                    &context,
+                );
+                continue;
+            };
+
+            let mut seen = HashSet::new();
+            for (idx, package) in packages.iter().enumerate() {
+                let path = child(&platform_path, Segment::Index(idx));
+                self.validate_package_reference(
+                    package,
+                    &path,
+                    &context,
+                    &mut seen,
+                    conda_packages,
+                    pypi_packages,
+                );
+            }
+        }
+    }
+
+    fn validate_channels(&mut self, channels: Option<&Value>, path: &[Segment], context: &Context) {
+        let channels_path = child(path, Segment::Key(String::from("channels")));
+        let channels = match channels {
+            None => {
+                self.error(path, "missing `channels` field", context);
+                return;
+            }
+            Some(Value::Sequence(channels)) => channels,
+            Some(_) => {
+                self.error(&channels_path, "`channels` must be a list", context);
+                return;
+            }
+        };
+
+        for (idx, channel) in channels.iter().enumerate() {
+            let path = child(&channels_path, Segment::Index(idx));
+            if let Some(mapping) = channel.as_mapping() {
+                self.check_ignored_fields::<Channel>(mapping, &path, &[], context);
+            }
+            if let Err(err) = serde_yaml::from_value::<Channel>(channel.clone()) {
+                self.error(&path, format!("invalid channel: {err}"), context);
+            }
+        }
+    }
+
+    fn validate_package_reference(
+        &mut self,
+        package: &Value,
+        path: &[Segment],
+        context: &Context,
+        seen: &mut HashSet<UrlOrPath>,
+        conda_packages: &HashMap<Url, Vec<Segment>>,
+        pypi_packages: &HashMap<UrlOrPath, Vec<Segment>>,
+    ) {
+        let Some(mapping) = package.as_mapping() else {
+            self.error(path, "a package reference must be a mapping", context);
+            return;
+        };
+
+        let (exists, url) = if let Some(url) = mapping.get("conda") {
+            self.check_ignored_fields::<DeserializablePackageSelector>(mapping, path, &[], context);
+            let key_path = child(path, Segment::Key(String::from("conda")));
+            match serde_yaml::from_value::<Url>(url.clone()) {
+                Ok(url) => {
+                    let exists = conda_packages.contains_key(&url);
+                    (exists, UrlOrPath::Url(url))
+                }
+                Err(err) => {
+                    self.error(&key_path, format!("invalid url: {err}"), context);
+                    return;
+                }
+            }
+        } else if let Some(url) = mapping.get("pypi") {
+            self.check_ignored_fields::<DeserializablePackageSelector>(mapping, path, &[], context);
+            if let Some(extras) = mapping.get("extras") {
+                if let Err(err) = serde_yaml::from_value::<BTreeSet<ExtraName>>(extras.clone()) {
+                    let extras_path = child(path, Segment::Key(String::from("extras")));
+                    self.error(&extras_path, format!("invalid extras: {err}"), context);
+                }
+            }
+            let key_path = child(path, Segment::Key(String::from("pypi")));
+            match serde_yaml::from_value::<UrlOrPath>(url.clone()) {
+                Ok(url) => (pypi_packages.contains_key(&url), url),
+                Err(err) => {
+                    self.error(&key_path, format!("invalid url or path: {err}"), context);
+                    return;
+                }
+            }
+        } else {
+            self.push(
+                Severity::Error,
+                path,
+                "invalid package reference",
+                context,
+                Some(String::from(
+                    "a package reference must contain either a `conda` or a `pypi` field",
+                )),
+            );
+            return;
+        };
+
+        if !exists {
+            self.push(
+                Severity::Error,
+                path,
+                format!("reference to a package that does not exist: {url}"),
+                context,
+                Some(String::from(
+                    "add the package to the top-level `packages` section or remove the reference",
+                )),
+            );
+        }
+
+        if !seen.insert(url.clone()) {
+            self.push(
+                Severity::Error,
+                path,
+                format!("duplicate package reference: {url}"),
+                context,
+                None,
+            );
+        }
+    }
+
+    fn validate_v3(&mut self, document: &Mapping) {
+        // Version 3 and older lock files are written by conda-lock, which
+        // stores a lot of information that is not read by rattler. Ignored
+        // fields are therefore not reported.
+        let root = Context::default();
+
+        let metadata_path = [Segment::Key(String::from("metadata"))];
+        match document.get("metadata") {
+            None => self.error(&[], "missing `metadata` field", &root),
+            Some(Value::Mapping(metadata)) => {
+                self.validate_channels(metadata.get("channels"), &metadata_path, &root);
+            }
+            Some(_) => self.error(&metadata_path, "`metadata` must be a mapping", &root),
+        }
+
+        let packages_path = [Segment::Key(String::from("package"))];
+        let packages = match document.get("package") {
+            None => {
+                self.error(&[], "missing `package` field", &root);
+                return;
+            }
+            Some(Value::Sequence(packages)) => packages,
+            Some(_) => {
+                self.error(&packages_path, "`package` must be a list", &root);
+                return;
+            }
+        };
+
+        let mut seen = HashMap::new();
+        for (idx, package) in packages.iter().enumerate() {
+            let path = child(&packages_path, Segment::Index(idx));
+            let Some(mapping) = package.as_mapping() else {
+                self.error(&path, "a package must be a mapping", &root);
+                continue;
+            };
+            let context = Context {
+                package: mapping
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .map(ToString::to_string),
+                platform: mapping
+                    .get("platform")
+                    .and_then(Value::as_str)
+                    .and_then(|platform| Platform::from_str(platform).ok()),
+                environment: mapping
+                    .get("category")
+                    .and_then(Value::as_str)
+                    .map(ToString::to_string),
+            };
+            if let Err(err) = serde_yaml::from_value::<LockedPackageV3>(package.clone()) {
+                self.error(&path, format!("invalid package: {err}"), &context);
+                continue;
+            }
+
+            let key = (
+                mapping
+                    .get("url")
+                    .and_then(Value::as_str)
+                    .map(ToString::to_string),
+                context.platform,
+                context.environment.clone(),
+            );
+            if let Some(first) = insert_first(&mut seen, key, &path) {
+                let help = locate(self.source, &first).map(|location| {
+                    format!("the package is first defined on line {}", location.line)
+                });
+                self.push(Severity::Error, &path, "duplicate package", &context, help);
+            }
+        }
+    }
+}
+
+/// Returns a new path that extends `path` with `segment`.
+fn child(path: &[Segment], segment: Segment) -> Vec<Segment> {
+    let mut path = path.to_vec();
+    path.push(segment);
+    path
+}
+
+/// Inserts `key` into `map` unless it is already present in which case the
+/// path of the first occurrence is returned.
+fn insert_first<K: std::hash::Hash + Eq>(
+    map: &mut HashMap<K, Vec<Segment>>,
+    key: K,
+    path: &[Segment],
+) -> Option<Vec<Segment>> {
+    match map.entry(key) {
+        std::collections::hash_map::Entry::Occupied(entry) => Some(entry.get().clone()),
+        std::collections::hash_map::Entry::Vacant(entry) => {
+            entry.insert(path.to_vec());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rattler_conda_types::Platform;
+
+    use super::Severity;
+    use crate::{LockFile, ParseCondaLockError};
+
+    const EDITED_LOCK_FILE: &str = r#"version: 5
+environments:
+  default:
+    channels:
+    - url: https://conda.anaconda.org/conda-forge/
+    packages:
+      linux-64:
+      - conda: https://conda.anaconda.org/conda-forge/linux-64/bzip2-1.0.8-hd590300_5.conda
+      - conda: https://conda.anaconda.org/conda-forge/linux-64/xz-5.2.6-h166bdaf_0.tar.bz2
+packages:
+- kind: conda
+  name: bzip2
+  version: 1.0.8
+  build: hd590300_5
+  subdir: linux-64
+  url: https://conda.anaconda.org/conda-forge/linux-64/bzip2-1.0.8-hd590300_5.conda
+  sha257: 242c0c1cd6fb0b2b8c2dcd44f2fa0e1bf4c2d4e0e2e3e0a2d3b3e7e4c9d3b2a1
+- kind: conda
+  name: bzip2
+  version: 1.0.8
+  build: hd590300_5
+  subdir: linux-64
+  url: https://conda.anaconda.org/conda-forge/linux-64/bzip2-1.0.8-hd590300_5.conda
+"#;
+
+    #[test]
+    fn test_valid_lock_files() {
+        for path in [
+            "v5/flat-index-lock.yml",
+            "v4/pypi-matplotlib-lock.yml",
+            "v3/robostack-turtlesim-conda-lock.yml",
+            "v0/categories-conda-lock.yml",
+            "v0/categories-list-conda-lock.yml",
+        ] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../../test-data/conda-lock")
+                .join(path);
+            let report = LockFile::validate(&std::fs::read_to_string(&path).unwrap());
+            assert!(
+                report.diagnostics.is_empty(),
+                "{}:\n{report}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn test_edited_lock_file() {
+        let report = LockFile::validate(EDITED_LOCK_FILE);
+        assert!(!report.is_valid());
+
+        let diagnostics = report
+            .diagnostics
+            .iter()
+            .map(|d| {
+                (
+                    d.severity,
+                    d.location.map(|l| (l.line, l.column)),
+                    d.message.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diagnostics,
+            [
+                (
+                    Severity::Error,
+                    Some((9, 9)),
+                    "reference to a package that does not exist: https://conda.anaconda.org/conda-forge/linux-64/xz-5.2.6-h166bdaf_0.tar.bz2"
+                ),
+                (
+                    Severity::Warning,
+                    Some((17, 3)),
+                    "unknown field `sha257` is ignored"
+                ),
+                (Severity::Error, Some((18, 3)), "duplicate package"),
+            ]
+        );
+
+        let missing = &report.diagnostics[0];
+        assert_eq!(missing.environment.as_deref(), Some("default"));
+        assert_eq!(missing.platform, Some(Platform::Linux64));
+        assert_eq!(report.diagnostics[1].package.as_deref(), Some("bzip2"));
+
+        let rendered = report.to_string();
+        assert!(rendered.contains("17 │   sha257:"));
+    }
+
+    #[test]
+    fn test_invalid_yaml() {
+        let report = LockFile::validate("version: 5\nenvironments: [\n");
+        let [diagnostic] = report.diagnostics.as_slice() else {
+            panic!("expected a single diagnostic: {report}");
+        };
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert!(diagnostic.location.is_some());
+    }
+
+    #[test]
+    fn test_newer_field() {
+        let source = "version: 4\nenvironments:\n  default:\n    channels: []\n    indexes:\n    - https://pypi.org/simple\n    packages: {}\npackages: []\n";
+        let report = LockFile::validate(source);
+        assert!(report.is_valid());
+        let [warning] = report.diagnostics.as_slice() else {
+            panic!("expected a single diagnostic: {report}");
+        };
+        assert_eq!(warning.location.map(|l| (l.line, l.column)), Some((5, 5)));
+        assert!(warning
+            .help
+            .as_deref()
+            .unwrap()
+            .contains("introduced in lock file version 5"));
+    }
+
+    #[test]
+    fn test_flow_style_and_quoted_keys() {
+        let source = r#"version: 5
+environments: {default: {channels: [{url: 'https://conda.anaconda.org/conda-forge/', "mirror": x}], packages: {}}}
+packages: []
+"#;
+        let report = LockFile::validate(source);
+        let [warning] = report.diagnostics.as_slice() else {
+            panic!("expected a single diagnostic: {report}");
+        };
+        assert_eq!(warning.message, "unknown field `mirror` is ignored");
+        assert_eq!(warning.location.map(|l| (l.line, l.column)), Some((2, 86)));
+    }
+
+    #[test]
+    fn test_unknown_flattened_field() {
+        let source = r#"version: 5
+environments:
+  default:
+    channels: []
+    packages:
+      linux-64:
+      - pypi: https://files.pythonhosted.org/packages/foo-1.0-py3-none-any.whl
+packages:
+- kind: pypi
+  name: foo
+  version: '1.0'
+  url: https://files.pythonhosted.org/packages/foo-1.0-py3-none-any.whl
+  sha256: 242c0c1cd6fb0b2b8c2dcd44f2fa0e1bf4c2d4e0e2e3e0a2d3b3e7e4c9d3b2a1
+  requires_pyhton: '>=3.8'
+"#;
+        let report = LockFile::validate(source);
+        let [warning] = report.diagnostics.as_slice() else {
+            panic!("expected a single diagnostic: {report}");
+        };
+        assert_eq!(
+            warning.message,
+            "unknown field `requires_pyhton` is ignored"
+        );
+        assert_eq!(warning.location.map(|l| (l.line, l.column)), Some((14, 3)));
+    }
+
+    #[test]
+    fn test_parse_error_contains_report() {
+        let source = EDITED_LOCK_FILE.replace("build: hd590300_5", "build_number: abc");
+        let err = source.parse::<LockFile>().unwrap_err();
+        let ParseCondaLockError::InvalidLockFile(report) = err else {
+            panic!("expected a validation report: {err}");
+        };
+        let error = report
+            .errors()
+            .find(|d| d.message.starts_with("invalid package"))
+            .unwrap();
+        assert_eq!(error.location.map(|l| (l.line, l.column)), Some((11, 3)));
+        assert_eq!(error.package.as_deref(), Some("bzip2"));
+    }
+}
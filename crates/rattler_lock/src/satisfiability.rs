@@ -0,0 +1,378 @@
+//! Static verification of a locked environment against a set of requirements.
+//!
+//! Use [`Environment::verify_satisfiability`] to check whether the conda packages locked for a
+//! platform still satisfy the specs, channels and virtual packages of a project without running a
+//! solver. If this check fails the environment needs to be re-locked.
+
+use std::collections::HashMap;
+
+use rattler_conda_types::{
+    GenericVirtualPackage, MatchSpec, Matches, PackageName, PackageRecord, ParseMatchSpecError,
+    ParseStrictness, Platform,
+};
+
+use crate::{Channel, CondaPackage, Environment, Package};
+
+/// A reason why a locked environment does not satisfy its requirements.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum UnsatisfiedRequirement {
+    /// The environment does not contain any packages for the platform.
+    #[error("the platform '{0}' is not locked")]
+    MissingPlatform(Platform),
+
+    /// The channels of the environment differ from the requested channels.
+    #[error("the locked channels [{}] differ from the requested channels [{}]", .locked.join(", "), .requested.join(", "))]
+    ChannelsMismatch {
+        /// The channels stored in the lock-file.
+        locked: Vec<String>,
+        /// The channels that were requested.
+        requested: Vec<String>,
+    },
+
+    /// None of the locked packages satisfies a requested spec.
+    #[error("no locked package satisfies '{0}'")]
+    UnsatisfiedSpec(MatchSpec),
+
+    /// A dependency of a locked package could not be parsed.
+    #[error("the dependency '{dependency}' of '{package}' could not be parsed")]
+    InvalidDependency {
+        /// The name of the package that declares the dependency.
+        package: String,
+        /// The dependency as it is stored in the lock-file.
+        dependency: String,
+        /// The reason why parsing failed.
+        #[source]
+        source: ParseMatchSpecError,
+    },
+
+    /// A dependency of a locked package is not satisfied by any other locked package or virtual
+    /// package.
+    #[error("the dependency '{dependency}' of '{package}' is not satisfied")]
+    UnsatisfiedDependency {
+        /// The name of the package that declares the dependency.
+        package: String,
+        /// The dependency that is not satisfied.
+        dependency: String,
+    },
+
+    /// A locked package violates a constraint of another locked package.
+    #[error("'{conflicting_package}' violates the constraint '{constraint}' of '{package}'")]
+    ViolatedConstraint {
+        /// The name of the package that declares the constraint.
+        package: String,
+        /// The constraint that is violated.
+        constraint: String,
+        /// The locked package that violates the constraint.
+        conflicting_package: String,
+    },
+}
+
+/// The error returned by [`Environment::verify_satisfiability`]. It contains every reason why the
+/// locked environment does not satisfy the requirements.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("the locked environment does not satisfy the requirements: {}", .reasons.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+pub struct UnsatisfiableError {
+    /// All the reasons why the environment is not satisfiable.
+    pub reasons: Vec<UnsatisfiedRequirement>,
+}
+
+impl Environment {
+    /// Verifies whether the conda packages that are locked for `platform` still satisfy the
+    /// given requirements.
+    ///
+    /// The environment is considered satisfiable if:
+    ///
+    /// * the channels of the environment are the same as `channels` (in the same order),
+    /// * every spec in `specs` is satisfied by a locked package (see [`CondaPackage::satisfies`]),
+    /// * the `depends` of every locked package are satisfied by another locked package or by
+    ///   one of the `virtual_packages`,
+    /// * no locked package violates the `constrains` of another locked package.
+    ///
+    /// Pypi packages are not taken into account. If the environment is not satisfiable all the
+    /// reasons are returned.
+    pub fn verify_satisfiability(
+        &self,
+        platform: Platform,
+        specs: &[MatchSpec],
+        virtual_packages: &[GenericVirtualPackage],
+        channels: &[Channel],
+    ) -> Result<(), UnsatisfiableError> {
+        let Some(packages) = self.packages(platform) else {
+            return Err(UnsatisfiableError {
+                reasons: vec![UnsatisfiedRequirement::MissingPlatform(platform)],
+            });
+        };
+        let conda_packages: Vec<CondaPackage> = packages.filter_map(Package::into_conda).collect();
+
+        let mut reasons = Vec::new();
+
+        if self.channels() != channels {
+            reasons.push(UnsatisfiedRequirement::ChannelsMismatch {
+                locked: self.channels().iter().map(|c| c.url.clone()).collect(),
+                requested: channels.iter().map(|c| c.url.clone()).collect(),
+            });
+        }
+
+        for spec in specs {
+            if !conda_packages.iter().any(|package| package.satisfies(spec)) {
+                reasons.push(UnsatisfiedRequirement::UnsatisfiedSpec(spec.clone()));
+            }
+        }
+
+        // Index all the records that can be used to satisfy a dependency by name.
+        let virtual_records: Vec<PackageRecord> = virtual_packages
+            .iter()
+            .map(|vp| {
+                PackageRecord::new(vp.name.clone(), vp.version.clone(), vp.build_string.clone())
+            })
+            .collect();
+        let mut records_by_name: HashMap<&PackageName, Vec<&PackageRecord>> = HashMap::new();
+        for record in conda_packages
+            .iter()
+            .map(CondaPackage::package_record)
+            .chain(virtual_records.iter())
+        {
+            records_by_name
+                .entry(&record.name)
+                .or_default()
+                .push(record);
+        }
+        let candidates = |spec: &MatchSpec| match &spec.name {
+            Some(name) => records_by_name.get(name).cloned().unwrap_or_default(),
+            None => records_by_name.values().flatten().copied().collect(),
+        };
+
+        for package in &conda_packages {
+            let record = package.package_record();
+            let package_name = record.name.as_normalized();
+
+            for dependency in &record.depends {
+                let spec = match MatchSpec::from_str(dependency, ParseStrictness::Lenient) {
+                    Ok(spec) => spec,
+                    Err(source) => {
+                        reasons.push(UnsatisfiedRequirement::InvalidDependency {
+                            package: package_name.to_string(),
+                            dependency: dependency.clone(),
+                            source,
+                        });
+                        continue;
+                    }
+                };
+
+                if !candidates(&spec)
+                    .into_iter()
+                    .any(|candidate| spec.matches(candidate))
+                {
+                    reasons.push(UnsatisfiedRequirement::UnsatisfiedDependency {
+                        package: package_name.to_string(),
+                        dependency: dependency.clone(),
+                    });
+                }
+            }
+
+            for constraint in &record.constrains {
+                let spec = match MatchSpec::from_str(constraint, ParseStrictness::Lenient) {
+                    Ok(spec) => spec,
+                    Err(source) => {
+                        reasons.push(UnsatisfiedRequirement::InvalidDependency {
+                            package: package_name.to_string(),
+                            dependency: constraint.clone(),
+                            source,
+                        });
+                        continue;
+                    }
+                };
+
+                // A constraint only applies if a package with that name is present.
+                if spec.name.is_none() {
+                    continue;
+                }
+                for candidate in candidates(&spec) {
+                    if !spec.matches(candidate) {
+                        reasons.push(UnsatisfiedRequirement::ViolatedConstraint {
+                            package: package_name.to_string(),
+                            constraint: constraint.clone(),
+                            conflicting_package: candidate.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        if reasons.is_empty() {
+            Ok(())
+        } else {
+            Err(UnsatisfiableError { reasons })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rattler_conda_types::{
+        GenericVirtualPackage, MatchSpec, PackageName, PackageRecord, ParseStrictness, Platform,
+        Version,
+    };
+    use url::Url;
+
+    use super::UnsatisfiedRequirement;
+    use crate::{CondaPackageData, LockFile, DEFAULT_ENVIRONMENT_NAME};
+
+    fn python_lock_file() -> LockFile {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock/v4/python-lock.yml");
+        LockFile::from_path(&path).unwrap()
+    }
+
+    fn spec(spec: &str) -> MatchSpec {
+        MatchSpec::from_str(spec, ParseStrictness::Strict).unwrap()
+    }
+
+    /// Returns a lock file with an `app` package that depends on `libfoo >=2` and `__glibc`
+    /// and constrains `libbar <2`.
+    fn app_lock_file(libfoo: &str, libbar: &str) -> LockFile {
+        let package = |name: &str, version: &str, depends: &[&str], constrains: &[&str]| {
+            let file_name = format!("{name}-{version}-0.conda");
+            CondaPackageData {
+                package_record: PackageRecord {
+                    subdir: String::from("linux-64"),
+                    depends: depends.iter().map(ToString::to_string).collect(),
+                    constrains: constrains.iter().map(ToString::to_string).collect(),
+                    ..PackageRecord::new(
+                        PackageName::new_unchecked(name),
+                        version.parse::<Version>().unwrap(),
+                        String::from("0"),
+                    )
+                },
+                url: Url::parse(&format!(
+                    "https://conda.anaconda.org/conda-forge/linux-64/{file_name}"
+                ))
+                .unwrap(),
+                file_name: None,
+                channel: None,
+            }
+        };
+
+        let mut builder = LockFile::builder();
+        builder.set_channels(DEFAULT_ENVIRONMENT_NAME, ["conda-forge"]);
+        for package in [
+            package(
+                "app",
+                "1.0",
+                &["libfoo >=2", "__glibc >=2.17"],
+                &["libbar <2"],
+            ),
+            package("libfoo", libfoo, &[], &[]),
+            package("libbar", libbar, &[], &[]),
+        ] {
+            builder.add_conda_package(DEFAULT_ENVIRONMENT_NAME, Platform::Linux64, package);
+        }
+        builder.finish()
+    }
+
+    fn glibc() -> GenericVirtualPackage {
+        GenericVirtualPackage {
+            name: PackageName::new_unchecked("__glibc"),
+            version: "2.28".parse().unwrap(),
+            build_string: String::from("0"),
+        }
+    }
+
+    #[test]
+    fn test_satisfiable() {
+        let lock_file = python_lock_file();
+        let environment = lock_file.environment(DEFAULT_ENVIRONMENT_NAME).unwrap();
+        let channels = environment.channels().to_vec();
+
+        environment
+            .verify_satisfiability(Platform::Linux64, &[spec("python >=3.9")], &[], &channels)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unsatisfiable() {
+        let lock_file = python_lock_file();
+        let environment = lock_file.environment(DEFAULT_ENVIRONMENT_NAME).unwrap();
+
+        let err = environment
+            .verify_satisfiability(
+                Platform::Linux64,
+                &[spec("python >=4")],
+                &[],
+                &["bioconda".into()],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.reasons.as_slice(),
+            [
+                UnsatisfiedRequirement::ChannelsMismatch { .. },
+                UnsatisfiedRequirement::UnsatisfiedSpec(_)
+            ]
+        ));
+
+        let err = environment
+            .verify_satisfiability(Platform::EmscriptenWasm32, &[], &[], &[])
+            .unwrap_err();
+        assert!(matches!(
+            err.reasons.as_slice(),
+            [UnsatisfiedRequirement::MissingPlatform(
+                Platform::EmscriptenWasm32
+            )]
+        ));
+    }
+
+    #[test]
+    fn test_consistent_dependencies() {
+        let lock_file = app_lock_file("2.1", "1.0");
+        let environment = lock_file.default_environment().unwrap();
+        let channels = environment.channels().to_vec();
+
+        environment
+            .verify_satisfiability(Platform::Linux64, &[], &[glibc()], &channels)
+            .unwrap();
+
+        // Without the virtual package the dependency on `__glibc` is not satisfied.
+        let err = environment
+            .verify_satisfiability(Platform::Linux64, &[], &[], &channels)
+            .unwrap_err();
+        assert!(matches!(
+            err.reasons.as_slice(),
+            [UnsatisfiedRequirement::UnsatisfiedDependency { package, dependency }]
+                if package == "app" && dependency == "__glibc >=2.17"
+        ));
+    }
+
+    #[test]
+    fn test_unsatisfied_dependency() {
+        let lock_file = app_lock_file("1.5", "1.0");
+        let environment = lock_file.default_environment().unwrap();
+        let channels = environment.channels().to_vec();
+
+        let err = environment
+            .verify_satisfiability(Platform::Linux64, &[], &[glibc()], &channels)
+            .unwrap_err();
+        assert!(matches!(
+            err.reasons.as_slice(),
+            [UnsatisfiedRequirement::UnsatisfiedDependency { package, dependency }]
+                if package == "app" && dependency == "libfoo >=2"
+        ));
+    }
+
+    #[test]
+    fn test_violated_constraint() {
+        let lock_file = app_lock_file("2.1", "2.0");
+        let environment = lock_file.default_environment().unwrap();
+        let channels = environment.channels().to_vec();
+
+        let err = environment
+            .verify_satisfiability(Platform::Linux64, &[], &[glibc()], &channels)
+            .unwrap_err();
+        assert!(matches!(
+            err.reasons.as_slice(),
+            [UnsatisfiedRequirement::ViolatedConstraint { package, constraint, .. }]
+                if package == "app" && constraint == "libbar <2"
+        ));
+    }
+}
@@ -0,0 +1,572 @@
+//! Software bill of materials (SBOM) generation.
+//!
+//! An [`Sbom`] can be created from a locked [`Environment`] or from the [`PrefixRecord`]s of an
+//! installed environment. It can be rendered as a [CycloneDX](https://cyclonedx.org) 1.5 or an
+//! [SPDX](https://spdx.dev) 2.3 JSON document.
+//!
+//! The output is deterministic: it only depends on the packages and the explicitly passed
+//! document metadata, so regenerating an SBOM for the same environment yields the same document.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rattler_conda_types::{
+    Channel, MatchSpec, PackageRecord, ParseStrictness, Platform, PrefixRecord, RepoDataRecord,
+};
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::{conda::channel_from_url, Environment, Package, PackageKind, UrlOrPath};
+
+/// A software bill of materials of a single environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sbom {
+    /// The packages in the environment.
+    pub packages: Vec<SbomPackage>,
+}
+
+/// A single package in an [`Sbom`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SbomPackage {
+    /// The name of the package
+    pub name: String,
+
+    /// The version of the package
+    pub version: String,
+
+    /// Whether this is a conda or a pypi package
+    pub kind: PackageKind,
+
+    /// The package url that identifies this package.
+    pub purl: String,
+
+    /// Additional package urls that refer to the same package in other ecosystems. For conda
+    /// packages these are taken from [`PackageRecord::purls`].
+    pub additional_purls: Vec<String>,
+
+    /// The license of the package as stored in the package metadata.
+    pub license: Option<String>,
+
+    /// The location from where the package was downloaded. This is `None` for packages that
+    /// were installed from the local filesystem.
+    pub download_location: Option<String>,
+
+    /// The hex encoded sha256 hash of the package archive.
+    pub sha256: Option<String>,
+
+    /// The hex encoded md5 hash of the package archive.
+    pub md5: Option<String>,
+
+    /// The names of the packages in the same [`Sbom`] this package depends on.
+    pub depends: Vec<String>,
+}
+
+impl Sbom {
+    /// Creates an SBOM from the conda and pypi packages of an environment for the given platform.
+    /// Returns `None` if the platform is not part of the environment.
+    pub fn from_environment(environment: &Environment, platform: Platform) -> Option<Self> {
+        let packages = environment
+            .packages(platform)?
+            .map(|package| match package {
+                Package::Conda(package) => conda_package(
+                    package.package_record(),
+                    package.url(),
+                    package.file_name(),
+                    package.channel(),
+                ),
+                Package::Pypi(package) => {
+                    let data = package.data().package;
+                    SbomPackage {
+                        name: data.name.to_string(),
+                        version: data.version.to_string(),
+                        kind: PackageKind::Pypi,
+                        purl: format!(
+                            "pkg:pypi/{}@{}",
+                            data.name,
+                            purl_encode(&data.version.to_string())
+                        ),
+                        additional_purls: Vec::new(),
+                        license: None,
+                        download_location: match &data.url_or_path {
+                            UrlOrPath::Url(url) => download_location(url),
+                            UrlOrPath::Path(_) => None,
+                        },
+                        sha256: data
+                            .hash
+                            .as_ref()
+                            .and_then(|hash| hash.sha256())
+                            .map(|hash| format!("{hash:x}")),
+                        md5: data
+                            .hash
+                            .as_ref()
+                            .and_then(|hash| hash.md5())
+                            .map(|hash| format!("{hash:x}")),
+                        depends: data
+                            .requires_dist
+                            .iter()
+                            .map(|requirement| requirement.name.to_string())
+                            .collect(),
+                    }
+                }
+            })
+            .collect();
+
+        Some(Self::new(packages))
+    }
+
+    /// Creates an SBOM from the records of an installed environment.
+    pub fn from_prefix_records(records: &[PrefixRecord]) -> Self {
+        let packages = records
+            .iter()
+            .map(|record| {
+                let RepoDataRecord {
+                    package_record,
+                    file_name,
+                    url,
+                    channel,
+                } = &record.repodata_record;
+                let channel = Url::parse(channel).ok().or_else(|| channel_from_url(url));
+                conda_package(package_record, url, Some(file_name), channel)
+            })
+            .collect();
+
+        Self::new(packages)
+    }
+
+    /// Constructs a new instance and removes all dependencies on packages that are not part of
+    /// the SBOM (e.g. virtual packages).
+    fn new(mut packages: Vec<SbomPackage>) -> Self {
+        packages.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+
+        let known = packages
+            .iter()
+            .map(|p| (p.kind, p.name.clone()))
+            .collect::<HashSet<_>>();
+        for package in &mut packages {
+            let kind = package.kind;
+            package
+                .depends
+                .retain(|name| known.contains(&(kind, name.clone())));
+            package.depends.sort();
+            package.depends.dedup();
+        }
+
+        Self { packages }
+    }
+
+    /// Returns the index of every package by kind and name.
+    fn index(&self) -> HashMap<(PackageKind, &str), usize> {
+        self.packages
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| ((p.kind, p.name.as_str()), idx))
+            .collect()
+    }
+
+    /// Renders this SBOM as a CycloneDX 1.5 JSON document.
+    pub fn to_cyclonedx_json(&self) -> String {
+        let index = self.index();
+
+        let components = self
+            .packages
+            .iter()
+            .map(|package| {
+                let mut component = json!({
+                    "type": "library",
+                    "bom-ref": package.purl,
+                    "name": package.name,
+                    "version": package.version,
+                    "purl": package.purl,
+                });
+
+                if let Some(license) = &package.license {
+                    component["licenses"] = json!([{ "license": { "name": license } }]);
+                }
+
+                let mut hashes = Vec::new();
+                if let Some(sha256) = &package.sha256 {
+                    hashes.push(json!({ "alg": "SHA-256", "content": sha256 }));
+                }
+                if let Some(md5) = &package.md5 {
+                    hashes.push(json!({ "alg": "MD5", "content": md5 }));
+                }
+                if !hashes.is_empty() {
+                    component["hashes"] = Value::Array(hashes);
+                }
+
+                if let Some(location) = &package.download_location {
+                    component["externalReferences"] =
+                        json!([{ "type": "distribution", "url": location }]);
+                }
+
+                if !package.additional_purls.is_empty() {
+                    component["properties"] = package
+                        .additional_purls
+                        .iter()
+                        .map(|purl| json!({ "name": "rattler:purl", "value": purl }))
+                        .collect();
+                }
+
+                component
+            })
+            .collect::<Vec<_>>();
+
+        let dependencies = self
+            .packages
+            .iter()
+            .map(|package| {
+                json!({
+                    "ref": package.purl,
+                    "dependsOn": package
+                        .depends
+                        .iter()
+                        .filter_map(|name| index.get(&(package.kind, name.as_str())))
+                        .map(|idx| &self.packages[*idx].purl)
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let document = json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "components": components,
+            "dependencies": dependencies,
+        });
+
+        serde_json::to_string_pretty(&document).expect("failed to serialize SBOM")
+    }
+
+    /// Renders this SBOM as an SPDX 2.3 JSON document.
+    ///
+    /// SPDX requires every document to have a name, a unique namespace and a creation time.
+    pub fn to_spdx_json(&self, name: &str, namespace: &Url, created: DateTime<Utc>) -> String {
+        let index = self.index();
+        let spdx_id = |idx: usize| {
+            let package = &self.packages[idx];
+            let name = package
+                .name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                .collect::<String>();
+            let kind = match package.kind {
+                PackageKind::Conda => "conda",
+                PackageKind::Pypi => "pypi",
+            };
+            format!("SPDXRef-{kind}-{name}-{idx}")
+        };
+
+        let packages = self
+            .packages
+            .iter()
+            .enumerate()
+            .map(|(idx, package)| {
+                let mut checksums = Vec::new();
+                if let Some(sha256) = &package.sha256 {
+                    checksums.push(json!({ "algorithm": "SHA256", "checksumValue": sha256 }));
+                }
+                if let Some(md5) = &package.md5 {
+                    checksums.push(json!({ "algorithm": "MD5", "checksumValue": md5 }));
+                }
+
+                let external_refs = std::iter::once(&package.purl)
+                    .chain(&package.additional_purls)
+                    .map(|purl| {
+                        json!({
+                            "referenceCategory": "PACKAGE-MANAGER",
+                            "referenceType": "purl",
+                            "referenceLocator": purl,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                // Conda packages often declare licenses that are not valid SPDX expressions
+                // (e.g. `BSD` or `Apache 2.0`), keep those as a comment instead.
+                let license = package.license.as_deref();
+                let license_declared = license
+                    .filter(|license| spdx::Expression::parse(license).is_ok())
+                    .unwrap_or("NOASSERTION");
+
+                let mut spdx_package = json!({
+                    "SPDXID": spdx_id(idx),
+                    "name": package.name,
+                    "versionInfo": package.version,
+                    "downloadLocation": package.download_location.as_deref().unwrap_or("NOASSERTION"),
+                    "filesAnalyzed": false,
+                    "licenseConcluded": "NOASSERTION",
+                    "licenseDeclared": license_declared,
+                    "copyrightText": "NOASSERTION",
+                    "checksums": checksums,
+                    "externalRefs": external_refs,
+                });
+                if let Some(license) = license.filter(|_| license_declared == "NOASSERTION") {
+                    spdx_package["licenseComments"] =
+                        json!(format!("The package declares the license '{license}'"));
+                }
+                spdx_package
+            })
+            .collect::<Vec<_>>();
+
+        let mut relationships = (0..self.packages.len())
+            .map(|idx| {
+                json!({
+                    "spdxElementId": "SPDXRef-DOCUMENT",
+                    "relationshipType": "DESCRIBES",
+                    "relatedSpdxElement": spdx_id(idx),
+                })
+            })
+            .collect::<Vec<_>>();
+        for (idx, package) in self.packages.iter().enumerate() {
+            for dependency in &package.depends {
+                if let Some(dependency_idx) = index.get(&(package.kind, dependency.as_str())) {
+                    relationships.push(json!({
+                        "spdxElementId": spdx_id(idx),
+                        "relationshipType": "DEPENDS_ON",
+                        "relatedSpdxElement": spdx_id(*dependency_idx),
+                    }));
+                }
+            }
+        }
+
+        let document = json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": name,
+            "documentNamespace": namespace.as_str(),
+            "creationInfo": {
+                "created": created.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "creators": [concat!("Tool: rattler_lock-", env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": packages,
+            "relationships": relationships,
+        });
+
+        serde_json::to_string_pretty(&document).expect("failed to serialize SBOM")
+    }
+}
+
+/// Creates an [`SbomPackage`] for a conda package that was retrieved from the given channel.
+fn conda_package(
+    record: &PackageRecord,
+    url: &Url,
+    file_name: Option<&str>,
+    channel: Option<Url>,
+) -> SbomPackage {
+    let name = record.name.as_normalized().to_string();
+    let version = record.version.as_str().into_owned();
+
+    // See <https://github.com/package-url/purl-spec/blob/master/PURL-TYPES.rst#conda>
+    let mut qualifiers = vec![
+        format!("build={}", purl_encode(&record.build)),
+        format!("subdir={}", purl_encode(&record.subdir)),
+    ];
+    // Local channels are omitted, their path is meaningless outside of this machine.
+    if let Some(channel) = channel.filter(|channel| channel.scheme() != "file") {
+        let channel = Channel::from_url(channel);
+        qualifiers.insert(1, format!("channel={}", purl_encode(channel.name())));
+    }
+    let archive_type = file_name.and_then(|file_name| {
+        if file_name.ends_with(".conda") {
+            Some("conda")
+        } else if file_name.ends_with(".tar.bz2") {
+            Some("tar.bz2")
+        } else {
+            None
+        }
+    });
+    if let Some(archive_type) = archive_type {
+        qualifiers.push(format!("type={archive_type}"));
+    }
+
+    let depends = record
+        .depends
+        .iter()
+        .filter_map(|dependency| {
+            MatchSpec::from_str(dependency, ParseStrictness::Lenient)
+                .ok()?
+                .name
+                .map(|name| name.as_normalized().to_string())
+        })
+        .collect();
+
+    SbomPackage {
+        purl: format!(
+            "pkg:conda/{}@{}?{}",
+            purl_encode(&name),
+            purl_encode(&version),
+            qualifiers.join("&")
+        ),
+        additional_purls: record
+            .purls
+            .iter()
+            .flatten()
+            .map(ToString::to_string)
+            .collect(),
+        license: record.license.clone(),
+        download_location: download_location(url),
+        sha256: record.sha256.map(|hash| format!("{hash:x}")),
+        md5: record.md5.map(|hash| format!("{hash:x}")),
+        kind: PackageKind::Conda,
+        name,
+        version,
+        depends,
+    }
+}
+
+/// Returns the location a package can be downloaded from, `None` if it is a local file.
+fn download_location(url: &Url) -> Option<String> {
+    (url.scheme() != "file").then(|| url.to_string())
+}
+
+/// Percent-encodes a component of a package url.
+fn purl_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rattler_conda_types::Platform;
+
+    use super::Sbom;
+    use crate::{LockFile, DEFAULT_ENVIRONMENT_NAME};
+
+    fn sbom() -> Sbom {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock/v4/pypi-matplotlib-lock.yml");
+        let lock_file = LockFile::from_path(&path).unwrap();
+        Sbom::from_environment(
+            &lock_file.environment(DEFAULT_ENVIRONMENT_NAME).unwrap(),
+            Platform::Linux64,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_conda_purl() {
+        let sbom = sbom();
+        let python = sbom.packages.iter().find(|p| p.name == "python").unwrap();
+        assert_eq!(
+            python.purl,
+            "pkg:conda/python@3.9.10?build=hc74c709_2_cpython&channel=conda-forge&subdir=linux-64&type=tar.bz2"
+        );
+        assert!(python.depends.iter().any(|d| d == "openssl"));
+    }
+
+    #[test]
+    fn test_channel_and_local_packages() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock/v4/pypi-matplotlib-lock.yml");
+        let source = std::fs::read_to_string(path).unwrap();
+        let sbom = |source: &str| {
+            let lock_file: LockFile = source.parse().unwrap();
+            Sbom::from_environment(
+                &lock_file.environment(DEFAULT_ENVIRONMENT_NAME).unwrap(),
+                Platform::Linux64,
+            )
+            .unwrap()
+        };
+        let python = |sbom: &Sbom| {
+            sbom.packages
+                .iter()
+                .find(|p| p.name == "python")
+                .unwrap()
+                .clone()
+        };
+
+        // The channel qualifier contains the complete channel name, including labels.
+        let labeled = sbom(&source.replace(
+            "https://conda.anaconda.org/conda-forge/linux-64/",
+            "https://conda.anaconda.org/conda-forge/label/rc/linux-64/",
+        ));
+        assert!(python(&labeled)
+            .purl
+            .contains("&channel=conda-forge%2Flabel%2Frc&"));
+
+        // Local paths do not end up in the SBOM.
+        let local = sbom(&source.replace(
+            "https://conda.anaconda.org/conda-forge/linux-64/",
+            "file:///home/user/channel/linux-64/",
+        ));
+        let python = python(&local);
+        assert!(!python.purl.contains("channel="));
+        assert_eq!(python.download_location, None);
+        let document: serde_json::Value = serde_json::from_str(&local.to_spdx_json(
+            "local",
+            &"https://example.com/sbom/local".parse().unwrap(),
+            chrono::DateTime::from_timestamp(0, 0).unwrap(),
+        ))
+        .unwrap();
+        assert!(document["packages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|package| package["name"] == "python")
+            .all(|package| package["downloadLocation"] == "NOASSERTION"));
+    }
+
+    #[test]
+    fn test_cyclonedx() {
+        let document: serde_json::Value =
+            serde_json::from_str(&sbom().to_cyclonedx_json()).unwrap();
+        assert_eq!(document["bomFormat"], "CycloneDX");
+        assert_eq!(
+            document["components"].as_array().unwrap().len(),
+            document["dependencies"].as_array().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_spdx() {
+        let sbom = sbom();
+        let document: serde_json::Value = serde_json::from_str(&sbom.to_spdx_json(
+            "pypi-matplotlib",
+            &"https://example.com/sbom/pypi-matplotlib".parse().unwrap(),
+            chrono::DateTime::from_timestamp(0, 0).unwrap(),
+        ))
+        .unwrap();
+        assert_eq!(document["spdxVersion"], "SPDX-2.3");
+        assert_eq!(document["creationInfo"]["created"], "1970-01-01T00:00:00Z");
+        assert_eq!(
+            document["packages"].as_array().unwrap().len(),
+            sbom.packages.len()
+        );
+    }
+
+    #[test]
+    fn test_spdx_license() {
+        let mut sbom = sbom();
+        sbom.packages.truncate(3);
+        sbom.packages[0].license = Some(String::from("MIT OR Apache-2.0"));
+        sbom.packages[1].license = Some(String::from("Apache 2.0"));
+        sbom.packages[2].license = None;
+
+        let document: serde_json::Value = serde_json::from_str(&sbom.to_spdx_json(
+            "licenses",
+            &"https://example.com/sbom/licenses".parse().unwrap(),
+            chrono::DateTime::from_timestamp(0, 0).unwrap(),
+        ))
+        .unwrap();
+        let packages = document["packages"].as_array().unwrap();
+        assert_eq!(packages[0]["licenseDeclared"], "MIT OR Apache-2.0");
+        assert!(packages[0].get("licenseComments").is_none());
+        assert_eq!(packages[1]["licenseDeclared"], "NOASSERTION");
+        assert_eq!(
+            packages[1]["licenseComments"],
+            "The package declares the license 'Apache 2.0'"
+        );
+        assert_eq!(packages[2]["licenseDeclared"], "NOASSERTION");
+        assert!(packages[2].get("licenseComments").is_none());
+    }
+}
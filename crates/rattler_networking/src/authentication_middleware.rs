@@ -3,6 +3,7 @@ use crate::{Authentication, AuthenticationStorage};
 use async_trait::async_trait;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
+use rattler_redaction::Redact;
 use reqwest::{Request, Response};
 use reqwest_middleware::{Middleware, Next};
 use std::path::{Path, PathBuf};
@@ -41,7 +42,10 @@ impl Middleware for AuthenticationMiddleware {
                 *req.url_mut() = url;
 
                 let req = Self::authenticate_request(req, &auth).await?;
-                next.run(req, extensions).await
+
+                // The URL of the request might now contain a secret, make sure it does not leak
+                // through any error.
+                next.run(req, extensions).await.map_err(Redact::redact)
             }
         }
     }
@@ -130,7 +134,9 @@ pub fn default_auth_store_fallback_directory() -> &'static Path {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::authentication_storage::backends::file::FileStorage;
+    use crate::authentication_storage::{
+        authentication::AuthenticationKind, backends::file::FileStorage, storage::StoredCredentials,
+    };
     use anyhow::anyhow;
     use std::sync::Arc;
     use tempfile::tempdir;
@@ -370,6 +376,42 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_list_credentials() -> anyhow::Result<()> {
+        let tdir = tempdir()?;
+        let mut storage = AuthenticationStorage::new();
+        storage.add_backend(Arc::from(FileStorage::new(
+            tdir.path().to_path_buf().join("auth.json"),
+        )?));
+
+        storage.store(
+            "repo.prefix.dev",
+            &Authentication::BearerToken("secret".to_string()),
+        )?;
+        storage.store(
+            "*.anaconda.org",
+            &Authentication::CondaToken("secret".to_string()),
+        )?;
+
+        let listed = storage.list()?;
+        assert_eq!(
+            listed,
+            vec![
+                StoredCredentials {
+                    host: "*.anaconda.org".to_string(),
+                    kind: AuthenticationKind::CondaToken,
+                },
+                StoredCredentials {
+                    host: "repo.prefix.dev".to_string(),
+                    kind: AuthenticationKind::BearerToken,
+                },
+            ]
+        );
+        assert!(!format!("{listed:?}").contains("secret"));
+
+        Ok(())
+    }
+
     #[test]
     fn test_host_wildcard_expansion() -> anyhow::Result<()> {
         for (host, should_succeed) in [
@@ -405,6 +447,155 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_path_scoped_credentials() -> anyhow::Result<()> {
+        let tdir = tempdir()?;
+        let mut storage = AuthenticationStorage::new();
+        storage.add_backend(Arc::from(FileStorage::new(
+            tdir.path().to_path_buf().join("auth.json"),
+        )?));
+
+        let host_token = Authentication::BearerToken("host".to_string());
+        let channel_a_token = Authentication::BearerToken("channel-a".to_string());
+        let nested_token = Authentication::BearerToken("nested".to_string());
+        let wildcard_token = Authentication::BearerToken("wildcard".to_string());
+        storage.store("repo.example.com", &host_token)?;
+        storage.store("repo.example.com/channel-a", &channel_a_token)?;
+        storage.store("repo.example.com/channel-a/label/dev", &nested_token)?;
+        storage.store("*.example.com/channel-c", &wildcard_token)?;
+
+        for (url, expected) in [
+            (
+                "https://repo.example.com/channel-a/noarch/repodata.json",
+                Some(&channel_a_token),
+            ),
+            (
+                "https://repo.example.com/channel-a/label/dev/noarch/repodata.json",
+                Some(&nested_token),
+            ),
+            (
+                "https://repo.example.com/channel-b/noarch/repodata.json",
+                Some(&host_token),
+            ),
+            // Only whole path segments match
+            (
+                "https://repo.example.com/channel-ab/noarch/repodata.json",
+                Some(&host_token),
+            ),
+            // Credentials for the exact host take precedence over wildcards
+            (
+                "https://repo.example.com/channel-c/noarch/repodata.json",
+                Some(&host_token),
+            ),
+            (
+                "https://other.example.com/channel-c/noarch/repodata.json",
+                Some(&wildcard_token),
+            ),
+            (
+                "https://other.example.com/channel-a/noarch/repodata.json",
+                None,
+            ),
+        ] {
+            let (_, retrieved) = storage.get_by_url(url)?;
+            assert_eq!(retrieved.as_ref(), expected, "{url}");
+        }
+
+        Ok(())
+    }
+
+    /// A backend that records the keys that are looked up and fails the first lookup.
+    #[derive(Debug, Default)]
+    struct FlakyBackend {
+        lookups: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl crate::authentication_storage::StorageBackend for FlakyBackend {
+        fn store(&self, _host: &str, _authentication: &Authentication) -> anyhow::Result<()> {
+            Err(anyhow!("read-only"))
+        }
+
+        fn get(&self, host: &str) -> anyhow::Result<Option<Authentication>> {
+            let mut lookups = self.lookups.lock().unwrap();
+            lookups.push(host.to_string());
+            if lookups.len() == 1 {
+                return Err(anyhow!("keyring is locked"));
+            }
+            Ok((host == "repo.example.com/channel-a")
+                .then(|| Authentication::CondaToken("token".to_string())))
+        }
+
+        fn delete(&self, _host: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_credential_lookups() -> anyhow::Result<()> {
+        let backend = Arc::new(FlakyBackend::default());
+        let mut storage = AuthenticationStorage::new();
+        storage.add_backend(backend.clone());
+
+        // A failing backend does not cause the credentials to be cached as missing.
+        assert_eq!(storage.get("repo.example.com/channel-a")?, None);
+        assert!(storage.get("repo.example.com/channel-a")?.is_some());
+
+        // The file name is never looked up as part of the scope.
+        backend.lookups.lock().unwrap().clear();
+        storage.get_by_url("https://other.example.com/channel-a/noarch/repodata.json")?;
+        storage.get_by_url("https://other.example.com/channel-a/noarch/repodata.json.zst")?;
+        let lookups = backend.lookups.lock().unwrap().clone();
+        assert!(lookups.contains(&"other.example.com/channel-a/noarch".to_string()));
+        assert!(!lookups.iter().any(|key| key.contains("repodata")));
+
+        // Negative lookups are cached.
+        let count = lookups.len();
+        storage.get_by_url("https://other.example.com/channel-a/noarch/other.conda")?;
+        assert_eq!(backend.lookups.lock().unwrap().len(), count);
+
+        // But only for a short while, credentials might be stored in the meantime.
+        storage.negative_cache_ttl = std::time::Duration::ZERO;
+        storage.get_by_url("https://other.example.com/channel-a/noarch/other.conda")?;
+        assert!(backend.lookups.lock().unwrap().len() > count);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_path_scoped_credentials_middleware() -> anyhow::Result<()> {
+        let tdir = tempdir()?;
+        let mut storage = AuthenticationStorage::new();
+        storage.add_backend(Arc::from(FileStorage::new(
+            tdir.path().to_path_buf().join("auth.json"),
+        )?));
+        storage.store(
+            "repo.example.com/channel-a",
+            &Authentication::CondaToken("token-a".to_string()),
+        )?;
+        storage.store(
+            "repo.example.com/channel-b",
+            &Authentication::CondaToken("token-b".to_string()),
+        )?;
+
+        let (client, mut captured_rx) = make_client_harness(&storage);
+        for channel in ["channel-a", "channel-b"] {
+            let request = client
+                .get(format!(
+                    "https://repo.example.com/{channel}/noarch/repodata.json"
+                ))
+                .build()?;
+            let _ = client.execute(request).await;
+
+            let captured_request = captured_rx.recv().await.unwrap();
+            let token = channel.replace("channel", "token");
+            assert_eq!(
+                captured_request.url().path(),
+                format!("/t/{token}/{channel}/noarch/repodata.json")
+            );
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_rattler_auth_file_env_var_handling() -> anyhow::Result<()> {
         let tdir = tempdir()?;
@@ -21,6 +21,38 @@ pub enum Authentication {
     CondaToken(String),
 }
 
+/// The kind of an [`Authentication`] without any of the secrets it contains.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub enum AuthenticationKind {
+    /// See [`Authentication::BearerToken`]
+    BearerToken,
+    /// See [`Authentication::BasicHTTP`]
+    BasicHTTP,
+    /// See [`Authentication::CondaToken`]
+    CondaToken,
+}
+
+impl Authentication {
+    /// Returns the kind of this authentication method
+    pub fn kind(&self) -> AuthenticationKind {
+        match self {
+            Authentication::BearerToken(_) => AuthenticationKind::BearerToken,
+            Authentication::BasicHTTP { .. } => AuthenticationKind::BasicHTTP,
+            Authentication::CondaToken(_) => AuthenticationKind::CondaToken,
+        }
+    }
+}
+
+impl std::fmt::Display for AuthenticationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthenticationKind::BearerToken => write!(f, "bearer token"),
+            AuthenticationKind::BasicHTTP => write!(f, "basic http"),
+            AuthenticationKind::CondaToken => write!(f, "conda token"),
+        }
+    }
+}
+
 /// An error that can occur when parsing an authentication string
 #[derive(Debug)]
 pub enum AuthenticationParseError {
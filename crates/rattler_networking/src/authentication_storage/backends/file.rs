@@ -17,6 +17,11 @@ struct FileStorageCache {
 
 /// A struct that implements storage and access of authentication
 /// information backed by a on-disk JSON file
+///
+/// The file contains a JSON object that maps hosts to credentials. Keys
+/// can be a host (`repo.prefix.dev`), a wildcard host (`*.prefix.dev`) or a
+/// host followed by a path (`repo.prefix.dev/my-channel`) to scope the
+/// credentials to a part of a server.
 #[derive(Clone, Debug)]
 pub struct FileStorage {
     /// The path to the JSON file
@@ -142,6 +147,10 @@ impl StorageBackend for FileStorage {
             Ok(())
         }
     }
+
+    fn list(&self) -> Result<Vec<String>> {
+        Ok(self.read_json()?.into_keys().collect())
+    }
 }
 
 impl Default for FileStorage {
@@ -201,6 +210,15 @@ mod tests {
 
         assert_snapshot!(fs::read_to_string(&path).unwrap());
 
+        assert_eq!(
+            storage.list().unwrap(),
+            vec![
+                "basic".to_string(),
+                "bearer".to_string(),
+                "test".to_string()
+            ]
+        );
+
         storage.delete("test").unwrap();
         assert_eq!(storage.get("test").unwrap(), None);
 
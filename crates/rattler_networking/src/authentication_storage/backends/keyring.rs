@@ -2,7 +2,7 @@
 
 use anyhow::Result;
 use keyring::Entry;
-use std::str::FromStr;
+use std::{collections::BTreeSet, str::FromStr};
 
 use crate::{authentication_storage::StorageBackend, Authentication};
 
@@ -14,6 +14,10 @@ pub struct KeyringAuthenticationStorage {
     pub store_key: String,
 }
 
+/// The operating system's keyring cannot enumerate entries, so the hosts for which credentials
+/// are stored are kept in a separate entry with this name.
+const HOST_INDEX_ENTRY: &str = "__rattler_credential_hosts__";
+
 impl KeyringAuthenticationStorage {
     /// Create a new authentication storage with the given store key
     pub fn from_key(store_key: &str) -> Self {
@@ -21,6 +25,33 @@ impl KeyringAuthenticationStorage {
             store_key: store_key.to_string(),
         }
     }
+
+    /// Reads the set of hosts that credentials were stored for
+    fn read_host_index(&self) -> Result<BTreeSet<String>> {
+        let entry = Entry::new(&self.store_key, HOST_INDEX_ENTRY)?;
+        match entry.get_password() {
+            Ok(index) => Ok(serde_json::from_str(&index)?),
+            Err(keyring::Error::NoEntry) => Ok(BTreeSet::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Adds or removes a host from the index of stored hosts
+    fn update_host_index(&self, host: &str, present: bool) -> Result<()> {
+        let mut hosts = self.read_host_index()?;
+        let changed = if present {
+            hosts.insert(host.to_string())
+        } else {
+            hosts.remove(host)
+        };
+
+        if changed {
+            let entry = Entry::new(&self.store_key, HOST_INDEX_ENTRY)?;
+            entry.set_password(&serde_json::to_string(&hosts)?)?;
+        }
+
+        Ok(())
+    }
 }
 
 /// An error that can occur when accessing the authentication storage
@@ -55,6 +86,10 @@ impl StorageBackend for KeyringAuthenticationStorage {
 
         entry.set_password(&password)?;
 
+        if let Err(e) = self.update_host_index(host, true) {
+            tracing::warn!("failed to update the index of hosts in the keyring: {e}");
+        }
+
         Ok(())
     }
 
@@ -84,6 +119,16 @@ impl StorageBackend for KeyringAuthenticationStorage {
         let entry = Entry::new(&self.store_key, host)?;
         entry.delete_password()?;
 
+        if let Err(e) = self.update_host_index(host, false) {
+            tracing::warn!("failed to update the index of hosts in the keyring: {e}");
+        }
+
         Ok(())
     }
+
+    /// Only credentials that were stored through this backend are listed, entries that were
+    /// added to the keyring by other means cannot be discovered.
+    fn list(&self) -> Result<Vec<String>> {
+        Ok(self.read_host_index()?.into_iter().collect())
+    }
 }
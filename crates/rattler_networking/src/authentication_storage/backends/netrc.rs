@@ -100,6 +100,12 @@ impl StorageBackend for NetRcStorage {
             Err(err) => Err(anyhow::Error::new(err)),
         }
     }
+
+    fn list(&self) -> anyhow::Result<Vec<String>> {
+        let mut hosts = self.machines.keys().cloned().collect::<Vec<_>>();
+        hosts.sort();
+        Ok(hosts)
+    }
 }
 
 #[cfg(test)]
@@ -129,6 +135,7 @@ mod tests {
         );
 
         assert_eq!(storage.get("test_unknown").unwrap(), None);
+        assert_eq!(storage.list().unwrap(), vec!["mainmachine".to_string()]);
     }
 
     #[test]
@@ -0,0 +1,121 @@
+//! Move credentials from one storage backend to another (e.g. from the credentials file or a
+//! `.netrc` file into the keyring of the operating system).
+
+use anyhow::Result;
+
+use super::StorageBackend;
+
+/// Options that control how credentials are migrated with [`migrate_credentials`].
+#[derive(Debug, Clone, Default)]
+pub struct MigrationOptions {
+    /// Remove the credentials from the source backend after they have been stored in the target
+    /// backend. Backends that do not support deleting (like `.netrc` files) are left untouched.
+    pub remove_from_source: bool,
+
+    /// Overwrite credentials that already exist in the target backend.
+    pub overwrite: bool,
+}
+
+/// The outcome of [`migrate_credentials`].
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    /// The hosts whose credentials were stored in the target backend
+    pub migrated: Vec<String>,
+
+    /// The hosts that were skipped because the target backend already contains credentials for
+    /// them
+    pub skipped: Vec<String>,
+
+    /// The hosts that could not be migrated together with the reason
+    pub failed: Vec<(String, anyhow::Error)>,
+}
+
+/// Copies all credentials from the `source` backend to the `target` backend.
+///
+/// Failing to migrate the credentials of a single host does not abort the migration, instead the
+/// host is recorded in [`MigrationReport::failed`]. An error is only returned if the credentials
+/// of the source backend cannot be listed at all.
+pub fn migrate_credentials(
+    source: &dyn StorageBackend,
+    target: &dyn StorageBackend,
+    options: &MigrationOptions,
+) -> Result<MigrationReport> {
+    let mut report = MigrationReport::default();
+
+    for host in source.list()? {
+        let auth = match source.get(&host) {
+            Ok(Some(auth)) => auth,
+            Ok(None) => continue,
+            Err(e) => {
+                report.failed.push((host, e));
+                continue;
+            }
+        };
+
+        if !options.overwrite && matches!(target.get(&host), Ok(Some(_))) {
+            report.skipped.push(host);
+            continue;
+        }
+
+        if let Err(e) = target.store(&host, &auth) {
+            report.failed.push((host, e));
+            continue;
+        }
+
+        if options.remove_from_source {
+            if let Err(e) = source.delete(&host) {
+                tracing::warn!("failed to remove migrated credentials for {host}: {e}");
+            }
+        }
+
+        report.migrated.push(host);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{authentication_storage::backends::file::FileStorage, Authentication};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_migrate_credentials() {
+        let dir = tempdir().unwrap();
+        let source = FileStorage::new(dir.path().join("source.json")).unwrap();
+        let target = FileStorage::new(dir.path().join("target.json")).unwrap();
+
+        let token = Authentication::BearerToken("token".to_string());
+        let conda_token = Authentication::CondaToken("conda-token".to_string());
+        source.store("repo.prefix.dev", &token).unwrap();
+        source.store("conda.anaconda.org", &conda_token).unwrap();
+        target
+            .store(
+                "conda.anaconda.org",
+                &Authentication::CondaToken("existing".to_string()),
+            )
+            .unwrap();
+
+        let report = migrate_credentials(
+            &source,
+            &target,
+            &MigrationOptions {
+                remove_from_source: true,
+                overwrite: false,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(report.migrated, vec!["repo.prefix.dev".to_string()]);
+        assert_eq!(report.skipped, vec!["conda.anaconda.org".to_string()]);
+        assert!(report.failed.is_empty());
+
+        assert_eq!(target.get("repo.prefix.dev").unwrap(), Some(token));
+        assert_eq!(source.list().unwrap(), vec!["conda.anaconda.org"]);
+        assert_eq!(
+            target.get("conda.anaconda.org").unwrap(),
+            Some(Authentication::CondaToken("existing".to_string()))
+        );
+    }
+}
@@ -4,6 +4,7 @@ use anyhow::Result;
 
 pub mod authentication;
 pub mod backends;
+pub mod migration;
 pub mod storage;
 
 /// A trait that defines the interface for authentication storage backends
@@ -16,4 +17,9 @@ pub trait StorageBackend: std::fmt::Debug {
 
     /// Delete the authentication information for the given host
     fn delete(&self, host: &str) -> Result<()>;
+
+    /// Returns the hosts for which this backend stores authentication information
+    fn list(&self) -> Result<Vec<String>> {
+        anyhow::bail!("{self:?} does not support listing credentials")
+    }
 }
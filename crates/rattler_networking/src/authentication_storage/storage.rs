@@ -5,15 +5,40 @@ use reqwest::IntoUrl;
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 use url::Url;
 
 use super::{
-    authentication::Authentication,
+    authentication::{Authentication, AuthenticationKind},
     backends::{file::FileStorage, keyring::KeyringAuthenticationStorage, netrc::NetRcStorage},
     StorageBackend,
 };
 
+/// Describes credentials that are stored for a host without exposing any secrets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredCredentials {
+    /// The host the credentials are stored for (e.g. `repo.prefix.dev` or `*.prefix.dev`)
+    pub host: String,
+
+    /// The kind of credentials that are stored
+    pub kind: AuthenticationKind,
+}
+
+/// How long the absence of credentials for a key is remembered. Credentials
+/// can be stored by another process at any time, so don't remember misses
+/// forever.
+const NEGATIVE_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// A cached lookup of the credentials for a key.
+#[derive(Debug, Clone)]
+enum CacheEntry {
+    /// Credentials were found
+    Found(Authentication),
+    /// No credentials were found at the given time
+    Missing(Instant),
+}
+
 #[derive(Debug, Clone)]
 /// This struct implements storage and access of authentication
 /// information backed by multiple storage backends
@@ -22,7 +47,9 @@ use super::{
 /// order they are added to the storage
 pub struct AuthenticationStorage {
     backends: Vec<Arc<dyn StorageBackend + Send + Sync>>,
-    cache: Arc<Mutex<HashMap<String, Option<Authentication>>>>,
+    cache: Arc<Mutex<HashMap<String, CacheEntry>>>,
+    /// How long a miss is cached before the backends are queried again
+    pub(crate) negative_cache_ttl: Duration,
 }
 
 impl Default for AuthenticationStorage {
@@ -48,6 +75,7 @@ impl AuthenticationStorage {
         Self {
             backends: vec![],
             cache: Arc::new(Mutex::new(HashMap::new())),
+            negative_cache_ttl: NEGATIVE_CACHE_TTL,
         }
     }
 
@@ -95,7 +123,7 @@ impl AuthenticationStorage {
     pub fn store(&self, host: &str, authentication: &Authentication) -> Result<()> {
         {
             let mut cache = self.cache.lock().unwrap();
-            cache.insert(host.to_string(), Some(authentication.clone()));
+            cache.insert(host.to_string(), CacheEntry::Found(authentication.clone()));
         }
 
         for backend in &self.backends {
@@ -113,16 +141,21 @@ impl AuthenticationStorage {
     pub fn get(&self, host: &str) -> Result<Option<Authentication>> {
         {
             let cache = self.cache.lock().unwrap();
-            if let Some(auth) = cache.get(host) {
-                return Ok(auth.clone());
+            match cache.get(host) {
+                Some(CacheEntry::Found(auth)) => return Ok(Some(auth.clone())),
+                Some(CacheEntry::Missing(at)) if at.elapsed() < self.negative_cache_ttl => {
+                    return Ok(None)
+                }
+                _ => {}
             }
         }
 
+        let mut backend_failed = false;
         for backend in &self.backends {
             match backend.get(host) {
                 Ok(Some(auth)) => {
                     let mut cache = self.cache.lock().unwrap();
-                    cache.insert(host.to_string(), Some(auth.clone()));
+                    cache.insert(host.to_string(), CacheEntry::Found(auth.clone()));
                     return Ok(Some(auth));
                 }
                 Ok(None) => {
@@ -130,10 +163,19 @@ impl AuthenticationStorage {
                 }
                 Err(e) => {
                     tracing::warn!("Error retrieving credentials from backend: {}", e);
+                    backend_failed = true;
                 }
             }
         }
 
+        // Briefly remember that there are no credentials for this host. `get_by_url` queries a
+        // lot of keys for every request, this avoids hitting the backends (e.g. the keyring) every
+        // time. If a backend failed the credentials might still exist, so try again next time.
+        if !backend_failed {
+            let mut cache = self.cache.lock().unwrap();
+            cache.insert(host.to_string(), CacheEntry::Missing(Instant::now()));
+        }
+
         Ok(None)
     }
 
@@ -141,9 +183,20 @@ impl AuthenticationStorage {
     /// (including the authentication information for the wildcard
     /// host if no credentials are found for the given host)
     ///
+    /// Credentials can be scoped to a path by storing them for a key of
+    /// the form `{host}/{path}`. The credentials with the longest path
+    /// that is a prefix of the path of the URL are used. E.g. if
+    /// credentials are stored for `repo.example.com/channel-a` and
+    /// `repo.example.com`, the URL
+    /// `https://repo.example.com/channel-a/noarch/repodata.json` uses the
+    /// former and `https://repo.example.com/channel-b/noarch/repodata.json`
+    /// the latter. Paths only match on whole segments and the last segment
+    /// of the URL (the file name) is never considered part of the scope.
+    ///
     /// E.g. if credentials are stored for `*.prefix.dev` and the
     /// given URL is `https://repo.prefix.dev`, the credentials
-    /// for `*.prefix.dev` will be returned.
+    /// for `*.prefix.dev` will be returned. Credentials for the exact host
+    /// always take precedence over wildcard hosts.
     pub fn get_by_url<U: IntoUrl>(
         &self,
         url: U,
@@ -153,44 +206,86 @@ impl AuthenticationStorage {
             return Ok((url, None));
         };
 
-        match self.get(host) {
-            Ok(None) => {}
-            Err(_) => return Ok((url, None)),
-            Ok(Some(credentials)) => return Ok((url, Some(credentials))),
-        };
+        // Credentials are scoped to directories, skip the file name (if any) to avoid looking up
+        // (and caching) a key for every file that is requested.
+        let mut segments = url
+            .path_segments()
+            .map(|segments| segments.collect::<Vec<_>>())
+            .unwrap_or_default();
+        segments.pop();
+        segments.retain(|segment| !segment.is_empty());
 
-        // Check for credentials under e.g. `*.prefix.dev`
-        let Some(mut domain) = url.domain() else {
-            return Ok((url, None));
-        };
+        // Check for credentials under e.g. `*.prefix.dev` after the host itself
+        let wildcard_hosts = url.domain().into_iter().flat_map(|domain| {
+            std::iter::successors(Some(domain), |domain| {
+                domain.split_once('.').map(|(_, rest)| rest)
+            })
+            .map(|domain| format!("*.{domain}"))
+        });
 
-        loop {
-            let wildcard_host = format!("*.{domain}");
+        for host in std::iter::once(host.to_string()).chain(wildcard_hosts) {
+            // Try the longest path prefix first
+            for len in (0..=segments.len()).rev() {
+                let key = if len == 0 {
+                    host.clone()
+                } else {
+                    format!("{host}/{}", segments[..len].join("/"))
+                };
 
-            let Ok(credentials) = self.get(&wildcard_host) else {
-                return Ok((url, None));
-            };
-
-            if let Some(credentials) = credentials {
-                return Ok((url, Some(credentials)));
+                match self.get(&key) {
+                    Ok(None) => {}
+                    Err(_) => return Ok((url, None)),
+                    Ok(Some(credentials)) => return Ok((url, Some(credentials))),
+                }
             }
+        }
 
-            let possible_rest = domain.split_once('.').map(|(_, rest)| rest);
+        Ok((url, None))
+    }
 
-            match possible_rest {
-                Some(rest) => {
-                    domain = rest;
+    /// Returns the hosts and kinds of all the credentials that are stored in any of the backends.
+    ///
+    /// If credentials for the same host are stored in multiple backends only the ones that would
+    /// be returned by [`Self::get`] are listed. Backends that do not support listing are
+    /// skipped.
+    pub fn list(&self) -> Result<Vec<StoredCredentials>> {
+        let mut result: Vec<StoredCredentials> = Vec::new();
+        for backend in &self.backends {
+            let hosts = match backend.list() {
+                Ok(hosts) => hosts,
+                Err(e) => {
+                    tracing::debug!("Error listing credentials from backend: {}", e);
+                    continue;
+                }
+            };
+
+            for host in hosts {
+                if result.iter().any(|stored| stored.host == host) {
+                    continue;
+                }
+
+                match backend.get(&host) {
+                    Ok(Some(auth)) => result.push(StoredCredentials {
+                        host,
+                        kind: auth.kind(),
+                    }),
+                    Ok(None) => {}
+                    Err(e) => {
+                        tracing::warn!("Error retrieving credentials from backend: {}", e);
+                    }
                 }
-                _ => return Ok((url, None)), // No more subdomains to check
             }
         }
+
+        result.sort_by(|a, b| a.host.cmp(&b.host));
+        Ok(result)
     }
 
     /// Delete the authentication information for the given host
     pub fn delete(&self, host: &str) -> Result<()> {
         {
             let mut cache = self.cache.lock().unwrap();
-            cache.insert(host.to_string(), None);
+            cache.insert(host.to_string(), CacheEntry::Missing(Instant::now()));
         }
 
         let mut all_failed = true;
@@ -1,6 +1,7 @@
 //! Middleware to handle `gcs://` URLs to pull artifacts from an GCS
 use async_trait::async_trait;
 use google_cloud_auth::project::{create_token_source, Config};
+use rattler_redaction::Redact;
 use reqwest::{Request, Response};
 use reqwest_middleware::{Middleware, Next, Result as MiddlewareResult};
 use url::Url;
@@ -27,7 +28,9 @@ impl Middleware for GCSMiddleware {
             );
             url = Url::parse(&new_url).expect("Failed to parse URL");
             *req.url_mut() = url;
-            req = authenticate_with_google_cloud(req).await?;
+            req = authenticate_with_google_cloud(req)
+                .await
+                .map_err(Redact::redact)?;
         }
         next.run(req, extensions).await
     }
@@ -4,7 +4,9 @@
 pub use authentication_middleware::AuthenticationMiddleware;
 pub use authentication_storage::{authentication::Authentication, storage::AuthenticationStorage};
 pub use mirror_middleware::MirrorMiddleware;
-pub use oci_middleware::OciMiddleware;
+pub use oci_middleware::{OciMiddleware, OciPushClient, OciPushError};
+#[cfg(not(target_arch = "wasm32"))]
+pub use rate_limit_middleware::RateLimitMiddleware;
 
 #[cfg(feature = "google-cloud-auth")]
 pub mod gcs_middleware;
@@ -16,4 +18,6 @@ pub mod authentication_storage;
 
 pub mod mirror_middleware;
 pub mod oci_middleware;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod rate_limit_middleware;
 pub mod retry_policies;
@@ -1,11 +1,15 @@
-//! Middleware to handle `oci://` URLs to pull artifacts from an OCI registry
+//! Middleware to handle `oci://` URLs to pull artifacts from an OCI registry and a client to push
+//! artifacts to one.
 use std::collections::HashMap;
 
-use http::header::{ACCEPT, AUTHORIZATION};
+use bytes::Bytes;
+use http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, LOCATION, WWW_AUTHENTICATE};
 use http::Extensions;
+use rattler_redaction::Redact;
 use reqwest::{Request, Response};
 use reqwest_middleware::{Middleware, Next};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use url::{ParseError, Url};
 
 use crate::mirror_middleware::create_404_response;
@@ -68,6 +72,7 @@ async fn get_token(url: &OCIUrl, action: OciAction) -> Result<String, OciMiddlew
 #[derive(Debug)]
 struct OCIUrl {
     url: Url,
+    scheme: &'static str,
     host: String,
     path: String,
     tag: String,
@@ -94,15 +99,16 @@ fn version_build_tag(tag: &str) -> String {
 impl OCIUrl {
     pub fn manifest_url(&self) -> Result<Url, ParseError> {
         format!(
-            "https://{}/v2/{}/manifests/{}",
-            self.host, self.path, self.tag
+            "{}://{}/v2/{}/manifests/{}",
+            self.scheme, self.host, self.path, self.tag
         )
         .parse()
     }
 
     pub fn token_url(&self, action: OciAction) -> Result<Url, ParseError> {
         format!(
-            "https://{}/token?scope=repository:{}:{}",
+            "{}://{}/token?scope=repository:{}:{}",
+            self.scheme,
             self.host,
             self.path,
             action.to_string()
@@ -111,7 +117,23 @@ impl OCIUrl {
     }
 
     pub fn blob_url(&self, sha256: &str) -> Result<Url, ParseError> {
-        format!("https://{}/v2/{}/blobs/{}", self.host, self.path, sha256).parse()
+        format!(
+            "{}://{}/v2/{}/blobs/{}",
+            self.scheme, self.host, self.path, sha256
+        )
+        .parse()
+    }
+
+    pub fn upload_url(&self) -> Result<Url, ParseError> {
+        format!(
+            "{}://{}/v2/{}/blobs/uploads/",
+            self.scheme, self.host, self.path
+        )
+        .parse()
+    }
+
+    pub fn registry_url(&self) -> Result<Url, ParseError> {
+        format!("{}://{}/v2/", self.scheme, self.host).parse()
     }
 
     pub fn new(url: &Url) -> Result<Self, ParseError> {
@@ -120,9 +142,13 @@ impl OCIUrl {
 
         let mut res = OCIUrl {
             url: url.clone(),
+            scheme: "https",
             tag: "latest".to_string(),
             media_type: "".to_string(),
-            host: url.host_str().unwrap_or("").to_string(),
+            host: match url.port() {
+                Some(port) => format!("{}:{port}", url.host_str().unwrap_or("")),
+                None => url.host_str().unwrap_or("").to_string(),
+            },
             path: url.path().trim_start_matches('/').to_string(),
         };
 
@@ -215,25 +241,438 @@ impl OCIUrl {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 struct Layer {
     digest: String,
     #[serde(rename = "mediaType")]
     media_type: String,
     size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
     annotations: Option<HashMap<String, String>>,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct Manifest {
     schema_version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
     layers: Vec<Layer>,
     config: Layer,
+    #[serde(skip_serializing_if = "Option::is_none")]
     annotations: Option<HashMap<String, String>>,
 }
 
+/// The media type of the info layer of a conda package (a gzipped tarball of the `info` folder)
+pub const CONDA_INFO_MEDIA_TYPE: &str = "application/vnd.conda.info.v1.tar+gzip";
+
+/// The media type of a `repodata.json` file
+pub const REPODATA_MEDIA_TYPE: &str = "application/vnd.conda.repodata.v1+json";
+
+const OCI_MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const OCI_EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+
+/// An error that can occur when pushing artifacts to an OCI registry
+#[derive(thiserror::Error, Debug)]
+pub enum OciPushError {
+    /// An error occurred while communicating with the registry
+    #[error(transparent)]
+    Reqwest(#[from] reqwest::Error),
+
+    /// The URL could not be parsed or is not a valid OCI URL
+    #[error("invalid OCI url: {0}")]
+    InvalidUrl(String),
+
+    /// The registry did not accept the upload
+    #[error("the registry responded with {status} while {action}")]
+    UnexpectedResponse {
+        /// The status code returned by the registry
+        status: http::StatusCode,
+        /// What was being done when the error occurred
+        action: &'static str,
+    },
+
+    /// The registry did not return a token for the requested scope
+    #[error("failed to obtain a token with push permissions")]
+    MissingToken,
+}
+
+impl From<ParseError> for OciPushError {
+    fn from(err: ParseError) -> Self {
+        OciPushError::InvalidUrl(err.to_string())
+    }
+}
+
+/// A single layer of an OCI artifact
+#[derive(Debug, Clone)]
+pub struct OciLayer {
+    /// The media type of the layer (e.g. `application/vnd.conda.package.v2`)
+    pub media_type: String,
+    /// The contents of the layer
+    pub data: Bytes,
+    /// Optional annotations of the layer (e.g. `org.opencontainers.image.title`)
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+impl OciLayer {
+    /// Create a new layer without annotations
+    pub fn new(media_type: impl Into<String>, data: impl Into<Bytes>) -> Self {
+        Self {
+            media_type: media_type.into(),
+            data: data.into(),
+            annotations: None,
+        }
+    }
+
+    /// Sets the `org.opencontainers.image.title` annotation of the layer
+    #[must_use]
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.annotations
+            .get_or_insert_with(HashMap::new)
+            .insert("org.opencontainers.image.title".to_string(), title.into());
+        self
+    }
+}
+
+/// Information about an artifact that was pushed to a registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OciPushResult {
+    /// The repository the artifact was pushed to
+    pub repository: String,
+    /// The tag of the artifact
+    pub tag: String,
+    /// The digest of the manifest
+    pub manifest_digest: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OCITokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Client to push conda packages and repodata to an OCI registry (e.g. `ghcr.io`).
+///
+/// The same URLs that are used to pull packages through the [`OciMiddleware`] are used to push
+/// them, e.g. `oci://ghcr.io/my-org/my-channel/linux-64/xtensor-0.25.0-h2ffa867_0.conda`.
+#[derive(Debug, Clone, Default)]
+pub struct OciPushClient {
+    client: reqwest::Client,
+    credentials: Option<(String, String)>,
+    insecure: bool,
+}
+
+impl OciPushClient {
+    /// Create a new client that uses the given `reqwest` client for all requests
+    pub fn new(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            credentials: None,
+            insecure: false,
+        }
+    }
+
+    /// Sets the username and password (or personal access token) that are used to request a
+    /// token with push permissions from the registry.
+    #[must_use]
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Talk to the registry over plain HTTP instead of HTTPS. Only use this for local registries.
+    #[must_use]
+    pub fn with_insecure(mut self, insecure: bool) -> Self {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Push a conda package to the registry.
+    ///
+    /// The artifact contains the `.conda` archive itself and the gzipped tarball of its `info`
+    /// folder so that clients can retrieve the metadata without downloading the whole package.
+    pub async fn push_package(
+        &self,
+        url: &Url,
+        package: impl Into<Bytes>,
+        info: impl Into<Bytes>,
+    ) -> Result<OciPushResult, OciPushError> {
+        let oci_url = self.oci_url(url)?;
+        if oci_url.media_type != "application/vnd.conda.package.v2" {
+            return Err(OciPushError::InvalidUrl(format!(
+                "{url} does not point to a .conda package"
+            )));
+        }
+
+        let filename = url
+            .path_segments()
+            .and_then(Iterator::last)
+            .unwrap_or_default();
+        let layers = vec![
+            OciLayer::new(oci_url.media_type.clone(), package).with_title(filename),
+            OciLayer::new(CONDA_INFO_MEDIA_TYPE, info).with_title("info.tar.gz"),
+        ];
+        self.push(&oci_url, layers).await
+    }
+
+    /// Push an updated `repodata.json` of a subdirectory to the registry.
+    ///
+    /// `url` is the url of the `repodata.json` file, e.g.
+    /// `oci://ghcr.io/my-org/my-channel/linux-64/repodata.json`.
+    pub async fn push_repodata(
+        &self,
+        url: &Url,
+        repodata: impl Into<Bytes>,
+    ) -> Result<OciPushResult, OciPushError> {
+        let oci_url = self.oci_url(url)?;
+        if oci_url.media_type != REPODATA_MEDIA_TYPE {
+            return Err(OciPushError::InvalidUrl(format!(
+                "{url} does not point to a repodata.json file"
+            )));
+        }
+
+        let layers = vec![OciLayer::new(REPODATA_MEDIA_TYPE, repodata).with_title("repodata.json")];
+        self.push(&oci_url, layers).await
+    }
+
+    /// Push an arbitrary artifact consisting of the given layers to the repository and tag
+    /// derived from `url`.
+    pub async fn push_layers(
+        &self,
+        url: &Url,
+        layers: Vec<OciLayer>,
+    ) -> Result<OciPushResult, OciPushError> {
+        let oci_url = self.oci_url(url)?;
+        self.push(&oci_url, layers).await
+    }
+
+    fn oci_url(&self, url: &Url) -> Result<OCIUrl, OciPushError> {
+        if url.scheme() != "oci" {
+            return Err(OciPushError::InvalidUrl(url.to_string()));
+        }
+
+        // `OCIUrl::new` expects package archives to be named `<name>-<version>-<build>`.
+        let Some(filename) = url.path_segments().and_then(Iterator::last) else {
+            return Err(OciPushError::InvalidUrl(url.to_string()));
+        };
+        let archive_name = filename
+            .strip_suffix(".conda")
+            .or_else(|| filename.strip_suffix(".tar.bz2"));
+        if let Some(archive_name) = archive_name {
+            let parts = archive_name.rsplitn(3, '-').collect::<Vec<_>>();
+            if parts.len() != 3 || parts.iter().any(|part| part.is_empty()) {
+                return Err(OciPushError::InvalidUrl(format!(
+                    "{url} is not a package archive of the form <name>-<version>-<build>"
+                )));
+            }
+        }
+
+        let mut oci_url = OCIUrl::new(url)?;
+        if self.insecure {
+            oci_url.scheme = "http";
+        }
+        Ok(oci_url)
+    }
+
+    async fn push(
+        &self,
+        oci_url: &OCIUrl,
+        layers: Vec<OciLayer>,
+    ) -> Result<OciPushResult, OciPushError> {
+        let token = self.push_token(oci_url).await?;
+
+        // OCI artifacts don't need a config, the spec recommends to use an empty JSON object.
+        let config_data = Bytes::from_static(b"{}");
+        let config = Layer {
+            digest: sha256_digest(&config_data),
+            media_type: OCI_EMPTY_MEDIA_TYPE.to_string(),
+            size: config_data.len() as u64,
+            annotations: None,
+        };
+        self.push_blob(oci_url, token.as_deref(), &config.digest, config_data)
+            .await?;
+
+        let mut manifest_layers = Vec::with_capacity(layers.len());
+        for layer in layers {
+            let digest = sha256_digest(&layer.data);
+            manifest_layers.push(Layer {
+                digest: digest.clone(),
+                media_type: layer.media_type,
+                size: layer.data.len() as u64,
+                annotations: layer.annotations,
+            });
+            self.push_blob(oci_url, token.as_deref(), &digest, layer.data)
+                .await?;
+        }
+
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: Some(OCI_MANIFEST_MEDIA_TYPE.to_string()),
+            layers: manifest_layers,
+            config,
+            annotations: None,
+        };
+        let manifest = serde_json::to_vec(&manifest).expect("manifest can always be serialized");
+        let manifest_digest = sha256_digest(&manifest);
+
+        tracing::debug!("OCI: pushing manifest for {}:{}", oci_url.path, oci_url.tag);
+        let response = authorized(self.client.put(oci_url.manifest_url()?), token.as_deref())
+            .header(CONTENT_TYPE, OCI_MANIFEST_MEDIA_TYPE)
+            .body(manifest)
+            .send()
+            .await?;
+        expect_status(&response, "pushing the manifest")?;
+
+        Ok(OciPushResult {
+            repository: oci_url.path.clone(),
+            tag: oci_url.tag.clone(),
+            manifest_digest,
+        })
+    }
+
+    /// Negotiates a token with `push` and `pull` permissions for the repository.
+    ///
+    /// The registry is asked for an authentication challenge (`WWW-Authenticate` header) which
+    /// tells us where to request a token. Returns `None` if the registry does not require
+    /// authentication.
+    async fn push_token(&self, oci_url: &OCIUrl) -> Result<Option<String>, OciPushError> {
+        let response = self.client.get(oci_url.registry_url()?).send().await?;
+        if response.status() != http::StatusCode::UNAUTHORIZED {
+            return Ok(None);
+        }
+
+        let scope = format!(
+            "repository:{}:{}",
+            oci_url.path,
+            OciAction::PushPull.to_string()
+        );
+        let token_url = match response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_bearer_challenge)
+        {
+            Some((realm, service)) => {
+                let mut token_url = Url::parse(&realm)?;
+                {
+                    let mut query = token_url.query_pairs_mut();
+                    if let Some(service) = service {
+                        query.append_pair("service", &service);
+                    }
+                    query.append_pair("scope", &scope);
+                }
+                token_url
+            }
+            None => oci_url.token_url(OciAction::PushPull)?,
+        };
+
+        tracing::trace!("OCI: requesting push token from {}", token_url);
+        let mut request = self.client.get(token_url);
+        if let Some((username, password)) = &self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        let response = request.send().await?;
+        expect_status(&response, "requesting a token")?;
+
+        let token = response.json::<OCITokenResponse>().await?;
+        token
+            .token
+            .or(token.access_token)
+            .map(Some)
+            .ok_or(OciPushError::MissingToken)
+    }
+
+    /// Uploads a single blob, unless the registry already has it.
+    async fn push_blob(
+        &self,
+        oci_url: &OCIUrl,
+        token: Option<&str>,
+        digest: &str,
+        data: Bytes,
+    ) -> Result<(), OciPushError> {
+        let response = authorized(self.client.head(oci_url.blob_url(digest)?), token)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            tracing::trace!("OCI: blob {digest} already exists");
+            return Ok(());
+        }
+
+        // Start an upload session
+        let response = authorized(self.client.post(oci_url.upload_url()?), token)
+            .send()
+            .await?;
+        expect_status(&response, "starting a blob upload")?;
+
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(OciPushError::UnexpectedResponse {
+                status: response.status(),
+                action: "starting a blob upload",
+            })?;
+        let mut upload_url = oci_url.registry_url()?.join(location)?;
+        upload_url.query_pairs_mut().append_pair("digest", digest);
+
+        // Upload the whole blob at once
+        tracing::debug!("OCI: uploading blob {digest} ({} bytes)", data.len());
+        let response = authorized(self.client.put(upload_url), token)
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(data)
+            .send()
+            .await?;
+        expect_status(&response, "uploading a blob")
+    }
+}
+
+fn authorized(request: reqwest::RequestBuilder, token: Option<&str>) -> reqwest::RequestBuilder {
+    match token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+fn sha256_digest(data: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(data))
+}
+
+fn expect_status(response: &Response, action: &'static str) -> Result<(), OciPushError> {
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(OciPushError::UnexpectedResponse {
+            status: response.status(),
+            action,
+        })
+    }
+}
+
+/// Parses a `WWW-Authenticate: Bearer realm="...",service="..."` header and returns the realm and
+/// service.
+fn parse_bearer_challenge(header: &str) -> Option<(String, Option<String>)> {
+    let params = header.strip_prefix("Bearer ")?;
+    let mut realm = None;
+    let mut service = None;
+    for param in params.split(',') {
+        let Some((key, value)) = param.trim().split_once('=') else {
+            continue;
+        };
+        let value = value.trim_matches('"').to_string();
+        match key {
+            "realm" => realm = Some(value),
+            "service" => service = Some(value),
+            _ => {}
+        }
+    }
+    Some((realm?, service))
+}
+
 #[async_trait::async_trait]
 impl Middleware for OciMiddleware {
     async fn handle(
@@ -267,7 +706,7 @@ impl Middleware for OciMiddleware {
                     ));
                 }
                 _ => {
-                    return Err(reqwest_middleware::Error::Middleware(e.into()));
+                    return Err(reqwest_middleware::Error::Middleware(e.into()).redact());
                 }
             },
         }
@@ -276,8 +715,217 @@ impl Middleware for OciMiddleware {
 
 #[cfg(test)]
 mod tests {
-    use crate::OciMiddleware;
+    use std::{
+        collections::HashMap,
+        future::IntoFuture,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{header, HeaderMap, Method, StatusCode, Uri},
+        response::{IntoResponse, Response},
+        routing::get,
+        Router,
+    };
     use sha2::{Digest, Sha256};
+    use url::Url;
+
+    use super::{Manifest, OciPushClient, OciPushError, CONDA_INFO_MEDIA_TYPE};
+    use crate::OciMiddleware;
+
+    /// A minimal in-memory stand-in for an OCI registry that requires a bearer token with push
+    /// permissions.
+    #[derive(Default)]
+    struct Registry {
+        blobs: Mutex<HashMap<String, Bytes>>,
+        manifests: Mutex<HashMap<String, Bytes>>,
+        uploads: Mutex<usize>,
+    }
+
+    const TOKEN: &str = "push-token";
+
+    async fn token(uri: Uri, headers: HeaderMap) -> Response {
+        let url = Url::parse(&format!("http://localhost{uri}")).unwrap();
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        // "user:password" base64 encoded
+        let authorized = headers
+            .get(header::AUTHORIZATION)
+            .is_some_and(|value| value == "Basic dXNlcjpwYXNzd29yZA==");
+        let scope_ok = params.get("scope").map(String::as_str)
+            == Some("repository:my-org/my-channel/linux-64/foo:push,pull")
+            && params.get("service").map(String::as_str) == Some("test-registry");
+        if !authorized || !scope_ok {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        (
+            [(header::CONTENT_TYPE, "application/json")],
+            serde_json::json!({ "token": TOKEN }).to_string(),
+        )
+            .into_response()
+    }
+
+    async fn registry(
+        State((registry, base)): State<(Arc<Registry>, String)>,
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let authorized = headers
+            .get(header::AUTHORIZATION)
+            .is_some_and(|value| value == &format!("Bearer {TOKEN}"));
+        if !authorized {
+            let challenge = format!("Bearer realm=\"{base}/token\",service=\"test-registry\"");
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, challenge)],
+            )
+                .into_response();
+        }
+
+        let path = uri.path();
+        if method == Method::HEAD {
+            let digest = path.rsplit('/').next().unwrap();
+            return if registry.blobs.lock().unwrap().contains_key(digest) {
+                StatusCode::OK.into_response()
+            } else {
+                StatusCode::NOT_FOUND.into_response()
+            };
+        }
+
+        if method == Method::POST && path.ends_with("/blobs/uploads/") {
+            let mut uploads = registry.uploads.lock().unwrap();
+            *uploads += 1;
+            let location = format!("{path}session-{uploads}");
+            return (StatusCode::ACCEPTED, [(header::LOCATION, location)]).into_response();
+        }
+
+        if method == Method::PUT && path.contains("/blobs/uploads/session-") {
+            let digest = uri
+                .query()
+                .and_then(|query| query.strip_prefix("digest="))
+                .unwrap()
+                .replace("%3A", ":");
+            if digest != format!("sha256:{:x}", Sha256::digest(&body)) {
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+            registry.blobs.lock().unwrap().insert(digest, body);
+            return StatusCode::CREATED.into_response();
+        }
+
+        if method == Method::PUT && path.contains("/manifests/") {
+            registry
+                .manifests
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), body);
+            return StatusCode::CREATED.into_response();
+        }
+
+        StatusCode::NOT_FOUND.into_response()
+    }
+
+    async fn test_registry(state: Arc<Registry>) -> Url {
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let base = format!("http://{}:{}", addr.ip(), addr.port());
+
+        let router = Router::new()
+            .route("/token", get(token))
+            .fallback(registry)
+            .with_state((state, base));
+
+        let service = router.into_make_service();
+        tokio::spawn(axum::serve(listener, service).into_future());
+        format!("oci://{}:{}/my-org/my-channel/", addr.ip(), addr.port())
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_push_package() {
+        let registry = Arc::new(Registry::default());
+        let channel = test_registry(registry.clone()).await;
+        let client = OciPushClient::new(reqwest::Client::new())
+            .with_credentials("user", "password")
+            .with_insecure(true);
+
+        let package = Bytes::from_static(b"conda package contents");
+        let info = Bytes::from_static(b"info tarball contents");
+        let result = client
+            .push_package(
+                &channel.join("linux-64/foo-1.0.0-h123_0.conda").unwrap(),
+                package.clone(),
+                info.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.repository, "my-org/my-channel/linux-64/foo");
+        assert_eq!(result.tag, "1.0.0-h123_0");
+
+        let manifests = registry.manifests.lock().unwrap();
+        let manifest = &manifests["/v2/my-org/my-channel/linux-64/foo/manifests/1.0.0-h123_0"];
+        assert_eq!(
+            result.manifest_digest,
+            format!("sha256:{:x}", Sha256::digest(manifest))
+        );
+        let manifest: Manifest = serde_json::from_slice(manifest).unwrap();
+        assert_eq!(manifest.layers.len(), 2);
+        assert_eq!(
+            manifest.layers[0].media_type,
+            "application/vnd.conda.package.v2"
+        );
+        assert_eq!(manifest.layers[1].media_type, CONDA_INFO_MEDIA_TYPE);
+
+        // The config and both layers have been uploaded
+        let blobs = registry.blobs.lock().unwrap();
+        assert_eq!(blobs.len(), 3);
+        assert_eq!(blobs[&manifest.layers[0].digest], package);
+        assert_eq!(blobs[&manifest.layers[1].digest], info);
+    }
+
+    #[tokio::test]
+    async fn test_push_invalid_package_url() {
+        let client = OciPushClient::new(reqwest::Client::new());
+        for url in [
+            "oci://ghcr.io/org/foo.conda",
+            "oci://ghcr.io/org/foo-1.0.conda",
+            "oci://ghcr.io/org/foo--h123_0.conda",
+            "oci://ghcr.io/org/",
+            "https://ghcr.io/org/foo-1.0-h123_0.conda",
+        ] {
+            let err = client
+                .push_package(&url.parse().unwrap(), Bytes::new(), Bytes::new())
+                .await
+                .unwrap_err();
+            assert!(matches!(err, OciPushError::InvalidUrl(_)), "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_repodata_requires_credentials() {
+        let registry = Arc::new(Registry::default());
+        let channel = test_registry(registry.clone()).await;
+        let url = channel.join("linux-64/repodata.json").unwrap();
+
+        let err = OciPushClient::new(reqwest::Client::new())
+            .with_insecure(true)
+            .push_repodata(&url, b"{}".to_vec())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            OciPushError::UnexpectedResponse {
+                status: StatusCode::UNAUTHORIZED,
+                ..
+            }
+        ));
+        assert!(registry.manifests.lock().unwrap().is_empty());
+    }
 
     // test pulling an image from OCI registry
     #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
@@ -0,0 +1,514 @@
+//! Middleware to limit the number of concurrent requests and the request rate per host
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use http::{header::RETRY_AFTER, Extensions, StatusCode};
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next, Result};
+use tokio::sync::Semaphore;
+
+/// The limits that apply to the requests made to a single host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HostLimits {
+    /// The maximum number of requests that can be in flight at the same time.
+    pub max_concurrent_requests: Option<usize>,
+
+    /// The maximum number of requests that are started per second.
+    pub max_requests_per_second: Option<f64>,
+}
+
+impl Default for HostLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent_requests: Some(50),
+            max_requests_per_second: None,
+        }
+    }
+}
+
+/// A snapshot of the counters kept for a single host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostStatistics {
+    /// The total number of requests that were sent to the host, including retries.
+    pub requests: u64,
+
+    /// The number of requests that are currently in flight.
+    pub in_flight: u64,
+
+    /// The number of requests that had to wait before they could be sent because of the
+    /// concurrency limit, the rate limit or a `Retry-After` header.
+    pub throttled: u64,
+
+    /// The number of `429 Too Many Requests` or `503 Service Unavailable` responses that were
+    /// received from the host.
+    pub rate_limited_responses: u64,
+
+    /// The number of requests that were retried after the host sent a `Retry-After` header.
+    pub retries: u64,
+}
+
+#[derive(Default)]
+struct HostCounters {
+    requests: AtomicU64,
+    in_flight: AtomicU64,
+    throttled: AtomicU64,
+    rate_limited_responses: AtomicU64,
+    retries: AtomicU64,
+}
+
+impl HostCounters {
+    fn snapshot(&self) -> HostStatistics {
+        HostStatistics {
+            requests: self.requests.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            rate_limited_responses: self.rate_limited_responses.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct HostState {
+    semaphore: Option<Arc<Semaphore>>,
+    min_interval: Option<Duration>,
+
+    /// The earliest moment the next request may be started to stay within the rate limit.
+    next_slot: Mutex<Instant>,
+
+    /// Set when the host asked us to back off with a `Retry-After` header.
+    blocked_until: Mutex<Option<Instant>>,
+
+    counters: HostCounters,
+}
+
+impl HostState {
+    fn new(limits: HostLimits) -> Self {
+        Self {
+            semaphore: limits
+                .max_concurrent_requests
+                .map(|max| Arc::new(Semaphore::new(max.max(1)))),
+            min_interval: limits
+                .max_requests_per_second
+                .filter(|rps| *rps > 0.0)
+                .map(|rps| Duration::from_secs_f64(1.0 / rps)),
+            next_slot: Mutex::new(Instant::now()),
+            blocked_until: Mutex::new(None),
+            counters: HostCounters::default(),
+        }
+    }
+
+    /// Returns the moment at which the next request to this host may be sent, reserving a slot
+    /// in the rate limit.
+    fn reserve_slot(&self) -> Instant {
+        let now = Instant::now();
+        let mut start = now;
+
+        if let Some(blocked_until) = *self.blocked_until.lock().unwrap() {
+            start = start.max(blocked_until);
+        }
+
+        if let Some(min_interval) = self.min_interval {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            start = start.max(*next_slot);
+            *next_slot = start + min_interval;
+        }
+
+        start
+    }
+
+    fn block_until(&self, until: Instant) {
+        let mut blocked_until = self.blocked_until.lock().unwrap();
+        if blocked_until.map_or(true, |current| current < until) {
+            *blocked_until = Some(until);
+        }
+    }
+}
+
+/// Decrements the in-flight counter of a host when dropped.
+struct InFlightGuard<'a>(&'a AtomicU64);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Middleware that limits the number of concurrent requests and the number of requests per second
+/// for every host individually.
+///
+/// When a host responds with `429 Too Many Requests` or `503 Service Unavailable` and includes a
+/// `Retry-After` header, all further requests to that host are held back until the requested
+/// moment and the request itself is retried (if its body can be cloned).
+///
+/// The middleware keeps a set of counters per host that can be queried with
+/// [`RateLimitMiddleware::statistics`]. Cloning the middleware shares the limits and counters.
+#[derive(Clone)]
+pub struct RateLimitMiddleware {
+    inner: Arc<RateLimitInner>,
+}
+
+struct RateLimitInner {
+    default_limits: HostLimits,
+    host_limits: HashMap<String, HostLimits>,
+    max_retries: u32,
+    max_retry_after: Duration,
+    hosts: Mutex<HashMap<String, Arc<HostState>>>,
+}
+
+impl Default for RateLimitMiddleware {
+    fn default() -> Self {
+        Self::new(HostLimits::default())
+    }
+}
+
+impl RateLimitMiddleware {
+    /// Create a new middleware that applies the given limits to every host.
+    pub fn new(default_limits: HostLimits) -> Self {
+        Self {
+            inner: Arc::new(RateLimitInner {
+                default_limits,
+                host_limits: HashMap::new(),
+                max_retries: 3,
+                max_retry_after: Duration::from_secs(60),
+                hosts: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Overwrite the limits for a specific host (e.g. `conda.anaconda.org`).
+    #[must_use]
+    pub fn with_host_limits(self, host: impl Into<String>, limits: HostLimits) -> Self {
+        self.map_inner(|inner| {
+            inner.host_limits.insert(host.into(), limits);
+        })
+    }
+
+    /// Sets the maximum number of times a request is retried when the host responds with a
+    /// `Retry-After` header. Defaults to 3.
+    #[must_use]
+    pub fn with_max_retries(self, max_retries: u32) -> Self {
+        self.map_inner(|inner| inner.max_retries = max_retries)
+    }
+
+    /// Sets the maximum duration that is honoured from a `Retry-After` header. Longer durations
+    /// are clamped to this value. Defaults to 60 seconds.
+    #[must_use]
+    pub fn with_max_retry_after(self, max_retry_after: Duration) -> Self {
+        self.map_inner(|inner| inner.max_retry_after = max_retry_after)
+    }
+
+    /// Returns the counters for the given host, or `None` if no request was made to the host yet.
+    pub fn host_statistics(&self, host: &str) -> Option<HostStatistics> {
+        let hosts = self.inner.hosts.lock().unwrap();
+        hosts.get(host).map(|state| state.counters.snapshot())
+    }
+
+    /// Returns the counters for all hosts that requests were made to.
+    pub fn statistics(&self) -> HashMap<String, HostStatistics> {
+        let hosts = self.inner.hosts.lock().unwrap();
+        hosts
+            .iter()
+            .map(|(host, state)| (host.clone(), state.counters.snapshot()))
+            .collect()
+    }
+
+    fn map_inner(mut self, f: impl FnOnce(&mut RateLimitInner)) -> Self {
+        let inner = Arc::get_mut(&mut self.inner)
+            .expect("the rate limit middleware cannot be configured after it has been cloned");
+        f(inner);
+        self
+    }
+
+    fn host_state(&self, host: &str) -> Arc<HostState> {
+        let mut hosts = self.inner.hosts.lock().unwrap();
+        hosts
+            .entry(host.to_string())
+            .or_insert_with(|| {
+                let limits = self
+                    .inner
+                    .host_limits
+                    .get(host)
+                    .copied()
+                    .unwrap_or(self.inner.default_limits);
+                Arc::new(HostState::new(limits))
+            })
+            .clone()
+    }
+}
+
+/// Parses the value of a `Retry-After` header which is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let date = SystemTime::from(date);
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Returns the duration the server asked us to wait before retrying, if any.
+fn retry_after(response: &Response) -> Option<Duration> {
+    if !matches!(
+        response.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    ) {
+        return None;
+    }
+
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after)
+}
+
+#[async_trait::async_trait]
+impl Middleware for RateLimitMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        // Requests without a host (e.g. `file://` urls) are not limited
+        let Some(host) = req.url().host_str().map(ToString::to_string) else {
+            return next.run(req, extensions).await;
+        };
+
+        let state = self.host_state(&host);
+        let mut req = req;
+        let mut attempt = 0;
+        loop {
+            // Keep a copy of the request around in case we have to retry it.
+            let retry_request = if attempt < self.inner.max_retries {
+                req.try_clone()
+            } else {
+                None
+            };
+
+            let _permit = match &state.semaphore {
+                Some(semaphore) => {
+                    if semaphore.available_permits() == 0 {
+                        state.counters.throttled.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Some(
+                        semaphore
+                            .clone()
+                            .acquire_owned()
+                            .await
+                            .expect("the semaphore is never closed"),
+                    )
+                }
+                None => None,
+            };
+
+            let start = state.reserve_slot();
+            if start > Instant::now() {
+                state.counters.throttled.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep_until(start.into()).await;
+            }
+
+            state.counters.requests.fetch_add(1, Ordering::Relaxed);
+            state.counters.in_flight.fetch_add(1, Ordering::Relaxed);
+            let in_flight = InFlightGuard(&state.counters.in_flight);
+            let response = next.clone().run(req, extensions).await;
+            drop(in_flight);
+
+            let Ok(response) = response else {
+                return response;
+            };
+
+            if matches!(
+                response.status(),
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+            ) {
+                state
+                    .counters
+                    .rate_limited_responses
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+
+            let Some(wait) = retry_after(&response) else {
+                return Ok(response);
+            };
+
+            let wait = wait.min(self.inner.max_retry_after);
+            tracing::debug!(
+                "{host} responded with {}, backing off for {}s",
+                response.status(),
+                wait.as_secs_f64()
+            );
+            state.block_until(Instant::now() + wait);
+
+            match retry_request {
+                Some(retry_request) => {
+                    state.counters.retries.fetch_add(1, Ordering::Relaxed);
+                    req = retry_request;
+                    attempt += 1;
+                }
+                None => return Ok(response),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::IntoFuture,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Router};
+    use url::Url;
+
+    use super::{parse_retry_after, HostLimits, RateLimitMiddleware};
+
+    #[derive(Default)]
+    struct ServerState {
+        current: AtomicUsize,
+        max_concurrent: AtomicUsize,
+        requests: AtomicUsize,
+    }
+
+    async fn slow(State(state): State<Arc<ServerState>>) -> &'static str {
+        let current = state.current.fetch_add(1, Ordering::SeqCst) + 1;
+        state.max_concurrent.fetch_max(current, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        state.current.fetch_sub(1, Ordering::SeqCst);
+        "done"
+    }
+
+    async fn throttled(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+        if state.requests.fetch_add(1, Ordering::SeqCst) == 0 {
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [("Retry-After", "1")],
+                "slow down",
+            )
+                .into_response()
+        } else {
+            (StatusCode::OK, "done").into_response()
+        }
+    }
+
+    async fn test_server(state: Arc<ServerState>) -> Url {
+        let router = Router::new()
+            .route("/slow", get(slow))
+            .route("/throttled", get(throttled))
+            .with_state(state);
+
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let service = router.into_make_service();
+        tokio::spawn(axum::serve(listener, service).into_future());
+        format!("http://{}:{}", addr.ip(), addr.port())
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_requests_per_host() {
+        let state = Arc::new(ServerState::default());
+        let url = test_server(state.clone()).await;
+
+        let middleware = RateLimitMiddleware::new(HostLimits {
+            max_concurrent_requests: Some(2),
+            max_requests_per_second: None,
+        });
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(middleware.clone())
+            .build();
+
+        let requests = (0..8).map(|_| {
+            let client = client.clone();
+            let url = url.join("slow").unwrap();
+            async move { client.get(url).send().await.unwrap().status() }
+        });
+        for status in futures::future::join_all(requests).await {
+            assert!(status.is_success());
+        }
+
+        assert_eq!(state.max_concurrent.load(Ordering::SeqCst), 2);
+
+        let stats = middleware.host_statistics("127.0.0.1").unwrap();
+        assert_eq!(stats.requests, 8);
+        assert_eq!(stats.in_flight, 0);
+        assert!(stats.throttled > 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_requests_per_second() {
+        let state = Arc::new(ServerState::default());
+        let url = test_server(state.clone()).await;
+
+        let middleware = RateLimitMiddleware::new(HostLimits {
+            max_concurrent_requests: None,
+            max_requests_per_second: Some(20.0),
+        });
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(middleware)
+            .build();
+
+        let start = Instant::now();
+        for _ in 0..5 {
+            client.get(url.join("slow").unwrap()).send().await.unwrap();
+        }
+
+        // 5 requests at 20 requests per second take at least 4 intervals of 50ms
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        let state = Arc::new(ServerState::default());
+        let url = test_server(state.clone()).await;
+
+        let middleware = RateLimitMiddleware::default();
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(middleware.clone())
+            .build();
+
+        let start = Instant::now();
+        let response = client
+            .get(url.join("throttled").unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        let stats = middleware.host_statistics("127.0.0.1").unwrap();
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.rate_limited_responses, 1);
+        assert_eq!(stats.retries, 1);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
@@ -493,7 +493,10 @@ async fn fetch_jlap_with_retry(
     client: &ClientWithMiddleware,
     position: u64,
 ) -> Result<(Response, u64), JLAPError> {
-    tracing::info!("fetching JLAP state from {url} (bytes={position}-)");
+    tracing::info!(
+        "fetching JLAP state from {} (bytes={position}-)",
+        url.clone().redact()
+    );
     let range = format!("bytes={position}-");
 
     match fetch_jlap(url, client, &range).await {
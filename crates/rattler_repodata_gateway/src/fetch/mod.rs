@@ -8,7 +8,7 @@ use cache_control::{Cachability, CacheControl};
 use futures::{future::ready, FutureExt, TryStreamExt};
 use humansize::{SizeFormatter, DECIMAL};
 use rattler_digest::{compute_file_digest, Blake2b256, HashingWriter};
-use rattler_redaction::Redact;
+use rattler_redaction::{Redact, Redacted};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
     Response, StatusCode,
@@ -444,7 +444,7 @@ pub async fn fetch_repo_data(
                 });
             }
             Err(error) => {
-                tracing::warn!("Error during JLAP request: {}", error);
+                tracing::warn!("Error during JLAP request: {}", Redacted(&error));
                 None
             }
         }
@@ -466,7 +466,7 @@ pub async fn fetch_repo_data(
     };
 
     // Construct the HTTP request
-    tracing::debug!("fetching '{}'", &repo_data_url);
+    tracing::debug!("fetching '{}'", repo_data_url.clone().redact());
     let request_builder = client.get(repo_data_url.clone());
 
     let mut headers = HeaderMap::default();
@@ -658,7 +658,7 @@ async fn stream_and_decode_to_file(
     // Decode, hash and write the data to the file.
     let bytes = tokio::io::copy(&mut decoded_repo_data_json_bytes, &mut hashing_file_writer)
         .await
-        .map_err(|e| FetchRepoDataError::FailedToDownload(url.redact(), e))?;
+        .map_err(|e| FetchRepoDataError::FailedToDownload(url.redact(), e.redact()))?;
 
     // Finalize the hash
     let (_, hash) = hashing_file_writer.finalize();
@@ -794,14 +794,15 @@ async fn check_valid_download_target(
     url: &Url,
     client: &reqwest_middleware::ClientWithMiddleware,
 ) -> bool {
-    tracing::debug!("checking availability of '{url}'");
+    let redacted_url = url.clone().redact();
+    tracing::debug!("checking availability of '{redacted_url}'");
 
     if url.scheme() == "file" {
         // If the url is a file url we can simply check if the file exists.
         let path = url.to_file_path().unwrap();
         let exists = tokio::fs::metadata(path).await.is_ok();
         tracing::debug!(
-            "'{url}' seems to be {}",
+            "'{redacted_url}' seems to be {}",
             if exists { "available" } else { "unavailable" }
         );
         exists
@@ -810,16 +811,17 @@ async fn check_valid_download_target(
         match client.head(url.clone()).send().await {
             Ok(response) => {
                 if response.status().is_success() {
-                    tracing::debug!("'{url}' seems to be available");
+                    tracing::debug!("'{redacted_url}' seems to be available");
                     true
                 } else {
-                    tracing::debug!("'{url}' seems to be unavailable");
+                    tracing::debug!("'{redacted_url}' seems to be unavailable");
                     false
                 }
             }
             Err(e) => {
                 tracing::warn!(
-                    "failed to perform HEAD request on '{url}': {e}. Assuming its unavailable.."
+                    "failed to perform HEAD request on '{redacted_url}': {}. Assuming its unavailable..",
+                    e.redact()
                 );
                 false
             }
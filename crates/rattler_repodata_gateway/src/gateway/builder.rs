@@ -75,6 +75,10 @@ impl GatewayBuilder {
     }
 
     /// Sets the maximum number of concurrent HTTP requests to make.
+    ///
+    /// This limit applies to all requests made by the gateway regardless of the host. To limit
+    /// the number of requests per host, add a
+    /// [`rattler_networking::RateLimitMiddleware`] to the client.
     #[must_use]
     pub fn with_max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
         self.set_max_concurrent_requests(max_concurrent_requests);
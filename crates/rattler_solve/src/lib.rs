@@ -6,12 +6,16 @@
 
 #[cfg(feature = "libsolv_c")]
 pub mod libsolv_c;
+#[cfg(feature = "lock_file")]
+pub mod lock_file;
+mod pins;
 #[cfg(feature = "resolvo")]
 pub mod resolvo;
 
 use std::fmt;
 
 use chrono::{DateTime, Utc};
+pub use pins::{find_pin_conflicts, PinConflict};
 use rattler_conda_types::{GenericVirtualPackage, MatchSpec, RepoDataRecord};
 
 /// Represents a solver implementation, capable of solving [`SolverTask`]s
@@ -0,0 +1,157 @@
+//! Targeted updates of the packages stored in a lock-file.
+//!
+//! Use [`update_lock_file`] to re-solve a single environment and platform of a
+//! [`LockFile`] while keeping all packages that should not be updated at their
+//! locked version.
+
+use rattler_conda_types::{PackageName, Platform};
+use rattler_lock::{CondaPackageData, ConversionError, LockFile};
+
+use crate::{IntoRepoData, SolveError, SolverImpl, SolverTask};
+
+/// An error that can occur when updating a lock-file with [`update_lock_file`].
+#[derive(Debug, thiserror::Error)]
+pub enum UpdateLockFileError {
+    /// The environment to update does not exist in the lock-file.
+    #[error("the environment '{0}' does not exist in the lock-file")]
+    MissingEnvironment(String),
+
+    /// A locked package could not be converted to a repodata record.
+    #[error(transparent)]
+    ConversionError(#[from] ConversionError),
+
+    /// The solver failed to solve the environment.
+    #[error(transparent)]
+    SolveError(#[from] SolveError),
+}
+
+/// Updates the conda packages of `environment` for `platform` in the given
+/// lock-file.
+///
+/// All the locked packages of the environment, except for the packages in
+/// `packages_to_update`, are added to the [`SolverTask::locked_packages`] of
+/// `task` so the solver prefers to keep them at their current version. The
+/// rest of the task (e.g. the available packages, specs and virtual packages)
+/// is passed to the solver as is.
+///
+/// Only the packages of the solved platform are written back to the
+/// lock-file, the order of the existing packages, all other environments and
+/// platforms and the pypi packages are preserved. If the solution does not
+/// differ from the locked packages the lock-file is returned unchanged.
+pub fn update_lock_file<'a, S, R, T>(
+    solver: &mut S,
+    lock_file: &LockFile,
+    environment: &str,
+    platform: Platform,
+    packages_to_update: &[PackageName],
+    mut task: SolverTask<T>,
+) -> Result<LockFile, UpdateLockFileError>
+where
+    S: SolverImpl,
+    R: IntoRepoData<'a, S::RepoData<'a>>,
+    T: IntoIterator<Item = R>,
+{
+    let locked_environment = lock_file
+        .environment(environment)
+        .ok_or_else(|| UpdateLockFileError::MissingEnvironment(environment.to_string()))?;
+    let locked_records = locked_environment
+        .conda_repodata_records_for_platform(platform)?
+        .unwrap_or_default();
+
+    task.locked_packages.extend(
+        locked_records
+            .iter()
+            .filter(|record| !packages_to_update.contains(&record.package_record.name))
+            .cloned(),
+    );
+
+    let solved_records = solver.solve(task)?;
+
+    // Don't touch the lock-file if nothing changed.
+    let is_unchanged = solved_records.len() == locked_records.len()
+        && solved_records
+            .iter()
+            .all(|record| locked_records.iter().any(|locked| locked.url == record.url));
+    if is_unchanged {
+        return Ok(lock_file.clone());
+    }
+
+    Ok(lock_file.with_conda_packages(
+        environment,
+        platform,
+        solved_records.into_iter().map(CondaPackageData::from),
+    ))
+}
+
+#[cfg(all(test, feature = "resolvo"))]
+mod test {
+    use std::path::Path;
+
+    use rattler_conda_types::{
+        Channel, ChannelConfig, MatchSpec, PackageName, ParseStrictness, Platform, RepoData,
+        RepoDataRecord,
+    };
+    use rattler_lock::{LockFile, DEFAULT_ENVIRONMENT_NAME};
+
+    use super::update_lock_file;
+    use crate::SolverTask;
+
+    fn dummy_records() -> Vec<RepoDataRecord> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/channels/dummy/linux-64/repodata.json");
+        let repo_data: RepoData =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let channel_config = ChannelConfig::default_with_root_dir(std::env::current_dir().unwrap());
+        repo_data.into_repo_data_records(&Channel::from_str("dummy", &channel_config).unwrap())
+    }
+
+    fn locked_version(lock_file: &LockFile, name: &str) -> String {
+        lock_file
+            .default_environment()
+            .unwrap()
+            .packages(Platform::Linux64)
+            .unwrap()
+            .find(|package| package.name() == name)
+            .unwrap()
+            .version()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_update_single_package() {
+        let records = dummy_records();
+        let mut builder = LockFile::builder();
+        builder.set_channels(DEFAULT_ENVIRONMENT_NAME, ["dummy"]);
+        for record in records.iter().filter(|record| {
+            record.file_name == "foo-3.0.2-py36h1af98f8_1.conda"
+                || record.file_name == "bors-1.0-bla_1.tar.bz2"
+        }) {
+            builder.add_conda_package(
+                DEFAULT_ENVIRONMENT_NAME,
+                Platform::Linux64,
+                record.clone().into(),
+            );
+        }
+        let lock_file = builder.finish();
+
+        let task = SolverTask {
+            specs: vec![
+                MatchSpec::from_str("foo", ParseStrictness::Lenient).unwrap(),
+                MatchSpec::from_str("bors", ParseStrictness::Lenient).unwrap(),
+            ],
+            ..SolverTask::from_iter([&records])
+        };
+        let updated = update_lock_file(
+            &mut crate::resolvo::Solver,
+            &lock_file,
+            DEFAULT_ENVIRONMENT_NAME,
+            Platform::Linux64,
+            &[PackageName::new_unchecked("bors")],
+            task,
+        )
+        .unwrap();
+
+        assert_eq!(locked_version(&updated, "foo"), "3.0.2");
+        assert_eq!(locked_version(&updated, "bors"), "2.1");
+    }
+}
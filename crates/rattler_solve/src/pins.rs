@@ -0,0 +1,150 @@
+//! Support for pinned packages, e.g. from the `conda-meta/pinned` file of a
+//! prefix (see [`rattler_conda_types::PinnedFile`]).
+//!
+//! Pins are passed to the solver as constraints (see
+//! [`SolverTask::with_pins`] and [`SolverTask::with_prefix_pins`]). Because an unsolvable problem caused by a pin
+//! can be hard to understand from the solver output alone,
+//! [`find_pin_conflicts`] can be used to detect requested specs that can
+//! never be satisfied together with a pin before solving.
+
+use std::{
+    fmt::{Display, Formatter},
+    path::Path,
+};
+
+use rattler_conda_types::{MatchSpec, Matches, PinnedFile, RepoDataRecord};
+
+use crate::SolverTask;
+
+/// A requested spec that cannot be satisfied by any available package
+/// because of a pin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinConflict {
+    /// The spec that was requested.
+    pub spec: MatchSpec,
+
+    /// The pin that excludes all packages that match the spec.
+    pub pin: MatchSpec,
+}
+
+impl Display for PinConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the requested spec '{}' conflicts with the pin '{}'",
+            self.spec, self.pin
+        )
+    }
+}
+
+impl<T> SolverTask<T> {
+    /// Adds pinned specs to the constraints of the task. Pinned packages are
+    /// not installed because of the pin, but if they are installed they must
+    /// match the pin.
+    pub fn with_pins(mut self, pins: impl IntoIterator<Item = MatchSpec>) -> Self {
+        self.constraints.extend(pins);
+        self
+    }
+
+    /// Reads the pinned file of `prefix` (see [`PinnedFile`]) and adds its
+    /// specs as pins with [`Self::with_pins`]. A prefix without a pinned file
+    /// has no pins.
+    pub fn with_prefix_pins(self, prefix: &Path) -> std::io::Result<Self> {
+        let pinned = PinnedFile::from_prefix(prefix)?;
+        Ok(self.with_pins(pinned.specs))
+    }
+}
+
+/// Returns the requested `specs` that conflict with one of the `pins`. A
+/// spec conflicts with a pin for the same package if none of the `available`
+/// packages that match the spec also match the pin.
+pub fn find_pin_conflicts<'r>(
+    specs: &[MatchSpec],
+    pins: &[MatchSpec],
+    available: impl IntoIterator<Item = &'r RepoDataRecord>,
+) -> Vec<PinConflict> {
+    let pairs = specs
+        .iter()
+        .flat_map(|spec| {
+            pins.iter()
+                .filter(move |pin| spec.name.is_some() && pin.name == spec.name)
+                .map(move |pin| (spec, pin))
+        })
+        .collect::<Vec<_>>();
+    if pairs.is_empty() {
+        return Vec::new();
+    }
+
+    let available = available.into_iter().collect::<Vec<_>>();
+    pairs
+        .into_iter()
+        .filter(|(spec, pin)| {
+            let mut candidates = available
+                .iter()
+                .map(|record| &record.package_record)
+                .filter(|record| spec.matches(record))
+                .peekable();
+            candidates.peek().is_some() && !candidates.any(|record| pin.matches(record))
+        })
+        .map(|(spec, pin)| PinConflict {
+            spec: spec.clone(),
+            pin: pin.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use rattler_conda_types::{
+        MatchSpec, PackageName, PackageRecord, ParseStrictness, RepoDataRecord, Version,
+    };
+
+    use super::find_pin_conflicts;
+
+    fn record(name: &str, version: &str) -> RepoDataRecord {
+        RepoDataRecord {
+            package_record: PackageRecord::new(
+                PackageName::new_unchecked(name),
+                version.parse::<Version>().unwrap(),
+                String::from("0"),
+            ),
+            file_name: format!("{name}-{version}-0.conda"),
+            url: format!("https://conda.anaconda.org/conda-forge/noarch/{name}-{version}-0.conda")
+                .parse()
+                .unwrap(),
+            channel: String::from("https://conda.anaconda.org/conda-forge/"),
+        }
+    }
+
+    fn specs(specs: &[&str]) -> Vec<MatchSpec> {
+        specs
+            .iter()
+            .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Lenient).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_find_pin_conflicts() {
+        let available = [
+            record("python", "3.11.9"),
+            record("python", "3.12.4"),
+            record("numpy", "1.26.4"),
+            record("numpy", "2.0.1"),
+        ];
+        let pins = specs(&["python 3.11.*", "numpy <2"]);
+
+        let conflicts = find_pin_conflicts(
+            &specs(&["python >=3.12", "numpy >=1.20", "pandas"]),
+            &pins,
+            &available,
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].to_string(),
+            "the requested spec 'python >=3.12' conflicts with the pin 'python 3.11.*'"
+        );
+
+        // Specs that match nothing at all are left to the solver to report.
+        assert!(find_pin_conflicts(&specs(&["python 4.*"]), &pins, &available).is_empty());
+    }
+}
@@ -563,6 +563,38 @@ macro_rules! solver_backend_tests {
             assert_eq!(operations[1].file_name, "foobar-2.1-bla_1.tar.bz2");
         }
 
+        #[test]
+        fn test_prefix_pins() {
+            use rattler_conda_types::{MatchSpec, ParseStrictness, PinnedFile, RepoDataRecord};
+            use rattler_solve::{SolverImpl, SolverTask};
+
+            let prefix = tempfile::tempdir().unwrap();
+            let repo_data = super::read_repodata(&dummy_channel_json_path());
+            let solve_foobar = || {
+                let task = SolverTask {
+                    specs: vec![MatchSpec::from_str("foobar", ParseStrictness::Lenient).unwrap()],
+                    ..SolverTask::from_iter([&repo_data])
+                }
+                .with_prefix_pins(prefix.path())
+                .unwrap();
+                let records: Vec<RepoDataRecord> = <$T>::default().solve(task).unwrap();
+                records
+                    .into_iter()
+                    .find(|record| record.package_record.name.as_normalized() == "bors")
+                    .unwrap()
+                    .file_name
+            };
+
+            // Without a pinned file the latest compatible version is selected.
+            assert_eq!(solve_foobar(), "bors-1.2.1-bla_1.tar.bz2");
+
+            PinnedFile::from_str("bors <=1\n")
+                .unwrap()
+                .to_prefix(prefix.path())
+                .unwrap();
+            assert_eq!(solve_foobar(), "bors-1.0-bla_1.tar.bz2");
+        }
+
         #[test]
         fn test_virtual_package_constrains() {
             // This tests that a package that has a constrains on a virtual package is